@@ -1,5 +1,15 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use printspots_core::config::{BacklitBase, HeightBand, Lighting, PrinterProfile, SettingsOverrides, SlicerFlavor, StructuringElement};
+use printspots_core::export::{stl::StlFormat, NAME_PLACEHOLDER, PART_PLACEHOLDER};
+use printspots_core::grayscale::generate::{CapMaterial, MeshBackend, MeshBudget, TopCap};
+use printspots_core::grayscale::shape::{BaseShape, ShapeFit, ShapeKind};
+use printspots_core::grayscale::tiling::{TileJoint, Tiling};
+use printspots_core::mesh::frame::{Frame, FrameMaterial, FrameProfile};
+use printspots_core::mesh::mount::{Mount, MountKind};
+use printspots_core::mesh::placement::{PadSettings, PadStyle, Placement};
+use printspots_core::mesh::text::Label;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 #[derive(Parser)]
 #[command(name = "printspots")]
@@ -23,6 +33,13 @@ pub enum Commands {
         #[arg(long, default_value_t = false)]
         flat_top: bool,
 
+        #[command(flatten)]
+        cap: CapArgs,
+
+        /// Generate a backlit pattern to be photographed against a lightbox
+        #[arg(long, default_value_t = false)]
+        backlit: bool,
+
         /// Optional output filename, defaults to calibration.3mf
         #[arg(short, long)]
         filename: Option<PathBuf>,
@@ -34,31 +51,623 @@ pub enum Commands {
         #[arg(short, long)]
         input: PathBuf,
 
-        /// Size of the image in mm (the larger dimension will be scaled to this size)
-        #[arg(short = 's', long, default_value = "100")]
-        size: u32,
-
         /// Should the output mesh have flat top
         #[arg(long)]
         flat_top: bool,
 
-        /// Also export individual STL files for black and white meshes
-        #[arg(long, default_value_t = false)]
-        stl: bool,
+        #[command(flatten)]
+        cap: CapArgs,
 
-        /// Add small pad on the build plate to every color object so the slicer let it float in the air
-        #[arg(long, default_value_t = false)]
-        add_pads: bool,
+        #[command(flatten)]
+        shape: Box<ShapeArgs>,
+
+        #[command(flatten)]
+        frame: Box<FrameArgs>,
+
+        #[command(flatten)]
+        mount: Box<MountArgs>,
+
+        #[command(flatten)]
+        label: Box<LabelArgs>,
+
+        #[command(flatten)]
+        stl: Box<StlArgs>,
+
+        #[command(flatten)]
+        colored: ColorExportArgs,
+
+        #[command(flatten)]
+        placement: Box<PlacementArgs>,
 
         /// Path to the palette file
         #[arg(short, long)]
         palette: PathBuf,
 
+        /// Lighting of the stand-in palette used when the palette file cannot be loaded: reflective or backlit
+        #[arg(long, default_value_t = Lighting::Reflective)]
+        lighting: Lighting,
+
         /// Output 3MF filename
         #[arg(short, long, default_value = "out.3mf")]
         output: PathBuf,
+
+        /// Mesh generator: boxes, heightfield for watertight shells that need no slicer repair,
+        /// contour for smooth outlines instead of pixel steps, or terraces for the boxes' shape with far fewer triangles
+        #[arg(long, default_value_t = MeshBackend::Boxes)]
+        mesher: MeshBackend,
+
+        #[command(flatten)]
+        budget: Box<BudgetArgs>,
+
+        #[command(flatten)]
+        tile: TileArgs,
+
+        /// Embed layer height, infill and extruders for prusaslicer, orca or bambu, or none.
+        /// Defaults to the slicer of the --profile printer.
+        #[arg(long)]
+        slicer: Option<SlicerFlavor>,
+
+        /// Check the generated meshes for open edges, non-manifold edges, overlapping faces and the like
+        #[arg(long, default_value_t = false)]
+        validate: bool,
+
+        /// Printer profile JSON as saved by the GUI, applied on top of the config file
+        #[arg(long)]
+        profile: Option<PathBuf>,
+
+        /// Project JSON as saved by the GUI, its settings apply on top of the printer profile
+        #[arg(long)]
+        project: Option<PathBuf>,
+
+        #[command(flatten)]
+        overrides: Box<OverrideArgs>,
+    },
+
+    /// Put several images, each with its own size and palette, onto one plate
+    Pack {
+        /// Image on the plate as IMAGE[,SIZE_MM[,PALETTE]], can be repeated. Size and palette default to
+        /// the configured image size and --palette.
+        #[arg(short, long = "item", required = true)]
+        items: Vec<PackItem>,
+
+        /// Path to the palette file of the images without their own
+        #[arg(short, long)]
+        palette: PathBuf,
+
+        /// Lighting of the stand-in palette used when a palette file cannot be loaded: reflective or backlit
+        #[arg(long, default_value_t = Lighting::Reflective)]
+        lighting: Lighting,
+
+        /// Output 3MF filename
+        #[arg(short, long, default_value = "plate.3mf")]
+        output: PathBuf,
+
+        /// Space between the images on the plate in mm
+        #[arg(long, default_value_t = 5.0)]
+        spacing: f32,
+
+        /// Should the output meshes have flat top
+        #[arg(long)]
+        flat_top: bool,
+
+        #[command(flatten)]
+        cap: CapArgs,
+
+        #[command(flatten)]
+        shape: Box<ShapeArgs>,
+
+        #[command(flatten)]
+        placement: Box<PlacementArgs>,
+
+        /// Embed layer height, infill and extruders for prusaslicer, orca or bambu, or none.
+        /// Defaults to the slicer of the --profile printer.
+        #[arg(long)]
+        slicer: Option<SlicerFlavor>,
+
+        /// Mesh generator: boxes, heightfield, contour or terraces
+        #[arg(long, default_value_t = MeshBackend::Boxes)]
+        mesher: MeshBackend,
+
+        /// Printer profile JSON as saved by the GUI, applied on top of the config file
+        #[arg(long)]
+        profile: Option<PathBuf>,
+
+        /// Project JSON as saved by the GUI, its settings apply on top of the printer profile
+        #[arg(long)]
+        project: Option<PathBuf>,
+
+        #[command(flatten)]
+        overrides: Box<OverrideArgs>,
     },
 
     /// Configure default print settings - run this before any other command
     Configure,
-}
\ No newline at end of file
+}
+
+/// Image of the pack command as `image[,size_mm[,palette]]`
+#[derive(Debug, Clone)]
+pub struct PackItem {
+    pub image: PathBuf,
+    pub size_mm: Option<f32>,
+    pub palette: Option<PathBuf>,
+}
+
+impl FromStr for PackItem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ',').map(str::trim);
+        let image = match parts.next() {
+            Some(image) if !image.is_empty() => PathBuf::from(image),
+            _ => return Err(format!("Invalid item '{}', expected IMAGE[,SIZE_MM[,PALETTE]]", s)),
+        };
+        let size_mm = match parts.next().filter(|size| !size.is_empty()) {
+            Some(size) => match size.parse::<f32>() {
+                Ok(size) if size > 0.0 => Some(size),
+                _ => return Err(format!("Invalid size '{}' of item '{}'", size, s)),
+            },
+            None => None,
+        };
+        let palette = parts.next().filter(|palette| !palette.is_empty()).map(PathBuf::from);
+        Ok(PackItem { image, size_mm, palette })
+    }
+}
+
+/// Cap over a flat top, printed in its own filament
+#[derive(Args, Debug, Default)]
+pub struct CapArgs {
+    /// Layers of solid cap printed over the flat top, 0 for none
+    #[arg(long, default_value_t = 0)]
+    pub cap_layers: u32,
+
+    /// Filament of the cap: white or transparent
+    #[arg(long, default_value_t = CapMaterial::White)]
+    pub cap_material: CapMaterial,
+}
+
+impl CapArgs {
+    pub fn to_cap(&self) -> Option<TopCap> {
+        (self.cap_layers > 0).then_some(TopCap { layers: self.cap_layers, material: self.cap_material })
+    }
+}
+
+/// Outline of the base when it should not be the image rectangle
+#[derive(Args, Debug, Default)]
+pub struct ShapeArgs {
+    /// Cut the image to a rounded_rectangle, circle, ellipse, hexagon, heart or path outline
+    #[arg(long)]
+    pub shape: Option<ShapeKind>,
+
+    /// cover crops the image to fill the shape, contain pads it with the base color to fit inside
+    #[arg(long, default_value_t = ShapeFit::Cover)]
+    pub shape_fit: ShapeFit,
+
+    /// Point of the image kept in view when cropping, as x,y from 0,0 top left to 1,1 bottom right
+    #[arg(long, default_value = "0.5,0.5")]
+    pub focus: Focus,
+
+    /// Corner radius of the rounded rectangle as a fraction of its shorter side
+    #[arg(long, default_value_t = 0.15)]
+    pub corner_radius: f32,
+
+    /// SVG path data of the path outline, or a file holding it
+    #[arg(long)]
+    pub shape_path: Option<String>,
+}
+
+impl ShapeArgs {
+    pub fn to_shape(&self) -> Result<Option<BaseShape>, String> {
+        let Some(kind) = self.shape else {
+            return Ok(None);
+        };
+        let path = match &self.shape_path {
+            Some(data) if Path::new(data).is_file() => {
+                Some(std::fs::read_to_string(data).map_err(|e| format!("Failed to read shape path {}: {}", data, e))?)
+            }
+            data => data.clone(),
+        };
+        Ok(Some(BaseShape {
+            kind,
+            fit: self.shape_fit,
+            focus: self.focus.0,
+            corner_radius: self.corner_radius,
+            path,
+        }))
+    }
+}
+
+/// Point on the image as `x,y` fractions of its size
+#[derive(Debug, Clone, Copy)]
+pub struct Focus(pub [f32; 2]);
+
+impl Default for Focus {
+    fn default() -> Self {
+        Focus([0.5, 0.5])
+    }
+}
+
+impl FromStr for Focus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        let [x, y] = parts.as_slice() else {
+            return Err(format!("Invalid focus '{}', expected x,y", s));
+        };
+        let parse = |value: &str| value.parse::<f32>().map_err(|_| format!("Invalid focus '{}', expected x,y", s));
+        Ok(Focus([parse(x)?.clamp(0.0, 1.0), parse(y)?.clamp(0.0, 1.0)]))
+    }
+}
+
+/// Frame around the image, printed in its own filament
+#[derive(Args, Debug, Default)]
+pub struct FrameArgs {
+    /// Width of the frame in mm, 0 for none
+    #[arg(long, default_value_t = 0.0)]
+    pub frame_width: f32,
+
+    /// Height of the frame in mm, defaults to the top of the image
+    #[arg(long)]
+    pub frame_height: Option<f32>,
+
+    /// Cross-section of the frame: flat, bevel or rounded
+    #[arg(long, default_value_t = FrameProfile::Flat)]
+    pub frame_profile: FrameProfile,
+
+    /// Filament of the frame: black or white
+    #[arg(long, default_value_t = FrameMaterial::Black)]
+    pub frame_material: FrameMaterial,
+
+    /// Bare base between the image and the frame in mm
+    #[arg(long, default_value_t = 0.0)]
+    pub frame_margin: f32,
+}
+
+impl FrameArgs {
+    pub fn to_frame(&self) -> Option<Frame> {
+        (self.frame_width > 0.0).then_some(Frame {
+            width_mm: self.frame_width,
+            height_mm: self.frame_height,
+            profile: self.frame_profile,
+            material: self.frame_material,
+            margin_mm: self.frame_margin,
+        })
+    }
+}
+
+/// Text on the front and back
+#[derive(Args, Debug, Default)]
+pub struct LabelArgs {
+    /// Text printed on a strip of base below the image, e.g. a title or signature
+    #[arg(long)]
+    pub label: Option<String>,
+
+    /// Text debossed into the back of the image, mirrored so it reads from behind
+    #[arg(long)]
+    pub back_label: Option<String>,
+
+    /// Height of a line of label text in mm
+    #[arg(long, default_value_t = 4.0)]
+    pub label_height: f32,
+
+    /// Deboss the palette and config identifiers into the back
+    #[arg(long, default_value_t = false)]
+    pub back_ids: bool,
+}
+
+impl LabelArgs {
+    pub fn to_label(&self) -> Option<Label> {
+        self.label.as_ref().map(|text| Label { text: text.clone(), height_mm: self.label_height })
+    }
+
+    pub fn to_back_label(&self) -> Option<Label> {
+        self.back_label.as_ref().map(|text| Label { text: text.clone(), height_mm: self.label_height })
+    }
+}
+
+/// Hanging hole, keyhole or kickstand
+#[derive(Args, Debug, Default)]
+pub struct MountArgs {
+    /// Cut a hanging_hole or keyhole near the top edge, or a kickstand slot with a stand to plug into it
+    #[arg(long)]
+    pub mount: Option<MountKind>,
+
+    /// Hole or screw head diameter in mm, or the kickstand height with 0 for half the print
+    #[arg(long)]
+    pub mount_size: Option<f32>,
+
+    /// Distance of the hole center from the top edge of the image in mm
+    #[arg(long)]
+    pub mount_offset: Option<f32>,
+}
+
+impl MountArgs {
+    pub fn to_mount(&self) -> Option<Mount> {
+        self.mount.map(|kind| {
+            let mut mount = Mount::new(kind);
+            mount.size_mm = self.mount_size.unwrap_or(mount.size_mm);
+            mount.offset_mm = self.mount_offset.unwrap_or(mount.offset_mm);
+            mount
+        })
+    }
+}
+
+/// STL files of the parts, next to the 3MF
+#[derive(Args, Debug, Default)]
+pub struct StlArgs {
+    /// Also export every part (black, white, frame, cap...) to its own STL file
+    #[arg(long, default_value_t = false)]
+    pub stl: bool,
+
+    /// STL flavour: binary or ascii
+    #[arg(long, default_value_t = StlFormat::Binary)]
+    pub stl_format: StlFormat,
+
+    /// Path of the STL files, {name} is the 3MF file name without extension and {part} the part.
+    /// Implies --stl, defaults to {name}_{part}.stl next to the 3MF. Needs {name} when tiling.
+    #[arg(long)]
+    pub stl_output: Option<String>,
+}
+
+impl StlArgs {
+    /// Pattern of the STL paths for the given 3MF output, `None` when no STL files are wanted
+    pub fn pattern(&self, output: &Path) -> Option<String> {
+        if let Some(pattern) = &self.stl_output {
+            return Some(pattern.clone());
+        }
+        self.stl.then(|| output.with_file_name(format!("{}_{}.stl", NAME_PLACEHOLDER, PART_PLACEHOLDER)).to_string_lossy().to_string())
+    }
+
+    /// Every tile has its own name, a pattern without it would write the files of all tiles to the same paths
+    pub fn check_tiled(&self) -> Result<(), String> {
+        match &self.stl_output {
+            Some(pattern) if !pattern.contains(NAME_PLACEHOLDER) => Err(format!(
+                "--stl-output '{}' has no {} placeholder, the tiles would overwrite each other's STL files",
+                pattern, NAME_PLACEHOLDER
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Exports that carry the colors of the parts, for web previews and renders, written next to the 3MF
+#[derive(Args, Debug, Default)]
+pub struct ColorExportArgs {
+    /// Also write a Wavefront OBJ with an MTL file of the part colors
+    #[arg(long, default_value_t = false)]
+    pub obj: bool,
+
+    /// Also write a PLY with vertex colors
+    #[arg(long, default_value_t = false)]
+    pub ply: bool,
+
+    /// Also write a binary glTF (GLB) with a PBR material per part
+    #[arg(long, default_value_t = false)]
+    pub glb: bool,
+}
+
+/// Limits on the size of the generated model
+#[derive(Args, Debug, Default)]
+pub struct BudgetArgs {
+    /// Largest number of triangles, small features are merged until the model fits
+    #[arg(long)]
+    pub max_triangles: Option<usize>,
+
+    /// Largest 3MF file size in MB, small features are merged until the model fits
+    #[arg(long)]
+    pub max_file_size: Option<f64>,
+}
+
+impl BudgetArgs {
+    pub fn to_budget(&self) -> MeshBudget {
+        MeshBudget {
+            max_triangles: self.max_triangles,
+            max_file_bytes: self.max_file_size.map(|megabytes| (megabytes * 1_000_000.0) as u64),
+        }
+    }
+}
+
+/// Splitting an image that is larger than the bed
+#[derive(Args, Debug, Default)]
+pub struct TileArgs {
+    /// Split the image into tiles that fit the bed, each saved to its own 3MF file
+    #[arg(long, default_value_t = false)]
+    pub tile: bool,
+
+    /// How neighbouring tiles lock into each other: flat, keys or dovetail
+    #[arg(long, default_value_t = TileJoint::Keys)]
+    pub tile_joint: TileJoint,
+
+    /// How far the joints reach into the neighbouring tile in mm
+    #[arg(long, default_value_t = 5.0)]
+    pub joint_size: f32,
+
+    /// Gap around the joints in the neighbouring tile in mm, at least a pixel, so the printed tiles slot together
+    #[arg(long, default_value_t = 0.2)]
+    pub joint_clearance: f32,
+
+    /// Deboss the name of every tile (R1C2) into its back
+    #[arg(long, default_value_t = false)]
+    pub tile_names: bool,
+}
+
+impl TileArgs {
+    pub fn to_tiling(&self, bed: Option<[f32; 2]>) -> Result<Option<Tiling>, String> {
+        if !self.tile {
+            return Ok(None);
+        }
+        let Some([width, depth]) = bed else {
+            return Err("Tiling needs the bed size, pass --bed-size or a --profile".to_string());
+        };
+        let mut tiling = Tiling::new(width, depth);
+        tiling.joint = self.tile_joint;
+        tiling.joint_size_mm = self.joint_size;
+        tiling.joint_clearance_mm = self.joint_clearance;
+        tiling.name_tiles = self.tile_names;
+        Ok(Some(tiling))
+    }
+}
+
+/// Where the model goes on the build plate
+#[derive(Args, Debug, Default)]
+pub struct PlacementArgs {
+    /// Bed size as WIDTH,DEPTH in mm, defaults to the bed of the --profile printer. The model is centered on it.
+    #[arg(long)]
+    pub bed_size: Option<BedSize>,
+
+    /// Pads on the build plate: anchors keep every part at its height and aligned when the slicer centers
+    /// the parts one by one, wipe adds a larger pad per material to prime the nozzle on
+    #[arg(long)]
+    pub pads: Option<PadStyle>,
+
+    /// Same as --pads anchors
+    #[arg(long, default_value_t = false)]
+    pub add_pads: bool,
+
+    /// Edge length of the wipe pads, or of the anchors without them, in mm
+    #[arg(long)]
+    pub pad_size: Option<f32>,
+
+    /// Space between the model and the pads in mm
+    #[arg(long, default_value_t = 3.0)]
+    pub pad_gap: f32,
+}
+
+impl PlacementArgs {
+    /// Bed from the command line, or from the printer profile
+    pub fn bed(&self, printer: Option<&PrinterProfile>) -> Option<[f32; 2]> {
+        self.bed_size.map(|bed| bed.0).or(printer.map(|printer| [printer.bed_width, printer.bed_depth]))
+    }
+
+    pub fn to_placement(&self, printer: Option<&PrinterProfile>) -> Placement {
+        let style = match self.pads {
+            Some(style) => style,
+            None if self.add_pads => PadStyle::Anchors,
+            None => PadStyle::None,
+        };
+        Placement {
+            bed: self.bed(printer),
+            pads: PadSettings { style, size_mm: self.pad_size, gap_mm: self.pad_gap },
+        }
+    }
+}
+
+/// Bed size as `width,depth` in mm
+#[derive(Debug, Clone, Copy)]
+pub struct BedSize(pub [f32; 2]);
+
+impl FromStr for BedSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split([',', 'x']).map(str::trim).collect();
+        let [width, depth] = parts.as_slice() else {
+            return Err(format!("Invalid bed size '{}', expected WIDTH,DEPTH", s));
+        };
+        let parse = |value: &str| match value.parse::<f32>() {
+            Ok(size) if size > 0.0 => Ok(size),
+            _ => Err(format!("Invalid bed size '{}', expected WIDTH,DEPTH", s)),
+        };
+        Ok(BedSize([parse(width)?, parse(depth)?]))
+    }
+}
+
+/// Per-run overrides, they take precedence over the config file, the printer profile and the project
+#[derive(Args, Debug, Default)]
+pub struct OverrideArgs {
+    /// Size of the image in mm (the larger dimension will be scaled to this size)
+    #[arg(short = 's', long)]
+    pub size: Option<f32>,
+
+    /// Thickness of the base in mm
+    #[arg(long)]
+    pub base_thickness: Option<f32>,
+
+    /// Height of a single image layer in mm
+    #[arg(long)]
+    pub layer_thickness: Option<f32>,
+
+    /// Maximum number of image layers
+    #[arg(long)]
+    pub max_layers: Option<f32>,
+
+    /// Base for backlit palettes: diffuser or none
+    #[arg(long)]
+    pub backlit_base: Option<BacklitBase>,
+
+    /// Thickness of the backlit diffuser in mm
+    #[arg(long)]
+    pub diffuser_thickness: Option<f32>,
+
+    /// Smallest feature size in mm
+    #[arg(long)]
+    pub min_feature_size: Option<f32>,
+
+    /// Smallest feature area in mm², 0 disables the check
+    #[arg(long)]
+    pub min_feature_area: Option<f32>,
+
+    /// Smallest width of a feature in mm, 0 disables the check
+    #[arg(long)]
+    pub min_feature_width: Option<f32>,
+
+    /// Merge features that are too small into their surroundings
+    #[arg(long)]
+    pub merge_small_features: Option<bool>,
+
+    /// Element of the opening: square, cross, disk or none
+    #[arg(long)]
+    pub structuring_element: Option<Limit<StructuringElement>>,
+
+    /// Largest layer difference between neighboring pixels, or none
+    #[arg(long)]
+    pub max_height_step: Option<Limit<u32>>,
+
+    /// Minimum width for regions of at least the given layers as <layers>:<mm>, can be repeated
+    #[arg(long = "height-band")]
+    pub height_bands: Vec<HeightBand>,
+
+    /// Maximum total height in mm, or none
+    #[arg(long)]
+    pub max_total_height: Option<Limit<f32>>,
+}
+
+impl OverrideArgs {
+    pub fn to_overrides(&self) -> SettingsOverrides {
+        SettingsOverrides {
+            base_thickness: self.base_thickness,
+            layer_thickness: self.layer_thickness,
+            image_size_mm: self.size,
+            max_layers: self.max_layers,
+            backlit_base: self.backlit_base,
+            diffuser_thickness: self.diffuser_thickness,
+            min_feature_size_mm: self.min_feature_size,
+            min_feature_area_mm2: self.min_feature_area,
+            min_feature_width_mm: self.min_feature_width,
+            merge_small_features: self.merge_small_features,
+            structuring_element: self.structuring_element.clone().map(|limit| limit.0),
+            max_height_step_layers: self.max_height_step.clone().map(|limit| limit.0),
+            height_bands: (!self.height_bands.is_empty()).then(|| self.height_bands.clone()),
+            max_total_height_mm: self.max_total_height.clone().map(|limit| limit.0),
+        }
+    }
+}
+
+/// Value of an optional setting, `none` or `off` switches it off
+#[derive(Debug, Clone)]
+pub struct Limit<T>(pub Option<T>);
+
+impl<T: FromStr> FromStr for Limit<T>
+where
+    T::Err: std::fmt::Display,
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(Limit(None)),
+            _ => s.parse().map(|value| Limit(Some(value))).map_err(|e: T::Err| e.to_string()),
+        }
+    }
+}
+
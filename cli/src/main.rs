@@ -1,17 +1,25 @@
 mod cli;
 // mod commands;
 // mod config;
-use image::{DynamicImage, ImageReader, Luma, GrayImage};
+use image::{DynamicImage, ImageReader, Luma, GrayImage, RgbImage};
 
 use clap::Parser;
-use cli::{Cli, Commands};
-use printspots_core::grayscale::generate::generate_image;
-use printspots_core::mesh::add_build_plate_padding;
-use printspots_core::{config::load_config, grayscale::calibration::generate_calibration_objects, grayscale::image_processing::dither_to_palette};
-use printspots_core::config::{save_config, PrintConfig, PrintingConstraints};
-use dialoguer::{theme::ColorfulTheme, Input, Confirm};
+use std::path::{Path, PathBuf};
+use cli::{Cli, Commands, OverrideArgs};
+use printspots_core::grayscale::generate::{back_ids, back_labels_allowed, expected_bounds, ignored_options, generate_image, generate_image_within_budget, validate_objects, BudgetTarget, GenerateOptions};
+use printspots_core::grayscale::layer_map::LayerMap;
+use printspots_core::grayscale::shape::apply_shape;
+use printspots_core::grayscale::tiling::{split_into_tiles, Tile, TileJoint};
+use printspots_core::mesh::packing::pack_objects;
+use printspots_core::mesh::placement::{place_objects, PadStyle, Placement};
+use printspots_core::utils::PrintObjects;
+use printspots_core::{config::load_config, grayscale::calibration::{generate_backlit_calibration_objects, generate_calibration_objects}, grayscale::image_processing::dither_to_layer_map};
+use printspots_core::config::{load_config_overrides, load_project_overrides, save_config, BacklitBase, EffectiveSettings, Lighting, PrintConfig, PrinterProfile, PrintingConstraints, SettingsSource, SlicerFlavor};
+use dialoguer::{theme::ColorfulTheme, Input, Confirm, Select};
 
-use printspots_core::grayscale::{enforce_min_feature_size, export_to_3mf, export_to_stl, ColorPalette};
+use printspots_core::grayscale::{enforce_min_feature_size_with_report, height::enforce_height_constraints, report::{ConstraintRule, PrintabilityReport}, export_groups_to_3mf, export_to_3mf, ColorPalette, PRINT_OBJECT_NAME};
+use printspots_core::export::{gltf::export_to_glb, obj::export_to_obj, ply::export_to_ply, slicer::SlicerSettings, stl::export_parts_to_stl};
+use printspots_core::recipe::RecipeHasher;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
@@ -24,9 +32,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     match cli.command {
 
-        Some(Commands::Calibration { size, flat_top, filename }) => {
+        Some(Commands::Calibration { size, flat_top, cap, backlit, filename }) => {
             let config = load_config();
-            let calibration_objects = generate_calibration_objects(&config, size as f32, flat_top);
+            let cap = cap.to_cap();
+            if cap.is_some() && !flat_top {
+                println!("⚠ Warning: A cap is only printed over a flat top, ignoring --cap-layers.");
+            }
+            let calibration_objects = if backlit {
+                if flat_top {
+                    println!("⚠ Warning: Backlit calibration has no flat top variant, ignoring --flat-top.");
+                }
+                println!("Generating backlit calibration, photograph the print against a lightbox.");
+                generate_backlit_calibration_objects(&config, size as f32)
+            } else {
+                generate_calibration_objects(&config, size as f32, flat_top, cap)
+            };
             let filename = filename.unwrap_or_else(|| "calibration.3mf".into());
             match export_to_3mf(&calibration_objects, filename.to_str().unwrap()) {
                 Ok(_) => {
@@ -38,77 +58,302 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        Some(Commands::Generate { input, size, flat_top, stl, add_pads, palette, output }) => {
-            if flat_top {
-                println!("⚠ Warning: Flat top option is not yet implemented and will be ignored.");
+        Some(Commands::Generate { input, flat_top, cap, shape, frame, mount, label, stl, colored, placement, palette, lighting, output, mesher, budget, tile, slicer, validate, profile, project, overrides }) => {
+            let cap = cap.to_cap();
+            let frame = frame.to_frame();
+            let mount = mount.to_mount();
+            let shape = shape.to_shape()?;
+            let budget = budget.to_budget();
+            if cap.is_some() && !flat_top {
+                println!("⚠ Warning: A cap is only printed over a flat top, ignoring --cap-layers.");
             }
             println!("Processing image: {}", input.to_str().unwrap());
-            let config = load_config();
-            let img = ImageReader::open(input).unwrap().decode().unwrap();
-            
-            // Check for alpha channel and extract mask if present
-            let (rgb_img, mask) = if img.color().has_alpha() {
-                println!("✓ Detected alpha channel, extracting mask...");
-                let rgba_img = img.to_rgba8();
-                let rgb_img = DynamicImage::ImageRgba8(rgba_img.clone()).to_rgb8();
-                
-                // Extract alpha channel as grayscale mask
-                let mask = GrayImage::from_fn(rgba_img.width(), rgba_img.height(), |x, y| {
-                    Luma([rgba_img.get_pixel(x, y)[3]])
-                });
-                
-                (rgb_img, Some(mask))
-            } else {
-                (img.to_rgb8(), None)
+
+            let printer = load_printer(profile.as_deref())?;
+            let tiling = tile.to_tiling(placement.bed(printer.as_ref()))?;
+            let placement = placement.to_placement(printer.as_ref());
+            let EffectiveSettings { config, constraints, .. } = resolve_settings(printer.as_ref(), project.as_deref(), &overrides)?;
+            let slicer = slicer_settings(slicer, printer.as_ref(), &config);
+            let (rgb_img, mask) = load_image(&input)?;
+            let palette = load_palette(&palette, &config, lighting);
+            if palette.lighting == Lighting::Backlit {
+                println!("✓ Backlit palette detected, generating lithophane with {:?} base.", config.backlit_base);
+            }
+            let (rgb_img, mask) = match &shape {
+                Some(shape) => {
+                    println!("✓ Shaping the base as {} ({})", shape.kind, shape.fit);
+                    let (rgb_img, mask) = apply_shape(&rgb_img, mask.as_ref(), shape, palette.base_color())?;
+                    (rgb_img, Some(mask))
+                }
+                None => (rgb_img, mask),
+            };
+            let dithered = dither_to_layer_map(&rgb_img, &palette, mask.as_ref())?;
+            let (printable, mut report) = make_printable(&dithered, &palette, &constraints, &config);
+
+            println!("Generating 3D printable objects...");
+
+            let mut options = GenerateOptions {
+                flat_top,
+                backend: mesher,
+                cap,
+                frame,
+                mount,
+                label: label.to_label(),
+                back_label: label.to_back_label(),
+                back_ids: label.back_ids,
+                ..GenerateOptions::default()
+            };
+            if let Some(printer) = &printer {
+                options.nozzle_diameter = printer.nozzle_diameter;
+            }
+            for warning in ignored_options(&palette, &options) {
+                println!("⚠ Warning: {}.", warning);
+            }
+            let recipe = RecipeHasher::new()
+                .add_image("image", &rgb_img)
+                .add_mask("mask", mask.as_ref())
+                .add_settings("palette", &palette)
+                .add_settings("config", &config)
+                .add_settings("constraints", &constraints)
+                .add_settings("options", &options)
+                .add_settings("budget", &budget)
+                .add_settings("tiling", &tiling)
+                .add_settings("placement", &placement)
+                .add_settings("slicer", &slicer)
+                .finish();
+            println!("Recipe: {}", recipe);
+            if options.back_ids && back_labels_allowed(&palette) {
+                println!("Back identifiers: {}", back_ids(&palette, &config));
+            }
+            // Tiles are cut from the whole dithered image, so they continue seamlessly into each other
+            let tiles = match &tiling {
+                Some(tiling) => split_into_tiles(&printable, &config, tiling)?,
+                None => vec![Tile::whole(&printable, &config)],
             };
-            
-            let palette = ColorPalette::load_from_file(palette).unwrap_or_else(|_| {
-                eprintln!("⚠ Could not load palette file, using fake grayscale palette.");
-                println!("⚠ Please generate a proper palette using the calibration command and provide its path.");
-                println!("⚠ Fake palette will map colors linearly to layer counts, which WILL NOT yield REAL results.");
-                ColorPalette::fake(config.max_layers as u32)
-            });
-            let dithered = dither_to_palette(&rgb_img, &palette);
-
-            let constrains = PrintingConstraints::default(); // for now default constraints
-
-            let printable = enforce_min_feature_size(&dithered, &palette, &constrains, &config);
-            match printable.save("prediction.png") {
+            let tiled = tiles.len() > 1;
+            if tiled {
+                stl.check_tiled()?;
+                println!("✓ Splitting the image into {} tiles with {} joints for a {}x{} mm bed",
+                    tiles.len(),
+                    tiling.map_or(TileJoint::Flat, |tiling| tiling.joint),
+                    tiling.map_or(0.0, |tiling| tiling.bed_width_mm),
+                    tiling.map_or(0.0, |tiling| tiling.bed_depth_mm));
+                if options.frame.is_some() || options.label.is_some() || options.mount.is_some() {
+                    println!("⚠ Warning: Frames, labels and mounts are left off tiles.");
+                }
+            }
+
+            // The budget may coarsen the layer counts, so the prediction is made from what gets printed
+            let mut printed = printable.clone();
+            let mut budget_reports = Vec::new();
+            let budget_target = BudgetTarget { limits: budget, recipe: Some(&recipe), slicer: slicer.as_ref() };
+            for tile in &tiles {
+                let (tile_config, tile_options) = match tiling.filter(|_| tiled) {
+                    Some(tiling) => (tile.config(&config), tile.options(&options, &tiling)),
+                    None => (config.clone(), options.clone()),
+                };
+                let (width, height) = tile.layers.dimensions();
+                let mut tile_report = PrintabilityReport::new(width, height, &tile_config);
+                let (tile_printable, mut image_objects, budget_report) =
+                    generate_image_within_budget(&tile.layers, &palette, &tile_config, &constraints, &tile_options, &budget_target, &mut tile_report);
+                tile.write_back(&tile_printable, &mut printed);
+                let tile_output = if tiled { tile_path(&output, &tile.name()) } else { output.clone() };
+                if tiled {
+                    println!("Tile {}:", tile.name());
+                }
+
+                // Before the placement, the pads lie outside of the image and the bounds are where it was generated
+                if validate {
+                    println!("Mesh validation:");
+                    let bounds = expected_bounds(&tile_printable, &palette, &tile_config, &tile_options);
+                    for (name, mesh_report) in validate_objects(&image_objects, &bounds) {
+                        if mesh_report.is_valid() {
+                            println!("  ✓ {} mesh: no problems found", name);
+                        } else {
+                            println!("  ⚠ {} mesh: {}", name, mesh_report.summary().join(", "));
+                        }
+                    }
+                }
+
+                let placed = place_objects(&mut image_objects, &placement);
+                if placement.bed.is_some() || placement.pads.style != PadStyle::None {
+                    println!("Placement on the build plate:");
+                    for line in placed.summary() {
+                        println!("  - {}", line);
+                    }
+                    if placed.fits_bed == Some(false) {
+                        println!("⚠ Warning: The model is larger than the bed, try --tile.");
+                    }
+                }
+
+                // Export results
+                match export_groups_to_3mf(&[(PRINT_OBJECT_NAME, &image_objects)], tile_output.to_str().unwrap(), Some(&recipe), slicer.as_ref()) {
+                    Ok(_) => {
+                        println!("✓ 3MF file saved successfully to {}", tile_output.to_str().unwrap());
+                    },
+                    Err(e) => {
+                        eprintln!("✗ Error exporting 3MF file: {}", e);
+                    }
+                }
+                // Optionally export also to STL files
+                if let Some(pattern) = stl.pattern(&tile_output) {
+                    println!("Exporting individual {} STL files of the parts...", stl.stl_format);
+                    let name = tile_output.file_stem().map_or("out".into(), |stem| stem.to_string_lossy());
+                    for (part, path) in export_parts_to_stl(&image_objects, &pattern, &name, stl.stl_format)? {
+                        println!("  ✓ {} saved to {}", part, path.display());
+                    }
+                }
+                if colored.obj {
+                    let obj_path = tile_output.with_extension("obj");
+                    let mtl_path = export_to_obj(&image_objects, &obj_path)?;
+                    println!("✓ OBJ saved to {} with materials in {}", obj_path.display(), mtl_path.display());
+                }
+                if colored.ply {
+                    let ply_path = tile_output.with_extension("ply");
+                    export_to_ply(&image_objects, &ply_path)?;
+                    println!("✓ PLY saved to {}", ply_path.display());
+                }
+                if colored.glb {
+                    let glb_path = tile_output.with_extension("glb");
+                    export_to_glb(&image_objects, &glb_path)?;
+                    println!("✓ GLB saved to {}", glb_path.display());
+                }
+
+                println!("✓ Complete! Stats:");
+                println!("Black mesh: {} vertices, {} triangles", 
+                    image_objects.black_mesh.vertices.vertex.len(), 
+                    image_objects.black_mesh.triangles.triangle.len());
+                println!("White mesh: {} vertices, {} triangles", 
+                    image_objects.white_mesh.vertices.vertex.len(), 
+                    image_objects.white_mesh.triangles.triangle.len());
+                for extra in &image_objects.extras {
+                    println!("{} mesh: {} vertices, {} triangles",
+                        extra.name,
+                        extra.mesh.vertices.vertex.len(),
+                        extra.mesh.triangles.triangle.len());
+                }
+                budget_reports.push(budget_report);
+            }
+            report.record_diff(ConstraintRule::MeshBudget, &printable, &printed);
+
+            let prediction = printed.to_image(&palette)?;
+            match prediction.save("prediction.png") {
                 Ok(_) => println!("✓ Saved prediction image to prediction.png"),
                 Err(e) => eprintln!("⚠ Could not save prediction image: {}", e),
             }
-            println!("Generating 3D printable objects...");
+            if report.is_empty() {
+                println!("✓ No pixels had to be changed for printability");
+            } else {
+                println!("Printability changes ({} pixels):", report.changed_cells());
+                for line in report.summary() {
+                    println!("  - {}", line);
+                }
+                match report.render_overlay(&prediction).save("prediction_overlay.png") {
+                    Ok(_) => println!("✓ Saved changed pixels overlay to prediction_overlay.png"),
+                    Err(e) => eprintln!("⚠ Could not save overlay image: {}", e),
+                }
+            }
 
-            let mut image_objects = generate_image(&printable, &palette, &config, flat_top, mask.as_ref());
+            if !budget.is_unlimited() {
+                for budget_report in &budget_reports {
+                    println!("Mesh budget:");
+                    for line in budget_report.summary() {
+                        println!("  - {}", line);
+                    }
+                    if !budget_report.within_budget {
+                        println!("⚠ Warning: The model is over the mesh budget, try a smaller size or fewer layers.");
+                    }
+                }
+            }
+        }
 
-            if add_pads {
-                println!("Adding build plate padding to objects...");
-                add_build_plate_padding(&mut image_objects.black_mesh, 3.0); // this is unnecesary
-                add_build_plate_padding(&mut image_objects.white_mesh, 5.0);
+        Some(Commands::Pack { items, palette, lighting, output, spacing, flat_top, cap, shape, placement, slicer, mesher, profile, project, overrides }) => {
+            let cap = cap.to_cap();
+            let shape = shape.to_shape()?;
+            if cap.is_some() && !flat_top {
+                println!("⚠ Warning: A cap is only printed over a flat top, ignoring --cap-layers.");
             }
+            let printer = load_printer(profile.as_deref())?;
+            let placement = placement.to_placement(printer.as_ref());
+            let EffectiveSettings { config, constraints, .. } = resolve_settings(printer.as_ref(), project.as_deref(), &overrides)?;
+            let default_palette = load_palette(&palette, &config, lighting);
+            let slicer = slicer_settings(slicer, printer.as_ref(), &config);
 
-            // Export results
-            match export_to_3mf(&image_objects, output.to_str().unwrap()) {
-                Ok(_) => {
-                    println!("✓ 3MF file saved successfully to {}", output.to_str().unwrap());
-                },
-                Err(e) => {
-                    eprintln!("✗ Error exporting 3MF file: {}", e);
-                }
+            let mut options = GenerateOptions { flat_top, backend: mesher, cap, ..GenerateOptions::default() };
+            if let Some(printer) = &printer {
+                options.nozzle_diameter = printer.nozzle_diameter;
             }
-            // Optionally export also to STL files
-            if stl {
-                println!("Exporting individual STL files for black and white meshes...");
-                export_to_stl(&image_objects, "black_mesh.stl", "white_mesh.stl")?;
+            let mut recipe = RecipeHasher::new();
+            recipe
+                .add_settings("constraints", &constraints)
+                .add_settings("options", &options)
+                .add_settings("placement", &placement)
+                .add_settings("spacing", &spacing)
+                .add_settings("slicer", &slicer);
+
+            let mut names = Vec::new();
+            let mut groups = Vec::new();
+            let mut plate_palette: Option<ColorPalette> = None;
+            for (index, item) in items.iter().enumerate() {
+                println!("Processing image {}: {}", index + 1, item.image.display());
+                let palette = match &item.palette {
+                    Some(path) => load_palette(path, &config, lighting),
+                    None => default_palette.clone(),
+                };
+                match &plate_palette {
+                    Some(plate_palette) => plate_palette.check_compatible(&palette)
+                        .map_err(|e| format!("{} can not go on this plate: {}", item.image.display(), e))?,
+                    None => {
+                        for warning in ignored_options(&palette, &options) {
+                            println!("⚠ Warning: {}.", warning);
+                        }
+                        plate_palette = Some(palette.clone());
+                    }
+                }
+                let item_config = PrintConfig {
+                    image_size_mm: item.size_mm.unwrap_or(config.image_size_mm),
+                    ..config.clone()
+                };
+
+                let (rgb_img, mask) = load_image(&item.image).map_err(|e| format!("Item {}: {}", index + 1, e))?;
+                let (rgb_img, mask) = match &shape {
+                    Some(shape) => {
+                        let (rgb_img, mask) = apply_shape(&rgb_img, mask.as_ref(), shape, palette.base_color())?;
+                        (rgb_img, Some(mask))
+                    }
+                    None => (rgb_img, mask),
+                };
+                let dithered = dither_to_layer_map(&rgb_img, &palette, mask.as_ref())?;
+                let (printable, report) = make_printable(&dithered, &palette, &constraints, &item_config);
+                if !report.is_empty() {
+                    println!("  {} pixels changed for printability", report.changed_cells());
+                }
+
+                let mut image_objects = generate_image(&printable, &palette, &item_config, &options);
+                // Pads go around each image, the plate is centered on the bed once everything is packed
+                place_objects(&mut image_objects, &Placement { bed: None, ..placement });
+                let prefix = format!("item{}", index + 1);
+                recipe
+                    .add_image(&format!("{}_image", prefix), &rgb_img)
+                    .add_mask(&format!("{}_mask", prefix), mask.as_ref())
+                    .add_settings(&format!("{}_palette", prefix), &palette)
+                    .add_settings(&format!("{}_config", prefix), &item_config);
+                let name = item.image.file_stem().map_or(prefix.clone(), |stem| stem.to_string_lossy().to_string());
+                names.push(name);
+                groups.push(image_objects);
             }
+            let recipe = recipe.finish();
+            println!("Recipe: {}", recipe);
 
-            println!("✓ Complete! Stats:");
-            println!("Black mesh: {} vertices, {} triangles", 
-                image_objects.black_mesh.vertices.vertex.len(), 
-                image_objects.black_mesh.triangles.triangle.len());
-            println!("White mesh: {} vertices, {} triangles", 
-                image_objects.white_mesh.vertices.vertex.len(), 
-                image_objects.white_mesh.triangles.triangle.len());
+            let packed = pack_objects(&mut groups, placement.bed, spacing)?;
+            println!("Plate layout:");
+            for (name, [x0, y0, x1, y1]) in names.iter().zip(&packed) {
+                println!("  - {}: x {:.1} to {:.1} mm, y {:.1} to {:.1} mm", name, x0, x1, y0, y1);
+            }
+            let named: Vec<(&str, &PrintObjects)> = names.iter().map(String::as_str).zip(&groups).collect();
+            export_groups_to_3mf(&named, &output.to_string_lossy(), Some(&recipe), slicer.as_ref())
+                .map_err(|e| format!("Error exporting 3MF file: {}", e))?;
+            println!("✓ 3MF file with {} images saved successfully to {}", groups.len(), output.display());
         }
 
         Some(Commands::Configure) => {
@@ -124,6 +369,110 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Printer profile given with --profile
+fn load_printer(profile: Option<&Path>) -> Result<Option<PrinterProfile>, String> {
+    profile
+        .map(|path| PrinterProfile::load_from_file(path)
+            .map_err(|e| format!("Could not load printer profile {}: {}", path.display(), e)))
+        .transpose()
+}
+
+/// Settings of the run, defaults < config file < printer profile < project < command line
+fn resolve_settings(printer: Option<&PrinterProfile>, project: Option<&Path>, overrides: &OverrideArgs) -> Result<EffectiveSettings, String> {
+    let config_overrides = load_config_overrides();
+    let profile_overrides = printer
+        .map(PrinterProfile::settings_overrides)
+        .unwrap_or_default();
+    let project_overrides = project
+        .map(|path| load_project_overrides(path)
+            .map_err(|e| format!("Could not load project {}: {}", path.display(), e)))
+        .transpose()?
+        .unwrap_or_default();
+    let settings = EffectiveSettings::resolve(&[
+        (SettingsSource::ConfigFile, &config_overrides),
+        (SettingsSource::PrinterProfile, &profile_overrides),
+        (SettingsSource::Project, &project_overrides),
+        (SettingsSource::CommandLine, &overrides.to_overrides()),
+    ]);
+    println!("Effective settings:");
+    for line in settings.describe() {
+        println!("  {}", line);
+    }
+    Ok(settings)
+}
+
+/// Slicer settings to embed, from --slicer or the printer profile
+fn slicer_settings(slicer: Option<SlicerFlavor>, printer: Option<&PrinterProfile>, config: &PrintConfig) -> Option<SlicerSettings> {
+    let flavor = slicer.or(printer.map(|printer| printer.slicer)).unwrap_or_default();
+    let settings = SlicerSettings::new(flavor, config);
+    if let Some(settings) = &settings {
+        println!("✓ Embedding {} settings: {} mm layers, 100% infill, one extruder per filament", settings.flavor, settings.layer_height_mm);
+    }
+    settings
+}
+
+/// Image as RGB, with its alpha channel as mask if it has one
+fn load_image(path: &Path) -> Result<(RgbImage, Option<GrayImage>), String> {
+    let img = ImageReader::open(path)
+        .map_err(|e| format!("Could not open image {}: {}", path.display(), e))?
+        .decode()
+        .map_err(|e| format!("Could not decode image {}: {}", path.display(), e))?;
+
+    // Check for alpha channel and extract mask if present
+    if img.color().has_alpha() {
+        println!("✓ Detected alpha channel, extracting mask...");
+        let rgba_img = img.to_rgba8();
+        let rgb_img = DynamicImage::ImageRgba8(rgba_img.clone()).to_rgb8();
+
+        // Extract alpha channel as grayscale mask
+        let mask = GrayImage::from_fn(rgba_img.width(), rgba_img.height(), |x, y| {
+            Luma([rgba_img.get_pixel(x, y)[3]])
+        });
+
+        Ok((rgb_img, Some(mask)))
+    } else {
+        Ok((img.to_rgb8(), None))
+    }
+}
+
+/// Palette from the file, or a fake grayscale one when it can not be loaded
+fn load_palette(path: &Path, config: &PrintConfig, lighting: Lighting) -> ColorPalette {
+    ColorPalette::load_from_file(path).unwrap_or_else(|_| {
+        eprintln!("⚠ Could not load palette file {}, using fake {} grayscale palette.", path.display(), lighting);
+        println!("⚠ Please generate a proper palette using the calibration command and provide its path.");
+        println!("⚠ Fake palette will map colors linearly to layer counts, which WILL NOT yield REAL results.");
+        match lighting {
+            Lighting::Reflective => ColorPalette::fake(config.max_layers as u32),
+            Lighting::Backlit => ColorPalette::fake_backlit(config.max_layers as u32),
+        }
+    })
+}
+
+/// Enforce the feature size and height constraints on the dithered layers
+fn make_printable(dithered: &LayerMap, palette: &ColorPalette, constraints: &PrintingConstraints, config: &PrintConfig) -> (LayerMap, PrintabilityReport) {
+    let (printable, mut report) = enforce_min_feature_size_with_report(dithered, constraints, config);
+    let (constrained, height_stats) = enforce_height_constraints(&printable, palette, constraints, config);
+    report.record_diff(ConstraintRule::Height, &printable, &constrained);
+    if height_stats.lowered_cells > 0 {
+        println!(
+            "✓ Height constraints lowered {} cells ({} by step limit, {} by height bands, {} by height budget), {} layers removed",
+            height_stats.lowered_cells,
+            height_stats.step_limited_cells,
+            height_stats.band_limited_cells,
+            height_stats.height_capped_cells,
+            height_stats.layers_removed,
+        );
+    }
+    (constrained, report)
+}
+
+/// Output file of a tile, `out.3mf` becomes `out_R1C2.3mf`
+fn tile_path(output: &Path, name: &str) -> PathBuf {
+    let stem = output.file_stem().map_or("out".into(), |stem| stem.to_string_lossy());
+    let extension = output.extension().map_or("3mf".into(), |extension| extension.to_string_lossy());
+    output.with_file_name(format!("{}_{}.{}", stem, name, extension))
+}
+
 /// Interactive configuration wizard using dialoguer
 pub fn interactive_configure() -> Result<PrintConfig, Box<dyn std::error::Error>> {
     let current_config = load_config();
@@ -165,12 +514,31 @@ pub fn interactive_configure() -> Result<PrintConfig, Box<dyn std::error::Error>
         .default(current_config.max_layers)
         .interact_text()?;
     
+    println!("  ℹ What the image layers sit on when printing with a backlit palette\n");
+
+    let backlit_bases = [BacklitBase::Diffuser, BacklitBase::None];
+    let backlit_base_index = Select::with_theme(&theme)
+        .with_prompt("Backlit base")
+        .items(["White diffuser", "No base"])
+        .default(backlit_bases.iter().position(|b| *b == current_config.backlit_base).unwrap_or(0))
+        .interact()?;
+    let backlit_base = backlit_bases[backlit_base_index];
+
+    println!("  ℹ A thin diffuser spreads the light without dimming it much. default: 0.6\n");
+
+    let diffuser_thickness: f32 = Input::with_theme(&theme)
+        .with_prompt("Diffuser thickness (mm)")
+        .with_initial_text(current_config.diffuser_thickness.to_string())
+        .default(current_config.diffuser_thickness)
+        .interact_text()?;
     
     let new_config = PrintConfig {
         base_thickness,
         layer_thickness,
         image_size_mm,
         max_layers,
+        backlit_base,
+        diffuser_thickness,
     };
     
     println!("\n┌─── New Configuration ───┐");
@@ -178,6 +546,8 @@ pub fn interactive_configure() -> Result<PrintConfig, Box<dyn std::error::Error>
     println!("│ Layer thickness: {:.2} mm", new_config.layer_thickness);
     println!("│ Image size:      {:.2} mm", new_config.image_size_mm);
     println!("│ Max layers:      {:.0}", new_config.max_layers);
+    println!("│ Backlit base:    {:?}", new_config.backlit_base);
+    println!("│ Diffuser:        {:.2} mm", new_config.diffuser_thickness);
     println!("└─────────────────────────┘\n");
     
     if Confirm::with_theme(&theme)
@@ -1,8 +1,103 @@
-use std::{fs, path::PathBuf};
+use std::{collections::BTreeMap, fmt, fs, path::PathBuf, str::FromStr};
 
 use serde::{Serialize, Deserialize};
 use anyhow::Result;
 
+/// How the finished print is meant to be viewed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Lighting {
+    /// Front-lit, brightness comes from white layers reflecting light over a black base
+    #[default]
+    Reflective,
+    /// Back-lit (lithophane), brightness comes from how much light passes through the stack
+    Backlit,
+}
+
+impl fmt::Display for Lighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lighting::Reflective => write!(f, "reflective"),
+            Lighting::Backlit => write!(f, "backlit"),
+        }
+    }
+}
+
+impl FromStr for Lighting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reflective" | "front" | "frontlit" => Ok(Lighting::Reflective),
+            "backlit" | "back" | "lithophane" => Ok(Lighting::Backlit),
+            _ => Err(format!("Unknown lighting '{}', expected reflective or backlit", s)),
+        }
+    }
+}
+
+/// What the white layers sit on when printing in backlit mode. The light shines through the whole print,
+/// so there is no black base.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BacklitBase {
+    /// Thin white diffuser of `diffuser_thickness` under the image layers.
+    /// Configs saved with the black base it replaced load as the diffuser.
+    #[default]
+    #[serde(alias = "black")]
+    Diffuser,
+    /// No base at all, the print consists of the image layers only
+    None,
+}
+
+impl FromStr for BacklitBase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "diffuser" => Ok(BacklitBase::Diffuser),
+            "none" => Ok(BacklitBase::None),
+            "black" => Err("A black base blocks the light of a backlit print, expected diffuser or none".to_string()),
+            _ => Err(format!("Unknown backlit base '{}', expected diffuser or none", s)),
+        }
+    }
+}
+
+/// Slicer whose project settings are embedded in the 3MF
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SlicerFlavor {
+    /// Bare geometry, the slicer settings are entered by hand
+    #[default]
+    None,
+    /// PrusaSlicer, `Metadata/Slic3r_PE_model.config`
+    PrusaSlicer,
+    /// OrcaSlicer and Bambu Studio, `Metadata/model_settings.config`
+    Orca,
+}
+
+impl fmt::Display for SlicerFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlicerFlavor::None => write!(f, "none"),
+            SlicerFlavor::PrusaSlicer => write!(f, "prusaslicer"),
+            SlicerFlavor::Orca => write!(f, "orca"),
+        }
+    }
+}
+
+impl FromStr for SlicerFlavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(SlicerFlavor::None),
+            "prusaslicer" | "prusa" | "prusa_slicer" => Ok(SlicerFlavor::PrusaSlicer),
+            "orca" | "orcaslicer" | "bambu" | "bambu_studio" | "bambustudio" => Ok(SlicerFlavor::Orca),
+            _ => Err(format!("Unknown slicer '{}', expected none, prusaslicer, orca or bambu", s)),
+        }
+    }
+}
+
 /// Global configuration for the entire printing process
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PrintConfig {
@@ -10,6 +105,16 @@ pub struct PrintConfig {
     pub layer_thickness: f32,
     pub image_size_mm: f32,
     pub max_layers: f32,
+    /// Base used for backlit palettes, ignored for reflective ones
+    #[serde(default)]
+    pub backlit_base: BacklitBase,
+    /// Thickness of the backlit diffuser, thinner than the base so it lets the light through
+    #[serde(default = "default_diffuser_thickness")]
+    pub diffuser_thickness: f32,
+}
+
+fn default_diffuser_thickness() -> f32 {
+    0.6
 }
 
 impl Default for PrintConfig {
@@ -19,6 +124,8 @@ impl Default for PrintConfig {
             layer_thickness: 0.05,
             image_size_mm: 100.0,
             max_layers: 19.0,
+            backlit_base: BacklitBase::default(),
+            diffuser_thickness: default_diffuser_thickness(),
         }
     }
     
@@ -31,6 +138,8 @@ impl PrintConfig {
             layer_thickness,
             image_size_mm,
             max_layers,
+            backlit_base: BacklitBase::default(),
+            diffuser_thickness: default_diffuser_thickness(),
         }
     }
 
@@ -50,6 +159,20 @@ impl PrintConfig {
         }
     }
 
+    /// Black and white thickness of a backlit stack with `white_layers` image layers.
+    /// The light has to pass through the stack, so it is all white.
+    pub fn calculate_backlit_stack(&self, white_layers: u32) -> (f32, f32) {
+        (0.0, self.backlit_base_thickness() + white_layers as f32 * self.layer_thickness)
+    }
+
+    /// Thickness of the white base of a backlit print
+    pub fn backlit_base_thickness(&self) -> f32 {
+        match self.backlit_base {
+            BacklitBase::Diffuser => self.diffuser_thickness,
+            BacklitBase::None => 0.0,
+        }
+    }
+
     pub fn pixel_size(&self, image_width_pixels: u32, image_height_pixels: u32) -> (f32,f32) {
         if image_width_pixels > image_height_pixels {
             // Width is the limiting dimension
@@ -139,26 +262,115 @@ pub fn save_config(config: &PrintConfig) -> Result<(), Box<dyn std::error::Error
         fs::create_dir_all(parent)?;
     }
     
-    let json = serde_json::to_string_pretty(config)?;
-    fs::write(&path, json)?;
+    // Keep constraints and other overrides that were added to the file by hand
+    let mut json = fs::read_to_string(&path).ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+        .filter(|value| value.is_object())
+        .unwrap_or_else(|| serde_json::json!({}));
+    if let (Some(existing), serde_json::Value::Object(fields)) = (json.as_object_mut(), serde_json::to_value(config)?) {
+        existing.extend(fields);
+    }
+    fs::write(&path, serde_json::to_string_pretty(&json)?)?;
     
     println!("✓ Configuration saved to: {}", path.display());
     Ok(())
 }
 
-#[derive(Debug, Clone)]
+/// Neighborhood used by the morphological opening in `enforce_min_feature_size`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StructuringElement {
+    Square,
+    Cross,
+    Disk,
+}
+
+impl StructuringElement {
+    /// Pixel offsets covered by the element with the given radius, including the center
+    pub fn offsets(&self, radius: u32) -> Vec<(i32, i32)> {
+        let r = radius as i32;
+        let mut offsets = Vec::new();
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let inside = match self {
+                    StructuringElement::Square => true,
+                    StructuringElement::Cross => dx == 0 || dy == 0,
+                    StructuringElement::Disk => dx * dx + dy * dy <= r * r,
+                };
+                if inside {
+                    offsets.push((dx, dy));
+                }
+            }
+        }
+        offsets
+    }
+}
+
+impl FromStr for StructuringElement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "square" => Ok(StructuringElement::Square),
+            "cross" => Ok(StructuringElement::Cross),
+            "disk" => Ok(StructuringElement::Disk),
+            _ => Err(format!("Unknown structuring element '{}', expected square, cross or disk", s)),
+        }
+    }
+}
+
+/// Regions at least `min_layers` high have to be at least `min_width_mm` wide
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeightBand {
+    pub min_layers: u32,
+    pub min_width_mm: f32,
+}
+
+/// Parses `layers:mm`, e.g. `10:2.5`
+impl FromStr for HeightBand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (layers, width) = s.split_once(':')
+            .ok_or_else(|| format!("Height band '{}' must look like <layers>:<min width mm>", s))?;
+        Ok(HeightBand {
+            min_layers: layers.trim().parse().map_err(|e| format!("Invalid layer count in '{}': {}", s, e))?,
+            min_width_mm: width.trim().parse().map_err(|e| format!("Invalid width in '{}': {}", s, e))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PrintingConstraints {
+    /// Smallest bounding box side of a feature, also sizes the structuring element
     pub min_feature_size_mm: f32,
+    /// Smallest area of a feature, 0 disables the check
+    pub min_feature_area_mm2: f32,
+    /// Smallest width of any part of a feature, catches long thin lines and thin tails of wider features, 0 disables the check
+    pub min_feature_width_mm: f32,
     pub merge_small_features: bool,
-    pub erosion_dilation_passes: u32,
+    /// Element for the opening that removes features thinner than `min_feature_size_mm`, `None` disables it
+    pub structuring_element: Option<StructuringElement>,
+    /// Largest difference in white layers between two adjacent cells
+    pub max_height_step_layers: Option<u32>,
+    /// Minimum width of the regions rising above each band
+    pub height_bands: Vec<HeightBand>,
+    /// Height budget for base plus image layers
+    pub max_total_height_mm: Option<f32>,
 }
 
 impl Default for PrintingConstraints {
     fn default() -> Self {
         Self {
             min_feature_size_mm: 0.8,
+            min_feature_area_mm2: 0.5,
+            min_feature_width_mm: 0.4,
             merge_small_features: true,
-            erosion_dilation_passes: 1,
+            structuring_element: Some(StructuringElement::Square),
+            max_height_step_layers: None,
+            height_bands: Vec::new(),
+            max_total_height_mm: None,
         }
     }
 }
@@ -174,4 +386,284 @@ impl PrintingConstraints {
         
         (min_pixels_x, min_pixels_y)
     }
-}
\ No newline at end of file
+
+    /// Minimum feature area in cells
+    pub fn calculate_min_area_pixels(&self, config: &PrintConfig, image_width: u32, image_height: u32) -> u32 {
+        let (pixel_width, pixel_height) = config.pixel_size(image_width, image_height);
+        (self.min_feature_area_mm2 / (pixel_width * pixel_height)).ceil() as u32
+    }
+
+    /// Minimum feature width in cells, compared against `Feature::min_width`
+    pub fn calculate_min_width_pixels(&self, config: &PrintConfig, image_width: u32, image_height: u32) -> f32 {
+        let (pixel_width, pixel_height) = config.pixel_size(image_width, image_height);
+        self.min_feature_width_mm / pixel_width.max(pixel_height)
+    }
+
+    /// Most image layers that fit into `max_total_height_mm` on top of the base
+    pub fn calculate_max_layers_for_height(&self, config: &PrintConfig) -> Option<u32> {
+        self.max_total_height_mm.map(|max_height| {
+            // Small epsilon so budgets that are an exact multiple of the layer height are not lost to rounding
+            ((max_height - config.base_thickness) / config.layer_thickness + 1e-4).floor().max(0.0) as u32
+        })
+    }
+
+    /// Radius of the structuring element. The element is `2 * radius + 1` cells wide, rounded down to at most
+    /// the minimum feature size, so the opening never removes a feature that meets it. Features an even
+    /// cell count below the limit may survive the opening and are left to the feature merge.
+    pub fn calculate_morphology_radius(&self, config: &PrintConfig, image_width: u32, image_height: u32) -> u32 {
+        let (min_pixels_x, min_pixels_y) = self.calculate_min_pixels(config, image_width, image_height);
+        min_pixels_x.max(min_pixels_y).saturating_sub(1) / 2
+    }
+}
+
+/// Where a setting came from, later sources win
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingsSource {
+    Default,
+    ConfigFile,
+    PrinterProfile,
+    Project,
+    CommandLine,
+}
+
+impl fmt::Display for SettingsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SettingsSource::Default => "default",
+            SettingsSource::ConfigFile => "config file",
+            SettingsSource::PrinterProfile => "printer profile",
+            SettingsSource::Project => "project",
+            SettingsSource::CommandLine => "command line",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Partial `PrintConfig` and `PrintingConstraints` from one source, `None` keeps the value of the previous source.
+/// Field names match both structs, so an existing `config.json` is a valid set of overrides.
+/// Settings that are themselves optional use `Some(None)` (JSON `null`) to switch them off.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SettingsOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_thickness: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer_thickness: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_size_mm: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_layers: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backlit_base: Option<BacklitBase>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diffuser_thickness: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_feature_size_mm: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_feature_area_mm2: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_feature_width_mm: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merge_small_features: Option<bool>,
+    #[serde(deserialize_with = "double_option::deserialize", skip_serializing_if = "Option::is_none")]
+    pub structuring_element: Option<Option<StructuringElement>>,
+    #[serde(deserialize_with = "double_option::deserialize", skip_serializing_if = "Option::is_none")]
+    pub max_height_step_layers: Option<Option<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height_bands: Option<Vec<HeightBand>>,
+    #[serde(deserialize_with = "double_option::deserialize", skip_serializing_if = "Option::is_none")]
+    pub max_total_height_mm: Option<Option<f32>>,
+}
+
+/// Present fields become `Some`, including an explicit `null`, missing ones fall back to the serde default
+mod double_option {
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        Option::<T>::deserialize(deserializer).map(Some)
+    }
+}
+
+impl SettingsOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Settings a run actually uses, together with the source of every value
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EffectiveSettings {
+    pub config: PrintConfig,
+    pub constraints: PrintingConstraints,
+    /// Source of every field that did not keep its default
+    pub sources: BTreeMap<String, SettingsSource>,
+}
+
+impl EffectiveSettings {
+    /// Apply the sources on top of the defaults, lowest precedence first whatever the order given.
+    /// Sources of the same kind keep their order, the later one wins.
+    pub fn resolve(sources: &[(SettingsSource, &SettingsOverrides)]) -> Self {
+        let mut sources = sources.to_vec();
+        sources.sort_by_key(|(source, _)| *source);
+
+        let mut settings = Self::default();
+        for (source, overrides) in sources {
+            settings.apply(source, overrides);
+        }
+        settings
+    }
+
+    pub fn apply(&mut self, source: SettingsSource, overrides: &SettingsOverrides) {
+        let config = &mut self.config;
+        let constraints = &mut self.constraints;
+        let sources = &mut self.sources;
+
+        override_value(sources, source, "base_thickness", &mut config.base_thickness, &overrides.base_thickness);
+        override_value(sources, source, "layer_thickness", &mut config.layer_thickness, &overrides.layer_thickness);
+        override_value(sources, source, "image_size_mm", &mut config.image_size_mm, &overrides.image_size_mm);
+        override_value(sources, source, "max_layers", &mut config.max_layers, &overrides.max_layers);
+        override_value(sources, source, "backlit_base", &mut config.backlit_base, &overrides.backlit_base);
+        override_value(sources, source, "diffuser_thickness", &mut config.diffuser_thickness, &overrides.diffuser_thickness);
+        override_value(sources, source, "min_feature_size_mm", &mut constraints.min_feature_size_mm, &overrides.min_feature_size_mm);
+        override_value(sources, source, "min_feature_area_mm2", &mut constraints.min_feature_area_mm2, &overrides.min_feature_area_mm2);
+        override_value(sources, source, "min_feature_width_mm", &mut constraints.min_feature_width_mm, &overrides.min_feature_width_mm);
+        override_value(sources, source, "merge_small_features", &mut constraints.merge_small_features, &overrides.merge_small_features);
+        override_value(sources, source, "structuring_element", &mut constraints.structuring_element, &overrides.structuring_element);
+        override_value(sources, source, "max_height_step_layers", &mut constraints.max_height_step_layers, &overrides.max_height_step_layers);
+        override_value(sources, source, "height_bands", &mut constraints.height_bands, &overrides.height_bands);
+        override_value(sources, source, "max_total_height_mm", &mut constraints.max_total_height_mm, &overrides.max_total_height_mm);
+    }
+
+    /// Source of a field, `Default` when nothing overrode it
+    pub fn source_of(&self, field: &str) -> SettingsSource {
+        self.sources.get(field).copied().unwrap_or(SettingsSource::Default)
+    }
+
+    /// One `name = value (source)` line per setting
+    pub fn describe(&self) -> Vec<String> {
+        let config = &self.config;
+        let constraints = &self.constraints;
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "off".to_string());
+        let values = [
+            ("base_thickness", format!("{} mm", config.base_thickness)),
+            ("layer_thickness", format!("{} mm", config.layer_thickness)),
+            ("image_size_mm", format!("{} mm", config.image_size_mm)),
+            ("max_layers", format!("{}", config.max_layers)),
+            ("backlit_base", format!("{:?}", config.backlit_base)),
+            ("diffuser_thickness", format!("{} mm", config.diffuser_thickness)),
+            ("min_feature_size_mm", format!("{} mm", constraints.min_feature_size_mm)),
+            ("min_feature_area_mm2", format!("{} mm²", constraints.min_feature_area_mm2)),
+            ("min_feature_width_mm", format!("{} mm", constraints.min_feature_width_mm)),
+            ("merge_small_features", format!("{}", constraints.merge_small_features)),
+            ("structuring_element", optional(constraints.structuring_element.map(|e| format!("{:?}", e)))),
+            ("max_height_step_layers", optional(constraints.max_height_step_layers.map(|s| s.to_string()))),
+            ("height_bands", if constraints.height_bands.is_empty() {
+                "none".to_string()
+            } else {
+                constraints.height_bands.iter()
+                    .map(|band| format!("{}:{}", band.min_layers, band.min_width_mm))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }),
+            ("max_total_height_mm", optional(constraints.max_total_height_mm.map(|h| format!("{} mm", h)))),
+        ];
+
+        values.into_iter()
+            .map(|(name, value)| format!("{} = {} ({})", name, value, self.source_of(name)))
+            .collect()
+    }
+}
+
+fn override_value<T: Clone>(
+    sources: &mut BTreeMap<String, SettingsSource>,
+    source: SettingsSource,
+    name: &str,
+    target: &mut T,
+    value: &Option<T>,
+) {
+    if let Some(value) = value {
+        *target = value.clone();
+        sources.insert(name.to_string(), source);
+    }
+}
+
+/// Printer profile as stored by the GUI in `profiles/<id>.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrinterProfile {
+    pub id: String, // uuid
+    pub name: String,
+    pub description: String,
+    pub bed_width: f32,
+    pub bed_depth: f32,
+    pub nozzle_diameter: f32,
+    pub min_layer_height: f32,
+    #[serde(default)]
+    pub has_automatic_filament_change: bool,
+    /// Slicer the 3MF files are made for, its layer height, infill and extruder settings are embedded
+    #[serde(default)]
+    pub slicer: SlicerFlavor,
+    /// Settings this printer always needs, applied on top of what the nozzle implies
+    #[serde(default, skip_serializing_if = "SettingsOverrides::is_empty")]
+    pub overrides: SettingsOverrides,
+}
+
+impl PrinterProfile {
+    pub fn load_from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Nothing narrower than two extrusion lines prints reliably
+    pub fn settings_overrides(&self) -> SettingsOverrides {
+        let mut overrides = self.overrides.clone();
+        overrides.min_feature_size_mm.get_or_insert(2.0 * self.nozzle_diameter);
+        overrides
+    }
+}
+
+/// Settings of a project as saved by the GUI in `projects/<id>/project.json`.
+/// The advanced overrides of the project win over its image size and thicknesses.
+pub fn load_project_overrides<P: AsRef<std::path::Path>>(path: P) -> Result<SettingsOverrides> {
+    #[derive(Deserialize)]
+    struct Project {
+        base_thickness: Option<f32>,
+        layer_thickness: Option<f32>,
+        image_size_mm: Option<f32>,
+        backlit_base: Option<BacklitBase>,
+        #[serde(default)]
+        overrides: SettingsOverrides,
+    }
+
+    let project: Project = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let advanced = project.overrides;
+    Ok(SettingsOverrides {
+        base_thickness: advanced.base_thickness.or(project.base_thickness),
+        layer_thickness: advanced.layer_thickness.or(project.layer_thickness),
+        image_size_mm: advanced.image_size_mm.or(project.image_size_mm),
+        backlit_base: advanced.backlit_base.or(project.backlit_base),
+        ..advanced
+    })
+}
+
+/// Overrides stored in the config file, including any constraints added by hand
+pub fn load_config_overrides() -> SettingsOverrides {
+    let Some(path) = get_config_path().filter(|path| path.try_exists().unwrap_or(false)) else {
+        return SettingsOverrides::default();
+    };
+
+    match fs::read_to_string(&path).map_err(|e| e.to_string())
+        .and_then(|contents| serde_json::from_str::<SettingsOverrides>(&contents).map_err(|e| e.to_string()))
+    {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            eprintln!("⚠ Error loading config file: {}. Using defaults.", e);
+            SettingsOverrides::default()
+        }
+    }
+}
+
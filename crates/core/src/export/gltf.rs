@@ -0,0 +1,142 @@
+use std::{fs, io, path::Path};
+
+use serde_json::json;
+
+use crate::{export::material_parts, utils::PrintObjects};
+
+/// glTF counts in meters
+const METERS_PER_MM: f64 = 0.001;
+/// Chunk types of the GLB container
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+/// Component types and buffer view targets of glTF
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// The print as binary glTF, one node per part with a PBR material in the color of the part.
+/// glTF is Y-up, so the build plate becomes the XZ plane and the print lies on it with its top facing +Y.
+pub fn write_glb(objects: &PrintObjects) -> io::Result<Vec<u8>> {
+    let parts: Vec<_> = material_parts(objects).into_iter()
+        .filter(|part| !part.mesh.triangles.triangle.is_empty())
+        .collect();
+
+    let mut buffer = Vec::new();
+    let (mut materials, mut meshes, mut nodes, mut accessors, mut views) = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let mut material_names: Vec<&str> = Vec::new();
+    for part in &parts {
+        let material = match material_names.iter().position(|name| *name == part.material) {
+            Some(index) => index,
+            None => {
+                let [red, green, blue, alpha] = part.rgba();
+                let mut material = json!({
+                    "name": part.material,
+                    "pbrMetallicRoughness": {
+                        "baseColorFactor": [linear(red), linear(green), linear(blue), alpha as f32 / 255.0],
+                        "metallicFactor": 0.0,
+                        "roughnessFactor": 0.8,
+                    },
+                });
+                if alpha < 255 {
+                    material["alphaMode"] = json!("BLEND");
+                }
+                materials.push(material);
+                material_names.push(&part.material);
+                material_names.len() - 1
+            }
+        };
+
+        // Positions, with their bounds that glTF requires
+        let positions_offset = buffer.len();
+        let (mut min, mut max) = ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]);
+        for vertex in &part.mesh.vertices.vertex {
+            let position = [vertex.x * METERS_PER_MM, vertex.z * METERS_PER_MM, -vertex.y * METERS_PER_MM].map(|value| value as f32);
+            for ((value, low), high) in position.into_iter().zip(&mut min).zip(&mut max) {
+                *low = low.min(value);
+                *high = high.max(value);
+                buffer.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        views.push(json!({ "buffer": 0, "byteOffset": positions_offset, "byteLength": buffer.len() - positions_offset, "target": ARRAY_BUFFER }));
+        accessors.push(json!({
+            "bufferView": views.len() - 1,
+            "componentType": FLOAT,
+            "count": part.mesh.vertices.vertex.len(),
+            "type": "VEC3",
+            "min": min,
+            "max": max,
+        }));
+
+        let indices_offset = buffer.len();
+        for triangle in &part.mesh.triangles.triangle {
+            for index in [triangle.v1, triangle.v2, triangle.v3] {
+                let index = u32::try_from(index)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Too many vertices for glTF"))?;
+                buffer.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+        views.push(json!({ "buffer": 0, "byteOffset": indices_offset, "byteLength": buffer.len() - indices_offset, "target": ELEMENT_ARRAY_BUFFER }));
+        accessors.push(json!({
+            "bufferView": views.len() - 1,
+            "componentType": UNSIGNED_INT,
+            "count": part.mesh.triangles.triangle.len() * 3,
+            "type": "SCALAR",
+        }));
+
+        meshes.push(json!({
+            "name": part.name,
+            "primitives": [{
+                "attributes": { "POSITION": accessors.len() - 2 },
+                "indices": accessors.len() - 1,
+                "material": material,
+            }],
+        }));
+        nodes.push(json!({ "name": part.name, "mesh": meshes.len() - 1 }));
+    }
+
+    let document = json!({
+        "asset": { "version": "2.0", "generator": format!("PrintSpots {}", env!("CARGO_PKG_VERSION")) },
+        "scene": 0,
+        "scenes": [{ "name": "Print Object", "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "accessors": accessors,
+        "bufferViews": views,
+        "buffers": [{ "byteLength": buffer.len() }],
+    });
+    let mut document = serde_json::to_vec(&document).map_err(io::Error::other)?;
+
+    // Chunks are padded to 4 bytes, JSON with spaces and the binary data with zeros
+    document.resize(document.len().next_multiple_of(4), b' ');
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+    let total = 12 + 8 + document.len() + 8 + buffer.len();
+    let total = u32::try_from(total).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Model too large for GLB"))?;
+    let mut glb = Vec::with_capacity(total as usize);
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&total.to_le_bytes());
+    glb.extend_from_slice(&(document.len() as u32).to_le_bytes());
+    glb.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+    glb.extend_from_slice(&document);
+    glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+    glb.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+    glb.extend_from_slice(&buffer);
+    Ok(glb)
+}
+
+/// Write the print to a GLB file
+pub fn export_to_glb(objects: &PrintObjects, path: &Path) -> io::Result<()> {
+    fs::write(path, write_glb(objects)?)
+}
+
+/// sRGB channel as the linear value glTF colors are given in
+fn linear(channel: u8) -> f32 {
+    let value = channel as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+
+use threemf::Mesh;
+
+use crate::utils::PrintObjects;
+
+pub mod gltf;
+pub mod obj;
+pub mod ply;
+pub mod slicer;
+pub mod stl;
+
+/// Placeholder for the name of a part in output patterns
+pub const PART_PLACEHOLDER: &str = "{part}";
+/// Placeholder for the name of the print, e.g. the stem of the 3MF file
+pub const NAME_PLACEHOLDER: &str = "{name}";
+
+/// Output path of one part. `{name}` and `{part}` in the pattern are replaced, a pattern without `{part}`
+/// gets `_{part}` before its extension so the parts of a print never overwrite each other. Prints written
+/// with the same pattern, like tiles, need `{name}` to stay apart.
+pub fn part_path(pattern: &str, name: &str, part: &str) -> PathBuf {
+    let pattern = pattern.replace(NAME_PLACEHOLDER, name);
+    if pattern.contains(PART_PLACEHOLDER) {
+        return PathBuf::from(pattern.replace(PART_PLACEHOLDER, part));
+    }
+    let path = Path::new(&pattern);
+    let stem = path.file_stem().map_or(name.into(), |stem| stem.to_string_lossy());
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, part, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, part),
+    };
+    path.with_file_name(file_name)
+}
+
+/// A part with the object and material names and color `export_to_3mf` gives it
+pub struct MaterialPart<'a> {
+    /// Short name of the part, as in `PrintObjects::parts`
+    pub part: &'a str,
+    /// Name of the object in the 3MF
+    pub name: String,
+    /// Name of the material
+    pub material: String,
+    /// sRGB color as #RRGGBB or #RRGGBBAA
+    pub display_color: String,
+    pub mesh: &'a Mesh,
+}
+
+impl MaterialPart<'_> {
+    /// Display color as RGBA bytes
+    pub fn rgba(&self) -> [u8; 4] {
+        parse_display_color(&self.display_color)
+    }
+}
+
+/// Every part of the print with its material, black and white first. Empty parts are included.
+pub fn material_parts(objects: &PrintObjects) -> Vec<MaterialPart<'_>> {
+    let mut parts = vec![
+        MaterialPart {
+            part: "black",
+            name: "Black Layer".to_string(),
+            material: "Black".to_string(),
+            display_color: "#000000".to_string(),
+            mesh: &objects.black_mesh,
+        },
+        MaterialPart {
+            part: "white",
+            name: "White Layer".to_string(),
+            material: "White".to_string(),
+            display_color: "#FFFFFF".to_string(),
+            mesh: &objects.white_mesh,
+        },
+    ];
+    parts.extend(objects.extras.iter().map(|extra| MaterialPart {
+        part: &extra.name,
+        name: extra.name.clone(),
+        material: extra.name.clone(),
+        display_color: extra.display_color.clone(),
+        mesh: &extra.mesh,
+    }));
+    parts
+}
+
+/// `#RRGGBB` or `#RRGGBBAA` as RGBA bytes, opaque unless the alpha is given. Anything else is opaque gray.
+pub fn parse_display_color(color: &str) -> [u8; 4] {
+    let hex = color.trim_start_matches('#');
+    let channel = |index: usize| hex.get(index * 2..index * 2 + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok());
+    match (hex.len(), channel(0), channel(1), channel(2)) {
+        (6 | 8, Some(red), Some(green), Some(blue)) => [red, green, blue, channel(3).unwrap_or(255)],
+        _ => [128, 128, 128, 255],
+    }
+}
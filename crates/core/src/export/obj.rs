@@ -0,0 +1,52 @@
+use std::{fs::File, io::{self, BufWriter, Write}, path::{Path, PathBuf}};
+
+use crate::{export::material_parts, utils::PrintObjects};
+
+/// Write the print as Wavefront OBJ, one object per part, and its materials as MTL.
+/// `mtl_name` is the file name the OBJ refers to for the materials.
+pub fn write_obj<O: Write, M: Write>(objects: &PrintObjects, mtl_name: &str, obj: &mut O, mtl: &mut M) -> io::Result<()> {
+    let parts = material_parts(objects);
+
+    let mut written = Vec::new();
+    for part in &parts {
+        if written.contains(&part.material) {
+            continue;
+        }
+        let [red, green, blue, alpha] = part.rgba();
+        writeln!(mtl, "newmtl {}", part.material)?;
+        writeln!(mtl, "Kd {:.4} {:.4} {:.4}", red as f32 / 255.0, green as f32 / 255.0, blue as f32 / 255.0)?;
+        writeln!(mtl, "d {:.4}", alpha as f32 / 255.0)?;
+        writeln!(mtl, "illum 1")?;
+        writeln!(mtl)?;
+        written.push(part.material.clone());
+    }
+
+    writeln!(obj, "# PrintSpots {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(obj, "mtllib {}", mtl_name)?;
+    // Vertex indices count from 1 across the whole file
+    let mut first_vertex = 1;
+    for part in parts.iter().filter(|part| !part.mesh.triangles.triangle.is_empty()) {
+        writeln!(obj, "o {}", part.name.replace(' ', "_"))?;
+        writeln!(obj, "usemtl {}", part.material)?;
+        for vertex in &part.mesh.vertices.vertex {
+            writeln!(obj, "v {} {} {}", vertex.x, vertex.y, vertex.z)?;
+        }
+        for triangle in &part.mesh.triangles.triangle {
+            writeln!(obj, "f {} {} {}", triangle.v1 + first_vertex, triangle.v2 + first_vertex, triangle.v3 + first_vertex)?;
+        }
+        first_vertex += part.mesh.vertices.vertex.len();
+    }
+    Ok(())
+}
+
+/// Write the print to an OBJ file with the MTL file next to it, which is returned
+pub fn export_to_obj(objects: &PrintObjects, path: &Path) -> io::Result<PathBuf> {
+    let mtl_path = path.with_extension("mtl");
+    let mtl_name = mtl_path.file_name().map_or("materials.mtl".into(), |name| name.to_string_lossy());
+    let mut obj = BufWriter::new(File::create(path)?);
+    let mut mtl = BufWriter::new(File::create(&mtl_path)?);
+    write_obj(objects, &mtl_name, &mut obj, &mut mtl)?;
+    obj.flush()?;
+    mtl.flush()?;
+    Ok(mtl_path)
+}
@@ -0,0 +1,60 @@
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use crate::{export::material_parts, utils::PrintObjects};
+
+/// Write the print as binary PLY with every vertex in the color of its part
+pub fn write_ply<W: Write>(objects: &PrintObjects, writer: &mut W) -> io::Result<()> {
+    let parts: Vec<_> = material_parts(objects).into_iter()
+        .filter(|part| !part.mesh.triangles.triangle.is_empty())
+        .collect();
+    let vertex_count: usize = parts.iter().map(|part| part.mesh.vertices.vertex.len()).sum();
+    let face_count: usize = parts.iter().map(|part| part.mesh.triangles.triangle.len()).sum();
+
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format binary_little_endian 1.0")?;
+    writeln!(writer, "comment PrintSpots {}", env!("CARGO_PKG_VERSION"))?;
+    for part in &parts {
+        writeln!(writer, "comment part {} {}", part.part, part.display_color)?;
+    }
+    writeln!(writer, "element vertex {}", vertex_count)?;
+    writeln!(writer, "property float x")?;
+    writeln!(writer, "property float y")?;
+    writeln!(writer, "property float z")?;
+    writeln!(writer, "property uchar red")?;
+    writeln!(writer, "property uchar green")?;
+    writeln!(writer, "property uchar blue")?;
+    writeln!(writer, "property uchar alpha")?;
+    writeln!(writer, "element face {}", face_count)?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+
+    for part in &parts {
+        let color = part.rgba();
+        for vertex in &part.mesh.vertices.vertex {
+            for value in [vertex.x, vertex.y, vertex.z] {
+                writer.write_all(&(value as f32).to_le_bytes())?;
+            }
+            writer.write_all(&color)?;
+        }
+    }
+    let mut first_vertex = 0;
+    for part in &parts {
+        for triangle in &part.mesh.triangles.triangle {
+            writer.write_all(&[3])?;
+            for index in [triangle.v1, triangle.v2, triangle.v3] {
+                let index = u32::try_from(index + first_vertex)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Too many vertices for PLY"))?;
+                writer.write_all(&index.to_le_bytes())?;
+            }
+        }
+        first_vertex += part.mesh.vertices.vertex.len();
+    }
+    Ok(())
+}
+
+/// Write the print to a PLY file
+pub fn export_to_ply(objects: &PrintObjects, path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_ply(objects, &mut writer)?;
+    writer.flush()
+}
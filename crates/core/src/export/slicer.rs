@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{PrintConfig, SlicerFlavor};
+
+/// Per-object settings embedded in the 3MF, so the file opens ready to slice
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SlicerSettings {
+    pub flavor: SlicerFlavor,
+    /// Layer height of every object, the image layers only come out right at exactly this height
+    pub layer_height_mm: f32,
+}
+
+impl SlicerSettings {
+    /// Settings for the config, `None` for bare geometry
+    pub fn new(flavor: SlicerFlavor, config: &PrintConfig) -> Option<Self> {
+        (flavor != SlicerFlavor::None).then_some(Self { flavor, layer_height_mm: config.layer_thickness })
+    }
+}
+
+/// Object of the 3MF as the slicer sees it, a parent with one part per material
+pub(crate) struct SlicerObject {
+    pub id: usize,
+    pub name: String,
+    pub parts: Vec<SlicerPart>,
+}
+
+pub(crate) struct SlicerPart {
+    pub id: usize,
+    pub name: String,
+    pub display_color: String,
+    pub triangles: usize,
+}
+
+/// Extruder of every filament, counted from 1 in the order the colors first appear. Parts that show the
+/// same color, like a black frame and the black layers, are printed with the same filament.
+pub fn assign_extruders<'a>(colors: impl IntoIterator<Item = &'a str>) -> Vec<(String, usize)> {
+    let mut extruders: Vec<(String, usize)> = Vec::new();
+    for color in colors {
+        let color = color.to_ascii_uppercase();
+        if !extruders.iter().any(|(known, _)| *known == color) {
+            let extruder = extruders.len() + 1;
+            extruders.push((color, extruder));
+        }
+    }
+    extruders
+}
+
+/// Files to add to the 3MF archive as (path, contents)
+pub(crate) fn config_files(settings: &SlicerSettings, objects: &[SlicerObject]) -> Vec<(String, String)> {
+    if settings.flavor == SlicerFlavor::None {
+        return Vec::new();
+    }
+    let extruders = assign_extruders(objects.iter().flat_map(|object| object.parts.iter().map(|part| part.display_color.as_str())));
+    let extruder = |part: &SlicerPart| {
+        let color = part.display_color.to_ascii_uppercase();
+        extruders.iter().find(|(known, _)| *known == color).map_or(1, |(_, extruder)| *extruder)
+    };
+    let layer_height = format!("{}", settings.layer_height_mm);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<config>\n");
+    match settings.flavor {
+        SlicerFlavor::None | SlicerFlavor::PrusaSlicer => {
+            // Objects are referred to by their ids in the model, volumes are ranges of the triangles of all parts in order
+            for object in objects {
+                xml.push_str(&format!(" <object id=\"{}\" instances_count=\"1\">\n", object.id));
+                xml.push_str(&prusa_metadata("object", "name", &object.name));
+                xml.push_str(&prusa_metadata("object", "layer_height", &layer_height));
+                xml.push_str(&prusa_metadata("object", "fill_density", "100%"));
+                let mut first = 0;
+                for part in object.parts.iter().filter(|part| part.triangles > 0) {
+                    xml.push_str(&format!("  <volume firstid=\"{}\" lastid=\"{}\">\n", first, first + part.triangles - 1));
+                    xml.push_str(&prusa_metadata("volume", "name", &part.name));
+                    xml.push_str(&prusa_metadata("volume", "volume_type", "ModelPart"));
+                    xml.push_str(&prusa_metadata("volume", "extruder", &extruder(part).to_string()));
+                    xml.push_str("  </volume>\n");
+                    first += part.triangles;
+                }
+                xml.push_str(" </object>\n");
+            }
+            xml.push_str("</config>\n");
+            vec![("Metadata/Slic3r_PE_model.config".to_string(), xml)]
+        }
+        SlicerFlavor::Orca => {
+            // Objects and parts are referred to by their ids in the model
+            for object in objects {
+                let first_extruder = object.parts.first().map_or(1, extruder);
+                xml.push_str(&format!("  <object id=\"{}\">\n", object.id));
+                xml.push_str(&orca_metadata("    ", "name", &object.name));
+                xml.push_str(&orca_metadata("    ", "extruder", &first_extruder.to_string()));
+                xml.push_str(&orca_metadata("    ", "layer_height", &layer_height));
+                xml.push_str(&orca_metadata("    ", "sparse_infill_density", "100%"));
+                for part in &object.parts {
+                    xml.push_str(&format!("    <part id=\"{}\" subtype=\"normal_part\">\n", part.id));
+                    xml.push_str(&orca_metadata("      ", "name", &part.name));
+                    xml.push_str(&orca_metadata("      ", "extruder", &extruder(part).to_string()));
+                    xml.push_str("    </part>\n");
+                }
+                xml.push_str("  </object>\n");
+            }
+            xml.push_str("</config>\n");
+            vec![("Metadata/model_settings.config".to_string(), xml)]
+        }
+    }
+}
+
+fn prusa_metadata(kind: &str, key: &str, value: &str) -> String {
+    let indent = if kind == "object" { "  " } else { "   " };
+    format!("{}<metadata type=\"{}\" key=\"{}\" value=\"{}\"/>\n", indent, kind, key, escape(value))
+}
+
+fn orca_metadata(indent: &str, key: &str, value: &str) -> String {
+    format!("{}<metadata key=\"{}\" value=\"{}\"/>\n", indent, key, escape(value))
+}
+
+/// Text as an XML attribute value
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
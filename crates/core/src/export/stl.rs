@@ -0,0 +1,106 @@
+use std::{fmt, fs::File, io::{self, BufWriter, Write}, path::{Path, PathBuf}, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use threemf::{model::Vertex, Mesh};
+
+use crate::{export::part_path, mesh::calculate_normal, utils::PrintObjects};
+
+/// STL flavour, binary files are about a fifth of the size of ASCII ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StlFormat {
+    #[default]
+    Binary,
+    Ascii,
+}
+
+impl fmt::Display for StlFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StlFormat::Binary => write!(f, "binary"),
+            StlFormat::Ascii => write!(f, "ascii"),
+        }
+    }
+}
+
+impl FromStr for StlFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "binary" | "bin" => Ok(StlFormat::Binary),
+            "ascii" | "text" => Ok(StlFormat::Ascii),
+            _ => Err(format!("Unknown STL format '{}', expected binary or ascii", s)),
+        }
+    }
+}
+
+/// Write a mesh as STL named `name`. The writer should be buffered, every facet is written on its own.
+pub fn write_stl<W: Write>(mesh: &Mesh, name: &str, format: StlFormat, writer: &mut W) -> io::Result<()> {
+    let facets = mesh.triangles.triangle.iter().map(|triangle| {
+        let v0 = &mesh.vertices.vertex[triangle.v1];
+        let v1 = &mesh.vertices.vertex[triangle.v2];
+        let v2 = &mesh.vertices.vertex[triangle.v3];
+        // STL readers recompute a zero normal from the vertices
+        let normal = calculate_normal(v0, v1, v2).unwrap_or(Vertex { x: 0.0, y: 0.0, z: 0.0 });
+        (normal, [v0, v1, v2])
+    });
+
+    match format {
+        StlFormat::Binary => {
+            // 80 byte header that must not start with "solid", then the facet count
+            let mut header = [b' '; 80];
+            let title = format!("PrintSpots {}", name);
+            let length = title.len().min(header.len());
+            header[..length].copy_from_slice(&title.as_bytes()[..length]);
+            writer.write_all(&header)?;
+            let count = u32::try_from(mesh.triangles.triangle.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Too many triangles for binary STL"))?;
+            writer.write_all(&count.to_le_bytes())?;
+            for (normal, corners) in facets {
+                for vertex in [&normal].into_iter().chain(corners) {
+                    for value in [vertex.x, vertex.y, vertex.z] {
+                        writer.write_all(&(value as f32).to_le_bytes())?;
+                    }
+                }
+                writer.write_all(&0u16.to_le_bytes())?;
+            }
+        }
+        StlFormat::Ascii => {
+            writeln!(writer, "solid {}", name)?;
+            for (normal, corners) in facets {
+                writeln!(writer, "  facet normal {} {} {}", normal.x, normal.y, normal.z)?;
+                writeln!(writer, "    outer loop")?;
+                for vertex in corners {
+                    writeln!(writer, "      vertex {} {} {}", vertex.x, vertex.y, vertex.z)?;
+                }
+                writeln!(writer, "    endloop")?;
+                writeln!(writer, "  endfacet")?;
+            }
+            writeln!(writer, "endsolid {}", name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a mesh to an STL file
+pub fn write_stl_file(mesh: &Mesh, name: &str, format: StlFormat, path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_stl(mesh, name, format, &mut writer)?;
+    writer.flush()
+}
+
+/// Write every part with triangles to its own STL file, at `part_path(pattern, name, part)`.
+/// Returns the part names with the files they went to.
+pub fn export_parts_to_stl(objects: &PrintObjects, pattern: &str, name: &str, format: StlFormat) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut written = Vec::new();
+    for (part, mesh) in objects.parts() {
+        if mesh.triangles.triangle.is_empty() {
+            continue;
+        }
+        let path = part_path(pattern, name, part);
+        write_stl_file(mesh, part, format, &path)?;
+        written.push((part.to_string(), path));
+    }
+    Ok(written)
+}
@@ -1,46 +1,89 @@
 use threemf::Mesh;
 
-use crate::config::PrintConfig;
+use crate::config::{BacklitBase, PrintConfig};
+use crate::grayscale::generate::TopCap;
 use crate::utils::{PrintObjects};
 use crate::mesh::generate_box;
 
-pub fn generate_calibration_objects(config: &PrintConfig, square_size: f32, flat_top: bool) -> PrintObjects {
-    let mut black_mesh = Mesh::new();
-    let mut white_mesh = Mesh::new();
-    
-    let grid_size = ((config.max_layers + 1.0) as f32).sqrt().ceil() as u32;
-    
-    for layer_count in 0..=config.max_layers as u32 {
-        let grid_x = layer_count % grid_size;
-        let grid_y = layer_count / grid_size;
-        
-        if grid_y >= grid_size {
-            break;
-        }
-        
-        let x_offset = grid_x as f32 * square_size;
-        let y_offset = grid_y as f32 * square_size;
+/// Calibration pattern for reflective palettes. With `flat_top` every square is printed the way
+/// a flat top image prints it, black filled up to the same height and covered by the optional cap,
+/// so the colors measured from it match the finished print.
+pub fn generate_calibration_objects(config: &PrintConfig, square_size: f32, flat_top: bool, cap: Option<TopCap>) -> PrintObjects {
+    let layer_counts: Vec<u32> = (0..=config.max_layers as u32).collect();
+
+    let mut objects = generate_calibration_grid(&layer_counts, square_size, |layer_count| {
         let black_thickness = config.calculate_black_thickness(layer_count, flat_top);
         let total_height = config.calculate_total_height(layer_count, flat_top);
+        (black_thickness, total_height - black_thickness)
+    });
+
+    if let Some(cap) = cap.filter(|cap| flat_top && cap.layers > 0) {
+        let mut cap_mesh = Mesh::new();
+        let z_offset = config.calculate_total_height(0, true);
+        for index in 0..layer_counts.len() {
+            let (x_offset, y_offset) = grid_offset(index, layer_counts.len(), square_size);
+            generate_box(
+                &mut cap_mesh.vertices,
+                &mut cap_mesh.triangles,
+                x_offset, y_offset, z_offset,
+                square_size, square_size, cap.thickness(config),
+            );
+        }
+        objects.extras.push(cap.material_mesh(cap_mesh));
+    }
+
+    objects
+}
+
+/// Calibration pattern for backlit palettes, meant to be photographed against a lightbox.
+/// Without any base a zero layer square would be a hole, so the pattern starts at one layer.
+pub fn generate_backlit_calibration_objects(config: &PrintConfig, square_size: f32) -> PrintObjects {
+    let first_layer = if config.backlit_base == BacklitBase::None { 1 } else { 0 };
+    let layer_counts: Vec<u32> = (first_layer..=config.max_layers as u32).collect();
+
+    generate_calibration_grid(&layer_counts, square_size, |layer_count| {
+        config.calculate_backlit_stack(layer_count)
+    })
+}
+
+/// Lay out one square per layer count in a grid, `stack` returns the (black, white) thickness of a square
+fn generate_calibration_grid<F>(layer_counts: &[u32], square_size: f32, stack: F) -> PrintObjects
+where
+    F: Fn(u32) -> (f32, f32),
+{
+    let mut black_mesh = Mesh::new();
+    let mut white_mesh = Mesh::new();
+
+    for (index, &layer_count) in layer_counts.iter().enumerate() {
+        let (x_offset, y_offset) = grid_offset(index, layer_counts.len(), square_size);
+        let (black_thickness, white_thickness) = stack(layer_count);
 
         // Generate black base
-        generate_box(
-            &mut black_mesh.vertices,
-            &mut black_mesh.triangles,
-            x_offset, y_offset, 0.0,
-            square_size, square_size, black_thickness,
-        );
-        
+        if black_thickness > 0.0 {
+            generate_box(
+                &mut black_mesh.vertices,
+                &mut black_mesh.triangles,
+                x_offset, y_offset, 0.0,
+                square_size, square_size, black_thickness,
+            );
+        }
+
         // Generate white layer if needed
-        if total_height > black_thickness {
+        if white_thickness > 0.0 {
             generate_box(
                 &mut white_mesh.vertices,
                 &mut white_mesh.triangles,
                 x_offset, y_offset, black_thickness,
-                square_size, square_size, total_height - black_thickness,
+                square_size, square_size, white_thickness,
             );
         }
     }
-    
-    PrintObjects { black_mesh, white_mesh }
-}
\ No newline at end of file
+
+    PrintObjects { black_mesh, white_mesh, extras: Vec::new() }
+}
+
+/// Corner of square `index` in a square grid of `count` squares
+fn grid_offset(index: usize, count: usize, square_size: f32) -> (f32, f32) {
+    let grid_size = (count as f32).sqrt().ceil() as usize;
+    ((index % grid_size) as f32 * square_size, (index / grid_size) as f32 * square_size)
+}
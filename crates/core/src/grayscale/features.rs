@@ -0,0 +1,292 @@
+use crate::grayscale::layer_map::LayerMap;
+
+/// Label of masked cells, they do not belong to any feature
+pub const NO_FEATURE: u32 = u32::MAX;
+
+/// 4-connected region of cells with the same layer count
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feature {
+    pub layer_count: u16,
+    /// Number of cells
+    pub area: u32,
+    /// Inclusive bounding box as (min_x, min_y, max_x, max_y)
+    pub bounds: (u32, u32, u32, u32),
+    /// Radius in cells of the largest disk that fits inside the feature, 0.5 for a single cell
+    pub max_radius: f32,
+}
+
+impl Feature {
+    pub fn width(&self) -> u32 {
+        self.bounds.2 - self.bounds.0 + 1
+    }
+
+    pub fn height(&self) -> u32 {
+        self.bounds.3 - self.bounds.1 + 1
+    }
+
+    /// Width of the thickest part of the feature in cells, a one cell line is 1 wide no matter how long it is.
+    /// Other parts can be thinner, `FeatureLabels::thin_cells` finds them.
+    pub fn thickness(&self) -> f32 {
+        2.0 * self.max_radius
+    }
+}
+
+/// Connected components of a `LayerMap`
+#[derive(Debug, Clone)]
+pub struct FeatureLabels {
+    width: u32,
+    height: u32,
+    /// Feature index for every cell in row-major order, `NO_FEATURE` for masked cells
+    pub labels: Vec<u32>,
+    pub features: Vec<Feature>,
+}
+
+impl FeatureLabels {
+    pub fn label(&self, x: u32, y: u32) -> u32 {
+        self.labels[(y * self.width + x) as usize]
+    }
+
+    /// Cells of every feature, indexed like `features`
+    pub fn cells_by_feature(&self) -> Vec<Vec<(u32, u32)>> {
+        let mut cells: Vec<Vec<(u32, u32)>> = self.features.iter()
+            .map(|feature| Vec::with_capacity(feature.area as usize))
+            .collect();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let label = self.label(x, y);
+                if label != NO_FEATURE {
+                    cells[label as usize].push((x, y));
+                }
+            }
+        }
+
+        cells
+    }
+
+    /// Cells where their feature is thinner than `min_thickness` cells: no disk of that diameter
+    /// fits inside the feature and covers them. Masked cells are never thin.
+    pub fn thin_cells(&self, min_thickness: f32) -> Vec<bool> {
+        let distances = self.boundary_distance_transform();
+        // Distances are in half cells, so a disk of diameter `min_thickness` cells has a radius of `min_thickness` half cells
+        let centers: Vec<bool> = distances.iter()
+            .enumerate()
+            .map(|(point, &distance)| distance >= min_thickness as f64 && self.point_label(point).is_some_and(|label| label != NO_FEATURE))
+            .collect();
+        let to_center = squared_distance_transform(&centers, 2 * self.width + 1, 2 * self.height + 1);
+
+        let grid_width = (2 * self.width + 1) as usize;
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let center = (2 * y + 1) as usize * grid_width + (2 * x + 1) as usize;
+                self.label(x, y) != NO_FEATURE && to_center[center].sqrt() > min_thickness as f64
+            })
+            .collect()
+    }
+
+    /// Distance from every point of a grid with half cell spacing to the nearest boundary between two features, in half cells.
+    /// Point (2x + 1, 2y + 1) is the center of cell (x, y), the others lie on its edges and corners.
+    /// The image border and the edges of masked cells are boundaries too.
+    fn boundary_distance_transform(&self) -> Vec<f64> {
+        let (grid_width, grid_height) = (2 * self.width + 1, 2 * self.height + 1);
+        let boundaries: Vec<bool> = (0..(grid_width * grid_height) as usize)
+            .map(|point| self.point_label(point).is_none())
+            .collect();
+
+        squared_distance_transform(&boundaries, grid_width, grid_height)
+            .into_iter()
+            .map(f64::sqrt)
+            .collect()
+    }
+
+    /// Label of the cells touching a point of the half cell grid, `None` on boundaries
+    fn point_label(&self, point: usize) -> Option<u32> {
+        let grid_width = (2 * self.width + 1) as usize;
+        let (i, j) = ((point % grid_width) as u32, (point / grid_width) as u32);
+        if i == 0 || j == 0 || i == 2 * self.width || j == 2 * self.height {
+            return None;
+        }
+
+        // Odd coordinates are inside one cell, even ones between two
+        let cells = |k: u32| if k % 2 == 1 { (k / 2, k / 2) } else { (k / 2 - 1, k / 2) };
+        let ((x0, x1), (y0, y1)) = (cells(i), cells(j));
+        let label = self.label(x0, y0);
+        [(x1, y0), (x0, y1), (x1, y1)].iter()
+            .all(|&(x, y)| self.label(x, y) == label)
+            .then_some(label)
+    }
+}
+
+/// Split the map into features with scanline labeling and union-find, then measure them.
+/// Labels are assigned in raster order of each feature's first cell, so the result is deterministic.
+pub fn label_features(layers: &LayerMap) -> FeatureLabels {
+    let (width, height) = layers.dimensions();
+    let mut provisional = vec![NO_FEATURE; (width * height) as usize];
+    let mut parents: Vec<u32> = Vec::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            let Some(layer_count) = layers.get(x, y) else {
+                continue;
+            };
+
+            let left = if x > 0 && layers.get(x - 1, y) == Some(layer_count) {
+                Some(provisional[layers.index(x - 1, y)])
+            } else {
+                None
+            };
+            let up = if y > 0 && layers.get(x, y - 1) == Some(layer_count) {
+                Some(provisional[layers.index(x, y - 1)])
+            } else {
+                None
+            };
+
+            let label = match (left, up) {
+                (Some(a), Some(b)) => {
+                    union(&mut parents, a, b);
+                    a.min(b)
+                }
+                (Some(a), None) | (None, Some(a)) => a,
+                (None, None) => {
+                    parents.push(parents.len() as u32);
+                    parents.len() as u32 - 1
+                }
+            };
+            provisional[layers.index(x, y)] = label;
+        }
+    }
+
+    // Resolve to compact labels and gather statistics
+    let mut compact = vec![NO_FEATURE; parents.len()];
+    let mut labels = vec![NO_FEATURE; (width * height) as usize];
+    let mut features: Vec<Feature> = Vec::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            let index = layers.index(x, y);
+            if provisional[index] == NO_FEATURE {
+                continue;
+            }
+
+            let root = find(&mut parents, provisional[index]) as usize;
+            if compact[root] == NO_FEATURE {
+                compact[root] = features.len() as u32;
+                features.push(Feature {
+                    layer_count: layers.layer(x, y),
+                    area: 0,
+                    bounds: (x, y, x, y),
+                    max_radius: 0.0,
+                });
+            }
+
+            let label = compact[root];
+            labels[index] = label;
+            let feature = &mut features[label as usize];
+            feature.area += 1;
+            feature.bounds.0 = feature.bounds.0.min(x);
+            feature.bounds.1 = feature.bounds.1.min(y);
+            feature.bounds.2 = feature.bounds.2.max(x);
+            feature.bounds.3 = feature.bounds.3.max(y);
+        }
+    }
+
+    let feature_labels = FeatureLabels { width, height, labels, features: Vec::new() };
+    for (point, distance) in feature_labels.boundary_distance_transform().into_iter().enumerate() {
+        if let Some(label) = feature_labels.point_label(point).filter(|&label| label != NO_FEATURE) {
+            let feature = &mut features[label as usize];
+            feature.max_radius = feature.max_radius.max(distance as f32 / 2.0);
+        }
+    }
+
+    FeatureLabels { features, ..feature_labels }
+}
+
+fn find(parents: &mut [u32], mut label: u32) -> u32 {
+    while parents[label as usize] != label {
+        let grandparent = parents[parents[label as usize] as usize];
+        parents[label as usize] = grandparent;
+        label = grandparent;
+    }
+    label
+}
+
+fn union(parents: &mut [u32], a: u32, b: u32) {
+    let root_a = find(parents, a);
+    let root_b = find(parents, b);
+    if root_a != root_b {
+        // Keep the smaller label as root so the first cell of a feature stays representative
+        let (root, child) = if root_a < root_b { (root_a, root_b) } else { (root_b, root_a) };
+        parents[child as usize] = root;
+    }
+}
+
+/// Squared Euclidean distance from every cell to the nearest seed cell,
+/// separable over columns then rows (Felzenszwalb & Huttenlocher). Without seeds every distance is huge.
+pub(crate) fn squared_distance_transform(seeds: &[bool], width: u32, height: u32) -> Vec<f64> {
+    let (w, h) = (width as usize, height as usize);
+    let infinity = (w * w + h * h) as f64 + 1.0;
+    let mut squared: Vec<f64> = seeds.iter()
+        .map(|&seed| if seed { 0.0 } else { infinity })
+        .collect();
+
+    let mut column = vec![0.0; h];
+    for x in 0..w {
+        for y in 0..h {
+            column[y] = squared[y * w + x];
+        }
+        let transformed = distance_transform_1d(&column);
+        for y in 0..h {
+            squared[y * w + x] = transformed[y];
+        }
+    }
+    for y in 0..h {
+        let transformed = distance_transform_1d(&squared[y * w..(y + 1) * w]);
+        squared[y * w..(y + 1) * w].copy_from_slice(&transformed);
+    }
+
+    squared
+}
+
+/// 1D squared distance transform by the lower envelope of parabolas
+fn distance_transform_1d(f: &[f64]) -> Vec<f64> {
+    let n = f.len();
+    let mut result = vec![0.0; n];
+    if n == 0 {
+        return result;
+    }
+
+    let mut vertices = vec![0usize; n];
+    let mut boundaries = vec![0.0f64; n + 1];
+    let mut k = 0;
+    boundaries[0] = f64::NEG_INFINITY;
+    boundaries[1] = f64::INFINITY;
+
+    let intersection = |q: usize, v: usize| {
+        ((f[q] + (q * q) as f64) - (f[v] + (v * v) as f64)) / (2.0 * (q as f64 - v as f64))
+    };
+
+    for q in 1..n {
+        // boundaries[0] is -inf, so this never walks below the first parabola
+        let mut s = intersection(q, vertices[k]);
+        while s <= boundaries[k] {
+            k -= 1;
+            s = intersection(q, vertices[k]);
+        }
+        k += 1;
+        vertices[k] = q;
+        boundaries[k] = s;
+        boundaries[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+    for (q, value) in result.iter_mut().enumerate() {
+        while boundaries[k + 1] < q as f64 {
+            k += 1;
+        }
+        let v = vertices[k];
+        *value = (q as f64 - v as f64).powi(2) + f[v];
+    }
+
+    result
+}
@@ -1,35 +1,804 @@
-use std::collections::HashMap;
+use std::{collections::BTreeMap, fmt, str::FromStr};
 
-use image::{GrayImage, RgbImage};
-use threemf::{model::Triangle, Mesh};
+use serde::{Deserialize, Serialize};
+use threemf::Mesh;
 
-use crate::{config::PrintConfig, grayscale::ColorPalette, mesh::{generate_box, Rectangle}, utils::{ PrintObjects}};
+use crate::{
+    config::{BacklitBase, Lighting, PrintConfig, PrintingConstraints},
+    export::slicer::SlicerSettings,
+    grayscale::{
+        enforce_min_feature_size_with_report,
+        height::enforce_height_constraints,
+        layer_map::LayerMap,
+        report::{ConstraintRule, PrintabilityReport},
+        write_3mf_groups, ColorPalette, PRINT_OBJECT_NAME,
+    },
+    mesh::{
+        contour::mesh_column_field_contours,
+        frame::{generate_frame, sweep_ring, Frame},
+        generate_box,
+        calculate_xy_bounds,
+        heightfield::{mesh_column_field, ColumnField},
+        mount::Mount,
+        rectangles::mesh_column_field_boxes,
+        rectangles::decompose_cells,
+        terrace::mesh_column_field_terraces,
+        text::{Label, TextBitmap, GLYPH_ROWS},
+        validate::{validate_mesh, Bounds, ValidationReport},
+        Rectangle,
+    },
+    recipe::RecipeHasher,
+    utils::{MaterialMesh, PrintObjects},
+};
 
+/// How deep the back label is debossed into the bottom, never more than half of a column
+const BACK_LABEL_DEPTH: f32 = 0.4;
+
+/// How the layer counts are turned into triangles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeshBackend {
+    /// A closed box per rectangle, boxes touch each other and slicers usually repair the result
+    #[default]
+    Boxes,
+    /// One watertight, manifold shell per material with welded vertices and no internal faces
+    Heightfield,
+    /// Smooth outlines traced from the pixels and extruded per layer band, for logos and masked silhouettes
+    Contour,
+    /// Nested slabs with the exact pixel outlines, the shape of the boxes with far fewer triangles on gradients
+    Terraces,
+}
+
+impl fmt::Display for MeshBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshBackend::Boxes => write!(f, "boxes"),
+            MeshBackend::Heightfield => write!(f, "heightfield"),
+            MeshBackend::Contour => write!(f, "contour"),
+            MeshBackend::Terraces => write!(f, "terraces"),
+        }
+    }
+}
+
+impl FromStr for MeshBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "boxes" => Ok(MeshBackend::Boxes),
+            "heightfield" => Ok(MeshBackend::Heightfield),
+            "contour" => Ok(MeshBackend::Contour),
+            "terraces" => Ok(MeshBackend::Terraces),
+            _ => Err(format!("Unknown mesher '{}', expected boxes, heightfield, contour or terraces", s)),
+        }
+    }
+}
+
+/// Filament of the cap printed over a flat top
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CapMaterial {
+    /// Seals the image under a plain white surface, only for a layer or two or the image washes out
+    #[default]
+    White,
+    /// Clear filament, protects the image of coasters without hiding it
+    Transparent,
+}
+
+impl CapMaterial {
+    /// Object and material name in the exported files
+    pub fn name(&self) -> &'static str {
+        match self {
+            CapMaterial::White => "cap_white",
+            CapMaterial::Transparent => "cap_transparent",
+        }
+    }
+
+    /// Color the slicer shows for the cap
+    pub fn display_color(&self) -> &'static str {
+        match self {
+            CapMaterial::White => "#FFFFFF",
+            CapMaterial::Transparent => "#E8F4FF80",
+        }
+    }
+}
+
+impl fmt::Display for CapMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CapMaterial::White => write!(f, "white"),
+            CapMaterial::Transparent => write!(f, "transparent"),
+        }
+    }
+}
+
+impl FromStr for CapMaterial {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "white" => Ok(CapMaterial::White),
+            "transparent" | "clear" => Ok(CapMaterial::Transparent),
+            _ => Err(format!("Unknown cap material '{}', expected white or transparent", s)),
+        }
+    }
+}
+
+/// Solid layers printed over the whole flat top in a separate filament
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopCap {
+    pub layers: u32,
+    #[serde(default)]
+    pub material: CapMaterial,
+}
+
+impl TopCap {
+    pub fn thickness(&self, config: &PrintConfig) -> f32 {
+        self.layers as f32 * config.layer_thickness
+    }
+
+    /// Wrap the cap geometry for export
+    pub fn material_mesh(&self, mesh: Mesh) -> MaterialMesh {
+        MaterialMesh {
+            name: self.material.name().to_string(),
+            display_color: self.material.display_color().to_string(),
+            mesh,
+        }
+    }
+}
+
+/// Options of `generate_image` that are not print settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerateOptions {
+    pub flat_top: bool,
+    pub backend: MeshBackend,
+    /// The contour mesher simplifies outlines by up to half of this, finer detail cannot be printed anyway
+    pub nozzle_diameter: f32,
+    /// Only printed with `flat_top` on a reflective palette
+    pub cap: Option<TopCap>,
+    pub frame: Option<Frame>,
+    /// Hanging hole, keyhole or kickstand, placed on the image bounds
+    pub mount: Option<Mount>,
+    /// Text on a strip of base below the image, in the other filament than the base
+    pub label: Option<Label>,
+    /// Text debossed mirrored into the bottom of the image, so it reads from the back. Left off backlit prints.
+    pub back_label: Option<Label>,
+    /// Print the identifiers of the palette and config on the back, under the back label
+    pub back_ids: bool,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            flat_top: false,
+            backend: MeshBackend::default(),
+            nozzle_diameter: 0.4,
+            cap: None,
+            frame: None,
+            mount: None,
+            label: None,
+            back_label: None,
+            back_ids: false,
+        }
+    }
+}
+
+/// Config the flat top is built with. The black fills up to `max_layers`, which has to cover
+/// the highest stack or the black would end up with a negative thickness under it.
+pub fn flat_top_config(layers: &LayerMap, config: &PrintConfig) -> PrintConfig {
+    PrintConfig {
+        max_layers: config.max_layers.max(layers.max_layer() as f32),
+        ..config.clone()
+    }
+}
+
+/// Turn the layer counts into meshes, the palette only decides between the reflective and backlit stacking
 pub fn generate_image(
-    image: &RgbImage,
+    layers: &LayerMap,
     palette: &ColorPalette,
     config: &PrintConfig,
-    flat_top: bool,
-    mask: Option<&GrayImage>,
+    options: &GenerateOptions,
 ) -> PrintObjects {
-    if flat_top {
-        // Use existing vectorized approach for flat top
-        generate_image_objects_vectorized(image, palette, config, flat_top, mask)
+    // Mounts are placed on the whole print, before their own cut takes anything away
+    let bounds = print_bounds(layers, config, options);
+    let floors = back_label_floors(layers, palette, config, options);
+    let cut_layers;
+    let layers = match &options.mount {
+        Some(mount) => {
+            cut_layers = cut_mount(layers, config, mount, bounds, frame_extent(options));
+            &cut_layers
+        }
+        None => layers,
+    };
+
+    let flat_top = options.flat_top && flat_top_allowed(palette);
+    let flat_config;
+    let config = if flat_top {
+        flat_config = flat_top_config(layers, config);
+        &flat_config
     } else {
+        config
+    };
+
+    let floors = floors.as_deref();
+    let shells = |mesh: fn(&ColumnField) -> Mesh| {
+        let (black, white) = material_fields(layers, palette, config, flat_top, floors);
+        PrintObjects { black_mesh: mesh(&black), white_mesh: mesh(&white), extras: Vec::new() }
+    };
+    let mut objects = match options.backend {
+        MeshBackend::Heightfield => shells(mesh_column_field),
+        MeshBackend::Contour => {
+            let (black, white) = material_fields(layers, palette, config, flat_top, floors);
+            let tolerance = options.nozzle_diameter as f64 / 2.0;
+            PrintObjects {
+                black_mesh: mesh_column_field_contours(&black, tolerance),
+                white_mesh: mesh_column_field_contours(&white, tolerance),
+                extras: Vec::new(),
+            }
+        }
+        MeshBackend::Terraces => shells(mesh_column_field_terraces),
+        // The boxes below only start at the build plate, a debossed back needs the columns
+        MeshBackend::Boxes if floors.is_some() => shells(mesh_column_field_boxes),
+        MeshBackend::Boxes if palette.lighting == Lighting::Backlit => generate_backlit_objects(layers, config),
+        MeshBackend::Boxes if flat_top => generate_image_objects_vectorized(layers, config, flat_top),
         // For variable height, create optimized layer structure
-        generate_variable_height_optimized(image, palette, config, mask)
+        MeshBackend::Boxes => generate_variable_height_optimized(layers, config),
+    };
+
+    if let Some(cap) = options.cap.filter(|cap| flat_top && cap.layers > 0) {
+        objects.extras.push(cap.material_mesh(generate_cap_mesh(layers, config, &cap, options)));
+    }
+
+    if let Some(label) = &options.label {
+        add_label(&mut objects, label, layers, palette, config);
+    }
+
+    if let Some(frame) = options.frame.filter(|frame| frame.width_mm > 0.0) {
+        add_frame(&mut objects, &frame, layers, palette, config, options);
     }
+
+    if let Some(mount) = &options.mount {
+        if let Some(kickstand) = mount.generate_kickstand(bounds, frame_extent(options), config.base_thickness) {
+            objects.extras.push(mount.material_mesh(kickstand));
+        }
+    }
+
+    objects
 }
 
-pub fn generate_image_objects_vectorized(
-    image: &RgbImage,
+/// Strip of base below the image with the label standing on it. The text is as high as the
+/// brightest palette color and in the filament the base is not printed in, so it shows against it.
+fn add_label(objects: &mut PrintObjects, label: &Label, layers: &LayerMap, palette: &ColorPalette, config: &PrintConfig) {
+    let strip = label_strip(label);
+    if strip == 0.0 {
+        return;
+    }
+    let lines: Vec<&str> = label.text.lines().collect();
+    let bitmap = TextBitmap::new(&lines);
+
+    let [x0, _, x1, strip_top] = image_footprint(layers, config, &GenerateOptions::default());
+    let white_base = palette.lighting == Lighting::Backlit && config.backlit_base == BacklitBase::Diffuser;
+    let (base_mesh, text_mesh) = if white_base {
+        (&mut objects.white_mesh, &mut objects.black_mesh)
+    } else {
+        (&mut objects.black_mesh, &mut objects.white_mesh)
+    };
+    let base = label_base(palette, config);
+    generate_box(&mut base_mesh.vertices, &mut base_mesh.triangles, x0, strip_top, 0.0, x1 - x0, strip, base);
+
+    // Shrunk to fit the width of the image, centered on the strip
+    let pixel = (label.height_mm / GLYPH_ROWS as f32).min(0.95 * (x1 - x0) / bitmap.width as f32);
+    let origin = (
+        (x0 + x1 - bitmap.width as f32 * pixel) / 2.0,
+        strip_top + (strip - bitmap.height as f32 * pixel) / 2.0,
+    );
+    let text_top = base + palette.max_layer_count().max(1) as f32 * config.layer_thickness;
+    bitmap.extrude(text_mesh, origin, pixel, base, text_top);
+}
+
+/// Thickness of the strip under the label, the diffuser on backlit prints that have one
+fn label_base(palette: &ColorPalette, config: &PrintConfig) -> f32 {
+    match (palette.lighting, config.backlit_base) {
+        (Lighting::Backlit, BacklitBase::Diffuser) => config.diffuser_thickness,
+        _ => config.base_thickness,
+    }
+}
+
+/// Height of the strip the label is printed on, a quarter of a line above and below the text
+fn label_strip(label: &Label) -> f32 {
+    if label.text.trim().is_empty() {
+        return 0.0;
+    }
+    let lines = label.text.lines().count() as f32;
+    let line_height = label.height_mm * 1.25;
+    lines * line_height + 0.25 * label.height_mm
+}
+
+/// Short identifiers of the palette and config, printed on the back to tell prints apart
+pub fn back_ids(palette: &ColorPalette, config: &PrintConfig) -> String {
+    let palette_id = RecipeHasher::new().add_settings("palette", palette).finish();
+    let config_id = RecipeHasher::new().add_settings("config", config).finish();
+    format!("P:{} C:{}", &palette_id[..8], &config_id[..8])
+}
+
+/// Whether the print can have a flat top, and with it a cap. The thickness of a backlit print is the image,
+/// so there is no flat top variant.
+pub fn flat_top_allowed(palette: &ColorPalette) -> bool {
+    palette.lighting == Lighting::Reflective
+}
+
+/// Options `generate_image` leaves off for this palette, as warnings for the user
+pub fn ignored_options(palette: &ColorPalette, options: &GenerateOptions) -> Vec<String> {
+    let mut ignored = Vec::new();
+    if options.flat_top && !flat_top_allowed(palette) {
+        ignored.push("Backlit prints have no flat top variant, the flat top and cap are left off".to_string());
+    }
+    let back_text = options.back_ids || options.back_label.as_ref().is_some_and(|label| !label.text.trim().is_empty());
+    if back_text && !back_labels_allowed(palette) {
+        ignored.push("Text on the back would show through a backlit print, it is left off".to_string());
+    }
+    ignored
+}
+
+/// Whether text can be debossed into the back. The thickness of a backlit print is the image, so the text would show through.
+pub fn back_labels_allowed(palette: &ColorPalette) -> bool {
+    palette.lighting == Lighting::Reflective
+}
+
+/// Height of the bottom of every column where the back label is debossed, `None` without a back label.
+/// The text is rasterized on the cell grid, centered on the image and mirrored to read from behind.
+fn back_label_floors(layers: &LayerMap, palette: &ColorPalette, config: &PrintConfig, options: &GenerateOptions) -> Option<Vec<f32>> {
+    if !back_labels_allowed(palette) {
+        return None;
+    }
+    let mut lines: Vec<String> = options.back_label.iter().flat_map(|label| label.text.lines().map(str::to_string)).collect();
+    if options.back_ids {
+        lines.push(back_ids(palette, config));
+    }
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let bitmap = TextBitmap::new(&lines).mirrored();
+    if bitmap.is_empty() {
+        return None;
+    }
+
+    // Whole cells per font pixel, fewer when the text would not fit the image
+    let (width, height) = layers.dimensions();
+    let (pixel_width, _) = config.pixel_size(width, height);
+    let height_mm = options.back_label.as_ref().map_or(Label::new("").height_mm, |label| label.height_mm);
+    let wanted = ((height_mm / GLYPH_ROWS as f32) / pixel_width).round().max(1.0) as u32;
+    let scale = wanted.min((width / bitmap.width).max(1));
+
+    let (left, top) = (
+        (width as i64 - (bitmap.width * scale) as i64) / 2,
+        (height as i64 - (bitmap.height * scale) as i64) / 2,
+    );
+    let mut floors = vec![0.0; (width * height) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let (bx, by) = (x as i64 - left, y as i64 - top);
+            if bx >= 0 && by >= 0 && bitmap.get((bx / scale as i64) as u32, (by / scale as i64) as u32) {
+                floors[layers.index(x, y)] = BACK_LABEL_DEPTH;
+            }
+        }
+    }
+    Some(floors)
+}
+
+/// Take the cells a mount cuts through out of the map. The rectangle meshers build around masked cells,
+/// so the cut is exact to the pixel in every backend.
+fn cut_mount(layers: &LayerMap, config: &PrintConfig, mount: &Mount, bounds: (f32, f32, f32, f32), extent: f32) -> LayerMap {
+    let cut = mount.cut(bounds, extent);
+    let (width, height) = layers.dimensions();
+    let (pixel_width, pixel_height) = config.pixel_size(width, height);
+    let mut cut_layers = layers.clone();
+    for y in 0..height {
+        for x in 0..width {
+            let min = [x as f32 * pixel_width, y as f32 * pixel_height];
+            if cut.overlaps(min, [min[0] + pixel_width, min[1] + pixel_height]) {
+                cut_layers.set_masked(x, y, true);
+            }
+        }
+    }
+    cut_layers
+}
+
+/// Frame as an extra part, and the margin as bare base in whichever material the base is printed in
+fn add_frame(objects: &mut PrintObjects, frame: &Frame, layers: &LayerMap, palette: &ColorPalette, config: &PrintConfig, options: &GenerateOptions) {
+    let footprint = image_footprint(layers, config, options);
+    if frame.margin_mm > 0.0 {
+        let base = match (palette.lighting, config.backlit_base) {
+            (Lighting::Reflective, _) => Some((&mut objects.black_mesh, config.base_thickness)),
+            (Lighting::Backlit, BacklitBase::Diffuser) => Some((&mut objects.white_mesh, config.diffuser_thickness)),
+            (Lighting::Backlit, BacklitBase::None) => None,
+        };
+        if let Some((mesh, thickness)) = base {
+            let margin = frame.margin_mm;
+            sweep_ring(mesh, footprint, &[(0.0, 0.0), (margin, 0.0), (margin, thickness), (0.0, thickness)]);
+        }
+    }
+
+    let top = image_top(layers, palette, config, options);
+    objects.extras.push(frame.material_mesh(generate_frame(footprint, frame, top)));
+}
+
+/// Space the objects of `generate_image` may take up, the image footprint up to the highest stack or the cap,
+/// grown by the label strip, the frame and the kickstand
+pub fn expected_bounds(layers: &LayerMap, palette: &ColorPalette, config: &PrintConfig, options: &GenerateOptions) -> Bounds {
+    let [x0, y0, x1, y1] = image_footprint(layers, config, options);
+    let mut top = image_top(layers, palette, config, options);
+    let extent = frame_extent(options);
+    if let Some(frame) = options.frame.filter(|frame| frame.width_mm > 0.0) {
+        top = top.max(frame.height_mm.unwrap_or(top));
+    }
+
+    if options.label.as_ref().is_some_and(|label| label_strip(label) > 0.0) {
+        top = top.max(label_base(palette, config) + palette.max_layer_count().max(1) as f32 * config.layer_thickness);
+    }
+
+    let mut max_x = x1 + extent;
+    let kickstand = options.mount.and_then(|mount| mount.generate_kickstand(print_bounds(layers, config, options), extent, config.base_thickness));
+    if let Some(kickstand) = kickstand {
+        max_x = max_x.max(calculate_xy_bounds(&kickstand.vertices).1);
+        top = kickstand.vertices.vertex.iter().fold(top, |top, vertex| top.max(vertex.z as f32));
+    }
+
+    Bounds {
+        min: [(x0 - extent) as f64, (y0 - extent) as f64, 0.0],
+        max: [max_x as f64, (y1 + extent) as f64, top as f64],
+    }
+}
+
+/// How far the frame reaches out from the image
+fn frame_extent(options: &GenerateOptions) -> f32 {
+    options.frame.filter(|frame| frame.width_mm > 0.0).map_or(0.0, |frame| frame.extent())
+}
+
+/// Bounds of the image parts with the label strip, which spans the whole width under the image
+fn print_bounds(layers: &LayerMap, config: &PrintConfig, options: &GenerateOptions) -> (f32, f32, f32, f32) {
+    let bounds = image_bounds(layers, config);
+    match options.label.as_ref().filter(|label| label_strip(label) > 0.0) {
+        Some(_) => {
+            let [x0, _, x1, y1] = image_footprint(layers, config, options);
+            (x0.min(bounds.0), x1.max(bounds.1), bounds.2, y1)
+        }
+        None => bounds,
+    }
+}
+
+/// Bounds of the unmasked cells as (min x, max x, min y, max y), what `calculate_xy_bounds` gives
+/// for the image parts without meshing them first
+fn image_bounds(layers: &LayerMap, config: &PrintConfig) -> (f32, f32, f32, f32) {
+    let (width, height) = layers.dimensions();
+    let (pixel_width, pixel_height) = config.pixel_size(width, height);
+    let mut cells = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).filter(|&(x, y)| !layers.is_masked(x, y));
+    let Some((x, y)) = cells.next() else {
+        return (0.0, 0.0, 0.0, 0.0);
+    };
+    let (min_x, max_x, min_y, max_y) = cells.fold((x, x, y, y), |(x0, x1, y0, y1), (x, y)| (x0.min(x), x1.max(x), y0.min(y), y1.max(y)));
+    (min_x as f32 * pixel_width, (max_x + 1) as f32 * pixel_width, min_y as f32 * pixel_height, (max_y + 1) as f32 * pixel_height)
+}
+
+/// Rectangle covered by the image and the label strip below it as (min x, min y, max x, max y)
+fn image_footprint(layers: &LayerMap, config: &PrintConfig, options: &GenerateOptions) -> [f32; 4] {
+    let (width, height) = layers.dimensions();
+    let (pixel_width, pixel_height) = config.pixel_size(width, height);
+    let strip = options.label.as_ref().map_or(0.0, label_strip);
+    [0.0, 0.0, width as f32 * pixel_width, height as f32 * pixel_height + strip]
+}
+
+/// Height of the highest stack, or of the cap over a flat top
+fn image_top(layers: &LayerMap, palette: &ColorPalette, config: &PrintConfig, options: &GenerateOptions) -> f32 {
+    let max_layer = layers.max_layer() as u32;
+    match palette.lighting {
+        Lighting::Backlit => {
+            let (black, white) = config.calculate_backlit_stack(max_layer);
+            black + white
+        }
+        Lighting::Reflective if options.flat_top => {
+            let config = flat_top_config(layers, config);
+            let cap = options.cap.map_or(0.0, |cap| cap.thickness(&config));
+            config.calculate_total_height(0, true) + cap
+        }
+        Lighting::Reflective => config.calculate_total_height(max_layer, false),
+    }
+}
+
+/// Validate every part by name, before build plate pads are added as those lie outside the bounds
+pub fn validate_objects(objects: &PrintObjects, bounds: &Bounds) -> Vec<(String, ValidationReport)> {
+    objects.parts()
+        .into_iter()
+        .map(|(name, mesh)| (name.to_string(), validate_mesh(mesh, Some(bounds))))
+        .collect()
+}
+
+/// Largest model `generate_image_within_budget` may produce, `None` leaves a limit off
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeshBudget {
+    pub max_triangles: Option<usize>,
+    /// Size of the 3MF archive, build plate pads add a few hundred bytes on top
+    pub max_file_bytes: Option<u64>,
+}
+
+impl MeshBudget {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_triangles.is_none() && self.max_file_bytes.is_none()
+    }
+
+    fn fits(&self, triangles: usize, file_bytes: Option<u64>) -> bool {
+        self.max_triangles.is_none_or(|max| triangles <= max)
+            && self.max_file_bytes.is_none_or(|max| file_bytes.is_some_and(|bytes| bytes <= max))
+    }
+}
+
+/// Budget of `generate_image_within_budget` with what the 3MF carries besides the meshes,
+/// which counts towards the file size
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BudgetTarget<'a> {
+    pub limits: MeshBudget,
+    pub recipe: Option<&'a str>,
+    pub slicer: Option<&'a SlicerSettings>,
+}
+
+/// Size of the 3MF estimated from the number of vertices and triangles, so candidates do not have to be written.
+/// The estimate is calibrated by the real writer and recalibrated whenever it is checked against it.
+struct FileSizer<'a> {
+    target: &'a BudgetTarget<'a>,
+    /// Archive without any geometry
+    fixed_bytes: u64,
+    /// Bytes per vertex or triangle, `None` until the writer ran once
+    element_bytes: Option<f64>,
+}
+
+impl<'a> FileSizer<'a> {
+    fn new(target: &'a BudgetTarget<'a>) -> Self {
+        let empty = PrintObjects { black_mesh: Mesh::new(), white_mesh: Mesh::new(), extras: Vec::new() };
+        let mut sizer = Self { target, fixed_bytes: 0, element_bytes: None };
+        if target.limits.max_file_bytes.is_some() {
+            sizer.fixed_bytes = sizer.write(&empty);
+        }
+        sizer
+    }
+
+    fn write(&self, objects: &PrintObjects) -> u64 {
+        write_3mf_groups(&[(PRINT_OBJECT_NAME, objects)], self.target.recipe, self.target.slicer)
+            .map_or(u64::MAX, |bytes| bytes.len() as u64)
+    }
+
+    /// Size from the writer, which also calibrates the estimate
+    fn exact(&mut self, objects: &PrintObjects) -> u64 {
+        let bytes = self.write(objects);
+        let elements = (objects.vertex_count() + objects.triangle_count()).max(1);
+        self.element_bytes = Some(bytes.saturating_sub(self.fixed_bytes) as f64 / elements as f64);
+        bytes
+    }
+
+    /// Triangles, file size and whether the objects fit. The size is estimated, and written
+    /// for real when the estimate fits, so only the candidate that is kept pays for the writer.
+    fn check(&mut self, objects: &PrintObjects) -> (usize, Option<u64>, bool) {
+        let limits = self.target.limits;
+        let triangles = objects.triangle_count();
+        if limits.max_file_bytes.is_none() {
+            return (triangles, None, limits.fits(triangles, None));
+        }
+
+        let elements = (objects.vertex_count() + triangles) as f64;
+        let estimate = self.element_bytes.map(|element_bytes| self.fixed_bytes + (element_bytes * elements).ceil() as u64);
+        if let Some(estimate) = estimate.filter(|&estimate| !limits.fits(triangles, Some(estimate))) {
+            return (triangles, Some(estimate), false);
+        }
+
+        let bytes = self.exact(objects);
+        (triangles, Some(bytes), limits.fits(triangles, Some(bytes)))
+    }
+}
+
+/// What `generate_image_within_budget` traded to fit the budget
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BudgetReport {
+    /// Mesher of the result, an exact mesher may be swapped for another exact one
+    pub backend: MeshBackend,
+    /// Triangles of the requested mesher before anything was traded
+    pub initial_triangles: usize,
+    pub triangles: usize,
+    /// Only measured when the budget limits the file size
+    pub file_bytes: Option<u64>,
+    /// Features under this area were merged into their surroundings, `None` when the image was left alone
+    pub min_feature_area_mm2: Option<f32>,
+    /// Cells that changed layer count for the budget
+    pub coarsened_cells: usize,
+    pub within_budget: bool,
+}
+
+impl BudgetReport {
+    /// Short human readable summary of the trade-off
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!("{} triangles with the {} mesher", self.triangles, self.backend)];
+        if self.triangles != self.initial_triangles {
+            lines.push(format!("{} triangles before fitting the budget", self.initial_triangles));
+        }
+        if let Some(bytes) = self.file_bytes {
+            lines.push(format!("{:.2} MB 3MF", bytes as f64 / 1_000_000.0));
+        }
+        if let Some(area) = self.min_feature_area_mm2 {
+            lines.push(format!("features under {:.2} mm² merged, {} pixels changed", area, self.coarsened_cells));
+        }
+        if !self.within_budget {
+            lines.push("still over budget, the image cannot be coarsened any further".to_string());
+        }
+        lines
+    }
+}
+
+/// Meshers that keep the exact geometry of the layer counts and can stand in for each other
+const EXACT_BACKENDS: [MeshBackend; 3] = [MeshBackend::Boxes, MeshBackend::Terraces, MeshBackend::Heightfield];
+
+/// `generate_image` that trades detail for a smaller model until it fits the budget. An exact mesher is
+/// first swapped for the exact mesher with the fewest triangles, then features under a doubling minimum
+/// area are repeatedly merged into their surroundings, as `merge_small_features` does, and the height constraints
+/// are applied again. Cells the budget changed are recorded in `report` under `ConstraintRule::MeshBudget`.
+/// File sizes are estimated from vertex and triangle counts, only a candidate that should fit is written to check.
+/// Returns the layer counts the objects were built from.
+pub fn generate_image_within_budget(
+    layers: &LayerMap,
     palette: &ColorPalette,
     config: &PrintConfig,
+    constraints: &PrintingConstraints,
+    options: &GenerateOptions,
+    target: &BudgetTarget,
+    report: &mut PrintabilityReport,
+) -> (LayerMap, PrintObjects, BudgetReport) {
+    let mut sizer = FileSizer::new(target);
+    let mut objects = generate_image(layers, palette, config, options);
+    let (initial_triangles, file_bytes, within_budget) = sizer.check(&objects);
+    let mut budget_report = BudgetReport {
+        backend: options.backend,
+        initial_triangles,
+        triangles: initial_triangles,
+        file_bytes,
+        min_feature_area_mm2: None,
+        coarsened_cells: 0,
+        within_budget,
+    };
+    if budget_report.within_budget {
+        return (layers.clone(), objects, budget_report);
+    }
+
+    let mut options = options.clone();
+    if EXACT_BACKENDS.contains(&options.backend) {
+        for backend in EXACT_BACKENDS.into_iter().filter(|&backend| backend != options.backend) {
+            let candidate = generate_image(layers, palette, config, &GenerateOptions { backend, ..options.clone() });
+            if candidate.triangle_count() < budget_report.triangles {
+                budget_report.backend = backend;
+                budget_report.triangles = candidate.triangle_count();
+                objects = candidate;
+            }
+        }
+        options.backend = budget_report.backend;
+        (_, budget_report.file_bytes, budget_report.within_budget) = sizer.check(&objects);
+        if budget_report.within_budget {
+            return (layers.clone(), objects, budget_report);
+        }
+    }
+
+    // Coarsen with a growing minimum area until the image is a single feature, each area on top of the last
+    let (width, height) = layers.dimensions();
+    let (pixel_width, pixel_height) = config.pixel_size(width, height);
+    let image_area = width as f32 * height as f32 * pixel_width * pixel_height;
+    let mut area = constraints.min_feature_area_mm2.max(pixel_width * pixel_height);
+    let mut result = layers.clone();
+    while !budget_report.within_budget && area < image_area {
+        area *= 2.0;
+        let coarse_constraints = PrintingConstraints {
+            min_feature_area_mm2: area,
+            merge_small_features: true,
+            structuring_element: None,
+            ..constraints.clone()
+        };
+        let (merged, _) = enforce_min_feature_size_with_report(&result, &coarse_constraints, config);
+        let (coarse, _) = enforce_height_constraints(&merged, palette, constraints, config);
+        if coarse == result {
+            continue;
+        }
+
+        objects = generate_image(&coarse, palette, config, &options);
+        result = coarse;
+        (budget_report.triangles, budget_report.file_bytes, budget_report.within_budget) = sizer.check(&objects);
+        budget_report.min_feature_area_mm2 = Some(area);
+    }
+    // A file over budget was only estimated, report its real size
+    if !budget_report.within_budget && target.limits.max_file_bytes.is_some() {
+        budget_report.file_bytes = Some(sizer.exact(&objects));
+    }
+
+    report.record_diff(ConstraintRule::MeshBudget, layers, &result);
+    budget_report.coarsened_cells = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|&(x, y)| layers.get(x, y) != result.get(x, y))
+        .count();
+
+    (result, objects, budget_report)
+}
+
+/// One slab over every cell with geometry, from the flat top up by the cap thickness
+fn generate_cap_mesh(layers: &LayerMap, config: &PrintConfig, cap: &TopCap, options: &GenerateOptions) -> Mesh {
+    let (width, height) = layers.dimensions();
+    let pixel_size = config.pixel_size(width, height);
+    let bottom = config.calculate_total_height(0, true);
+    let top = bottom + cap.thickness(config);
+
+    match options.backend {
+        MeshBackend::Boxes => {
+            let cells: Vec<(u32, u32)> = (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .filter(|&(x, y)| !layers.is_masked(x, y))
+                .collect();
+            let mut mesh = Mesh::new();
+            for rect in decompose_cells(&cells) {
+                generate_box(
+                    &mut mesh.vertices,
+                    &mut mesh.triangles,
+                    rect.x as f32 * pixel_size.0, rect.y as f32 * pixel_size.1, bottom,
+                    rect.width as f32 * pixel_size.0, rect.height as f32 * pixel_size.1, top - bottom,
+                );
+            }
+            mesh
+        }
+        MeshBackend::Heightfield | MeshBackend::Contour | MeshBackend::Terraces => {
+            let field = ColumnField::from_fn(width, height, pixel_size, |x, y| {
+                layers.get(x, y).map(|_| (bottom, top))
+            });
+            match options.backend {
+                MeshBackend::Contour => mesh_column_field_contours(&field, options.nozzle_diameter as f64 / 2.0),
+                MeshBackend::Terraces => mesh_column_field_terraces(&field),
+                _ => mesh_column_field(&field),
+            }
+        }
+    }
+}
+
+/// Black and white thickness of the stack for one cell, the same stacking the box backends use
+fn material_stack(layer_count: u16, lighting: Lighting, config: &PrintConfig, flat_top: bool) -> (f32, f32) {
+    match lighting {
+        Lighting::Backlit => config.calculate_backlit_stack(layer_count as u32),
+        Lighting::Reflective => (
+            config.calculate_black_thickness(layer_count as u32, flat_top),
+            layer_count as f32 * config.layer_thickness,
+        ),
+    }
+}
+
+/// Black and white material of every cell as column fields, the input of the heightfield and contour meshers
+/// `floors` raises the bottom of the columns, by at most half of their height.
+fn material_fields(layers: &LayerMap, palette: &ColorPalette, config: &PrintConfig, flat_top: bool, floors: Option<&[f32]>) -> (ColumnField, ColumnField) {
+    let (width, height) = layers.dimensions();
+    let pixel_size = config.pixel_size(width, height);
+    let stack = |x: u32, y: u32| {
+        layers.get(x, y).map(|layer_count| {
+            let (black, white) = material_stack(layer_count, palette.lighting, config, flat_top);
+            let floor = floors.map_or(0.0, |floors| floors[layers.index(x, y)]).min((black + white) / 2.0);
+            (floor, black, white)
+        })
+    };
+
+    let black = ColumnField::from_fn(width, height, pixel_size, |x, y| {
+        stack(x, y).map(|(floor, black, _)| (floor, black))
+    });
+    let white = ColumnField::from_fn(width, height, pixel_size, |x, y| {
+        stack(x, y).map(|(floor, black, white)| (black.max(floor), black + white))
+    });
+
+    (black, white)
+}
+
+pub fn generate_image_objects_vectorized(
+    layers: &LayerMap,
+    config: &PrintConfig,
     flat_top: bool,
-    mask: Option<&GrayImage>,
 ) -> PrintObjects {
-    let regions = vectorize_image_to_regions(image, palette, mask);
-    let (width, height) = image.dimensions();
+    let regions = vectorize_layers_to_regions(layers);
+    let (width, height) = layers.dimensions();
     let (pixel_width, pixel_height) = config.pixel_size(width, height);
     
     let mut black_mesh = Mesh::new();
@@ -63,37 +832,76 @@ pub fn generate_image_objects_vectorized(
         }
     }
     
-    PrintObjects { black_mesh, white_mesh }
+    PrintObjects { black_mesh, white_mesh, extras: Vec::new() }
+}
+
+/// Generate backlit objects, every region is a single stack sized by `calculate_backlit_stack`
+fn generate_backlit_objects(layers: &LayerMap, config: &PrintConfig) -> PrintObjects {
+    let regions = vectorize_layers_to_regions(layers);
+    let (width, height) = layers.dimensions();
+    let (pixel_width, pixel_height) = config.pixel_size(width, height);
+
+    let mut black_mesh = Mesh::new();
+    let mut white_mesh = Mesh::new();
+
+    for (&layer_count, rectangles) in &regions {
+        let (black_thickness, white_thickness) = config.calculate_backlit_stack(layer_count);
+
+        for rect in rectangles {
+            let world_x = rect.x as f32 * pixel_width;
+            let world_y = rect.y as f32 * pixel_height;
+            let rect_width = rect.width as f32 * pixel_width;
+            let rect_height = rect.height as f32 * pixel_height;
+
+            if black_thickness > 0.0 {
+                generate_box(
+                    &mut black_mesh.vertices,
+                    &mut black_mesh.triangles,
+                    world_x, world_y, 0.0,
+                    rect_width, rect_height, black_thickness,
+                );
+            }
+
+            if white_thickness > 0.0 {
+                generate_box(
+                    &mut white_mesh.vertices,
+                    &mut white_mesh.triangles,
+                    world_x, world_y, black_thickness,
+                    rect_width, rect_height, white_thickness,
+                );
+            }
+        }
+    }
+
+    PrintObjects { black_mesh, white_mesh, extras: Vec::new() }
 }
 
 /// Generate ultra-optimized variable height objects
-fn generate_variable_height_optimized(
-    image: &RgbImage,
-    palette: &ColorPalette,
-    config: &PrintConfig,
-    mask: Option<&GrayImage>,
-) -> PrintObjects {
-    let (width, height) = image.dimensions();
+fn generate_variable_height_optimized(layers: &LayerMap, config: &PrintConfig) -> PrintObjects {
+    let (width, height) = layers.dimensions();
     let (pixel_width, pixel_height) = config.pixel_size(width, height);
     
     // Group white regions by layer count and vectorize each group
-    let layer_map = image_to_layer_map(image, palette, mask);
     let mut black_mesh = Mesh::new();
     let mut white_mesh = Mesh::new();
     
     // Collect all pixels that should have geometry (not masked out)
     let mut all_pixels: Vec<(u32, u32)> = Vec::new();
-    let mut layer_groups: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
+    let mut layer_groups: BTreeMap<u32, Vec<(u32, u32)>> = BTreeMap::new();
     
-    for ((x, y), &layer_count) in &layer_map {
-        all_pixels.push((*x, *y));
-        if layer_count > 0 {
-            layer_groups.entry(layer_count).or_default().push((*x, *y));
+    for y in 0..height {
+        for x in 0..width {
+            if let Some(layer_count) = layers.get(x, y) {
+                all_pixels.push((x, y));
+                if layer_count > 0 {
+                    layer_groups.entry(layer_count as u32).or_default().push((x, y));
+                }
+            }
         }
     }
     
     // Create vectorized black base from all non-masked pixels
-    let black_rectangles = pixels_to_rectangles(&all_pixels);
+    let black_rectangles = decompose_cells(&all_pixels);
     for rect in black_rectangles {
         let world_x = rect.x as f32 * pixel_width;
         let world_y = rect.y as f32 * pixel_height;
@@ -110,7 +918,7 @@ fn generate_variable_height_optimized(
     
     // Create vectorized regions for each layer count
     for (&layer_count, pixels) in &layer_groups {
-        let rectangles = pixels_to_rectangles(pixels);
+        let rectangles = decompose_cells(pixels);
         let z_offset = config.base_thickness;
         let layer_height = layer_count as f32 * config.layer_thickness;
         
@@ -129,226 +937,23 @@ fn generate_variable_height_optimized(
         }
     }
     
-    PrintObjects { black_mesh, white_mesh }
-}
-
-fn vectorize_image_to_regions(image: &RgbImage, palette: &ColorPalette, mask: Option<&GrayImage>) -> HashMap<u32, Vec<Rectangle>> {
-    let layer_map = image_to_layer_map(image, palette, mask);
-    let mut regions = HashMap::new();
-    let mut layer_pixels: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
-    
-    for ((x, y), &layer_count) in &layer_map {
-        layer_pixels.entry(layer_count).or_default().push((*x, *y));
-    }
-    
-    for (&layer_count, pixels) in &layer_pixels {
-        let rectangles = pixels_to_rectangles(pixels);
-        regions.insert(layer_count, rectangles);
-    }
-    
-    regions
+    PrintObjects { black_mesh, white_mesh, extras: Vec::new() }
 }
 
-fn image_to_layer_map(image: &RgbImage, palette: &ColorPalette, mask: Option<&GrayImage>) -> HashMap<(u32, u32), u32> {
-    let (width, height) = image.dimensions();
-    let mut layer_map = HashMap::with_capacity((width * height) as usize);
+/// Rectangles per layer count, ordered by layer count
+fn vectorize_layers_to_regions(layers: &LayerMap) -> BTreeMap<u32, Vec<Rectangle>> {
+    let (width, height) = layers.dimensions();
+    let mut layer_pixels: BTreeMap<u32, Vec<(u32, u32)>> = BTreeMap::new();
     
     for y in 0..height {
         for x in 0..width {
-            // Skip pixels that are masked (transparent)
-            if let Some(mask_img) = mask {
-                let alpha = mask_img.get_pixel(x, y)[0];
-                if alpha < 128 {
-                    // Skip transparent pixels
-                    continue;
-                }
-            }
-            
-            let pixel = *image.get_pixel(x, y);
-            let layer_count = palette.get_layer_count_for_color(&pixel);
-            layer_map.insert((x, y), layer_count);
-        }
-    }
-    
-    layer_map
-}
-
-// Helper function from existing code
-fn pixels_to_rectangles(pixels: &[(u32, u32)]) -> Vec<Rectangle> {
-    let mut rectangles = Vec::new();
-    let mut pixel_set: std::collections::HashSet<(u32, u32)> = pixels.iter().cloned().collect();
-    
-    while let Some(&(start_x, start_y)) = pixel_set.iter().next() {
-        let rect = find_largest_rectangle(&pixel_set, start_x, start_y);
-        
-        for y in rect.y..rect.y + rect.height {
-            for x in rect.x..rect.x + rect.width {
-                pixel_set.remove(&(x, y));
-            }
-        }
-        
-        rectangles.push(rect);
-    }
-    
-    rectangles
-}
-
-/// Optimize meshes by consolidating layers of the same height
-pub fn optimize_mesh_layers(objects: PrintObjects, flat_top: bool) -> PrintObjects {
-    if flat_top {
-        // For flat top, each color might have different heights per region, so use existing vectorization
-        objects // TODO: Could still be optimized but more complex
-    } else {
-        // For variable height, we can create large consolidated layers
-        optimize_variable_height_mesh(&objects)
-    }
-}
-
-/// Optimize variable height meshes by creating consolidated layers
-fn optimize_variable_height_mesh(objects: &PrintObjects) -> PrintObjects {
-    PrintObjects {
-        black_mesh: consolidate_uniform_layer(&objects.black_mesh),
-        white_mesh: consolidate_by_height(&objects.white_mesh),
-    }
-}
-
-/// Consolidate mesh where all geometry is at the same height (like black base)
-fn consolidate_uniform_layer(mesh: &Mesh) -> Mesh {
-    if mesh.vertices.vertex.is_empty() {
-        return Mesh::new();
-    }
-    
-    // Find the Z-level and bounds of all geometry
-    let mut min_x = f64::MAX;
-    let mut max_x = f64::MIN;
-    let mut min_y = f64::MAX;
-    let mut max_y = f64::MIN;
-    let mut z_bottom = f64::MAX;
-    let mut z_top = f64::MIN;
-    
-    for vertex in &mesh.vertices.vertex {
-        min_x = min_x.min(vertex.x);
-        max_x = max_x.max(vertex.x);
-        min_y = min_y.min(vertex.y);
-        max_y = max_y.max(vertex.y);
-        z_bottom = z_bottom.min(vertex.z);
-        z_top = z_top.max(vertex.z);
-    }
-    
-    // Create a single large box covering the entire area
-    let mut optimized_mesh = Mesh::new();
-    let thickness = z_top - z_bottom;
-    
-    if thickness > 0.0 {
-        generate_box(
-            &mut optimized_mesh.vertices,
-            &mut optimized_mesh.triangles,
-            min_x as f32, min_y as f32, z_bottom as f32,
-            (max_x - min_x) as f32, (max_y - min_y) as f32, thickness as f32,
-        );
-    }
-    
-    optimized_mesh
-}
-
-/// Consolidate mesh by grouping regions of the same height
-fn consolidate_by_height(mesh: &Mesh) -> Mesh {
-    if mesh.vertices.vertex.is_empty() {
-        return Mesh::new();
-    }
-    
-    // Group triangles by their Z-levels (height)
-    let mut height_groups: HashMap<(i32, i32), Vec<&Triangle>> = HashMap::new(); // (z_bottom, z_top) -> triangles
-    
-    for triangle in &mesh.triangles.triangle {
-        let v0 = &mesh.vertices.vertex[triangle.v1];
-        let v1 = &mesh.vertices.vertex[triangle.v2];
-        let v2 = &mesh.vertices.vertex[triangle.v3];
-        
-        // Find the Z-range of this triangle
-        let min_z = v0.z.min(v1.z).min(v2.z);
-        let max_z = v0.z.max(v1.z).max(v2.z);
-        
-        // Quantize Z values to group similar heights (0.01mm precision)
-        let z_bottom_key = (min_z * 100.0).round() as i32;
-        let z_top_key = (max_z * 100.0).round() as i32;
-        
-        height_groups.entry((z_bottom_key, z_top_key)).or_default().push(triangle);
-    }
-    
-    // For each height group, find the bounding rectangle and create one box
-    let mut optimized_mesh = Mesh::new();
-    
-    for ((z_bottom_key, z_top_key), triangles) in height_groups {
-        let z_bottom = z_bottom_key as f64 / 100.0;
-        let z_top = z_top_key as f64 / 100.0;
-        let thickness = z_top - z_bottom;
-        
-        if thickness <= 0.001 { // Skip very thin layers (likely artifacts)
-            continue;
-        }
-        
-        // Find bounding rectangle for this height group
-        let mut min_x = f64::MAX;
-        let mut max_x = f64::MIN;
-        let mut min_y = f64::MAX;
-        let mut max_y = f64::MIN;
-        
-        for triangle in &triangles {
-            for &vertex_idx in [triangle.v1, triangle.v2, triangle.v3].iter() {
-                let vertex = &mesh.vertices.vertex[vertex_idx];
-                min_x = min_x.min(vertex.x);
-                max_x = max_x.max(vertex.x);
-                min_y = min_y.min(vertex.y);
-                max_y = max_y.max(vertex.y);
+            if let Some(layer_count) = layers.get(x, y) {
+                layer_pixels.entry(layer_count as u32).or_default().push((x, y));
             }
         }
-        
-        // Create one box for this height level
-        generate_box(
-            &mut optimized_mesh.vertices,
-            &mut optimized_mesh.triangles,
-            min_x as f32, min_y as f32, z_bottom as f32,
-            (max_x - min_x) as f32, (max_y - min_y) as f32, thickness as f32,
-        );
     }
     
-    optimized_mesh
+    layer_pixels.into_iter()
+        .map(|(layer_count, pixels)| (layer_count, decompose_cells(&pixels)))
+        .collect()
 }
-
-fn find_largest_rectangle(pixel_set: &std::collections::HashSet<(u32, u32)>, start_x: u32, start_y: u32) -> Rectangle {
-    let mut width = 1;
-    let mut height = 1;
-    
-    // Expand right
-    while pixel_set.contains(&(start_x + width, start_y)) {
-        let mut column_available = true;
-        for y in start_y..start_y + height {
-            if !pixel_set.contains(&(start_x + width, y)) {
-                column_available = false;
-                break;
-            }
-        }
-        if column_available {
-            width += 1;
-        } else {
-            break;
-        }
-    }
-    
-    // Expand down
-    while {
-        let mut row_available = true;
-        for x in start_x..start_x + width {
-            if !pixel_set.contains(&(x, start_y + height)) {
-                row_available = false;
-                break;
-            }
-        }
-        row_available
-    } {
-        height += 1;
-    }
-    
-    Rectangle { x: start_x, y: start_y, width, height }
-}
\ No newline at end of file
@@ -0,0 +1,226 @@
+use crate::{
+    config::{PrintConfig, PrintingConstraints},
+    grayscale::{features::squared_distance_transform, layer_map::LayerMap, ColorPalette},
+};
+
+/// What `enforce_height_constraints` had to change
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeightConstraintStats {
+    /// Cells lowered by any of the rules, each cell counted once
+    pub lowered_cells: usize,
+    /// Cells lowered because they rose too far above a neighbor
+    pub step_limited_cells: usize,
+    /// Cells lowered because the region above a height band was too narrow
+    pub band_limited_cells: usize,
+    /// Cells lowered to fit the total height budget
+    pub height_capped_cells: usize,
+    /// Sum of white layers removed over all cells
+    pub layers_removed: u64,
+}
+
+/// Enforce the height related constraints, meant to run after `enforce_min_feature_size`.
+/// Cells are only ever lowered, which darkens them slightly but never adds material where there was none.
+/// Lowered cells land on a layer count the palette has, calibrated palettes may skip some.
+pub fn enforce_height_constraints(
+    layers: &LayerMap,
+    palette: &ColorPalette,
+    constraints: &PrintingConstraints,
+    config: &PrintConfig,
+) -> (LayerMap, HeightConstraintStats) {
+    let mut result = layers.clone();
+    let mut stats = HeightConstraintStats::default();
+    let levels = Levels::new(palette);
+    let (width, height) = layers.dimensions();
+    let size = (width * height) as usize;
+    let (mut step_limited, mut band_limited, mut height_capped) = (vec![false; size], vec![false; size], vec![false; size]);
+
+    let mut bands = constraints.height_bands.clone();
+    bands.sort_by_key(|band| std::cmp::Reverse(band.min_layers));
+    let (pixel_width, pixel_height) = config.pixel_size(width, height);
+
+    // Lowering a cell for one rule can break another, so repeat until a round changes nothing.
+    // Every change strictly lowers a cell, which bounds the number of rounds.
+    loop {
+        let mut changed = 0;
+
+        for band in &bands {
+            let min_width = band.min_width_mm / pixel_width.max(pixel_height);
+            changed += limit_band_width(&mut result, &levels, band.min_layers, min_width, &mut band_limited);
+        }
+
+        if let Some(max_layers) = constraints.calculate_max_layers_for_height(config) {
+            changed += cap_layers(&mut result, &levels, max_layers, &mut height_capped);
+        }
+
+        if let Some(max_step) = constraints.max_height_step_layers {
+            changed += limit_height_steps(&mut result, &levels, max_step, &mut step_limited);
+        }
+
+        if changed == 0 {
+            break;
+        }
+    }
+
+    let count = |cells: &[bool]| cells.iter().filter(|&&changed| changed).count();
+    stats.step_limited_cells = count(&step_limited);
+    stats.band_limited_cells = count(&band_limited);
+    stats.height_capped_cells = count(&height_capped);
+    stats.lowered_cells = layers.layers().iter()
+        .zip(result.layers().iter())
+        .filter(|(before, after)| before != after)
+        .count();
+    stats.layers_removed = layers.layers().iter()
+        .zip(result.layers().iter())
+        .map(|(&before, &after)| (before - after) as u64)
+        .sum();
+
+    (result, stats)
+}
+
+/// Layer counts the palette can show, in ascending order
+struct Levels(Vec<u16>);
+
+impl Levels {
+    fn new(palette: &ColorPalette) -> Self {
+        let mut levels: Vec<u16> = palette.layer_counts.iter()
+            .map(|&layer_count| layer_count.min(u16::MAX as u32) as u16)
+            .collect();
+        levels.sort_unstable();
+        levels.dedup();
+        Self(levels)
+    }
+
+    /// Highest layer count of the palette that is at most `layer_count`, the lowest one when all are higher
+    fn at_most(&self, layer_count: u16) -> u16 {
+        match self.0.partition_point(|&level| level <= layer_count) {
+            0 => self.0.first().copied().unwrap_or(layer_count),
+            index => self.0[index - 1],
+        }
+    }
+}
+
+/// Lower the cell to `target` snapped to the palette, returns whether it changed
+fn lower(layers: &mut LayerMap, levels: &Levels, x: u32, y: u32, layer_count: u16, target: u16, marks: &mut [bool]) -> bool {
+    let target = levels.at_most(target);
+    if target < layer_count {
+        layers.set(x, y, target);
+        marks[layers.index(x, y)] = true;
+        true
+    } else {
+        false
+    }
+}
+
+fn cap_layers(layers: &mut LayerMap, levels: &Levels, max_layers: u32, marks: &mut [bool]) -> usize {
+    let cap = max_layers.min(u16::MAX as u32) as u16;
+    let (width, height) = layers.dimensions();
+    let mut changed = 0;
+
+    for y in 0..height {
+        for x in 0..width {
+            if let Some(layer_count) = layers.get(x, y).filter(|&layer_count| layer_count > cap) {
+                changed += lower(layers, levels, x, y, layer_count, cap, marks) as usize;
+            }
+        }
+    }
+
+    changed
+}
+
+/// Lower cells until no cell is more than `max_step` layers above an unmasked 4-neighbor.
+/// Forward and backward chamfer sweeps, repeated because masked holes can block propagation.
+fn limit_height_steps(layers: &mut LayerMap, levels: &Levels, max_step: u32, marks: &mut [bool]) -> usize {
+    let step = max_step.min(u16::MAX as u32) as u16;
+    let (width, height) = layers.dimensions();
+    let mut changed = 0;
+
+    loop {
+        let before = changed;
+
+        for y in 0..height {
+            for x in 0..width {
+                let neighbors = [
+                    if x > 0 { layers.get(x - 1, y) } else { None },
+                    if y > 0 { layers.get(x, y - 1) } else { None },
+                ];
+                changed += lower_to_neighbors(layers, levels, x, y, &neighbors, step, marks) as usize;
+            }
+        }
+
+        for y in (0..height).rev() {
+            for x in (0..width).rev() {
+                let neighbors = [
+                    if x + 1 < width { layers.get(x + 1, y) } else { None },
+                    if y + 1 < height { layers.get(x, y + 1) } else { None },
+                ];
+                changed += lower_to_neighbors(layers, levels, x, y, &neighbors, step, marks) as usize;
+            }
+        }
+
+        if changed == before {
+            break;
+        }
+    }
+
+    changed
+}
+
+fn lower_to_neighbors(layers: &mut LayerMap, levels: &Levels, x: u32, y: u32, neighbors: &[Option<u16>], step: u16, marks: &mut [bool]) -> bool {
+    let Some(layer_count) = layers.get(x, y) else {
+        return false;
+    };
+
+    let limit = neighbors.iter()
+        .flatten()
+        .map(|&neighbor| neighbor.saturating_add(step))
+        .min()
+        .unwrap_or(u16::MAX);
+
+    layer_count > limit && lower(layers, levels, x, y, layer_count, limit, marks)
+}
+
+/// Morphological opening of the region at least `min_layers` high with a disk that fits `min_width` cells.
+/// Cells that do not survive drop to just below the band. Image borders and masked cells bound the region.
+fn limit_band_width(layers: &mut LayerMap, levels: &Levels, min_layers: u32, min_width: f32, marks: &mut [bool]) -> usize {
+    if min_layers == 0 || min_width <= 1.0 {
+        return 0;
+    }
+    let Ok(threshold) = u16::try_from(min_layers) else {
+        return 0;
+    };
+
+    let (width, height) = layers.dimensions();
+    let region: Vec<bool> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| layers.get(x, y).is_some_and(|layer_count| layer_count >= threshold))
+        .collect();
+    if !region.iter().any(|&inside| inside) {
+        return 0;
+    }
+
+    // A cell is the center of a wide enough disk when 2 * distance - 1 >= min_width
+    let radius = (min_width + 1.0) / 2.0;
+
+    let outside: Vec<bool> = region.iter().map(|&inside| !inside).collect();
+    let to_outside = squared_distance_transform(&outside, width, height);
+    let eroded: Vec<bool> = (0..(width * height) as usize)
+        .map(|index| {
+            let (x, y) = (index as u32 % width, index as u32 / width);
+            let to_border = (x + 1).min(y + 1).min(width - x).min(height - y) as f64;
+            region[index] && to_outside[index].sqrt().min(to_border) >= radius as f64
+        })
+        .collect();
+
+    let to_eroded = squared_distance_transform(&eroded, width, height);
+    let mut changed = 0;
+    for y in 0..height {
+        for x in 0..width {
+            let index = layers.index(x, y);
+            if region[index] && to_eroded[index].sqrt() >= radius as f64 {
+                changed += lower(layers, levels, x, y, layers.layer(x, y), threshold - 1, marks) as usize;
+            }
+        }
+    }
+
+    changed
+}
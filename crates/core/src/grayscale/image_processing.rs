@@ -1,25 +1,55 @@
-use image::{ImageBuffer, Rgb, RgbImage};
+use anyhow::Result;
+use image::{GrayImage, ImageBuffer, Rgb, RgbImage};
 
-use crate::grayscale::ColorPalette;
+use crate::{grayscale::{layer_map::{layer_count_to_cell, LayerMap}, ColorPalette}, utils::is_masked};
 
 pub fn dither_to_palette(input_image: &RgbImage, palette: &ColorPalette) -> RgbImage {
+    let indices = dither_to_palette_indices(input_image, palette);
+    let mut output = input_image.clone();
+    
+    for (pixel, &index) in output.pixels_mut().zip(indices.iter()) {
+        *pixel = palette.colors[index];
+    }
+    
+    output
+}
+
+/// Dither straight into a `LayerMap`, this is where colors enter the pipeline
+pub fn dither_to_layer_map(input_image: &RgbImage, palette: &ColorPalette, mask: Option<&GrayImage>) -> Result<LayerMap> {
+    let (width, height) = input_image.dimensions();
+    let indices = dither_to_palette_indices(input_image, palette);
+    let mut layers = LayerMap::new(width, height, 0);
+    
+    for y in 0..height {
+        for x in 0..width {
+            let index = indices[(y * width + x) as usize];
+            layers.set(x, y, layer_count_to_cell(palette.layer_counts[index])?);
+            layers.set_masked(x, y, is_masked(mask, x, y));
+        }
+    }
+    
+    Ok(layers)
+}
+
+/// Floyd-Steinberg dithering, returns the palette index chosen for every pixel in row-major order
+fn dither_to_palette_indices(input_image: &RgbImage, palette: &ColorPalette) -> Vec<usize> {
     let (width, height) = input_image.dimensions();
     let mut grayscale = to_grayscale(input_image);
-    let mut output = input_image.clone();
+    let mut indices = Vec::with_capacity((width * height) as usize);
     
-    // Floyd-Steinberg dithering
     for y in 0..height {
         for x in 0..width {
             let old_pixel = *grayscale.get_pixel(x, y);
-            let new_pixel = find_closest_palette_color(&old_pixel, palette);
-            output.put_pixel(x, y, new_pixel);
+            let index = find_closest_palette_index(&old_pixel, palette);
+            let new_pixel = palette.colors[index];
+            indices.push(index);
             
             let error = calculate_color_error(&old_pixel, &new_pixel);
             distribute_error(&mut grayscale, x, y, width, height, &error);
         }
     }
     
-    output
+    indices
 }
 
 pub fn to_grayscale(image: &RgbImage) -> RgbImage {
@@ -47,13 +77,14 @@ pub fn mirror_image(image: &RgbImage) -> RgbImage {
     mirrored_image
 }
 
-fn find_closest_palette_color(pixel: &Rgb<u8>, palette: &ColorPalette) -> Rgb<u8> {
+fn find_closest_palette_index(pixel: &Rgb<u8>, palette: &ColorPalette) -> usize {
     palette.colors.iter()
-        .min_by(|a, b| {
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
             color_distance(pixel, a).partial_cmp(&color_distance(pixel, b)).unwrap()
         })
-        .copied()
-        .unwrap_or(palette.colors[0])
+        .map(|(index, _)| index)
+        .unwrap_or(0)
 }
 
 fn color_distance(c1: &Rgb<u8>, c2: &Rgb<u8>) -> f32 {
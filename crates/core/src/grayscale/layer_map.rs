@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use image::{GrayImage, Rgb, RgbImage};
+
+use crate::{grayscale::ColorPalette, utils::is_masked};
+
+/// Dense raster of white layer counts, one cell per image pixel.
+/// Everything between dithering and prediction/meshing works on this instead of RGB colors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerMap {
+    width: u32,
+    height: u32,
+    layers: Vec<u16>,
+    masked: Vec<bool>,
+}
+
+impl LayerMap {
+    pub fn new(width: u32, height: u32, layer_count: u16) -> Self {
+        let size = (width * height) as usize;
+        Self {
+            width,
+            height,
+            layers: vec![layer_count; size],
+            masked: vec![false; size],
+        }
+    }
+
+    /// Convert an image that only contains palette colors (a dithered image or a saved prediction).
+    /// Colors missing from the palette are an error instead of silently becoming zero layers.
+    pub fn from_image(image: &RgbImage, palette: &ColorPalette, mask: Option<&GrayImage>) -> Result<Self> {
+        let lookup = palette.layer_lookup()?;
+        let (width, height) = image.dimensions();
+        let mut map = Self::new(width, height, 0);
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let layer_count = lookup.get(pixel).ok_or_else(|| {
+                anyhow!(
+                    "Color #{:02x}{:02x}{:02x} at ({}, {}) is not in the palette",
+                    pixel[0], pixel[1], pixel[2], x, y
+                )
+            })?;
+            let index = map.index(x, y);
+            map.layers[index] = *layer_count;
+            map.masked[index] = is_masked(mask, x, y);
+        }
+
+        Ok(map)
+    }
+
+    /// Render the layer counts back to palette colors, masked cells keep their color so the prediction shows the whole image
+    pub fn to_image(&self, palette: &ColorPalette) -> Result<RgbImage> {
+        let mut colors: HashMap<u16, Rgb<u8>> = HashMap::new();
+        for (&layer_count, &color) in palette.layer_counts.iter().zip(palette.colors.iter()) {
+            colors.entry(layer_count_to_cell(layer_count)?).or_insert(color);
+        }
+
+        let mut image = RgbImage::new(self.width, self.height);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let layer_count = self.layers[(y * self.width + x) as usize];
+            *pixel = *colors.get(&layer_count)
+                .ok_or_else(|| anyhow!("Layer count {} at ({}, {}) has no palette color", layer_count, x, y))?;
+        }
+
+        Ok(image)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    /// Layer count of the cell, `None` when it is cut away by the mask
+    pub fn get(&self, x: u32, y: u32) -> Option<u16> {
+        let index = self.index(x, y);
+        if self.masked[index] {
+            None
+        } else {
+            Some(self.layers[index])
+        }
+    }
+
+    /// Layer count of the cell regardless of the mask
+    pub fn layer(&self, x: u32, y: u32) -> u16 {
+        self.layers[self.index(x, y)]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, layer_count: u16) {
+        let index = self.index(x, y);
+        self.layers[index] = layer_count;
+    }
+
+    pub fn is_masked(&self, x: u32, y: u32) -> bool {
+        self.masked[self.index(x, y)]
+    }
+
+    pub fn set_masked(&mut self, x: u32, y: u32, masked: bool) {
+        let index = self.index(x, y);
+        self.masked[index] = masked;
+    }
+
+    /// Raw layer counts in row-major order
+    pub fn layers(&self) -> &[u16] {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut [u16] {
+        &mut self.layers
+    }
+
+    /// Raw mask in row-major order, `true` for cells without geometry
+    pub fn masked(&self) -> &[bool] {
+        &self.masked
+    }
+
+    /// Highest layer count of any cell that gets geometry
+    pub fn max_layer(&self) -> u16 {
+        self.layers.iter()
+            .zip(self.masked.iter())
+            .filter(|(_, &masked)| !masked)
+            .map(|(&layer_count, _)| layer_count)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+pub(crate) fn layer_count_to_cell(layer_count: u32) -> Result<u16> {
+    u16::try_from(layer_count).map_err(|_| anyhow!("Layer count {} is out of range", layer_count))
+}
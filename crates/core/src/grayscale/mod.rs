@@ -1,14 +1,24 @@
-use std::{collections::HashMap, fs::{self, File}, io::{self, BufWriter, Read, Write}, path::Path};
-use image::{Rgb, RgbImage};
+use std::{collections::HashMap, fs, io::{self, Cursor, Read, Write}, path::Path};
+use image::Rgb;
 use serde::{Deserialize, Serialize};
-use threemf::model::{Base, BaseMaterials, Item, Model, Object};
+use threemf::model::{Base, BaseMaterials, Item, Metadata, Model, Object};
 
 
-use crate::{config::{PrintConfig, PrintingConstraints}, mesh::calculate_normal, utils::PrintObjects};
+use features::{label_features, Feature, FeatureLabels, NO_FEATURE};
+use layer_map::{layer_count_to_cell, LayerMap};
+use report::{ChangeAction, ConstraintRule, PrintabilityReport};
+
+use crate::{config::{Lighting, PrintConfig, PrintingConstraints}, export::{material_parts, slicer::{config_files, SlicerObject, SlicerPart, SlicerSettings}}, utils::PrintObjects};
 
 pub mod calibration;
 pub mod image_processing;
 pub mod generate;
+pub mod features;
+pub mod height;
+pub mod layer_map;
+pub mod report;
+pub mod shape;
+pub mod tiling;
 
 #[cfg(test)]
 mod tests;
@@ -35,6 +45,9 @@ pub struct ColorPalette {
     #[serde(with = "vec_rgb_serde")] // Use a custom module for Vec<Rgb<u8>>
     pub colors: Vec<Rgb<u8>>,
     pub layer_counts: Vec<u32>,
+    /// Whether the colors were measured front-lit or against a lightbox
+    #[serde(default)]
+    pub lighting: Lighting,
 }
 
 // Custom serialization/deserialization for Vec<Rgb<u8>>
@@ -72,15 +85,45 @@ impl ColorPalette {
             layer_counts.push(i);
         }
         
-        Self { colors, layer_counts }
+        Self { colors, layer_counts, lighting: Lighting::Reflective }
+    }
+
+    /// Linear stand-in palette for backlit prints, more layers let less light through
+    pub fn fake_backlit(max_layers: u32) -> Self {
+        let mut palette = Self::fake(max_layers);
+        palette.colors.reverse();
+        palette.lighting = Lighting::Backlit;
+        palette
+    }
+
+    /// Color with the fewest layers, what bare base looks like
+    pub fn base_color(&self) -> Rgb<u8> {
+        self.layer_counts.iter()
+            .zip(self.colors.iter())
+            .min_by_key(|(&layer_count, _)| layer_count)
+            .map_or(Rgb([0, 0, 0]), |(_, &color)| color)
+    }
+
+    /// Highest layer count of the palette, the number of layers the full image is built from
+    pub fn max_layer_count(&self) -> u32 {
+        self.layer_counts.iter().copied().max().unwrap_or(0)
     }
 
-    pub fn get_layer_count_for_color(&self, color: &Rgb<u8>) -> u32 {
+    /// Whether prints dithered with both palettes can share a plate. They are printed with the same filaments
+    /// and layer heights, so only palettes measured under the same lighting fit together.
+    pub fn check_compatible(&self, other: &ColorPalette) -> Result<(), String> {
+        if self.lighting != other.lighting {
+            let name = |lighting: Lighting| format!("{:?}", lighting).to_lowercase();
+            return Err(format!("A {} palette can not share a plate with a {} one", name(other.lighting), name(self.lighting)));
+        }
+        Ok(())
+    }
+
+    pub fn get_layer_count_for_color(&self, color: &Rgb<u8>) -> Option<u32> {
         self.colors.iter()
             .zip(self.layer_counts.iter())
             .find(|(c, _)| **c == *color)
             .map(|(_, &count)| count)
-            .unwrap_or(0)
     }
 
     pub fn get_color_for_layer_count(&self, layer_count: u32) -> Option<Rgb<u8>> {
@@ -90,6 +133,15 @@ impl ColorPalette {
             .map(|(_, &color)| color)
     }
 
+    /// Color to layer count table for converting whole images at once
+    pub fn layer_lookup(&self) -> anyhow::Result<HashMap<Rgb<u8>, u16>> {
+        let mut lookup = HashMap::with_capacity(self.colors.len());
+        for (&color, &layer_count) in self.colors.iter().zip(self.layer_counts.iter()) {
+            lookup.entry(color).or_insert(layer_count_to_cell(layer_count)?);
+        }
+        Ok(lookup)
+    }
+
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
         let ron_string = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
             .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to serialize ColorPalette to RON: {}", e)))?;
@@ -116,158 +168,178 @@ impl ColorPalette {
     }
 }
 
-pub fn export_to_stl(objects: &PrintObjects, filename_black: &str, filename_white: &str) -> io::Result<()> {
-    let mut file = File::create(filename_black)?;
-    writeln!(file, "solid object")?;
-    
-    for triangle in &objects.black_mesh.triangles.triangle {
-        let v0 = &objects.black_mesh.vertices.vertex[triangle.v1];
-        let v1 = &objects.black_mesh.vertices.vertex[triangle.v2];
-        let v2 = &objects.black_mesh.vertices.vertex[triangle.v3];
-        let normal = calculate_normal(v0, v1, v2);
-        
-        writeln!(file, "  facet normal {} {} {}", normal.x, normal.y, normal.z)?;
-        writeln!(file, "    outer loop")?;
-        writeln!(file, "      vertex {} {} {}", v0.x, v0.y, v0.z)?;
-        writeln!(file, "      vertex {} {} {}", v1.x, v1.y, v1.z)?;
-        writeln!(file, "      vertex {} {} {}", v2.x, v2.y, v2.z)?;
-        writeln!(file, "    endloop")?;
-        writeln!(file, "  endfacet")?;
-    }
-    writeln!(file, "endsolid object")?;
+/// Name of the object that holds the parts of a single print in the 3MF
+pub const PRINT_OBJECT_NAME: &str = "Print Object";
 
-    let mut filew = File::create(filename_white)?;
-    writeln!(filew, "solid object")?;
-    
-    for triangle in &objects.white_mesh.triangles.triangle {
-        let v0 = &objects.white_mesh.vertices.vertex[triangle.v1];
-        let v1 = &objects.white_mesh.vertices.vertex[triangle.v2];
-        let v2 = &objects.white_mesh.vertices.vertex[triangle.v3];
-        let normal = calculate_normal(v0, v1, v2);
-        
-        writeln!(filew, "  facet normal {} {} {}", normal.x, normal.y, normal.z)?;
-        writeln!(filew, "    outer loop")?;
-        writeln!(filew, "      vertex {} {} {}", v0.x, v0.y, v0.z)?;
-        writeln!(filew, "      vertex {} {} {}", v1.x, v1.y, v1.z)?;
-        writeln!(filew, "      vertex {} {} {}", v2.x, v2.y, v2.z)?;
-        writeln!(filew, "    endloop")?;
-        writeln!(filew, "  endfacet")?;
-    }
-    
-    writeln!(filew, "endsolid object")?;
+pub fn export_to_3mf(objects: &PrintObjects, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+    export_to_3mf_with_recipe(objects, filename, None)
+}
+
+/// Same as `export_to_3mf`, with the recipe hash of the inputs (see `RecipeHasher`) stored in the model metadata.
+/// The archive only depends on the objects and the recipe, so identical inputs give identical bytes.
+pub fn export_to_3mf_with_recipe(objects: &PrintObjects, filename: &str, recipe: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(filename, write_3mf(objects, recipe)?)?;
     Ok(())
 }
 
+/// The 3MF archive `export_to_3mf_with_recipe` writes, in memory
+pub fn write_3mf(objects: &PrintObjects, recipe: Option<&str>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    write_3mf_groups(&[(PRINT_OBJECT_NAME, objects)], recipe, None)
+}
 
-pub fn export_to_3mf(objects: &PrintObjects, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Several prints in one 3MF, e.g. packed onto one plate by `mesh::packing`. Each becomes a parent object
+/// with its parts as components, the meshes are written where they are. With slicer settings the file
+/// also carries the layer height, infill and extruder of every part for that slicer.
+pub fn export_groups_to_3mf(
+    groups: &[(&str, &PrintObjects)],
+    filename: &str,
+    recipe: Option<&str>,
+    slicer: Option<&SlicerSettings>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(filename, write_3mf_groups(groups, recipe, slicer)?)?;
+    Ok(())
+}
+
+/// The 3MF archive `export_groups_to_3mf` writes, in memory
+pub fn write_3mf_groups(
+    groups: &[(&str, &PrintObjects)],
+    recipe: Option<&str>,
+    slicer: Option<&SlicerSettings>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     let mut model = Model::default();
     model.unit = threemf::model::Unit::Millimeter;
+    model.metadata.push(Metadata {
+        name: "Application".to_string(),
+        value: Some(format!("PrintSpots {}", env!("CARGO_PKG_VERSION"))),
+    });
+    if let Some(recipe) = recipe {
+        model.metadata.push(Metadata {
+            name: "Description".to_string(),
+            value: Some(format!("PrintSpots recipe sha256:{}", recipe)),
+        });
+    }
     let mut object_id = 1;
 
-    // Define materials/colors
-    let materials = BaseMaterials {
-        id: 1,
-        base: vec![
-            Base {
-                name: "Black".to_string(),
-                displaycolor: "#000000".to_string(),
-            },
-            Base {
-                name: "White".to_string(),
-                displaycolor: "#FFFFFF".to_string(),
-            },
-        ],
-    };
+    // Define materials/colors, black and white first, every other material once however many groups use it
+    let mut materials = BaseMaterials { id: 1, base: Vec::new() };
+    let group_parts: Vec<_> = groups.iter()
+        .map(|(group_name, objects)| {
+            let parts: Vec<_> = material_parts(objects).into_iter()
+                .map(|part| {
+                    let material_index = match materials.base.iter()
+                        .position(|base| base.name == part.material && base.displaycolor == part.display_color)
+                    {
+                        Some(index) => index,
+                        None => {
+                            materials.base.push(Base {
+                                name: part.material.clone(),
+                                displaycolor: part.display_color.clone(),
+                            });
+                            materials.base.len() - 1
+                        }
+                    };
+                    (part.mesh, part.name, part.display_color, material_index)
+                })
+                .collect();
+            (group_name, parts)
+        })
+        .collect();
 
     model.resources.basematerials = Some(vec![materials]);
-    
-    // Add black mesh as object 1
-    if !objects.black_mesh.vertices.vertex.is_empty() {
-        let object = Object {
-            id: object_id,
-            mesh: Some(threemf::Mesh {
-                vertices: objects.black_mesh.vertices.clone(),
-                triangles: objects.black_mesh.triangles.clone(),
-            }),
-            name: Some("Black Layer".to_string()),
-            partnumber: None,
-            pid: Some(1),           // Reference to basematerials group
-            pindex: Some(0),        // First material (Black)
-            components: None,
-        };
-        
-        model.resources.object.push(object);
-        object_id += 1;
-    }
-    
-    let black_id = if !objects.black_mesh.vertices.vertex.is_empty() { object_id - 1 } else { 0 };
-    
-    // Add white mesh as object 2
-    if !objects.white_mesh.vertices.vertex.is_empty() {
-        let object = Object {
-            id: object_id,
-            mesh: Some(threemf::Mesh {
-                vertices: objects.white_mesh.vertices.clone(),
-                triangles: objects.white_mesh.triangles.clone(),
-            }),
-            name: Some("White Layer".to_string()),
-            partnumber: None,
-            pid: Some(1),           // Reference to basematerials group
-            pindex: Some(1),        // Second material (White)
-            components: None,
-        };
-        
-        model.resources.object.push(object);
-        object_id += 1;
-    }
-    
-    let white_id = if !objects.white_mesh.vertices.vertex.is_empty() { object_id - 1 } else { 0 };
-    
-    // Create parent object with components referencing both meshes
-    if black_id > 0 || white_id > 0 {
+
+    let mut slicer_objects = Vec::new();
+    for (group_name, parts) in group_parts {
+        // One object per non-empty part, all of them components of a single print object
         let mut component_vec = Vec::new();
-        
-        if black_id > 0 {
+        let mut slicer_parts = Vec::new();
+        for (mesh, name, display_color, material_index) in parts {
+            if mesh.vertices.vertex.is_empty() {
+                continue;
+            }
+            slicer_parts.push(SlicerPart {
+                id: object_id,
+                name: name.clone(),
+                display_color,
+                triangles: mesh.triangles.triangle.len(),
+            });
+            let object = Object {
+                id: object_id,
+                mesh: Some(threemf::Mesh {
+                    vertices: mesh.vertices.clone(),
+                    triangles: mesh.triangles.clone(),
+                }),
+                name: Some(name),
+                partnumber: None,
+                pid: Some(1),           // Reference to basematerials group
+                pindex: Some(material_index),
+                components: None,
+            };
+
+            model.resources.object.push(object);
             component_vec.push(threemf::model::Component {
-                objectid: black_id,
+                objectid: object_id,
                 transform: None,
             });
+            object_id += 1;
         }
-        
-        if white_id > 0 {
-            component_vec.push(threemf::model::Component {
-                objectid: white_id,
+
+        if !component_vec.is_empty() {
+            let parent_object = Object {
+                id: object_id,
+                mesh: None,
+                name: Some(group_name.to_string()),
+                partnumber: None,
+                pid: None,
+                pindex: None,
+                components: Some(threemf::model::Components {
+                    component: component_vec,
+                }),
+            };
+
+            model.resources.object.push(parent_object);
+
+            // Add ONLY the parent objects to the build
+            model.build.item.push(Item {
+                objectid: object_id,
                 transform: None,
+                partnumber: None,
             });
+            slicer_objects.push(SlicerObject { id: object_id, name: group_name.to_string(), parts: slicer_parts });
+            object_id += 1;
         }
-        
-        let parent_object = Object {
-            id: object_id,
-            mesh: None,
-            name: Some("Print Object".to_string()),
-            partnumber: None,
-            pid: None,
-            pindex: None,
-            components: Some(threemf::model::Components {
-                component: component_vec,
-            }),
-        };
-        
-        model.resources.object.push(parent_object);
-        
-        // Add ONLY the parent object to the build
-        model.build.item.push(Item {
-            objectid: object_id,
-            transform: None,
-            partnumber: None,
-        });
     }
-    
-    // Write the model to file
-    let file = File::create(filename)?;
-    threemf::write(&mut BufWriter::new(file), model)?;
-    
-    Ok(())
+
+    // Write the model to memory first, the archive is repacked so it does not depend on the clock
+    let mut archive = Cursor::new(Vec::new());
+    threemf::write(&mut archive, model)?;
+    let extra_files = slicer.map_or(Vec::new(), |slicer| config_files(slicer, &slicer_objects));
+    Ok(normalize_zip(archive.get_ref(), &extra_files)?)
+}
+
+/// Rewrite a zip archive with fixed timestamps and permissions, entries keep their order and contents.
+/// `extra_files` are added at the end as (path, contents).
+fn normalize_zip(bytes: &[u8], extra_files: &[(String, String)]) -> zip::result::ZipResult<Vec<u8>> {
+    let mut source = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let mut target = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .last_modified_time(zip::DateTime::default())
+        .unix_permissions(0o644);
+
+    for index in 0..source.len() {
+        let mut entry = source.by_index(index)?;
+        if entry.is_dir() {
+            continue;
+        }
+        target.start_file(entry.name().to_string(), options)?;
+        io::copy(&mut entry, &mut target)?;
+    }
+    for (path, contents) in extra_files {
+        target.start_file(path.clone(), options)?;
+        target.write_all(contents.as_bytes())?;
+    }
+
+    Ok(target.finish()?.into_inner())
 }
 
 /// Improve printability of image
@@ -276,156 +348,225 @@ pub fn export_to_3mf(objects: &PrintObjects, filename: &str) -> Result<(), Box<d
 
 
 pub fn enforce_min_feature_size(
-    dithered_image: &RgbImage,
-    palette: &ColorPalette,
+    layers: &LayerMap,
+    constraints: &PrintingConstraints,
+    config: &PrintConfig,
+) -> LayerMap {
+    enforce_min_feature_size_with_report(layers, constraints, config).0
+}
+
+/// Same as `enforce_min_feature_size`, but also reports which features were changed and by which rule
+pub fn enforce_min_feature_size_with_report(
+    layers: &LayerMap,
     constraints: &PrintingConstraints,
     config: &PrintConfig,
-) -> RgbImage {
-    let (width, height) = dithered_image.dimensions();
+) -> (LayerMap, PrintabilityReport) {
+    let (width, height) = layers.dimensions();
     let (min_pixels_x, min_pixels_y) = constraints.calculate_min_pixels(config, width, height);
+    let limits = FeatureLimits {
+        min_width: min_pixels_x,
+        min_height: min_pixels_y,
+        min_area: constraints.calculate_min_area_pixels(config, width, height),
+        min_thickness: constraints.calculate_min_width_pixels(config, width, height),
+    };
     
-    let mut result = dithered_image.clone();
+    let mut result = layers.clone();
+    let mut report = PrintabilityReport::new(width, height, config);
     
-    if constraints.erosion_dilation_passes > 0 {
-        result = apply_morphological_operations(&result, palette, constraints.erosion_dilation_passes);
+    if let Some(element) = constraints.structuring_element {
+        let radius = constraints.calculate_morphology_radius(config, width, height);
+        if radius > 0 {
+            let opened = apply_morphological_operations(&result, &element.offsets(radius));
+            report.record_diff(ConstraintRule::Opening, &result, &opened);
+            result = opened;
+        }
     }
     
     if constraints.merge_small_features {
-        result = merge_small_features(&result, palette, min_pixels_x, min_pixels_y);
+        result = merge_small_features(&result, &limits, &mut report);
     }
     
-    result
+    (result, report)
 }
 
-fn apply_morphological_operations(image: &RgbImage, palette: &ColorPalette, passes: u32) -> RgbImage {
-    let mut result = image.clone();
-    for _ in 0..passes {
-        result = morphological_erosion(&result, palette);
-        result = morphological_dilation(&result, palette);
-    }
-    result
+/// Morphological opening (erosion followed by dilation) of the layer counts
+fn apply_morphological_operations(layers: &LayerMap, offsets: &[(i32, i32)]) -> LayerMap {
+    let eroded = morphological_erosion(layers, offsets);
+    morphological_dilation(&eroded, offsets)
+}
+
+fn morphological_erosion(layers: &LayerMap, offsets: &[(i32, i32)]) -> LayerMap {
+    morphological_filter(layers, offsets, u16::min)
 }
 
-fn morphological_erosion(image: &RgbImage, palette: &ColorPalette) -> RgbImage {
-    let (width, height) = image.dimensions();
-    let mut result = image.clone();
+fn morphological_dilation(layers: &LayerMap, offsets: &[(i32, i32)]) -> LayerMap {
+    morphological_filter(layers, offsets, u16::max)
+}
+
+/// Combine every cell with its neighborhood. Cells outside the image or under the mask
+/// are not part of any neighborhood, so borders are processed and masked cells are left alone.
+fn morphological_filter<F>(layers: &LayerMap, offsets: &[(i32, i32)], combine: F) -> LayerMap
+where
+    F: Fn(u16, u16) -> u16,
+{
+    let (width, height) = layers.dimensions();
+    let mut result = layers.clone();
     
-    for y in 1..height-1 {
-        for x in 1..width-1 {
-            let center_layer = palette.get_layer_count_for_color(image.get_pixel(x, y));
-            let mut min_layer = center_layer;
+    for y in 0..height {
+        for x in 0..width {
+            let Some(mut value) = layers.get(x, y) else {
+                continue;
+            };
             
-            for dy in -1i32..=1 {
-                for dx in -1i32..=1 {
-                    let nx = (x as i32 + dx) as u32;
-                    let ny = (y as i32 + dy) as u32;
-                    let neighbor_layer = palette.get_layer_count_for_color(image.get_pixel(nx, ny));
-                    min_layer = min_layer.min(neighbor_layer);
+            for &(dx, dy) in offsets {
+                let nx = x as i32 + dx;
+                let ny = y as i32 + dy;
+                if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+                if let Some(neighbor) = layers.get(nx as u32, ny as u32) {
+                    value = combine(value, neighbor);
                 }
             }
-            
-            if let Some(color) = palette.get_color_for_layer_count(min_layer) {
-                result.put_pixel(x, y, color);
-            }
+            result.set(x, y, value);
         }
     }
     
     result
 }
 
-fn morphological_dilation(image: &RgbImage, palette: &ColorPalette) -> RgbImage {
-    let (width, height) = image.dimensions();
-    let mut result = image.clone();
-    
-    for y in 1..height-1 {
-        for x in 1..width-1 {
-            let center_layer = palette.get_layer_count_for_color(image.get_pixel(x, y));
-            let mut max_layer = center_layer;
-            
-            for dy in -1i32..=1 {
-                for dx in -1i32..=1 {
-                    let nx = (x as i32 + dx) as u32;
-                    let ny = (y as i32 + dy) as u32;
-                    let neighbor_layer = palette.get_layer_count_for_color(image.get_pixel(nx, ny));
-                    max_layer = max_layer.max(neighbor_layer);
-                }
-            }
-            
-            if let Some(color) = palette.get_color_for_layer_count(max_layer) {
-                result.put_pixel(x, y, color);
-            }
+/// Thresholds a feature has to meet, in cells
+struct FeatureLimits {
+    min_width: u32,
+    min_height: u32,
+    min_area: u32,
+    min_thickness: f32,
+}
+
+impl FeatureLimits {
+    /// First rule the feature breaks, `None` when it is large enough
+    fn violated_rule(&self, feature: &Feature) -> Option<ConstraintRule> {
+        if feature.width() < self.min_width || feature.height() < self.min_height {
+            Some(ConstraintRule::MinSize)
+        } else if feature.area < self.min_area {
+            Some(ConstraintRule::MinArea)
+        } else if feature.thickness() < self.min_thickness {
+            Some(ConstraintRule::MinWidth)
+        } else {
+            None
         }
     }
-    
-    result
 }
 
-fn merge_small_features(image: &RgbImage, palette: &ColorPalette, min_width: u32, min_height: u32) -> RgbImage {
-    let (width, height) = image.dimensions();
-    let mut result = image.clone();
-    let mut visited = vec![vec![false; width as usize]; height as usize];
-    
-    for y in 0..height {
-        for x in 0..width {
-            if !visited[y as usize][x as usize] {
-                let pixel = *image.get_pixel(x, y);
-                let (feature_pixels, bounds) = flood_fill_get_feature(image, x, y, &pixel, &mut visited);
-                
-                let feature_width = bounds.2 - bounds.0 + 1;
-                let feature_height = bounds.3 - bounds.1 + 1;
-                
-                if feature_width < min_width || feature_height < min_height {
-                    let replacement_color = get_surrounding_average_color(image, &feature_pixels, palette);
-                    for (fx, fy) in feature_pixels {
-                        result.put_pixel(fx, fy, replacement_color);
-                    }
-                }
-            }
+/// Upper bound on rounds of merging. A round usually settles everything, but trimmed cells can move back and forth.
+const MAX_MERGE_ROUNDS: usize = 16;
+
+fn merge_small_features(layers: &LayerMap, limits: &FeatureLimits, report: &mut PrintabilityReport) -> LayerMap {
+    let mut result = layers.clone();
+
+    // A merge can join two small features or leave a neighbor too small, so relabel and repeat until a round changes nothing
+    for _ in 0..MAX_MERGE_ROUNDS {
+        let mut changed = merge_features_once(&label_features(&result), limits, &mut result, report);
+        changed |= trim_thin_parts(&label_features(&result), limits, &mut result, report);
+        if !changed {
+            break;
         }
     }
-    
+
+    let labels = label_features(&result);
+    report.unresolved_features = labels.features.iter()
+        .filter(|feature| limits.violated_rule(feature).is_some())
+        .count()
+        + thin_parts(&result, &labels, limits).map_or(0, |parts| parts.features.len());
+
     result
 }
 
-fn flood_fill_get_feature(
-    image: &RgbImage,
-    start_x: u32, start_y: u32,
-    target_color: &Rgb<u8>,
-    visited: &mut Vec<Vec<bool>>,
-) -> (Vec<(u32, u32)>, (u32, u32, u32, u32)) {
-    let (width, height) = image.dimensions();
-    let mut pixels = Vec::new();
-    let mut stack = vec![(start_x, start_y)];
-    let mut bounds = (start_x, start_y, start_x, start_y);
-    
-    while let Some((x, y)) = stack.pop() {
-        if x >= width || y >= height || visited[y as usize][x as usize] || 
-           *image.get_pixel(x, y) != *target_color {
+/// Merge every feature that breaks a limit into its surroundings, smallest first.
+/// Surroundings are read from `result`, so a feature sees the merges before it. Returns whether anything changed.
+fn merge_features_once(labels: &FeatureLabels, limits: &FeatureLimits, result: &mut LayerMap, report: &mut PrintabilityReport) -> bool {
+    let cells = labels.cells_by_feature();
+    let mut small: Vec<(usize, ConstraintRule)> = labels.features.iter()
+        .enumerate()
+        .filter_map(|(label, feature)| Some((label, limits.violated_rule(feature)?)))
+        .collect();
+    small.sort_by_key(|&(label, _)| (labels.features[label].area, label));
+
+    let mut changed = false;
+    for (label, rule) in small {
+        let feature = &labels.features[label];
+        let feature_cells = &cells[label];
+        // A smaller neighbor merged into this feature already when the surroundings have its layer count
+        let Some(replacement) = get_surrounding_layer_count(result, labels, label as u32, feature_cells)
+            .filter(|&replacement| replacement != feature.layer_count)
+        else {
             continue;
+        };
+
+        for &(x, y) in feature_cells {
+            result.set(x, y, replacement);
         }
-        
-        visited[y as usize][x as usize] = true;
-        pixels.push((x, y));
-        
-        bounds.0 = bounds.0.min(x);
-        bounds.1 = bounds.1.min(y);
-        bounds.2 = bounds.2.max(x);
-        bounds.3 = bounds.3.max(y);
-        
-        if x > 0 { stack.push((x - 1, y)); }
-        if x < width - 1 { stack.push((x + 1, y)); }
-        if y > 0 { stack.push((x, y - 1)); }
-        if y < height - 1 { stack.push((x, y + 1)); }
+        report.record_feature(rule, ChangeAction::Merged, feature_cells, feature.layer_count, replacement);
+        changed = true;
     }
-    
-    (pixels, bounds)
+
+    changed
 }
 
-fn get_surrounding_average_color(image: &RgbImage, feature_pixels: &[(u32, u32)], palette: &ColorPalette) -> Rgb<u8> {
-    let mut color_counts = HashMap::new();
-    let (width, height) = image.dimensions();
+/// Parts of features that are thick enough elsewhere, like a thin tail on a blob, take the layer count around them.
+/// Returns whether anything changed.
+fn trim_thin_parts(labels: &FeatureLabels, limits: &FeatureLimits, result: &mut LayerMap, report: &mut PrintabilityReport) -> bool {
+    let Some(parts) = thin_parts(result, labels, limits) else {
+        return false;
+    };
+
+    let mut changed = false;
+    for (part, cells) in parts.features.iter().zip(parts.cells_by_feature()) {
+        let (x, y) = cells[0];
+        let Some(replacement) = get_surrounding_layer_count(result, labels, labels.label(x, y), &cells) else {
+            continue;
+        };
+
+        for &(x, y) in &cells {
+            result.set(x, y, replacement);
+        }
+        report.record_feature(ConstraintRule::MinWidth, ChangeAction::Removed, &cells, part.layer_count, replacement);
+        changed = true;
+    }
+
+    changed
+}
+
+/// Connected cells thinner than the limit in features that meet every other limit, `None` without a width limit.
+/// Thin cells of one feature share a layer count, so they label as features of their own.
+fn thin_parts(layers: &LayerMap, labels: &FeatureLabels, limits: &FeatureLimits) -> Option<FeatureLabels> {
+    if limits.min_thickness <= 1.0 {
+        return None;
+    }
+
+    let thin = labels.thin_cells(limits.min_thickness);
+    let mut thin_parts = layers.clone();
+    let (width, height) = layers.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            let label = labels.label(x, y);
+            let too_small = label != NO_FEATURE && limits.violated_rule(&labels.features[label as usize]).is_some();
+            if !thin[layers.index(x, y)] || too_small {
+                thin_parts.set_masked(x, y, true);
+            }
+        }
+    }
+
+    Some(label_features(&thin_parts))
+}
+
+/// Most common layer count around the feature, `None` when it has no unmasked neighbors.
+/// Ties go to the higher layer count so the result does not depend on iteration order.
+fn get_surrounding_layer_count(layers: &LayerMap, labels: &FeatureLabels, label: u32, feature_cells: &[(u32, u32)]) -> Option<u16> {
+    let mut layer_counts: HashMap<u16, u32> = HashMap::new();
+    let (width, height) = layers.dimensions();
     
-    for &(x, y) in feature_pixels {
+    for &(x, y) in feature_cells {
         for dy in -1i32..=1 {
             for dx in -1i32..=1 {
                 if dx == 0 && dy == 0 { continue; }
@@ -434,17 +575,16 @@ fn get_surrounding_average_color(image: &RgbImage, feature_pixels: &[(u32, u32)]
                 let ny = y as i32 + dy;
                 
                 if nx >= 0 && nx < width as i32 && ny >= 0 && ny < height as i32 {
-                    let neighbor_pixel = *image.get_pixel(nx as u32, ny as u32);
-                    if !feature_pixels.contains(&(nx as u32, ny as u32)) {
-                        *color_counts.entry(neighbor_pixel).or_insert(0) += 1;
+                    let neighbor_label = labels.label(nx as u32, ny as u32);
+                    if neighbor_label != label && neighbor_label != NO_FEATURE {
+                        *layer_counts.entry(layers.layer(nx as u32, ny as u32)).or_insert(0) += 1;
                     }
                 }
             }
         }
     }
     
-    color_counts.into_iter()
-        .max_by_key(|(_, count)| *count)
-        .map(|(color, _)| color)
-        .unwrap_or(palette.colors[0])
-}
\ No newline at end of file
+    layer_counts.into_iter()
+        .max_by_key(|&(layer_count, count)| (count, layer_count))
+        .map(|(layer_count, _)| layer_count)
+}
@@ -0,0 +1,236 @@
+use std::{collections::BTreeMap, fmt};
+
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::PrintConfig, grayscale::layer_map::LayerMap};
+
+/// Printability rule that changed a group of cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConstraintRule {
+    /// Morphological opening with the structuring element
+    Opening,
+    /// Bounding box smaller than `min_feature_size_mm`
+    MinSize,
+    /// Fewer cells than `min_feature_area_mm2`
+    MinArea,
+    /// Thinner than `min_feature_width_mm`
+    MinWidth,
+    /// Height step, height band or total height limits
+    Height,
+    /// Small features merged to fit the mesh budget
+    MeshBudget,
+}
+
+impl ConstraintRule {
+    /// Highlight color in the overlay image
+    pub fn color(&self) -> Rgb<u8> {
+        match self {
+            ConstraintRule::Opening => Rgb([230, 40, 40]),
+            ConstraintRule::MinSize => Rgb([255, 140, 0]),
+            ConstraintRule::MinArea => Rgb([240, 220, 0]),
+            ConstraintRule::MinWidth => Rgb([220, 40, 220]),
+            ConstraintRule::Height => Rgb([0, 200, 230]),
+            ConstraintRule::MeshBudget => Rgb([60, 220, 90]),
+        }
+    }
+}
+
+impl fmt::Display for ConstraintRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ConstraintRule::Opening => "opening",
+            ConstraintRule::MinSize => "minimum size",
+            ConstraintRule::MinArea => "minimum area",
+            ConstraintRule::MinWidth => "minimum width",
+            ConstraintRule::Height => "height limits",
+            ConstraintRule::MeshBudget => "mesh budget",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeAction {
+    /// Cells were trimmed away and took the layer count of their surroundings
+    Removed,
+    /// A whole feature was merged into its most common neighbor
+    Merged,
+}
+
+/// One group of connected cells that changed from one layer count to another
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureChange {
+    pub rule: ConstraintRule,
+    pub action: ChangeAction,
+    /// Inclusive bounding box in pixels as (min_x, min_y, max_x, max_y)
+    pub bounds: (u32, u32, u32, u32),
+    /// Size of the bounding box in mm
+    pub size_mm: (f32, f32),
+    /// Number of changed cells
+    pub area: u32,
+    pub old_layer_count: u16,
+    pub new_layer_count: u16,
+}
+
+/// Number of features and cells changed by one rule
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleTotals {
+    pub features: usize,
+    pub cells: usize,
+}
+
+/// Everything the printability passes changed, so palette problems can be told apart from constraint problems.
+/// Only serialized, the per-cell rules the overlay is drawn from are not part of the output.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PrintabilityReport {
+    pub width: u32,
+    pub height: u32,
+    pixel_size: (f32, f32),
+    pub changes: Vec<FeatureChange>,
+    pub totals: BTreeMap<ConstraintRule, RuleTotals>,
+    /// Features and thin parts that still break a limit after merging, they have nothing unmasked around them to merge into
+    pub unresolved_features: usize,
+    /// Rule that last changed every cell, in row-major order
+    #[serde(skip)]
+    cell_rules: Vec<Option<ConstraintRule>>,
+}
+
+impl PrintabilityReport {
+    pub fn new(width: u32, height: u32, config: &PrintConfig) -> Self {
+        Self {
+            width,
+            height,
+            pixel_size: config.pixel_size(width, height),
+            changes: Vec::new(),
+            totals: BTreeMap::new(),
+            unresolved_features: 0,
+            cell_rules: vec![None; (width * height) as usize],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.unresolved_features == 0
+    }
+
+    pub fn changed_cells(&self) -> usize {
+        self.cell_rules.iter().filter(|rule| rule.is_some()).count()
+    }
+
+    /// Record one feature whose cells all changed to `new_layer_count`
+    pub fn record_feature(
+        &mut self,
+        rule: ConstraintRule,
+        action: ChangeAction,
+        cells: &[(u32, u32)],
+        old_layer_count: u16,
+        new_layer_count: u16,
+    ) {
+        if cells.is_empty() || old_layer_count == new_layer_count {
+            return;
+        }
+
+        let mut bounds = (u32::MAX, u32::MAX, 0, 0);
+        for &(x, y) in cells {
+            bounds.0 = bounds.0.min(x);
+            bounds.1 = bounds.1.min(y);
+            bounds.2 = bounds.2.max(x);
+            bounds.3 = bounds.3.max(y);
+            self.cell_rules[(y * self.width + x) as usize] = Some(rule);
+        }
+
+        let size_mm = (
+            (bounds.2 - bounds.0 + 1) as f32 * self.pixel_size.0,
+            (bounds.3 - bounds.1 + 1) as f32 * self.pixel_size.1,
+        );
+        self.changes.push(FeatureChange {
+            rule,
+            action,
+            bounds,
+            size_mm,
+            area: cells.len() as u32,
+            old_layer_count,
+            new_layer_count,
+        });
+
+        let totals = self.totals.entry(rule).or_default();
+        totals.features += 1;
+        totals.cells += cells.len();
+    }
+
+    /// Record every difference between two maps as removed features, grouping 4-connected cells
+    /// that went from the same old to the same new layer count
+    pub fn record_diff(&mut self, rule: ConstraintRule, before: &LayerMap, after: &LayerMap) {
+        let (width, height) = before.dimensions();
+        let change_at = |x: u32, y: u32| {
+            let (old, new) = (before.get(x, y)?, after.get(x, y)?);
+            (old != new).then_some((old, new))
+        };
+
+        let mut visited = vec![false; (width * height) as usize];
+        for y in 0..height {
+            for x in 0..width {
+                let Some(change) = change_at(x, y) else {
+                    continue;
+                };
+                if visited[before.index(x, y)] {
+                    continue;
+                }
+
+                // Flood fill the group
+                let mut cells = Vec::new();
+                let mut stack = vec![(x, y)];
+                visited[before.index(x, y)] = true;
+                while let Some((cx, cy)) = stack.pop() {
+                    cells.push((cx, cy));
+                    let neighbors = [
+                        (cx.wrapping_sub(1), cy),
+                        (cx + 1, cy),
+                        (cx, cy.wrapping_sub(1)),
+                        (cx, cy + 1),
+                    ];
+                    for (nx, ny) in neighbors {
+                        if nx >= width || ny >= height || visited[before.index(nx, ny)] {
+                            continue;
+                        }
+                        if change_at(nx, ny) == Some(change) {
+                            visited[before.index(nx, ny)] = true;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+                cells.sort_by_key(|&(cx, cy)| (cy, cx));
+
+                self.record_feature(rule, ChangeAction::Removed, &cells, change.0, change.1);
+            }
+        }
+    }
+
+    /// Dim the prediction and paint every changed cell in the color of its rule
+    pub fn render_overlay(&self, prediction: &RgbImage) -> RgbImage {
+        let mut overlay = prediction.clone();
+        for (x, y, pixel) in overlay.enumerate_pixels_mut() {
+            if x >= self.width || y >= self.height {
+                continue;
+            }
+            *pixel = match self.cell_rules[(y * self.width + x) as usize] {
+                Some(rule) => rule.color(),
+                None => Rgb(pixel.0.map(|channel| (channel as u16 * 2 / 5 + 40) as u8)),
+            };
+        }
+        overlay
+    }
+
+    /// Short human readable summary, one line per rule
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.totals.iter()
+            .map(|(rule, totals)| format!("{}: {} features, {} pixels changed", rule, totals.features, totals.cells))
+            .collect();
+        if self.unresolved_features > 0 {
+            lines.push(format!("{} features still break a limit, nothing around them could take them", self.unresolved_features));
+        }
+        lines
+    }
+}
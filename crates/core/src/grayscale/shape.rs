@@ -0,0 +1,472 @@
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+use image::{imageops, GrayImage, Luma, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::is_masked;
+
+/// Segments of a full circle, and of every curve of a path
+const CIRCLE_SEGMENTS: usize = 96;
+const CURVE_SEGMENTS: usize = 16;
+
+/// Closed outline, the last point connects back to the first
+type Ring = Vec<[f64; 2]>;
+
+/// Outline of the base
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShapeKind {
+    /// Rectangle of the image with rounded corners
+    #[default]
+    RoundedRectangle,
+    Circle,
+    /// Ellipse with the proportions of the image
+    Ellipse,
+    /// Regular hexagon with flat top and bottom sides
+    Hexagon,
+    Heart,
+    /// Outline from SVG path data, with the even-odd fill rule
+    Path,
+}
+
+impl fmt::Display for ShapeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShapeKind::RoundedRectangle => write!(f, "rounded_rectangle"),
+            ShapeKind::Circle => write!(f, "circle"),
+            ShapeKind::Ellipse => write!(f, "ellipse"),
+            ShapeKind::Hexagon => write!(f, "hexagon"),
+            ShapeKind::Heart => write!(f, "heart"),
+            ShapeKind::Path => write!(f, "path"),
+        }
+    }
+}
+
+impl FromStr for ShapeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "rounded_rectangle" | "rounded" => Ok(ShapeKind::RoundedRectangle),
+            "circle" => Ok(ShapeKind::Circle),
+            "ellipse" | "oval" => Ok(ShapeKind::Ellipse),
+            "hexagon" => Ok(ShapeKind::Hexagon),
+            "heart" => Ok(ShapeKind::Heart),
+            "path" | "svg" => Ok(ShapeKind::Path),
+            _ => Err(format!("Unknown shape '{}', expected rounded_rectangle, circle, ellipse, hexagon, heart or path", s)),
+        }
+    }
+}
+
+/// How the image and the shape are fitted to each other
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShapeFit {
+    /// The image is cropped to the proportions of the shape around the focus, the shape is filled with image
+    #[default]
+    Cover,
+    /// The shape grows until the whole image fits inside it, the rest of the shape is plain base
+    Contain,
+}
+
+impl fmt::Display for ShapeFit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShapeFit::Cover => write!(f, "cover"),
+            ShapeFit::Contain => write!(f, "contain"),
+        }
+    }
+}
+
+impl FromStr for ShapeFit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cover" => Ok(ShapeFit::Cover),
+            "contain" => Ok(ShapeFit::Contain),
+            _ => Err(format!("Unknown shape fit '{}', expected cover or contain", s)),
+        }
+    }
+}
+
+/// Outline of the base instead of the image rectangle, turned into a mask like the alpha channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BaseShape {
+    pub kind: ShapeKind,
+    #[serde(default)]
+    pub fit: ShapeFit,
+    /// Point of the image kept in view by `Cover`, from (0, 0) top left to (1, 1) bottom right
+    #[serde(default = "default_focus")]
+    pub focus: [f32; 2],
+    /// Corner radius of the rounded rectangle as a fraction of its shorter side
+    #[serde(default = "default_corner_radius")]
+    pub corner_radius: f32,
+    /// SVG path data of the `Path` outline
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+fn default_focus() -> [f32; 2] {
+    [0.5, 0.5]
+}
+
+fn default_corner_radius() -> f32 {
+    0.15
+}
+
+impl BaseShape {
+    pub fn new(kind: ShapeKind) -> Self {
+        Self { kind, fit: ShapeFit::default(), focus: default_focus(), corner_radius: default_corner_radius(), path: None }
+    }
+
+    /// Rings of the outline scaled into the box from (0, 0) to (width / height, 1), y pointing down like the image.
+    /// `aspect` is width / height of the image, the rounded rectangle and the ellipse take it on.
+    fn rings(&self, aspect: f64) -> Result<Vec<Ring>, String> {
+        let rings = match self.kind {
+            ShapeKind::RoundedRectangle => {
+                let radius = (self.corner_radius.clamp(0.0, 0.5) as f64) * aspect.min(1.0);
+                let corners = [(aspect - radius, 1.0 - radius, 0.0), (radius, 1.0 - radius, 0.5), (radius, radius, 1.0), (aspect - radius, radius, 1.5)];
+                let steps = CIRCLE_SEGMENTS / 4;
+                vec![corners.iter()
+                    .flat_map(|&(cx, cy, start)| (0..=steps).map(move |step| {
+                        let angle = PI * (start + 0.5 * step as f64 / steps as f64);
+                        [cx + radius * angle.cos(), cy + radius * angle.sin()]
+                    }))
+                    .collect()]
+            }
+            ShapeKind::Circle | ShapeKind::Ellipse => {
+                let stretch = if self.kind == ShapeKind::Ellipse { aspect } else { 1.0 };
+                vec![(0..CIRCLE_SEGMENTS)
+                    .map(|step| {
+                        let angle = 2.0 * PI * step as f64 / CIRCLE_SEGMENTS as f64;
+                        [stretch * angle.cos(), angle.sin()]
+                    })
+                    .collect()]
+            }
+            ShapeKind::Hexagon => vec![(0..6).map(|k| [(k as f64 * PI / 3.0).cos(), (k as f64 * PI / 3.0).sin()]).collect()],
+            ShapeKind::Heart => vec![(0..CIRCLE_SEGMENTS)
+                .map(|step| {
+                    let t = 2.0 * PI * step as f64 / CIRCLE_SEGMENTS as f64;
+                    let y = 13.0 * t.cos() - 5.0 * (2.0 * t).cos() - 2.0 * (3.0 * t).cos() - (4.0 * t).cos();
+                    [16.0 * t.sin().powi(3), -y]
+                })
+                .collect()],
+            ShapeKind::Path => parse_svg_path(self.path.as_deref().ok_or("The path shape needs SVG path data")?)?,
+        };
+        normalize(rings)
+    }
+}
+
+/// Crop or pad the image to the shape and mask everything outside of it. Padding around the image is
+/// filled with `fill`, best the palette color of the bare base. Pixels the alpha `mask` takes out stay out.
+pub fn apply_shape(image: &RgbImage, mask: Option<&GrayImage>, shape: &BaseShape, fill: Rgb<u8>) -> Result<(RgbImage, GrayImage), String> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err("The image is empty".to_string());
+    }
+    let alpha = GrayImage::from_fn(width, height, |x, y| Luma([if is_masked(mask, x, y) { 0 } else { 255 }]));
+    let rings = shape.rings(width as f64 / height as f64)?;
+    let shape_aspect = rings_aspect(&rings);
+
+    let (image, alpha) = match shape.fit {
+        ShapeFit::Cover => {
+            let (crop_width, crop_height) = if width as f64 / height as f64 > shape_aspect {
+                (((height as f64 * shape_aspect).round() as u32).clamp(1, width), height)
+            } else {
+                (width, ((width as f64 / shape_aspect).round() as u32).clamp(1, height))
+            };
+            let left = crop_start(width, crop_width, shape.focus[0]);
+            let top = crop_start(height, crop_height, shape.focus[1]);
+            (
+                imageops::crop_imm(image, left, top, crop_width, crop_height).to_image(),
+                imageops::crop_imm(&alpha, left, top, crop_width, crop_height).to_image(),
+            )
+        }
+        ShapeFit::Contain => {
+            // Smallest shape height whose outline holds the image rectangle centered in it
+            let fits = |shape_height: f64| {
+                let (box_width, box_height) = (shape_height * shape_aspect, shape_height);
+                let (left, top) = ((box_width - width as f64) / 2.0, (box_height - height as f64) / 2.0);
+                let samples = 64;
+                (0..samples).all(|step| {
+                    let t = step as f64 / samples as f64;
+                    let edges = [[t, 0.0], [1.0, t], [1.0 - t, 1.0], [0.0, 1.0 - t]];
+                    edges.iter().all(|&[u, v]| {
+                        contains(&rings, [(left + u * width as f64) / shape_height, (top + v * height as f64) / shape_height])
+                    })
+                })
+            };
+            let mut low = (width as f64 / shape_aspect).max(height as f64);
+            let Some(mut high) = (0..=6).map(|doubling| low * (1 << doubling) as f64).find(|&size| fits(size)) else {
+                return Err(format!("The image does not fit inside the {} shape", shape.kind));
+            };
+            for _ in 0..24 {
+                let middle = (low + high) / 2.0;
+                if fits(middle) { high = middle } else { low = middle }
+            }
+
+            let (canvas_width, canvas_height) = ((high * shape_aspect).ceil() as u32, high.ceil() as u32);
+            let (left, top) = ((canvas_width - width) / 2, (canvas_height - height) / 2);
+            let mut canvas = RgbImage::from_pixel(canvas_width, canvas_height, fill);
+            imageops::replace(&mut canvas, image, left as i64, top as i64);
+            let mut canvas_alpha = GrayImage::from_pixel(canvas_width, canvas_height, Luma([255]));
+            imageops::replace(&mut canvas_alpha, &alpha, left as i64, top as i64);
+            (canvas, canvas_alpha)
+        }
+    };
+
+    let (width, height) = image.dimensions();
+    let inside = rasterize(&rings, width, height);
+    let mask = GrayImage::from_fn(width, height, |x, y| {
+        let keep = inside[(y * width + x) as usize] && alpha.get_pixel(x, y)[0] == 255;
+        Luma([if keep { 255 } else { 0 }])
+    });
+    Ok((image, mask))
+}
+
+/// Start of the crop window that puts `focus` as close to its middle as the image allows
+fn crop_start(size: u32, crop: u32, focus: f32) -> u32 {
+    let center = focus.clamp(0.0, 1.0) as f64 * size as f64;
+    (center - crop as f64 / 2.0).round().clamp(0.0, (size - crop) as f64) as u32
+}
+
+/// Move and scale the rings into the box from (0, 0) to (aspect, 1)
+fn normalize(rings: Vec<Ring>) -> Result<Vec<Ring>, String> {
+    let points = || rings.iter().flatten();
+    let min = [0, 1].map(|axis| points().map(|point| point[axis]).fold(f64::INFINITY, f64::min));
+    let max = [0, 1].map(|axis| points().map(|point| point[axis]).fold(f64::NEG_INFINITY, f64::max));
+    let height = max[1] - min[1];
+    if !(height > 0.0 && max[0] > min[0]) {
+        return Err("The shape outline has no area".to_string());
+    }
+    Ok(rings.iter()
+        .map(|ring| ring.iter().map(|point| [(point[0] - min[0]) / height, (point[1] - min[1]) / height]).collect())
+        .collect())
+}
+
+fn rings_aspect(rings: &[Ring]) -> f64 {
+    rings.iter().flatten().map(|point| point[0]).fold(0.0, f64::max)
+}
+
+/// Even-odd test of a point against all rings
+fn contains(rings: &[Ring], point: [f64; 2]) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        for i in 0..ring.len() {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            if (a[1] > point[1]) != (b[1] > point[1]) {
+                let x = a[0] + (point[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0]);
+                if x > point[0] {
+                    inside = !inside;
+                }
+            }
+        }
+    }
+    inside
+}
+
+/// Pixels whose center lies inside the rings scaled to fill the image, scanline by scanline
+fn rasterize(rings: &[Ring], width: u32, height: u32) -> Vec<bool> {
+    let scale = height as f64;
+    let mut inside = vec![false; (width * height) as usize];
+    let mut crossings = Vec::new();
+    for y in 0..height {
+        let center = (y as f64 + 0.5) / scale;
+        crossings.clear();
+        for ring in rings {
+            for i in 0..ring.len() {
+                let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+                if (a[1] > center) != (b[1] > center) {
+                    crossings.push((a[0] + (center - a[1]) / (b[1] - a[1]) * (b[0] - a[0])) * scale);
+                }
+            }
+        }
+        crossings.sort_by(f64::total_cmp);
+        for pair in crossings.chunks_exact(2) {
+            let first = (pair[0] - 0.5).ceil().max(0.0) as u32;
+            let last = ((pair[1] - 0.5).ceil().max(0.0) as u32).min(width);
+            for x in first..last {
+                inside[(y * width + x) as usize] = true;
+            }
+        }
+    }
+    inside
+}
+
+/// Rings of SVG path data with the commands M, L, H, V, C, S, Q, T and Z in absolute and relative form.
+/// Curves are flattened, every subpath is closed.
+pub fn parse_svg_path(data: &str) -> Result<Vec<Ring>, String> {
+    let tokens = tokenize_path(data)?;
+    let mut rings: Vec<Ring> = Vec::new();
+    let mut ring: Ring = Vec::new();
+    let (mut current, mut start) = ([0.0, 0.0], [0.0, 0.0]);
+    // Control point of the last curve, for the smooth variants
+    let mut last_control: Option<(char, [f64; 2])> = None;
+    let mut index = 0;
+    let mut command = ' ';
+
+    let number = |index: &mut usize| -> Result<f64, String> {
+        match tokens.get(*index) {
+            Some(PathToken::Number(value)) => {
+                *index += 1;
+                Ok(*value)
+            }
+            _ => Err("SVG path is missing a number".to_string()),
+        }
+    };
+
+    while index < tokens.len() {
+        if let PathToken::Command(next) = tokens[index] {
+            command = next;
+            index += 1;
+        } else if command == ' ' {
+            return Err("SVG path has to start with a command".to_string());
+        }
+
+        let relative = command.is_ascii_lowercase();
+        let offset = if relative { current } else { [0.0, 0.0] };
+        let point = |index: &mut usize| -> Result<[f64; 2], String> {
+            Ok([offset[0] + number(index)?, offset[1] + number(index)?])
+        };
+
+        match command.to_ascii_uppercase() {
+            'M' => {
+                if ring.len() > 2 {
+                    rings.push(std::mem::take(&mut ring));
+                }
+                ring.clear();
+                current = point(&mut index)?;
+                start = current;
+                ring.push(current);
+                // Further pairs after a move are lines
+                command = if relative { 'l' } else { 'L' };
+                last_control = None;
+            }
+            'L' => {
+                current = point(&mut index)?;
+                ring.push(current);
+                last_control = None;
+            }
+            'H' => {
+                current = [offset[0] + number(&mut index)?, current[1]];
+                ring.push(current);
+                last_control = None;
+            }
+            'V' => {
+                current = [current[0], offset[1] + number(&mut index)?];
+                ring.push(current);
+                last_control = None;
+            }
+            'C' | 'S' => {
+                let first = if command.eq_ignore_ascii_case(&'C') {
+                    point(&mut index)?
+                } else {
+                    reflect(current, last_control, 'C')
+                };
+                let second = point(&mut index)?;
+                let end = point(&mut index)?;
+                flatten(&mut ring, |t| {
+                    let u = 1.0 - t;
+                    [0, 1].map(|axis| u * u * u * current[axis] + 3.0 * u * u * t * first[axis] + 3.0 * u * t * t * second[axis] + t * t * t * end[axis])
+                });
+                last_control = Some(('C', second));
+                current = end;
+            }
+            'Q' | 'T' => {
+                let control = if command.eq_ignore_ascii_case(&'Q') {
+                    point(&mut index)?
+                } else {
+                    reflect(current, last_control, 'Q')
+                };
+                let end = point(&mut index)?;
+                flatten(&mut ring, |t| {
+                    let u = 1.0 - t;
+                    [0, 1].map(|axis| u * u * current[axis] + 2.0 * u * t * control[axis] + t * t * end[axis])
+                });
+                last_control = Some(('Q', control));
+                current = end;
+            }
+            'Z' => {
+                if ring.len() > 2 {
+                    rings.push(std::mem::take(&mut ring));
+                }
+                ring.clear();
+                current = start;
+                last_control = None;
+                // A number right after Z has no command to belong to
+                if matches!(tokens.get(index), Some(PathToken::Number(_))) {
+                    return Err("SVG path has numbers after Z".to_string());
+                }
+            }
+            other => return Err(format!("SVG path command '{}' is not supported", other)),
+        }
+    }
+    if ring.len() > 2 {
+        rings.push(ring);
+    }
+
+    if rings.is_empty() {
+        return Err("SVG path has no closed outline".to_string());
+    }
+    Ok(rings)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PathToken {
+    Command(char),
+    Number(f64),
+}
+
+fn tokenize_path(data: &str) -> Result<Vec<PathToken>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = data.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() || c == ',' {
+            i += 1;
+        } else if c.is_ascii_alphabetic() && !matches!(c, 'e' | 'E') {
+            tokens.push(PathToken::Command(c));
+            i += 1;
+        } else {
+            // Number with optional sign, fraction and exponent, "1.5.5" is two numbers
+            let begin = i;
+            let mut seen_dot = false;
+            if matches!(c, '+' | '-') {
+                i += 1;
+            }
+            while i < chars.len() && (chars[i].is_ascii_digit() || (chars[i] == '.' && !seen_dot)) {
+                seen_dot |= chars[i] == '.';
+                i += 1;
+            }
+            if i < chars.len() && matches!(chars[i], 'e' | 'E') {
+                i += 1;
+                if i < chars.len() && matches!(chars[i], '+' | '-') {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let text: String = chars[begin..i].iter().collect();
+            let value = text.parse::<f64>().map_err(|_| format!("SVG path has an invalid number '{}'", text))?;
+            tokens.push(PathToken::Number(value));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Control point mirrored through the current point, or the current point when the last curve was of another kind
+fn reflect(current: [f64; 2], last_control: Option<(char, [f64; 2])>, kind: char) -> [f64; 2] {
+    match last_control {
+        Some((last, control)) if last == kind => [2.0 * current[0] - control[0], 2.0 * current[1] - control[1]],
+        _ => current,
+    }
+}
+
+fn flatten<F: Fn(f64) -> [f64; 2]>(ring: &mut Ring, curve: F) {
+    for step in 1..=CURVE_SEGMENTS {
+        ring.push(curve(step as f64 / CURVE_SEGMENTS as f64));
+    }
+}
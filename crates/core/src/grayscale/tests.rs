@@ -1,7 +1,17 @@
 
 #[cfg(test)]
 mod tests {
-    use crate::grayscale::ColorPalette;
+    use crate::config::{load_project_overrides, BacklitBase, EffectiveSettings, HeightBand, Lighting, PrintConfig, PrintingConstraints, SettingsOverrides, SettingsSource, SlicerFlavor, StructuringElement};
+    use crate::grayscale::generate::{back_ids, expected_bounds, generate_image, ignored_options, generate_image_within_budget, validate_objects, BudgetTarget, CapMaterial, GenerateOptions, MeshBackend, MeshBudget, TopCap};
+    use crate::grayscale::{export_to_3mf_with_recipe, normalize_zip, write_3mf, write_3mf_groups, PRINT_OBJECT_NAME};
+    use crate::mesh::{frame::{generate_frame, Frame, FrameMaterial, FrameProfile}, generate_box, mount::{Mount, MountKind}, packing::{pack_objects, pack_rectangles}, placement::{place_objects, PadSettings, PadStyle, Placement}, text::{Label, TextBitmap}, rectangles::decompose_mask, validate::{validate_mesh, Bounds, MeshIssue}};
+    use crate::export::{gltf::write_glb, material_parts, obj::write_obj, parse_display_color, part_path, ply::write_ply, slicer::{assign_extruders, SlicerSettings}, stl::{export_parts_to_stl, write_stl, StlFormat}};
+    use crate::recipe::RecipeHasher;
+    use crate::utils::{MaterialMesh, PrintObjects};
+    use crate::grayscale::{calibration::{generate_backlit_calibration_objects, generate_calibration_objects}, enforce_min_feature_size, enforce_min_feature_size_with_report, features::label_features, height::enforce_height_constraints, layer_map::LayerMap, report::{ChangeAction, ConstraintRule, PrintabilityReport}, shape::{apply_shape, parse_svg_path, BaseShape, ShapeFit, ShapeKind}, tiling::{split_into_tiles, TileJoint, Tiling}, ColorPalette};
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+    use std::collections::HashMap;
+    use threemf::Mesh;
     use std::fs;
     use tempfile::NamedTempFile;
 
@@ -33,4 +43,1625 @@ mod tests {
         let loaded_palette = ColorPalette::load_from_file(path).unwrap();
         assert_eq!(palette, loaded_palette);
     }
+
+    #[test]
+    fn test_palette_without_lighting_is_reflective() {
+        let palette: ColorPalette = toml::from_str("colors = [[0, 0, 0], [255, 255, 255]]\nlayer_counts = [0, 1]").unwrap();
+        assert_eq!(palette.lighting, Lighting::Reflective);
+    }
+
+    #[test]
+    fn test_backlit_calibration_without_base_skips_zero_layers() {
+        let config = PrintConfig { backlit_base: BacklitBase::None, max_layers: 3.0, ..PrintConfig::default() };
+        let objects = generate_backlit_calibration_objects(&config, 10.0);
+
+        // Three squares with 1..=3 layers, 12 triangles per box, and nothing black
+        assert!(objects.black_mesh.triangles.triangle.is_empty());
+        assert_eq!(objects.white_mesh.triangles.triangle.len(), 3 * 12);
+        let max_z = objects.white_mesh.vertices.vertex.iter().map(|v| v.z).fold(0.0, f64::max);
+        assert!((max_z - 3.0 * config.layer_thickness as f64).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_backlit_diffuser_has_its_own_thickness() {
+        let config = PrintConfig { base_thickness: 2.0, diffuser_thickness: 0.4, ..PrintConfig::default() };
+        let (black, white) = config.calculate_backlit_stack(3);
+        assert_eq!(black, 0.0);
+        assert!((white - (0.4 + 3.0 * config.layer_thickness)).abs() < 1e-6);
+
+        assert!("black".parse::<BacklitBase>().is_err());
+        assert_eq!("none".parse::<BacklitBase>(), Ok(BacklitBase::None));
+        assert_eq!("backlit".parse::<Lighting>(), Ok(Lighting::Backlit));
+    }
+
+    #[test]
+    fn test_opening_processes_border_and_respects_mask() {
+        // 10 px over 10 mm, so a 3 mm minimum feature gives a radius 1 element
+        let config = PrintConfig { image_size_mm: 10.0, ..PrintConfig::default() };
+        let constraints = PrintingConstraints {
+            min_feature_size_mm: 3.0,
+            merge_small_features: false,
+            structuring_element: Some(StructuringElement::Cross),
+            ..PrintingConstraints::default()
+        };
+
+        // Lone white cell in the corner, used to survive because the border was skipped
+        let mut layers = LayerMap::new(10, 10, 0);
+        layers.set(0, 0, 1);
+        let result = enforce_min_feature_size(&layers, &constraints, &config);
+        assert_eq!(result.get(0, 0), Some(0));
+
+        // White block next to a transparent column keeps its edge
+        let mut layers = LayerMap::new(10, 10, 1);
+        for y in 0..10 {
+            layers.set(0, y, 0);
+            layers.set_masked(0, y, true);
+        }
+        let result = enforce_min_feature_size(&layers, &constraints, &config);
+        assert_eq!(result.get(1, 5), Some(1));
+        assert_eq!(result.get(0, 5), None);
+    }
+
+    #[test]
+    fn test_layer_map_rejects_colors_outside_palette() {
+        let palette = ColorPalette::fake(1);
+        let mut image = RgbImage::from_pixel(4, 4, palette.colors[1]);
+        let layers = LayerMap::from_image(&image, &palette, None).unwrap();
+        assert_eq!(layers.to_image(&palette).unwrap(), image);
+
+        image.put_pixel(2, 3, Rgb([12, 34, 56]));
+        let error = LayerMap::from_image(&image, &palette, None).unwrap_err();
+        assert!(error.to_string().contains("(2, 3)"));
+    }
+
+    #[test]
+    fn test_thin_diagonal_line_is_merged() {
+        // 20 px over 20 mm, bounding box and area checks alone would let the line through
+        let config = PrintConfig { image_size_mm: 20.0, ..PrintConfig::default() };
+        let constraints = PrintingConstraints {
+            min_feature_size_mm: 2.0,
+            min_feature_area_mm2: 0.0,
+            min_feature_width_mm: 2.0,
+            merge_small_features: true,
+            structuring_element: None,
+            ..PrintingConstraints::default()
+        };
+
+        let mut layers = LayerMap::new(20, 20, 0);
+        for i in 0..20 {
+            layers.set(i, i, 3);
+            if i + 1 < 20 {
+                layers.set(i + 1, i, 3);
+            }
+        }
+        // A solid 5x5 block stays
+        for y in 0..5 {
+            for x in 12..17 {
+                layers.set(x, y, 5);
+            }
+        }
+
+        let labels = label_features(&layers);
+        let line = &labels.features[labels.label(0, 0) as usize];
+        assert_eq!(line.area, 39);
+        assert_eq!((line.width(), line.height()), (20, 20));
+        assert!(line.thickness() < 2.0);
+        let block = &labels.features[labels.label(14, 2) as usize];
+        assert_eq!(block.thickness(), 5.0);
+
+        let result = enforce_min_feature_size(&layers, &constraints, &config);
+        assert_eq!(result.get(10, 10), Some(0));
+        assert_eq!(result.get(14, 2), Some(5));
+    }
+
+    #[test]
+    fn test_thin_tail_of_a_wide_feature_is_trimmed() {
+        // 1 mm pixels, a 5x5 blob is wide enough but the tail it shares the layer count with is not
+        let config = PrintConfig { image_size_mm: 20.0, ..PrintConfig::default() };
+        let constraints = PrintingConstraints {
+            min_feature_size_mm: 2.0,
+            min_feature_area_mm2: 0.0,
+            min_feature_width_mm: 2.0,
+            merge_small_features: true,
+            structuring_element: None,
+            ..PrintingConstraints::default()
+        };
+
+        let mut layers = LayerMap::new(20, 20, 0);
+        for y in 2..7 {
+            for x in 2..7 {
+                layers.set(x, y, 3);
+            }
+        }
+        for x in 7..17 {
+            layers.set(x, 4, 3);
+        }
+        let labels = label_features(&layers);
+        assert_eq!(labels.features.len(), 2);
+        assert_eq!(labels.features[labels.label(4, 4) as usize].thickness(), 5.0, "the blob is as thick as it is wide");
+
+        let (result, report) = enforce_min_feature_size_with_report(&layers, &constraints, &config);
+        assert!((2..7).all(|x| (2..7).all(|y| result.get(x, y) == Some(3))), "the blob is kept");
+        assert!((8..17).all(|x| result.get(x, 4) == Some(0)), "the tail is trimmed");
+        assert!(report.changes.iter().any(|change| change.rule == ConstraintRule::MinWidth && change.action == ChangeAction::Removed));
+    }
+
+    #[test]
+    fn test_adjacent_small_features_merge_instead_of_swapping() {
+        // 1 mm pixels, two 1x3 features surrounded by the mask only touch each other
+        let config = PrintConfig { image_size_mm: 6.0, ..PrintConfig::default() };
+        let constraints = PrintingConstraints {
+            min_feature_size_mm: 1.0,
+            min_feature_area_mm2: 10.0,
+            min_feature_width_mm: 0.0,
+            merge_small_features: true,
+            structuring_element: None,
+            ..PrintingConstraints::default()
+        };
+
+        let mut layers = LayerMap::new(6, 6, 0);
+        for y in 0..6 {
+            for x in 0..6 {
+                layers.set_masked(x, y, true);
+            }
+        }
+        for y in 1..4 {
+            layers.set(2, y, 3);
+            layers.set(3, y, 5);
+            layers.set_masked(2, y, false);
+            layers.set_masked(3, y, false);
+        }
+
+        let (result, report) = enforce_min_feature_size_with_report(&layers, &constraints, &config);
+        let merged: Vec<u16> = (1..4).flat_map(|y| [result.layer(2, y), result.layer(3, y)]).collect();
+        assert!(merged.iter().all(|&layer_count| layer_count == merged[0]), "one feature took the other's layer count: {:?}", merged);
+        assert_eq!(report.changes.len(), 1, "only the merge that happened is reported");
+        assert_eq!(report.unresolved_features, 1, "the joined feature is still too small with nothing around it");
+    }
+
+    #[test]
+    fn test_even_widths_are_measured_exactly() {
+        let mut layers = LayerMap::new(20, 20, 0);
+        for y in 0..20 {
+            for x in 2..6 {
+                layers.set(x, y, 3);
+            }
+        }
+        let labels = label_features(&layers);
+        assert_eq!(labels.features[labels.label(3, 10) as usize].thickness(), 4.0);
+        let thin = labels.thin_cells(4.0);
+        assert!((2..6).all(|x| !thin[layers.index(x, 10)]), "a 4 cell bar is not thinner than 4 cells");
+        assert!(labels.thin_cells(4.5)[layers.index(3, 10)]);
+    }
+
+    #[test]
+    fn test_opening_keeps_features_of_an_even_minimum_width() {
+        // 1 mm pixels, a 4 mm limit is 4 cells and opens with a 3 cell element
+        let config = PrintConfig { image_size_mm: 20.0, ..PrintConfig::default() };
+        let constraints = PrintingConstraints {
+            min_feature_size_mm: 4.0,
+            min_feature_area_mm2: 0.0,
+            min_feature_width_mm: 0.0,
+            merge_small_features: false,
+            structuring_element: Some(StructuringElement::Square),
+            ..PrintingConstraints::default()
+        };
+        assert_eq!(constraints.calculate_morphology_radius(&config, 20, 20), 1);
+        assert_eq!(PrintingConstraints { min_feature_size_mm: 5.0, ..constraints.clone() }.calculate_morphology_radius(&config, 20, 20), 2);
+
+        let mut layers = LayerMap::new(20, 20, 0);
+        for y in 0..20 {
+            for x in 2..6 {
+                layers.set(x, y, 3);
+            }
+            for x in 12..14 {
+                layers.set(x, y, 3);
+            }
+        }
+        let result = enforce_min_feature_size(&layers, &constraints, &config);
+        assert!((2..6).all(|x| result.get(x, 10) == Some(3)), "a bar as wide as the limit is kept");
+        assert!((12..14).all(|x| result.get(x, 10) == Some(0)), "a narrower bar is removed");
+    }
+
+    #[test]
+    fn test_height_constraints_limit_steps_bands_and_budget() {
+        // 1 mm pixels, base 0.6 mm and 0.1 mm layers
+        let config = PrintConfig { image_size_mm: 10.0, base_thickness: 0.6, layer_thickness: 0.1, ..PrintConfig::default() };
+        let constraints = PrintingConstraints {
+            max_height_step_layers: Some(2),
+            height_bands: vec![HeightBand { min_layers: 4, min_width_mm: 3.0 }],
+            max_total_height_mm: Some(1.2),
+            ..PrintingConstraints::default()
+        };
+
+        // Single tall column next to zero layer cells
+        let mut layers = LayerMap::new(10, 10, 0);
+        layers.set(2, 2, 9);
+        // Wide plateau that may keep the band but not exceed the budget
+        for y in 4..10 {
+            for x in 4..10 {
+                layers.set(x, y, 9);
+            }
+        }
+        layers.set_masked(9, 9, true);
+
+        let (result, stats) = enforce_height_constraints(&layers, &ColorPalette::fake(9), &constraints, &config);
+
+        assert_eq!(result.get(2, 2), Some(2));
+        assert_eq!(result.layer(9, 9), 9, "masked cells are left alone");
+        assert!(result.get(7, 7).unwrap() <= 6, "budget allows 6 layers");
+        assert!(result.get(7, 7).unwrap() >= 4, "wide plateau keeps the band");
+        for y in 0..10 {
+            for x in 0..10 {
+                let Some(layer_count) = result.get(x, y) else { continue };
+                for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+                    if let Some(neighbor) = (nx < 10 && ny < 10).then(|| result.get(nx, ny)).flatten() {
+                        assert!(layer_count.abs_diff(neighbor) <= 2, "step at ({}, {})", x, y);
+                    }
+                }
+            }
+        }
+        assert!(stats.step_limited_cells > 0);
+        assert!(stats.height_capped_cells > 0);
+        let lowered = layers.layers().iter().zip(result.layers()).filter(|(a, b)| a != b).count();
+        assert_eq!(stats.lowered_cells, lowered, "cells lowered in several rounds are counted once");
+        assert_eq!(stats.layers_removed, layers.layers().iter().zip(result.layers()).map(|(&a, &b)| (a - b) as u64).sum::<u64>());
+    }
+
+    #[test]
+    fn test_height_constraints_keep_to_sparse_palette_layers() {
+        let config = PrintConfig { image_size_mm: 10.0, base_thickness: 0.6, layer_thickness: 0.1, ..PrintConfig::default() };
+        let constraints = PrintingConstraints {
+            max_height_step_layers: Some(2),
+            height_bands: vec![HeightBand { min_layers: 6, min_width_mm: 3.0 }],
+            max_total_height_mm: Some(1.3),
+            ..PrintingConstraints::default()
+        };
+        // Calibration only picked some of the layers
+        let mut palette = ColorPalette::fake(9);
+        let kept = [0, 3, 4, 8, 9];
+        palette.colors = kept.iter().map(|&layer_count| palette.colors[layer_count as usize]).collect();
+        palette.layer_counts = kept.to_vec();
+
+        let mut layers = LayerMap::new(10, 10, 0);
+        layers.set(2, 2, 9);
+        layers.set(5, 5, 8);
+        for y in 6..10 {
+            for x in 0..10 {
+                layers.set(x, y, 4);
+            }
+        }
+
+        let (result, stats) = enforce_height_constraints(&layers, &palette, &constraints, &config);
+        assert!(stats.lowered_cells > 0);
+        for &layer_count in result.layers() {
+            assert!(palette.layer_counts.contains(&(layer_count as u32)), "{} layers are not in the palette", layer_count);
+        }
+        assert!(result.to_image(&palette).is_ok());
+        assert_eq!(result.get(2, 2), Some(0), "2 layers is not in the palette, the step limit drops to 0");
+    }
+
+    #[test]
+    fn test_height_constraints_run_to_a_fixpoint() {
+        let config = PrintConfig { image_size_mm: 40.0, base_thickness: 0.6, layer_thickness: 0.1, ..PrintConfig::default() };
+        let constraints = PrintingConstraints {
+            max_height_step_layers: Some(1),
+            height_bands: vec![HeightBand { min_layers: 3, min_width_mm: 2.0 }, HeightBand { min_layers: 6, min_width_mm: 5.0 }],
+            max_total_height_mm: Some(1.4),
+            ..PrintingConstraints::default()
+        };
+        let palette = ColorPalette::fake(9);
+        let layers = random_layers(40, 40, 9, 7);
+
+        let (result, _) = enforce_height_constraints(&layers, &palette, &constraints, &config);
+        let (again, stats) = enforce_height_constraints(&result, &palette, &constraints, &config);
+        assert_eq!(stats.lowered_cells, 0, "a second pass finds nothing left to lower");
+        assert_eq!(again.layers(), result.layers());
+    }
+
+    #[test]
+    fn test_backlit_palettes_report_the_options_they_leave_off() {
+        let options = GenerateOptions { flat_top: true, back_ids: true, ..GenerateOptions::default() };
+        assert!(ignored_options(&ColorPalette::fake(4), &options).is_empty());
+        assert_eq!(ignored_options(&ColorPalette::fake_backlit(4), &options).len(), 2);
+        assert!(ignored_options(&ColorPalette::fake_backlit(4), &GenerateOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_printability_report_lists_merged_features() {
+        let config = PrintConfig { image_size_mm: 20.0, ..PrintConfig::default() };
+        let constraints = PrintingConstraints {
+            min_feature_size_mm: 2.0,
+            min_feature_area_mm2: 0.0,
+            min_feature_width_mm: 0.0,
+            merge_small_features: true,
+            structuring_element: None,
+            ..PrintingConstraints::default()
+        };
+
+        let mut layers = LayerMap::new(20, 20, 2);
+        layers.set(5, 5, 4);
+
+        let (result, report) = enforce_min_feature_size_with_report(&layers, &constraints, &config);
+        assert_eq!(result.get(5, 5), Some(2));
+        assert_eq!(report.changes.len(), 1);
+        let change = &report.changes[0];
+        assert_eq!((change.rule, change.action), (ConstraintRule::MinSize, ChangeAction::Merged));
+        assert_eq!(change.bounds, (5, 5, 5, 5));
+        assert_eq!((change.old_layer_count, change.new_layer_count), (4, 2));
+        assert_eq!(report.totals[&ConstraintRule::MinSize].cells, 1);
+
+        let overlay = report.render_overlay(&RgbImage::from_pixel(20, 20, Rgb([255, 255, 255])));
+        assert_eq!(*overlay.get_pixel(5, 5), ConstraintRule::MinSize.color());
+        assert_ne!(*overlay.get_pixel(0, 0), ConstraintRule::MinSize.color());
+    }
+
+    #[test]
+    fn test_settings_precedence() {
+        // An existing config.json is a valid set of overrides
+        let config_file: SettingsOverrides = serde_json::from_str(
+            r#"{"base_thickness": 0.8, "layer_thickness": 0.08, "image_size_mm": 120.0, "max_layers": 10.0}"#
+        ).unwrap();
+        // null switches an optional setting off
+        let profile: SettingsOverrides = serde_json::from_str(
+            r#"{"layer_thickness": 0.1, "min_feature_size_mm": 0.8, "structuring_element": null}"#
+        ).unwrap();
+        assert_eq!(profile.structuring_element, Some(None));
+        let project = SettingsOverrides {
+            image_size_mm: Some(80.0),
+            min_feature_width_mm: Some(0.6),
+            ..SettingsOverrides::default()
+        };
+        let command_line = SettingsOverrides {
+            image_size_mm: Some(50.0),
+            max_height_step_layers: Some(Some(3)),
+            ..SettingsOverrides::default()
+        };
+
+        // Precedence follows the source, not the order given
+        let settings = EffectiveSettings::resolve(&[
+            (SettingsSource::CommandLine, &command_line),
+            (SettingsSource::Project, &project),
+            (SettingsSource::PrinterProfile, &profile),
+            (SettingsSource::ConfigFile, &config_file),
+        ]);
+
+        assert_eq!(settings.config.base_thickness, 0.8);
+        assert_eq!(settings.config.layer_thickness, 0.1);
+        assert_eq!(settings.config.image_size_mm, 50.0);
+        assert_eq!(settings.constraints.structuring_element, None);
+        assert_eq!(settings.constraints.max_height_step_layers, Some(3));
+        assert_eq!(settings.constraints.min_feature_area_mm2, PrintingConstraints::default().min_feature_area_mm2);
+        assert_eq!(settings.source_of("base_thickness"), SettingsSource::ConfigFile);
+        assert_eq!(settings.source_of("layer_thickness"), SettingsSource::PrinterProfile);
+        assert_eq!(settings.source_of("image_size_mm"), SettingsSource::CommandLine, "the command line beats the project");
+        assert_eq!(settings.constraints.min_feature_width_mm, 0.6);
+        assert_eq!(settings.source_of("min_feature_width_mm"), SettingsSource::Project);
+        assert_eq!(settings.source_of("height_bands"), SettingsSource::Default);
+    }
+
+    #[test]
+    fn test_project_file_settings() {
+        // Trimmed down project.json as the GUI saves it
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), r#"{
+            "id": "p1", "name": "Test", "image_size_mm": 80.0, "base_thickness": 0.6, "layer_thickness": 0.08,
+            "backlit_base": "none", "flat_top": true, "last_modified": 0,
+            "overrides": {"image_size_mm": 90.0, "min_feature_width_mm": 0.6}
+        }"#).unwrap();
+
+        let project = load_project_overrides(file.path()).unwrap();
+        assert_eq!(project.image_size_mm, Some(90.0), "advanced overrides win over the project size");
+        assert_eq!(project.base_thickness, Some(0.6));
+        assert_eq!(project.layer_thickness, Some(0.08));
+        assert_eq!(project.backlit_base, Some(BacklitBase::None));
+        assert_eq!(project.min_feature_width_mm, Some(0.6));
+        assert_eq!(project.max_layers, None, "the palette decides the layers");
+    }
+
+    /// Every directed edge has exactly one reverse twin and no triangle is degenerate
+    fn assert_closed_manifold(mesh: &Mesh) {
+        let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+        for triangle in &mesh.triangles.triangle {
+            let [a, b, c] = [triangle.v1, triangle.v2, triangle.v3];
+            assert!(a != b && b != c && a != c, "degenerate triangle {:?}", (a, b, c));
+            for edge in [(a, b), (b, c), (c, a)] {
+                *edges.entry(edge).or_default() += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {:?} used {} times in the same direction", (a, b), count);
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {:?} has no twin", (a, b));
+        }
+    }
+
+    /// Enclosed volume, positive when all triangles wind outwards
+    fn signed_volume(mesh: &Mesh) -> f64 {
+        let vertices = &mesh.vertices.vertex;
+        mesh.triangles.triangle.iter()
+            .map(|triangle| {
+                let (a, b, c) = (&vertices[triangle.v1], &vertices[triangle.v2], &vertices[triangle.v3]);
+                (a.x * (b.y * c.z - b.z * c.y) - a.y * (b.x * c.z - b.z * c.x) + a.z * (b.x * c.y - b.y * c.x)) / 6.0
+            })
+            .sum()
+    }
+
+    fn random_layers(width: u32, height: u32, max_layer: u16, seed: u64) -> LayerMap {
+        let mut state = seed;
+        let mut layers = LayerMap::new(width, height, 0);
+        for y in 0..height {
+            for x in 0..width {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let value = (state >> 33) as u16;
+                layers.set(x, y, value % (max_layer + 1));
+                layers.set_masked(x, y, value.is_multiple_of(7));
+            }
+        }
+        layers
+    }
+
+    /// 60×40 pixels rising from 1 to 4 layers in steps of 15 columns. The steps run in one
+    /// direction only, columns that touch diagonally would be non-manifold edges on their own.
+    fn staircase_layers() -> LayerMap {
+        let mut layers = LayerMap::new(60, 40, 0);
+        for (x, y) in (0..40).flat_map(|y| (0..60).map(move |x| (x, y))) {
+            layers.set(x, y, 1 + (x / 15) as u16);
+        }
+        layers
+    }
+
+    #[test]
+    fn test_heightfield_mesher_is_watertight() {
+        let config = PrintConfig { image_size_mm: 12.0, max_layers: 5.0, ..PrintConfig::default() };
+        let reflective = ColorPalette::fake(5);
+        let backlit = ColorPalette::fake_backlit(5);
+        let cases = [
+            (&reflective, false, BacklitBase::default()),
+            (&reflective, true, BacklitBase::default()),
+            (&backlit, false, BacklitBase::Diffuser),
+            (&backlit, false, BacklitBase::None),
+        ];
+
+        for seed in 1..4 {
+            let layers = random_layers(12, 9, 5, seed);
+            let (pixel_width, pixel_height) = config.pixel_size(12, 9);
+
+            for &(palette, flat_top, backlit_base) in &cases {
+                let config = PrintConfig { backlit_base, ..config.clone() };
+                let options = GenerateOptions { flat_top, backend: MeshBackend::Heightfield, ..GenerateOptions::default() };
+                let objects = generate_image(&layers, palette, &config, &options);
+
+                let mut expected = (0.0, 0.0);
+                for y in 0..9 {
+                    for x in 0..12 {
+                        let Some(layer_count) = layers.get(x, y) else { continue };
+                        let (black, white) = match palette.lighting {
+                            Lighting::Backlit => config.calculate_backlit_stack(layer_count as u32),
+                            Lighting::Reflective => (
+                                config.calculate_black_thickness(layer_count as u32, flat_top),
+                                layer_count as f32 * config.layer_thickness,
+                            ),
+                        };
+                        expected.0 += black as f64 * (pixel_width * pixel_height) as f64;
+                        expected.1 += white as f64 * (pixel_width * pixel_height) as f64;
+                    }
+                }
+
+                for (mesh, expected) in [(&objects.black_mesh, expected.0), (&objects.white_mesh, expected.1)] {
+                    assert_closed_manifold(mesh);
+                    assert!((signed_volume(mesh) - expected).abs() < 1e-3, "volume {} != {}", signed_volume(mesh), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_heightfield_mesher_splits_diagonal_contacts() {
+        // Checkerboard, every white column touches the others only along edges
+        let mut layers = LayerMap::new(4, 4, 0);
+        for y in 0..4 {
+            for x in 0..4 {
+                if (x + y) % 2 == 0 {
+                    layers.set(x, y, 3);
+                }
+            }
+        }
+        let options = GenerateOptions { backend: MeshBackend::Heightfield, ..GenerateOptions::default() };
+        let objects = generate_image(&layers, &ColorPalette::fake(5), &PrintConfig::default(), &options);
+
+        assert_closed_manifold(&objects.white_mesh);
+        assert_closed_manifold(&objects.black_mesh);
+        // The base is a single flat plate, its top and bottom merge into one rectangle each
+        assert!(objects.black_mesh.triangles.triangle.len() < 64);
+        // Eight separate columns with 8 corners each
+        assert_eq!(objects.white_mesh.vertices.vertex.len(), 64);
+    }
+
+    #[test]
+    fn test_rectangle_decomposition_is_exact_and_deterministic() {
+        let layers = random_layers(40, 30, 2, 11);
+        let mask: Vec<bool> = (0..30)
+            .flat_map(|y| (0..40).map(move |x| (x, y)))
+            .map(|(x, y)| layers.get(x, y).is_some_and(|layer_count| layer_count > 0))
+            .collect();
+
+        let rectangles = decompose_mask(40, 30, &mask);
+        let mut covered = vec![false; mask.len()];
+        for rect in &rectangles {
+            for y in rect.y..rect.y + rect.height {
+                for x in rect.x..rect.x + rect.width {
+                    let index = (y * 40 + x) as usize;
+                    assert!(mask[index] && !covered[index], "rectangle {:?} covers ({}, {}) twice or outside the mask", rect, x, y);
+                    covered[index] = true;
+                }
+            }
+        }
+        assert_eq!(covered, mask);
+
+        // Never worse than one rectangle per horizontal run
+        let runs = (0..mask.len()).filter(|&i| mask[i] && (i % 40 == 0 || !mask[i - 1])).count();
+        assert!(rectangles.len() <= runs, "{} rectangles for {} runs", rectangles.len(), runs);
+
+        // Same layers, same mesh
+        let options = GenerateOptions::default();
+        let first = generate_image(&layers, &ColorPalette::fake(2), &PrintConfig::default(), &options);
+        let second = generate_image(&layers, &ColorPalette::fake(2), &PrintConfig::default(), &options);
+        let coordinates = |mesh: &Mesh| mesh.vertices.vertex.iter().map(|v| (v.x, v.y, v.z)).collect::<Vec<_>>();
+        assert_eq!(coordinates(&first.white_mesh), coordinates(&second.white_mesh));
+        assert_eq!(coordinates(&first.black_mesh), coordinates(&second.black_mesh));
+    }
+
+    #[test]
+    fn test_exports_are_byte_reproducible() {
+        let layers = random_layers(16, 12, 5, 3);
+        let palette = ColorPalette::fake(5);
+        let image = layers.to_image(&palette).unwrap();
+        let config = PrintConfig::default();
+        let options = GenerateOptions::default();
+        let recipe = |config: &PrintConfig| {
+            RecipeHasher::new()
+                .add_image("image", &image)
+                .add_mask("mask", None)
+                .add_settings("palette", &palette)
+                .add_settings("config", config)
+                .add_settings("options", &options)
+                .finish()
+        };
+
+        let hash = recipe(&config);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, recipe(&config.clone()));
+        assert_ne!(hash, recipe(&PrintConfig { layer_thickness: 0.12, ..config.clone() }));
+
+        let mut exports = Vec::new();
+        for _ in 0..2 {
+            let objects = generate_image(&layers, &palette, &config, &options);
+            let file = NamedTempFile::new().unwrap();
+            export_to_3mf_with_recipe(&objects, file.path().to_str().unwrap(), Some(&hash)).unwrap();
+            exports.push(fs::read(file.path()).unwrap());
+        }
+        assert!(!exports[0].is_empty());
+        assert_eq!(exports[0], exports[1]);
+
+        // Archives written at different times come out the same
+        let archive = |time: zip::DateTime| {
+            let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            let options = zip::write::SimpleFileOptions::default().last_modified_time(time);
+            writer.start_file("3D/3dmodel.model", options).unwrap();
+            std::io::Write::write_all(&mut writer, b"<model/>").unwrap();
+            normalize_zip(&writer.finish().unwrap().into_inner(), &[]).unwrap()
+        };
+        let earlier = zip::DateTime::from_date_and_time(2024, 1, 2, 3, 4, 6).unwrap();
+        let later = zip::DateTime::from_date_and_time(2025, 6, 7, 8, 9, 10).unwrap();
+        assert_eq!(archive(earlier), archive(later));
+    }
+
+    #[test]
+    fn test_contour_mesher_is_closed_and_smooth() {
+        let config = PrintConfig { image_size_mm: 40.0, ..PrintConfig::default() };
+        let options = GenerateOptions { backend: MeshBackend::Contour, nozzle_diameter: 0.4, ..GenerateOptions::default() };
+        let palette = ColorPalette::fake(5);
+
+        // Noise has saddles, holes and single cells everywhere
+        for seed in 1..4 {
+            let layers = random_layers(20, 15, 5, seed);
+            for flat_top in [false, true] {
+                let objects = generate_image(&layers, &palette, &config, &GenerateOptions { flat_top, ..options.clone() });
+                for mesh in [&objects.black_mesh, &objects.white_mesh] {
+                    assert_closed_manifold(mesh);
+                    assert!(signed_volume(mesh) > 0.0);
+                }
+            }
+        }
+
+        // A disk with a hole, masked outside, keeps its area with fewer triangles than the pixel outline
+        let size = 40;
+        let mut layers = LayerMap::new(size, size, 0);
+        let mut cells = 0;
+        for y in 0..size {
+            for x in 0..size {
+                let distance = ((x as f32 - 19.5).powi(2) + (y as f32 - 19.5).powi(2)).sqrt();
+                layers.set(x, y, 3);
+                layers.set_masked(x, y, !(6.0..18.0).contains(&distance));
+                cells += usize::from(layers.get(x, y).is_some());
+            }
+        }
+        let objects = generate_image(&layers, &palette, &config, &options);
+        let shells = generate_image(&layers, &palette, &config, &GenerateOptions { backend: MeshBackend::Heightfield, ..options.clone() });
+        assert_closed_manifold(&objects.white_mesh);
+
+        let pixel_area = (config.image_size_mm as f64 / size as f64).powi(2);
+        let expected = cells as f64 * pixel_area * 3.0 * config.layer_thickness as f64;
+        let volume = signed_volume(&objects.white_mesh);
+        assert!((volume - expected).abs() < expected * 0.03, "volume {} != {}", volume, expected);
+        assert!(objects.white_mesh.triangles.triangle.len() < shells.white_mesh.triangles.triangle.len());
+    }
+
+    /// Area of the upward facing triangles at height `z`
+    fn top_area(mesh: &Mesh, z: f64) -> f64 {
+        let vertices = &mesh.vertices.vertex;
+        mesh.triangles.triangle.iter()
+            .map(|triangle| (&vertices[triangle.v1], &vertices[triangle.v2], &vertices[triangle.v3]))
+            .filter(|(a, b, c)| [a.z, b.z, c.z].iter().all(|vz| (vz - z).abs() < 1e-4))
+            .map(|(a, b, c)| ((b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)) / 2.0)
+            .filter(|&area| area > 0.0)
+            .sum()
+    }
+
+    #[test]
+    fn test_flat_top_is_level_and_capped() {
+        // The layers go higher than max_layers, the black must still never be thinner than the base
+        let config = PrintConfig { image_size_mm: 12.0, max_layers: 5.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(7);
+        let cap = TopCap { layers: 2, material: CapMaterial::Transparent };
+        let top = (config.base_thickness + 7.0 * config.layer_thickness) as f64;
+        let cap_top = top + 2.0 * config.layer_thickness as f64;
+
+        let layers = random_layers(12, 9, 7, 3);
+        let (pixel_width, pixel_height) = config.pixel_size(12, 9);
+        let cells = layers.masked().iter().filter(|&&masked| !masked).count();
+        let area = cells as f64 * (pixel_width * pixel_height) as f64;
+
+        for backend in [MeshBackend::Boxes, MeshBackend::Heightfield, MeshBackend::Contour] {
+            let options = GenerateOptions { flat_top: true, backend, cap: Some(cap), ..GenerateOptions::default() };
+            let objects = generate_image(&layers, &palette, &config, &options);
+
+            let black = &objects.black_mesh.vertices.vertex;
+            let lowest_black_top = objects.black_mesh.triangles.triangle.iter()
+                .map(|triangle| (&black[triangle.v1], &black[triangle.v2], &black[triangle.v3]))
+                .filter(|(a, b, c)| a.z == b.z && b.z == c.z && (b.x - a.x) * (c.y - a.y) > (b.y - a.y) * (c.x - a.x))
+                .map(|(a, _, _)| a.z)
+                .fold(f64::MAX, f64::min);
+            assert!(lowest_black_top >= config.base_thickness as f64 - 1e-4, "{}: black thinner than the base", backend);
+            for mesh in [&objects.black_mesh, &objects.white_mesh] {
+                assert!(mesh.vertices.vertex.iter().all(|v| v.z <= top + 1e-4), "{}: geometry above the flat top", backend);
+            }
+
+            // Black and white together cover every cell at the same height
+            let level = top_area(&objects.black_mesh, top) + top_area(&objects.white_mesh, top);
+            assert!((level - area).abs() < area * 0.03, "{}: flat top area {} != {}", backend, level, area);
+
+            assert_eq!(objects.extras.len(), 1);
+            assert_eq!(objects.extras[0].name, "cap_transparent");
+            let cap_mesh = &objects.extras[0].mesh;
+            assert!(cap_mesh.vertices.vertex.iter().all(|v| v.z > top - 1e-4 && v.z < cap_top + 1e-4));
+            let covered = top_area(cap_mesh, cap_top);
+            assert!((covered - area).abs() < area * 0.03, "{}: cap area {} != {}", backend, covered, area);
+            if backend != MeshBackend::Boxes {
+                for mesh in [&objects.black_mesh, &objects.white_mesh, cap_mesh] {
+                    assert_closed_manifold(mesh);
+                }
+            }
+        }
+
+        // No cap without a flat top or on a backlit palette
+        let options = GenerateOptions { cap: Some(cap), ..GenerateOptions::default() };
+        assert!(generate_image(&layers, &palette, &config, &options).extras.is_empty());
+        let options = GenerateOptions { flat_top: true, ..options };
+        assert!(generate_image(&layers, &ColorPalette::fake_backlit(7), &config, &options).extras.is_empty());
+
+        // The calibration squares get the same stacks and cap
+        let objects = generate_calibration_objects(&config, 10.0, true, Some(cap));
+        let squares = config.max_layers as usize + 1;
+        assert_eq!(objects.extras.len(), 1);
+        assert_eq!(objects.extras[0].mesh.triangles.triangle.len(), squares * 12);
+        let calibration_top = config.calculate_total_height(0, true) as f64;
+        let level = top_area(&objects.black_mesh, calibration_top) + top_area(&objects.white_mesh, calibration_top);
+        assert!((level - squares as f64 * 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_terraces_keep_the_box_geometry() {
+        let config = PrintConfig { image_size_mm: 16.0, max_layers: 6.0, ..PrintConfig::default() };
+        let reflective = ColorPalette::fake(6);
+        let backlit = ColorPalette::fake_backlit(6);
+        let (pixel_width, pixel_height) = config.pixel_size(16, 12);
+        let pixel_area = (pixel_width * pixel_height) as f64;
+
+        for seed in 1..5 {
+            // Noise has pinched corners and holes, diagonal bands are where boxes need the most pieces
+            let mut smooth = LayerMap::new(16, 12, 0);
+            for y in 0..12 {
+                for x in 0..16 {
+                    smooth.set(x, y, ((x + y + seed as u32) / 4 % 7) as u16);
+                }
+            }
+
+            for (layers, regions) in [(random_layers(16, 12, 6, seed), false), (smooth, true)] {
+                for (palette, flat_top) in [(&reflective, false), (&reflective, true), (&backlit, false)] {
+                    let options = GenerateOptions { flat_top, backend: MeshBackend::Terraces, ..GenerateOptions::default() };
+                    let terraces = generate_image(&layers, palette, &config, &options);
+                    let boxes = generate_image(&layers, palette, &config, &GenerateOptions { backend: MeshBackend::Boxes, ..options.clone() });
+
+                    let mut expected = (0.0, 0.0);
+                    for y in 0..12 {
+                        for x in 0..16 {
+                            let Some(layer_count) = layers.get(x, y) else { continue };
+                            let (black, white) = match palette.lighting {
+                                Lighting::Backlit => config.calculate_backlit_stack(layer_count as u32),
+                                Lighting::Reflective => (
+                                    config.calculate_black_thickness(layer_count as u32, flat_top),
+                                    layer_count as f32 * config.layer_thickness,
+                                ),
+                            };
+                            expected.0 += black as f64 * pixel_area;
+                            expected.1 += white as f64 * pixel_area;
+                        }
+                    }
+
+                    for (mesh, expected) in [(&terraces.black_mesh, expected.0), (&terraces.white_mesh, expected.1)] {
+                        assert_closed_manifold(mesh);
+                        assert!((signed_volume(mesh) - expected).abs() < 1e-3, "volume {} != {}", signed_volume(mesh), expected);
+                    }
+                    assert!(!regions || terraces.triangle_count() < boxes.triangle_count(), "{} terrace triangles, {} box triangles", terraces.triangle_count(), boxes.triangle_count());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mesh_budget_coarsens_until_it_fits() {
+        let config = PrintConfig { image_size_mm: 20.0, max_layers: 6.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(6);
+        let constraints = PrintingConstraints::default();
+        // Noise with a masked strip, scattered masked pixels would leave holes no coarsening can close
+        let mut layers = random_layers(40, 40, 6, 11);
+        for y in 0..40 {
+            for x in 0..40 {
+                layers.set_masked(x, y, x < 4);
+            }
+        }
+        let options = GenerateOptions::default();
+        let full = generate_image(&layers, &palette, &config, &options);
+        let full_bytes = write_3mf(&full, None).unwrap().len() as u64;
+
+        // Without a budget nothing changes
+        let mut report = PrintabilityReport::new(40, 40, &config);
+        let (unchanged, objects, budget_report) =
+            generate_image_within_budget(&layers, &palette, &config, &constraints, &options, &BudgetTarget::default(), &mut report);
+        assert_eq!(unchanged, layers);
+        assert_eq!(objects.triangle_count(), full.triangle_count());
+        assert!(budget_report.within_budget && budget_report.min_feature_area_mm2.is_none());
+        assert!(report.is_empty());
+
+        let budgets = [
+            MeshBudget { max_triangles: Some(full.triangle_count() / 4), max_file_bytes: None },
+            MeshBudget { max_triangles: None, max_file_bytes: Some(full_bytes / 3) },
+        ];
+        // The recipe is written next to the meshes and counts towards the file size
+        let recipe = "a3f9c2 ".repeat(40);
+        for budget in budgets {
+            let mut report = PrintabilityReport::new(40, 40, &config);
+            let target = BudgetTarget { limits: budget, recipe: Some(&recipe), slicer: None };
+            let (coarse, objects, budget_report) =
+                generate_image_within_budget(&layers, &palette, &config, &constraints, &options, &target, &mut report);
+
+            assert!(budget_report.within_budget, "{:?}", budget_report);
+            assert_eq!(budget_report.initial_triangles, full.triangle_count());
+            assert_eq!(budget_report.triangles, objects.triangle_count());
+            assert!(budget.max_triangles.is_none_or(|max| objects.triangle_count() <= max));
+            if let Some(max) = budget.max_file_bytes {
+                let bytes = write_3mf(&objects, Some(&recipe)).unwrap().len() as u64;
+                assert!(bytes <= max);
+                assert_eq!(budget_report.file_bytes, Some(bytes), "the size of the result is measured, not estimated");
+            }
+
+            // Noise is too busy for any mesher, features had to be merged and the report says which
+            assert!(budget_report.min_feature_area_mm2.is_some());
+            assert!(budget_report.coarsened_cells > 0);
+            assert_eq!(report.totals[&ConstraintRule::MeshBudget].cells, budget_report.coarsened_cells);
+
+            // The objects are the mesh of the returned layer counts, the mask is untouched
+            let rebuilt = generate_image(&coarse, &palette, &config, &GenerateOptions { backend: budget_report.backend, ..options.clone() });
+            assert_eq!(rebuilt.triangle_count(), objects.triangle_count());
+            for y in 0..40 {
+                for x in 0..40 {
+                    assert_eq!(coarse.is_masked(x, y), layers.is_masked(x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_validation_finds_broken_meshes() {
+        let cube = || {
+            let mut mesh = Mesh::new();
+            generate_box(&mut mesh.vertices, &mut mesh.triangles, 0.0, 0.0, 0.0, 2.0, 3.0, 4.0);
+            mesh
+        };
+        let bounds = Bounds { min: [0.0; 3], max: [2.0, 3.0, 4.0] };
+
+        let report = validate_mesh(&cube(), Some(&bounds));
+        assert!(report.is_valid() && report.is_watertight(), "{:?}", report.summary());
+        assert_eq!(report.bounds.map(|bounds| bounds.size()), Some([2.0, 3.0, 4.0]));
+
+        let mut open = cube();
+        open.triangles.triangle.pop();
+        let report = validate_mesh(&open, None);
+        assert_eq!(report.count(MeshIssue::OpenBoundary), 3);
+        assert!(!report.is_watertight());
+
+        let mut flipped = cube();
+        let triangle = &mut flipped.triangles.triangle[0];
+        std::mem::swap(&mut triangle.v2, &mut triangle.v3);
+        assert_eq!(validate_mesh(&flipped, None).count(MeshIssue::InconsistentWinding), 3);
+
+        let mut broken = cube();
+        broken.triangles.triangle[1].v3 = 100;
+        broken.triangles.triangle[2].v3 = broken.triangles.triangle[2].v2;
+        let report = validate_mesh(&broken, None);
+        assert_eq!(report.count(MeshIssue::IndexOutOfRange), 1);
+        assert_eq!(report.count(MeshIssue::DegenerateTriangle), 1);
+        assert!(report.examples.iter().any(|example| example.issue == MeshIssue::IndexOutOfRange && example.triangles == [1]));
+
+        // Boxes side by side, the touching walls overlap and their edges are shared by four triangles.
+        // The walls are split along different diagonals, so every triangle overlaps both of the other wall.
+        let mut touching = cube();
+        generate_box(&mut touching.vertices, &mut touching.triangles, 2.0, 0.0, 0.0, 2.0, 3.0, 4.0);
+        let report = validate_mesh(&touching, Some(&bounds));
+        assert_eq!(report.count(MeshIssue::OverlappingFaces), 4);
+        assert_eq!(report.count(MeshIssue::NonManifoldEdge), 4);
+        assert_eq!(report.count(MeshIssue::OutOfBounds), 10);
+        assert_eq!(report.count(MeshIssue::OpenBoundary), 0);
+
+        // Generated meshes, nested rectangles have no diagonal contacts that a slicer would see as non-manifold
+        let config = PrintConfig { image_size_mm: 18.0, max_layers: 5.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(5);
+        let mut layers = LayerMap::new(18, 14, 0);
+        for y in 0..14 {
+            for x in 0..18 {
+                layers.set(x, y, (x.min(y).min(17 - x).min(13 - y) / 2).min(5) as u16);
+            }
+        }
+        let cap = Some(TopCap { layers: 2, material: CapMaterial::White });
+        let mut box_overlaps = 0;
+        for backend in [MeshBackend::Boxes, MeshBackend::Heightfield, MeshBackend::Terraces] {
+            for flat_top in [false, true] {
+                let options = GenerateOptions { flat_top, backend, cap, ..GenerateOptions::default() };
+                let objects = generate_image(&layers, &palette, &config, &options);
+                let bounds = expected_bounds(&layers, &palette, &config, &options);
+                for (name, report) in validate_objects(&objects, &bounds) {
+                    assert_eq!(report.count(MeshIssue::OutOfBounds), 0, "{} {} {:?}", backend, name, report.bounds);
+                    // Boxes touch side by side and terrace slabs sit on each other, only the heightfield is a single shell.
+                    // The slabs are closed on their own, so every edge still has a twin.
+                    match backend {
+                        MeshBackend::Boxes => box_overlaps += report.count(MeshIssue::OverlappingFaces),
+                        MeshBackend::Terraces => assert!(
+                            report.count(MeshIssue::OpenBoundary) + report.count(MeshIssue::InconsistentWinding) == 0,
+                            "{} {} {:?}", backend, name, report.summary()
+                        ),
+                        _ => assert!(report.is_valid(), "{} {} {:?}", backend, name, report.summary()),
+                    }
+                }
+            }
+        }
+        assert!(box_overlaps > 0, "touching boxes should be reported");
+    }
+
+    #[test]
+    fn test_frame_is_closed_around_the_image() {
+        let frame = |profile, height_mm, margin_mm| Frame { width_mm: 4.0, height_mm, profile, material: FrameMaterial::White, margin_mm };
+
+        for profile in [FrameProfile::Flat, FrameProfile::Bevel, FrameProfile::Rounded] {
+            for height in [1.0, 2.0, 6.0] {
+                let mesh = generate_frame([0.0, 0.0, 30.0, 20.0], &frame(profile, Some(height), 2.0), 0.0);
+                let report = validate_mesh(&mesh, Some(&Bounds { min: [-6.0, -6.0, 0.0], max: [36.0, 26.0, height as f64] }));
+                assert!(report.is_valid(), "{} {} {:?}", profile, height, report.summary());
+
+                // Flat is the full bar, the other profiles take material away from it
+                let bar = height as f64 * (42.0 * 32.0 - 34.0 * 24.0);
+                let volume = signed_volume(&mesh);
+                match profile {
+                    FrameProfile::Flat => assert!((volume - bar).abs() < 1e-3, "{} != {}", volume, bar),
+                    _ => assert!(volume > 0.0 && volume < bar, "{} {} {}", profile, volume, bar),
+                }
+            }
+        }
+
+        // Through generate_image: the frame is its own part at the image height and the margin is bare base
+        let config = PrintConfig { image_size_mm: 12.0, max_layers: 4.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(4);
+        let layers = random_layers(12, 8, 4, 3);
+        let plain = generate_image(&layers, &palette, &config, &GenerateOptions::default());
+        let options = GenerateOptions { frame: Some(frame(FrameProfile::Bevel, None, 1.5)), ..GenerateOptions::default() };
+        let objects = generate_image(&layers, &palette, &config, &options);
+
+        assert_eq!(objects.extras.len(), 1);
+        assert_eq!(objects.extras[0].name, "frame_white");
+        assert_eq!(objects.white_mesh.triangles.triangle.len(), plain.white_mesh.triangles.triangle.len());
+        assert_eq!(objects.black_mesh.triangles.triangle.len(), plain.black_mesh.triangles.triangle.len() + 32);
+
+        let top = config.calculate_total_height(layers.max_layer() as u32, false) as f64;
+        let frame_bounds = validate_mesh(&objects.extras[0].mesh, None).bounds.unwrap();
+        assert!((frame_bounds.max[2] - top).abs() < 1e-5);
+        assert!((frame_bounds.min[0] + 5.5).abs() < 1e-5 && (frame_bounds.max[0] - 17.5).abs() < 1e-5);
+        let bounds = expected_bounds(&layers, &palette, &config, &options);
+        for (name, report) in validate_objects(&objects, &bounds) {
+            assert_eq!(report.count(MeshIssue::OutOfBounds), 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_mounts_cut_through_the_base() {
+        // One mm per pixel, so the cut cells can be counted against the lost volume
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(4);
+        let layers = staircase_layers();
+        let bounds = (0.0, 60.0, 0.0, 40.0);
+        let base = GenerateOptions { backend: MeshBackend::Heightfield, ..GenerateOptions::default() };
+        let plain = generate_image(&layers, &palette, &config, &base);
+
+        for kind in [MountKind::HangingHole, MountKind::Keyhole, MountKind::Kickstand] {
+            let mount = Mount::new(kind);
+            let cut = mount.cut(bounds, 0.0);
+            let cut_cells: Vec<(u32, u32)> = (0..40).flat_map(|y| (0..60).map(move |x| (x, y)))
+                .filter(|&(x, y)| cut.overlaps([x as f32, y as f32], [x as f32 + 1.0, y as f32 + 1.0]))
+                .collect();
+            assert!(!cut_cells.is_empty(), "{}", kind);
+
+            let options = GenerateOptions { mount: Some(mount), ..base.clone() };
+            let objects = generate_image(&layers, &palette, &config, &options);
+            let expected = expected_bounds(&layers, &palette, &config, &options);
+            for (name, report) in validate_objects(&objects, &expected) {
+                assert!(report.is_valid(), "{} {} {:?}", kind, name, report.summary());
+            }
+
+            // Whole columns are gone, base and white layers alike
+            let lost_black = signed_volume(&plain.black_mesh) - signed_volume(&objects.black_mesh);
+            let lost_white = signed_volume(&plain.white_mesh) - signed_volume(&objects.white_mesh);
+            let black = cut_cells.len() as f64 * config.base_thickness as f64;
+            let white: f64 = cut_cells.iter().map(|&(x, y)| layers.layer(x, y) as f64 * config.layer_thickness as f64).sum();
+            assert!((lost_black - black).abs() < 1e-3, "{} {} != {}", kind, lost_black, black);
+            assert!((lost_white - white).abs() < 1e-3, "{} {} != {}", kind, lost_white, white);
+
+            match kind {
+                MountKind::HangingHole => {
+                    // Never smaller than asked for, the hole covers its whole circle
+                    assert!(cut_cells.len() as f32 >= std::f32::consts::PI * 2.5 * 2.5);
+                    assert!(cut_cells.contains(&(30, 8)) && !cut_cells.contains(&(30, 11)));
+                }
+                MountKind::Keyhole => {
+                    // Head at 16 mm, the slot for the shank runs up towards the top edge
+                    assert!(cut_cells.contains(&(30, 16)) && cut_cells.contains(&(30, 8)));
+                    assert!(!cut_cells.contains(&(27, 8)) && cut_cells.contains(&(27, 16)));
+                }
+                MountKind::Kickstand => {
+                    assert_eq!(objects.extras.len(), 1);
+                    assert_eq!(objects.extras[0].name, "kickstand");
+                    let stand = validate_mesh(&objects.extras[0].mesh, None);
+                    assert!(stand.is_valid(), "{:?}", stand.summary());
+                    assert!(signed_volume(&objects.extras[0].mesh) > 0.0);
+
+                    // The tab fits the slot: wider than the plate is thick, longer than the tab is wide.
+                    // The stand is 20 mm high, the tab between 7 and 15 mm above the edge the print stands on.
+                    let stand_bounds = stand.bounds.unwrap();
+                    assert!(stand_bounds.min[0] > 60.0 && (stand_bounds.max[2] - 3.0).abs() < 1e-5);
+                    let columns: Vec<u32> = cut_cells.iter().map(|&(x, _)| x).collect();
+                    let rows: Vec<u32> = cut_cells.iter().map(|&(_, y)| y).collect();
+                    let slot_width = columns.iter().max().unwrap() - columns.iter().min().unwrap() + 1;
+                    let (top_row, bottom_row) = (*rows.iter().min().unwrap(), *rows.iter().max().unwrap());
+                    assert!(slot_width as f32 >= 3.4, "{}", slot_width);
+                    assert!(top_row <= 40 - 15 && bottom_row >= 40 - 7, "{} {}", top_row, bottom_row);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_text_bitmap_lines_and_mirroring() {
+        let bitmap = TextBitmap::new(&["Hi", "I"]);
+        assert_eq!((bitmap.width, bitmap.height), (11, 18));
+        assert!(bitmap.get(0, 0) && !bitmap.get(10, 0));
+        let mirrored = bitmap.mirrored();
+        assert!(mirrored.get(10, 0) && !mirrored.get(0, 0));
+        assert!(TextBitmap::new(&["  "]).is_empty());
+    }
+
+    #[test]
+    fn test_front_label_stands_on_a_strip_below_the_image() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(4);
+        let layers = staircase_layers();
+
+        let plain = generate_image(&layers, &palette, &config, &GenerateOptions::default());
+        let options = GenerateOptions { label: Some(Label::new("PRINTSPOTS")), ..GenerateOptions::default() };
+        let objects = generate_image(&layers, &palette, &config, &options);
+        assert_eq!(objects.black_mesh.triangles.triangle.len(), plain.black_mesh.triangles.triangle.len() + 12);
+        assert!(objects.white_mesh.triangles.triangle.len() > plain.white_mesh.triangles.triangle.len());
+        let bounds = expected_bounds(&layers, &palette, &config, &options);
+        assert!((bounds.max[1] - 46.0).abs() < 1e-5, "{:?}", bounds);
+        for (name, report) in validate_objects(&objects, &bounds) {
+            assert_eq!(report.count(MeshIssue::OutOfBounds), 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_back_label_is_debossed_into_the_base() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(4);
+        let layers = staircase_layers();
+
+        // The text cells lose the bottom of their base, in the shells and in the boxes alike
+        let back = TextBitmap::new(&["II"]);
+        let text_cells = back.cells.iter().filter(|&&cell| cell).count() as f64;
+        for backend in [MeshBackend::Heightfield, MeshBackend::Boxes] {
+            let base = GenerateOptions { backend, ..GenerateOptions::default() };
+            let options = GenerateOptions { back_label: Some(Label::new("II")), ..base.clone() };
+            let plain = generate_image(&layers, &palette, &config, &base);
+            let objects = generate_image(&layers, &palette, &config, &options);
+
+            let lost = signed_volume(&plain.black_mesh) - signed_volume(&objects.black_mesh);
+            assert!((lost - text_cells * 0.4).abs() < 1e-3, "{} {}", backend, lost);
+            assert!((signed_volume(&plain.white_mesh) - signed_volume(&objects.white_mesh)).abs() < 1e-3);
+            if backend == MeshBackend::Heightfield {
+                let report = validate_mesh(&objects.black_mesh, None);
+                assert!(report.is_valid(), "{:?}", report.summary());
+            }
+        }
+    }
+
+    #[test]
+    fn test_back_ids_tell_palettes_and_configs_apart() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(4);
+        let layers = staircase_layers();
+
+        // The identifiers alone are enough for a back label
+        let plain = generate_image(&layers, &palette, &config, &GenerateOptions::default());
+        let options = GenerateOptions { back_ids: true, ..GenerateOptions::default() };
+        let objects = generate_image(&layers, &palette, &config, &options);
+        assert!(signed_volume(&objects.black_mesh) < signed_volume(&plain.black_mesh) - 1.0);
+        assert_ne!(back_ids(&palette, &config), back_ids(&ColorPalette::fake(5), &config));
+        assert_ne!(back_ids(&palette, &config), back_ids(&palette, &PrintConfig { base_thickness: 1.2, ..config.clone() }));
+    }
+
+    #[test]
+    fn test_backlit_prints_get_nothing_on_the_back() {
+        // The text would show through
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let backlit = ColorPalette::fake_backlit(4);
+        let layers = staircase_layers();
+        let options = GenerateOptions { back_label: Some(Label::new("II")), back_ids: true, ..GenerateOptions::default() };
+        let plain = generate_image(&layers, &backlit, &config, &GenerateOptions::default());
+        let objects = generate_image(&layers, &backlit, &config, &options);
+        for (plain, labeled) in [(&plain.black_mesh, &objects.black_mesh), (&plain.white_mesh, &objects.white_mesh)] {
+            assert_eq!(labeled.triangles.triangle.len(), plain.triangles.triangle.len());
+            assert!((signed_volume(labeled) - signed_volume(plain)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_base_shapes_mask_the_image() {
+        let image = RgbImage::from_fn(120, 80, |x, _| if x < 60 { Rgb([0, 0, 0]) } else { Rgb([255, 255, 255]) });
+        let fill = Rgb([10, 20, 30]);
+        let kept = |mask: &GrayImage, x: u32, y: u32| mask.get_pixel(x, y)[0] == 255;
+
+        // Cover crops a landscape image to a square around the focus and masks the corners
+        let circle = BaseShape::new(ShapeKind::Circle);
+        let (shaped, mask) = apply_shape(&image, None, &circle, fill).unwrap();
+        assert_eq!(shaped.dimensions(), (80, 80));
+        assert!(kept(&mask, 40, 40) && kept(&mask, 1, 40) && kept(&mask, 40, 78));
+        assert!(!kept(&mask, 0, 0) && !kept(&mask, 79, 79));
+        let left = BaseShape { focus: [0.0, 0.5], ..circle.clone() };
+        let (shaped, _) = apply_shape(&image, None, &left, fill).unwrap();
+        assert_eq!(*shaped.get_pixel(50, 40), Rgb([0, 0, 0]));
+
+        // Contain pads with the fill color and keeps all of the image
+        let contain = BaseShape { fit: ShapeFit::Contain, ..circle.clone() };
+        let (shaped, mask) = apply_shape(&image, None, &contain, fill).unwrap();
+        let (width, height) = shaped.dimensions();
+        assert_eq!(width, height);
+        assert!(width as f64 >= (120.0f64.powi(2) + 80.0f64.powi(2)).sqrt() - 1.0, "{}", width);
+        let (left, top) = ((width - 120) / 2, (height - 80) / 2);
+        assert!((0..120).all(|x| (0..80).all(|y| kept(&mask, left + x, top + y))));
+        assert_eq!(*shaped.get_pixel(width / 2, 0), fill);
+        assert!(!kept(&mask, 0, 0));
+
+        // The alpha mask stays in effect inside the shape
+        let alpha = GrayImage::from_fn(120, 80, |x, _| Luma([if x < 60 { 0 } else { 255 }]));
+        let (_, mask) = apply_shape(&image, Some(&alpha), &circle, fill).unwrap();
+        assert!(!kept(&mask, 10, 40) && kept(&mask, 70, 40));
+
+        // Every built-in outline masks some pixels and keeps the middle
+        for kind in [ShapeKind::RoundedRectangle, ShapeKind::Ellipse, ShapeKind::Hexagon, ShapeKind::Heart] {
+            let (shaped, mask) = apply_shape(&image, None, &BaseShape::new(kind), fill).unwrap();
+            let (width, height) = shaped.dimensions();
+            assert!(kept(&mask, width / 2, height / 2), "{}", kind);
+            assert!(mask.pixels().any(|pixel| pixel[0] == 0), "{}", kind);
+        }
+        let (shaped, _) = apply_shape(&image, None, &BaseShape::new(ShapeKind::Ellipse), fill).unwrap();
+        assert_eq!(shaped.dimensions(), (120, 80));
+
+        // A path with a hole, even-odd like SVG, relative commands included
+        let frame = BaseShape {
+            path: Some("M0 0 H100 V100 H0 Z m25 25 l50 0 l0 50 l-50 0 z".to_string()),
+            ..BaseShape::new(ShapeKind::Path)
+        };
+        let (shaped, mask) = apply_shape(&image, None, &frame, fill).unwrap();
+        assert_eq!(shaped.dimensions(), (80, 80));
+        assert!(kept(&mask, 5, 5) && !kept(&mask, 40, 40));
+        assert_eq!(parse_svg_path("M0 0 Q 10 10 20 0 Z").unwrap()[0].len(), 17);
+
+        assert!(parse_svg_path("M0 0 A 5 5 0 0 1 10 0").is_err());
+        assert!(parse_svg_path("L 10 10").is_err());
+        assert!(apply_shape(&image, None, &BaseShape::new(ShapeKind::Path), fill).is_err());
+        assert_eq!("oval".parse::<ShapeKind>(), Ok(ShapeKind::Ellipse));
+    }
+
+    #[test]
+    fn test_tiles_fit_the_bed_and_cover_the_image() {
+        // One mm per pixel on a 120 mm bed leaves 110 mm, 105 mm without the tabs, so three tiles across
+        let config = PrintConfig { image_size_mm: 300.0, max_layers: 4.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(4);
+        let mut layers = LayerMap::new(300, 100, 0);
+        for (x, y) in (0..100).flat_map(|y| (0..300).map(move |x| (x, y))) {
+            layers.set(x, y, ((x * 7 + y * 3) % 5) as u16);
+        }
+        layers.set_masked(150, 50, true);
+
+        for joint in [TileJoint::Flat, TileJoint::Keys, TileJoint::Dovetail] {
+            let tiling = Tiling { joint, ..Tiling::new(120.0, 120.0) };
+            let tiles = split_into_tiles(&layers, &config, &tiling).unwrap();
+            assert_eq!(tiles.len(), 3, "{}", joint);
+
+            // Every cell is in at most one tile, with the layers of the whole image. Only the clearance around tabs is in none.
+            let mut owners = vec![0; 300 * 100];
+            for tile in &tiles {
+                let (width, height) = tile.layers.dimensions();
+                assert!(width as f32 * tile.pixel_mm <= 110.0 && height as f32 * tile.pixel_mm <= 110.0, "{} {}", joint, tile.name());
+                let tile_config = tile.config(&config);
+                assert!((tile_config.pixel_size(width, height).0 - 1.0).abs() < 1e-5);
+                for (x, y) in (0..height).flat_map(|y| (0..width).map(move |x| (x, y))) {
+                    if let Some(layer_count) = tile.layers.get(x, y) {
+                        let (x, y) = (tile.origin.0 + x, tile.origin.1 + y);
+                        assert_eq!(layers.get(x, y), Some(layer_count));
+                        owners[layers.index(x, y)] += 1;
+                    }
+                }
+            }
+            for (x, y) in (0..100).flat_map(|y| (0..300).map(move |x| (x, y))) {
+                let expected = if layers.is_masked(x, y) { 0 } else { 1 };
+                if joint == TileJoint::Flat {
+                    assert_eq!(owners[layers.index(x, y)], expected, "{} ({}, {})", joint, x, y);
+                } else {
+                    assert!(owners[layers.index(x, y)] <= expected, "{} ({}, {})", joint, x, y);
+                }
+            }
+
+            // The first tile only reaches into its neighbour with its tabs, dovetails widen towards the end
+            let first = &tiles[0];
+            let tab_cells = |x: u32| (0..100).filter(|&y| first.layers.get(x, y).is_some()).count();
+            match joint {
+                TileJoint::Flat => assert_eq!(first.layers.width(), 100),
+                TileJoint::Keys => {
+                    assert_eq!(first.layers.width(), 105);
+                    assert!(tab_cells(100) > 0 && tab_cells(100) == tab_cells(104));
+                }
+                TileJoint::Dovetail => {
+                    assert_eq!(first.layers.width(), 105);
+                    assert!(tab_cells(100) > 0 && tab_cells(100) < tab_cells(104));
+                }
+            }
+
+            // The slot in the neighbour is wider than the tab all along it and deeper than its tip
+            if joint != TileJoint::Flat {
+                let second = &tiles[1];
+                let slot_cells = |x: u32| (0..100).filter(|&y| second.layers.get(x - second.origin.0, y).is_none()).count();
+                for x in 100..105 {
+                    assert!(slot_cells(x) >= tab_cells(x) + 2, "{} slot at {}: {} for a {} cell tab", joint, x, slot_cells(x), tab_cells(x));
+                }
+                assert!(slot_cells(105) > 0, "{} no clearance at the tip", joint);
+            }
+        }
+
+        // A tile prints on its own with the frame of the whole image left off, and its name on the back when asked for
+        let tiling = Tiling::new(120.0, 120.0);
+        let tiles = split_into_tiles(&layers, &config, &tiling).unwrap();
+        let whole = GenerateOptions {
+            backend: MeshBackend::Heightfield,
+            frame: Some(Frame { width_mm: 5.0, height_mm: None, profile: FrameProfile::Flat, material: FrameMaterial::Black, margin_mm: 0.0 }),
+            ..GenerateOptions::default()
+        };
+        assert!(tiles[1].options(&whole, &tiling).back_label.is_none());
+        let options = tiles[1].options(&whole, &Tiling { name_tiles: true, ..tiling });
+        assert_eq!(options.back_label.as_ref().map(|label| label.text.as_str()), Some("R1C2"));
+        assert!(options.frame.is_none());
+        let tile_config = tiles[1].config(&config);
+        let objects = generate_image(&tiles[1].layers, &palette, &tile_config, &options);
+        let bounds = expected_bounds(&tiles[1].layers, &palette, &tile_config, &options);
+        assert!(bounds.max[0] - bounds.min[0] <= 110.0, "{:?}", bounds);
+        for (name, report) in validate_objects(&objects, &bounds) {
+            assert_eq!(report.count(MeshIssue::OutOfBounds), 0, "{}", name);
+        }
+
+        assert_eq!(split_into_tiles(&layers, &config, &Tiling::new(400.0, 400.0)).unwrap().len(), 1);
+        assert!(split_into_tiles(&layers, &config, &Tiling::new(20.0, 20.0)).is_err());
+        assert_eq!("dovetails".parse::<TileJoint>(), Ok(TileJoint::Dovetail));
+    }
+
+    #[test]
+    fn test_placement_centers_the_model_and_adds_pads() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let palette = ColorPalette::fake(4);
+        let layers = staircase_layers();
+        let options = GenerateOptions { backend: MeshBackend::Heightfield, ..GenerateOptions::default() };
+        let xy_bounds = |mesh: &Mesh| {
+            let (x0, x1, y0, y1) = crate::mesh::calculate_xy_bounds(&mesh.vertices);
+            [x0, y0, x1, y1]
+        };
+        let lowest = |mesh: &Mesh| mesh.vertices.vertex.iter().map(|vertex| vertex.z).fold(f64::INFINITY, f64::min);
+
+        // Nothing to do without a bed and pads
+        let mut objects = generate_image(&layers, &palette, &config, &options);
+        let plain_white = signed_volume(&objects.white_mesh);
+        assert!(lowest(&objects.white_mesh) > 0.0);
+        let report = place_objects(&mut objects, &Placement::default());
+        assert_eq!(report.offset, [0.0, 0.0]);
+        assert_eq!(report.fits_bed, None);
+        assert_eq!(xy_bounds(&objects.black_mesh), [0.0, 0.0, 60.0, 40.0]);
+
+        // Anchors give every part the same footprint down on the plate, centered on the bed
+        let placement = Placement {
+            bed: Some([200.0, 180.0]),
+            pads: PadSettings { style: PadStyle::Anchors, ..PadSettings::default() },
+        };
+        let mut objects = generate_image(&layers, &palette, &config, &options);
+        let report = place_objects(&mut objects, &placement);
+        let footprint = xy_bounds(&objects.black_mesh);
+        assert_eq!(xy_bounds(&objects.white_mesh), footprint);
+        assert_eq!(footprint, report.bounds);
+        assert!(((footprint[0] + footprint[2]) / 2.0 - 100.0).abs() < 1e-4 && ((footprint[1] + footprint[3]) / 2.0 - 90.0).abs() < 1e-4);
+        assert!((footprint[2] - footprint[0] - (60.0 + 2.0 * 4.0)).abs() < 1e-4);
+        assert_eq!(lowest(&objects.white_mesh), 0.0);
+        assert!((signed_volume(&objects.white_mesh) - plain_white - 4.0 * 0.2).abs() < 1e-4);
+        assert_eq!(report.fits_bed, Some(true));
+        let white = &report.parts[1];
+        assert_eq!(white.name, "white");
+        assert!((white.bounds[0] - report.offset[0]).abs() < 1e-4 && (white.bounds[2] - white.bounds[0] - 60.0).abs() < 1e-4);
+        let centers: Vec<[f32; 2]> = report.parts.iter().flat_map(|part| part.pads.clone()).collect();
+        assert_eq!(centers.len(), 8);
+        for (index, a) in centers.iter().enumerate() {
+            for b in &centers[index + 1..] {
+                assert!((a[0] - b[0]).abs() >= 1.0 || (a[1] - b[1]).abs() >= 1.0, "{:?} {:?}", a, b);
+            }
+        }
+
+        // Wipe pads are larger, and a bed smaller than the model is reported
+        let placement = Placement {
+            bed: Some([50.0, 50.0]),
+            pads: PadSettings { style: PadStyle::Wipe, ..PadSettings::default() },
+        };
+        let mut objects = generate_image(&layers, &palette, &config, &options);
+        let report = place_objects(&mut objects, &placement);
+        assert!((signed_volume(&objects.white_mesh) - plain_white - (25.0 + 3.0) * 0.2).abs() < 1e-4);
+        assert_eq!(report.fits_bed, Some(false));
+        assert!(report.summary().last().unwrap().contains("NOT"));
+        assert_eq!("wipe".parse::<PadStyle>(), Ok(PadStyle::Wipe));
+    }
+
+    #[test]
+    fn test_pads_of_many_parts_stay_on_their_edge() {
+        // Eight parts on a 20 mm print have more anchors per edge than its 28 mm footprint fits
+        let part = |x: f32| {
+            let mut mesh = Mesh::new();
+            generate_box(&mut mesh.vertices, &mut mesh.triangles, x, 0.0, 0.0, 2.0, 20.0, 1.0);
+            mesh
+        };
+        let mut objects = PrintObjects {
+            black_mesh: part(0.0),
+            white_mesh: part(2.5),
+            extras: (0..6).map(|index| MaterialMesh {
+                name: format!("extra {}", index),
+                display_color: "#FF0000".to_string(),
+                mesh: part(5.0 + 2.5 * index as f32),
+            }).collect(),
+        };
+        let placement = Placement {
+            bed: Some([200.0, 200.0]),
+            pads: PadSettings { style: PadStyle::Anchors, ..PadSettings::default() },
+        };
+
+        let report = place_objects(&mut objects, &placement);
+        let [x0, y0, x1, y1] = report.bounds;
+        let centers: Vec<[f32; 2]> = report.parts.iter().flat_map(|part| part.pads.clone()).collect();
+        assert_eq!(centers.len(), 32);
+        for (index, a) in centers.iter().enumerate() {
+            assert!(a[0] - 0.5 >= x0 - 1e-4 && a[0] + 0.5 <= x1 + 1e-4 && a[1] - 0.5 >= y0 - 1e-4 && a[1] + 0.5 <= y1 + 1e-4, "{:?} is off the footprint", a);
+            for b in &centers[index + 1..] {
+                assert!((a[0] - b[0]).abs() >= 1.0 || (a[1] - b[1]).abs() >= 1.0, "{:?} {:?}", a, b);
+            }
+        }
+        assert!(x1 - x0 > 28.0, "the footprint grows to fit the pads");
+        assert_eq!(report.fits_bed, Some(true));
+    }
+
+    #[test]
+    fn test_packing_keeps_prints_apart_on_the_bed() {
+        let spacing = 5.0;
+        let sizes = [[60.0, 40.0], [30.0, 80.0], [50.0, 50.0], [20.0, 20.0]];
+        let overlap = |a: [f32; 4], b: [f32; 4]| a[0] < b[2] + spacing - 1e-4 && b[0] < a[2] + spacing - 1e-4
+            && a[1] < b[3] + spacing - 1e-4 && b[1] < a[3] + spacing - 1e-4;
+        for bed in [Some([120.0, 200.0]), None] {
+            let positions = pack_rectangles(&sizes, bed, spacing).unwrap();
+            let boxes: Vec<[f32; 4]> = positions.iter().zip(&sizes).map(|([x, y], [w, d])| [*x, *y, x + w, y + d]).collect();
+            for (index, a) in boxes.iter().enumerate() {
+                assert!(a[0] >= 0.0 && a[1] >= 0.0);
+                if let Some([width, depth]) = bed {
+                    assert!(a[2] <= width && a[3] <= depth, "{:?}", a);
+                }
+                for b in &boxes[index + 1..] {
+                    assert!(!overlap(*a, *b), "{:?} {:?}", a, b);
+                }
+            }
+        }
+        assert!(pack_rectangles(&sizes, Some([100.0, 60.0]), spacing).is_err());
+        assert!(pack_rectangles(&[[130.0, 10.0]], Some([120.0, 200.0]), spacing).is_err());
+
+        // Prints of different sizes end up centered on the bed, each as its own object of one 3MF
+        let palette = ColorPalette::fake(4);
+        let layers = staircase_layers();
+        let options = GenerateOptions { backend: MeshBackend::Heightfield, ..GenerateOptions::default() };
+        let mut groups: Vec<_> = [60.0, 30.0]
+            .iter()
+            .map(|&size| generate_image(&layers, &palette, &PrintConfig { image_size_mm: size, max_layers: 4.0, ..PrintConfig::default() }, &options))
+            .collect();
+        let volumes: Vec<f64> = groups.iter().map(|objects| signed_volume(&objects.white_mesh)).collect();
+        let packed = pack_objects(&mut groups, Some([200.0, 200.0]), spacing).unwrap();
+        assert!((packed[0][2] - packed[0][0] - 60.0).abs() < 1e-4 && (packed[1][2] - packed[1][0] - 30.0).abs() < 1e-4);
+        assert!(!overlap(packed[0], packed[1]));
+        let (x0, x1) = (packed[0][0].min(packed[1][0]), packed[0][2].max(packed[1][2]));
+        assert!(((x0 + x1) / 2.0 - 100.0).abs() < 1e-3);
+        for (objects, volume) in groups.iter().zip(&volumes) {
+            assert!((signed_volume(&objects.white_mesh) - volume).abs() < 1e-6);
+        }
+
+        // One group writes the same archive as a single print, two groups keep the shared materials
+        let single = write_3mf_groups(&[("Print Object", &groups[0])], None, None).unwrap();
+        assert_eq!(single, write_3mf(&groups[0], None).unwrap());
+        let plate = write_3mf_groups(&[("a", &groups[0]), ("b", &groups[1])], None, None).unwrap();
+        assert!(plate.len() > single.len());
+
+        let backlit = ColorPalette::fake_backlit(4);
+        assert!(palette.check_compatible(&ColorPalette::fake(6)).is_ok());
+        assert!(palette.check_compatible(&backlit).is_err());
+    }
+
+    /// The staircase with a flat top and a two layer cap, so the exports have three parts
+    fn capped_staircase(config: &PrintConfig, material: CapMaterial) -> PrintObjects {
+        let options = GenerateOptions {
+            backend: MeshBackend::Heightfield,
+            flat_top: true,
+            cap: Some(TopCap { layers: 2, material }),
+            ..GenerateOptions::default()
+        };
+        generate_image(&staircase_layers(), &ColorPalette::fake(4), config, &options)
+    }
+
+    #[test]
+    fn test_binary_stl_layout() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let objects = capped_staircase(&config, CapMaterial::White);
+        let mesh = &objects.white_mesh;
+        let count = mesh.triangles.triangle.len();
+
+        // Header, count, then 50 bytes per facet with the vertices as little endian floats
+        let mut binary = Vec::new();
+        write_stl(mesh, "white", StlFormat::Binary, &mut binary).unwrap();
+        assert_eq!(binary.len(), 84 + 50 * count);
+        assert!(!binary.starts_with(b"solid"));
+        assert_eq!(u32::from_le_bytes(binary[80..84].try_into().unwrap()) as usize, count);
+        let float = |offset: usize| f32::from_le_bytes(binary[offset..offset + 4].try_into().unwrap());
+        let first = &mesh.vertices.vertex[mesh.triangles.triangle[0].v1];
+        assert_eq!([float(96), float(100), float(104)], [first.x as f32, first.y as f32, first.z as f32]);
+    }
+
+    #[test]
+    fn test_ascii_stl_layout() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let objects = capped_staircase(&config, CapMaterial::White);
+        let mesh = &objects.white_mesh;
+        let count = mesh.triangles.triangle.len();
+
+        let mut ascii = Vec::new();
+        write_stl(mesh, "white", StlFormat::Ascii, &mut ascii).unwrap();
+        let ascii = String::from_utf8(ascii).unwrap();
+        assert!(ascii.starts_with("solid white") && ascii.trim_end().ends_with("endsolid white"));
+        assert_eq!(ascii.matches("facet normal").count(), count);
+        assert!(84 + 50 * count < ascii.len());
+        assert_eq!("ascii".parse::<StlFormat>(), Ok(StlFormat::Ascii));
+    }
+
+    #[test]
+    fn test_stl_parts_get_their_own_files() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let objects = capped_staircase(&config, CapMaterial::White);
+
+        let dir = tempfile::tempdir().unwrap();
+        let pattern = dir.path().join("{name}-{part}.stl").to_string_lossy().to_string();
+        let written = export_parts_to_stl(&objects, &pattern, "coaster", StlFormat::Binary).unwrap();
+        let parts: Vec<&str> = written.iter().map(|(part, _)| part.as_str()).collect();
+        assert_eq!(parts, ["black", "white", "cap_white"]);
+        for ((_, path), (_, mesh)) in written.iter().zip(objects.parts()) {
+            assert_eq!(fs::metadata(path).unwrap().len() as usize, 84 + 50 * mesh.triangles.triangle.len());
+        }
+        assert!(written[2].1.ends_with("coaster-cap_white.stl"));
+        assert_eq!(part_path("out/plate.stl", "plate", "black"), std::path::PathBuf::from("out/plate_black.stl"));
+    }
+
+    /// Vertex and triangle counts over all parts
+    fn part_totals(objects: &PrintObjects) -> (usize, usize) {
+        let parts = material_parts(objects);
+        (
+            parts.iter().map(|part| part.mesh.vertices.vertex.len()).sum(),
+            parts.iter().map(|part| part.mesh.triangles.triangle.len()).sum(),
+        )
+    }
+
+    #[test]
+    fn test_material_parts_and_display_colors() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let objects = capped_staircase(&config, CapMaterial::Transparent);
+        let parts = material_parts(&objects);
+        assert_eq!(parts.iter().map(|part| part.material.as_str()).collect::<Vec<_>>(), ["Black", "White", "cap_transparent"]);
+        assert_eq!(parse_display_color("#FF8000"), [255, 128, 0, 255]);
+        assert_eq!(parse_display_color("#FFFFFF80"), [255, 255, 255, 128]);
+        assert_eq!(parse_display_color("nope"), [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn test_obj_export_carries_the_part_colors() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let objects = capped_staircase(&config, CapMaterial::Transparent);
+        let (vertices, triangles) = part_totals(&objects);
+
+        // Faces point at vertices of their own part, counted from 1 across the file
+        let (mut obj, mut mtl) = (Vec::new(), Vec::new());
+        write_obj(&objects, "print.mtl", &mut obj, &mut mtl).unwrap();
+        let (obj, mtl) = (String::from_utf8(obj).unwrap(), String::from_utf8(mtl).unwrap());
+        assert!(obj.contains("mtllib print.mtl") && obj.contains("usemtl cap_transparent"));
+        assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), vertices);
+        let faces: Vec<usize> = obj.lines()
+            .filter_map(|line| line.strip_prefix("f "))
+            .flat_map(|face| face.split(' ').map(|index| index.parse::<usize>().unwrap()).collect::<Vec<_>>())
+            .collect();
+        assert_eq!(faces.len(), 3 * triangles);
+        assert!(faces.iter().all(|&index| (1..=vertices).contains(&index)));
+        assert_eq!(mtl.matches("newmtl").count(), 3);
+        assert!(mtl.contains("Kd 1.0000 1.0000 1.0000") && mtl.contains("d 1.0000"));
+    }
+
+    #[test]
+    fn test_ply_export_carries_the_part_colors() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let objects = capped_staircase(&config, CapMaterial::Transparent);
+        let (vertices, triangles) = part_totals(&objects);
+
+        // Text header, then 16 bytes per vertex and 13 per face
+        let mut ply = Vec::new();
+        write_ply(&objects, &mut ply).unwrap();
+        let header_end = ply.windows(11).position(|window| window == b"end_header\n").unwrap() + 11;
+        let header = String::from_utf8(ply[..header_end].to_vec()).unwrap();
+        assert!(header.contains(&format!("element vertex {}", vertices)) && header.contains(&format!("element face {}", triangles)));
+        assert_eq!(ply.len() - header_end, 16 * vertices + 13 * triangles);
+        let black_vertices = material_parts(&objects)[0].mesh.vertices.vertex.len();
+        let white_color = &ply[header_end + 16 * black_vertices + 12..][..4];
+        assert_eq!(white_color, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_glb_export_carries_the_part_colors() {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, ..PrintConfig::default() };
+        let objects = capped_staircase(&config, CapMaterial::Transparent);
+
+        // Header, JSON and binary chunks, one node and material per part
+        let glb = write_glb(&objects).unwrap();
+        assert_eq!(&glb[..4], b"glTF");
+        let word = |offset: usize| u32::from_le_bytes(glb[offset..offset + 4].try_into().unwrap()) as usize;
+        assert_eq!(word(8), glb.len());
+        let json_length = word(12);
+        assert_eq!(json_length % 4, 0);
+        let document: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+        assert_eq!(document["nodes"].as_array().unwrap().len(), 3);
+        assert_eq!(document["materials"][1]["pbrMetallicRoughness"]["baseColorFactor"], serde_json::json!([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(document["materials"][2]["alphaMode"], "BLEND");
+        let binary_length = word(20 + json_length);
+        assert_eq!(binary_length, glb.len() - 28 - json_length);
+        assert!(document["buffers"][0]["byteLength"].as_u64().unwrap() as usize <= binary_length);
+        let height = document["accessors"][2]["max"][1].as_f64().unwrap();
+        assert!(height > 0.0 && height < 0.02, "{}", height);
+    }
+
+    /// Capped and framed staircase printed at 0.08 mm layers, with black in two parts
+    fn slicer_fixture() -> (PrintConfig, PrintObjects) {
+        let config = PrintConfig { image_size_mm: 60.0, max_layers: 4.0, layer_thickness: 0.08, ..PrintConfig::default() };
+        let options = GenerateOptions {
+            backend: MeshBackend::Heightfield,
+            flat_top: true,
+            cap: Some(TopCap { layers: 2, material: CapMaterial::Transparent }),
+            frame: Some(Frame { width_mm: 3.0, height_mm: None, profile: FrameProfile::Flat, material: FrameMaterial::Black, margin_mm: 0.0 }),
+            ..GenerateOptions::default()
+        };
+        let objects = generate_image(&staircase_layers(), &ColorPalette::fake(4), &config, &options);
+        (config, objects)
+    }
+
+    fn zip_entry(archive: &[u8], name: &str) -> Option<String> {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive.to_vec())).unwrap();
+        let mut file = zip.by_name(name).ok()?;
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut file, &mut contents).unwrap();
+        Some(contents)
+    }
+
+    #[test]
+    fn test_3mf_without_slicer_is_the_bare_model() {
+        let (config, objects) = slicer_fixture();
+        assert!(SlicerSettings::new(SlicerFlavor::None, &config).is_none());
+        assert_eq!(write_3mf_groups(&[(PRINT_OBJECT_NAME, &objects)], None, None).unwrap(), write_3mf(&objects, None).unwrap());
+    }
+
+    #[test]
+    fn test_parts_of_one_color_share_an_extruder() {
+        // Black layers and the black frame share extruder 1, white is 2 and the transparent cap 3
+        let (_, objects) = slicer_fixture();
+        let extruders = assign_extruders(material_parts(&objects).iter().map(|part| part.display_color.as_str()));
+        assert_eq!(extruders.iter().map(|(_, extruder)| *extruder).collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_prusaslicer_settings_are_embedded_in_the_3mf() {
+        let (config, objects) = slicer_fixture();
+        let prusa = SlicerSettings::new(SlicerFlavor::PrusaSlicer, &config).unwrap();
+        let archive = write_3mf_groups(&[(PRINT_OBJECT_NAME, &objects)], Some("recipe"), Some(&prusa)).unwrap();
+        let prusa_config = zip_entry(&archive, "Metadata/Slic3r_PE_model.config").unwrap();
+        assert!(zip_entry(&archive, "Metadata/model_settings.config").is_none());
+        // The settings belong to the parent object, not to the first part
+        assert!(prusa_config.contains("<object id=\"5\" instances_count=\"1\">"));
+        assert!(!prusa_config.contains("<object id=\"1\""));
+        assert!(prusa_config.contains("key=\"layer_height\" value=\"0.08\""));
+        assert!(prusa_config.contains("key=\"fill_density\" value=\"100%\""));
+        let extruder_values: Vec<&str> = prusa_config.lines()
+            .filter(|line| line.contains("key=\"extruder\""))
+            .map(|line| line.split("value=\"").nth(1).unwrap().trim_end_matches("\"/>"))
+            .collect();
+        assert_eq!(extruder_values, ["1", "2", "3", "1"]);
+        // Volumes cover the triangles of all parts back to back
+        let triangles: usize = objects.parts().iter().map(|(_, mesh)| mesh.triangles.triangle.len()).sum();
+        assert!(prusa_config.contains("<volume firstid=\"0\""));
+        assert!(prusa_config.contains(&format!("lastid=\"{}\"", triangles - 1)));
+    }
+
+    #[test]
+    fn test_orca_settings_are_embedded_in_the_3mf() {
+        let (config, objects) = slicer_fixture();
+        let groups = [(PRINT_OBJECT_NAME, &objects)];
+        let orca = SlicerSettings::new("bambu".parse().unwrap(), &config).unwrap();
+        assert_eq!(orca.flavor, SlicerFlavor::Orca);
+        let archive = write_3mf_groups(&groups, None, Some(&orca)).unwrap();
+        let orca_config = zip_entry(&archive, "Metadata/model_settings.config").unwrap();
+        assert!(orca_config.contains("<object id=\"5\">") && orca_config.contains("<part id=\"1\" subtype=\"normal_part\">"));
+        assert!(orca_config.contains("key=\"sparse_infill_density\" value=\"100%\""));
+        assert_eq!(orca_config.matches("<part ").count(), 4);
+        assert_eq!(archive, write_3mf_groups(&groups, None, Some(&orca)).unwrap());
+    }
 }
@@ -0,0 +1,299 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{PrintConfig, PrinterProfile},
+    grayscale::{generate::GenerateOptions, layer_map::LayerMap},
+    mesh::text::Label,
+};
+
+/// Space kept free around every tile on the bed, for the skirt or brim
+const BED_MARGIN: f32 = 5.0;
+/// Edge length each tab stands for, longer edges get more of them
+const TAB_SPACING: f32 = 50.0;
+
+/// How neighbouring tiles lock into each other
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileJoint {
+    /// Straight edges, the tiles are only butted together
+    Flat,
+    /// Square tabs that slot into the neighbour and keep the tiles aligned
+    #[default]
+    Keys,
+    /// Tabs that widen towards their end, the tiles can only be pulled apart upwards
+    Dovetail,
+}
+
+impl fmt::Display for TileJoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileJoint::Flat => write!(f, "flat"),
+            TileJoint::Keys => write!(f, "keys"),
+            TileJoint::Dovetail => write!(f, "dovetail"),
+        }
+    }
+}
+
+impl FromStr for TileJoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "flat" | "none" => Ok(TileJoint::Flat),
+            "keys" | "key" => Ok(TileJoint::Keys),
+            "dovetail" | "dovetails" => Ok(TileJoint::Dovetail),
+            _ => Err(format!("Unknown tile joint '{}', expected flat, keys or dovetail", s)),
+        }
+    }
+}
+
+/// Split an image that is larger than the bed into tiles that each fit it. The image x runs along the bed width.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tiling {
+    pub bed_width_mm: f32,
+    pub bed_depth_mm: f32,
+    #[serde(default)]
+    pub joint: TileJoint,
+    /// How far the tabs reach into the neighbouring tile
+    #[serde(default = "default_joint_size")]
+    pub joint_size_mm: f32,
+    /// Gap left free around every tab in the neighbouring tile so the printed tiles slot together, at least a cell
+    #[serde(default = "default_joint_clearance")]
+    pub joint_clearance_mm: f32,
+    /// Deboss the name of every tile into its back, where the palette allows back labels
+    #[serde(default)]
+    pub name_tiles: bool,
+}
+
+fn default_joint_size() -> f32 {
+    5.0
+}
+
+fn default_joint_clearance() -> f32 {
+    0.2
+}
+
+impl Tiling {
+    pub fn new(bed_width_mm: f32, bed_depth_mm: f32) -> Self {
+        Self {
+            bed_width_mm,
+            bed_depth_mm,
+            joint: TileJoint::default(),
+            joint_size_mm: default_joint_size(),
+            joint_clearance_mm: default_joint_clearance(),
+            name_tiles: false,
+        }
+    }
+
+    /// Tiles as large as the bed of the printer
+    pub fn from_profile(profile: &PrinterProfile) -> Self {
+        Self::new(profile.bed_width, profile.bed_depth)
+    }
+}
+
+/// Part of the image printed on its own. Its layers cover the bounding box of the tile, the cells that belong
+/// to the neighbours are masked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tile {
+    pub column: u32,
+    pub row: u32,
+    /// Top left cell of the tile in the whole image
+    pub origin: (u32, u32),
+    pub layers: LayerMap,
+    /// Size of a cell in mm, the same as in the whole image
+    pub pixel_mm: f32,
+}
+
+impl Tile {
+    /// The whole image as a single tile
+    pub fn whole(layers: &LayerMap, config: &PrintConfig) -> Self {
+        let (width, height) = layers.dimensions();
+        Self { column: 0, row: 0, origin: (0, 0), layers: layers.clone(), pixel_mm: config.pixel_size(width, height).0 }
+    }
+
+    /// Name to tell the tiles apart, row and column counted from 1 at the top left
+    pub fn name(&self) -> String {
+        format!("R{}C{}", self.row + 1, self.column + 1)
+    }
+
+    /// Config that gives the tile the cell size of the whole image
+    pub fn config(&self, config: &PrintConfig) -> PrintConfig {
+        let (width, height) = self.layers.dimensions();
+        PrintConfig {
+            image_size_mm: self.pixel_mm * width.max(height) as f32,
+            ..config.clone()
+        }
+    }
+
+    /// Options for the tile. Frames, labels and mounts belong to the whole image and are left off.
+    /// With `Tiling::name_tiles` the name of the tile goes on its back, above the back label.
+    pub fn options(&self, options: &GenerateOptions, tiling: &Tiling) -> GenerateOptions {
+        let back_label = if tiling.name_tiles {
+            let text = match &options.back_label {
+                Some(label) => format!("{}\n{}", self.name(), label.text),
+                None => self.name(),
+            };
+            let height_mm = options.back_label.as_ref().map_or(Label::new("").height_mm, |label| label.height_mm);
+            Some(Label { text, height_mm })
+        } else {
+            options.back_label.clone()
+        };
+        GenerateOptions {
+            frame: None,
+            mount: None,
+            label: None,
+            back_label,
+            ..options.clone()
+        }
+    }
+
+    /// Copy the cells of the tile back into the whole image, e.g. after the mesh budget changed them
+    pub fn write_back(&self, tile_layers: &LayerMap, layers: &mut LayerMap) {
+        let (width, height) = tile_layers.dimensions();
+        for y in 0..height {
+            for x in 0..width {
+                if let Some(layer_count) = tile_layers.get(x, y) {
+                    layers.set(self.origin.0 + x, self.origin.1 + y, layer_count);
+                }
+            }
+        }
+    }
+}
+
+/// Split the layers into tiles that fit the bed. The image is dithered as a whole before, so the tiles
+/// continue seamlessly into each other. Every cell ends up in at most one tile, only the clearance around the tabs
+/// is in none. Tiles with nothing to print are left out.
+pub fn split_into_tiles(layers: &LayerMap, config: &PrintConfig, tiling: &Tiling) -> Result<Vec<Tile>, String> {
+    let (width, height) = layers.dimensions();
+    let (pixel_mm, _) = config.pixel_size(width, height);
+    let (depth, clearance) = match tiling.joint {
+        TileJoint::Flat => (0, 0),
+        TileJoint::Keys | TileJoint::Dovetail => (
+            (tiling.joint_size_mm / pixel_mm).ceil().max(1.0) as u32,
+            (tiling.joint_clearance_mm / pixel_mm).ceil().max(1.0) as u32,
+        ),
+    };
+
+    // Tabs stick out of the right and bottom edges, the tile without them has to leave room for them
+    let cells_across = |bed: f32| ((bed - 2.0 * BED_MARGIN) / pixel_mm).floor() as i64 - depth as i64;
+    let (max_width, max_height) = (cells_across(tiling.bed_width_mm), cells_across(tiling.bed_depth_mm));
+    if max_width < 2 * depth as i64 + 1 || max_height < 2 * depth as i64 + 1 {
+        return Err(format!(
+            "A {:.0}x{:.0} mm bed is too small for tiles with {:.1} mm joints",
+            tiling.bed_width_mm, tiling.bed_depth_mm, tiling.joint_size_mm
+        ));
+    }
+    let columns = bands(width, max_width as u32);
+    let rows = bands(height, max_height as u32);
+    if columns.len() == 1 && rows.len() == 1 {
+        return Ok(vec![Tile::whole(layers, config)]);
+    }
+
+    let mut owners = vec![None; (width * height) as usize];
+    for (row, &(y0, y1)) in rows.iter().enumerate() {
+        for (column, &(x0, x1)) in columns.iter().enumerate() {
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    owners[layers.index(x, y)] = Some((column as u32, row as u32));
+                }
+            }
+        }
+    }
+
+    // Tabs of the left tile into the right one, placed on the part of the edge away from the corners
+    if depth > 0 {
+        let tab_cells = |length: u32, along: &mut dyn FnMut(u32, u32, u32)| {
+            for (center, half_width) in tabs(length, depth, pixel_mm) {
+                for step in 0..depth {
+                    let half = match tiling.joint {
+                        TileJoint::Dovetail => {
+                            let flare = 0.6 + 0.4 * (step + 1) as f32 / depth as f32;
+                            ((half_width as f32 * flare).round() as u32).max(1)
+                        }
+                        _ => half_width,
+                    };
+                    along(center - half, center + half, step);
+                }
+            }
+        };
+        // Tab cells with the tile they reach into
+        let mut tab_list = Vec::new();
+        for (column, &(_, x1)) in columns.iter().enumerate().take(columns.len() - 1) {
+            for (row, &(y0, y1)) in rows.iter().enumerate() {
+                tab_cells(y1 - y0, &mut |low, high, step| {
+                    for y in y0 + low..y0 + high {
+                        owners[layers.index(x1 + step, y)] = Some((column as u32, row as u32));
+                        tab_list.push((x1 + step, y, (column as u32 + 1, row as u32)));
+                    }
+                });
+            }
+        }
+        for (row, &(_, y1)) in rows.iter().enumerate().take(rows.len() - 1) {
+            for (column, &(x0, x1)) in columns.iter().enumerate() {
+                tab_cells(x1 - x0, &mut |low, high, step| {
+                    for x in x0 + low..x0 + high {
+                        owners[layers.index(x, y1 + step)] = Some((column as u32, row as u32));
+                        tab_list.push((x, y1 + step, (column as u32, row as u32 + 1)));
+                    }
+                });
+            }
+        }
+
+        // The slot in the receiving tile is wider than the tab by the clearance on every side
+        let reach = clearance as i64;
+        for (x, y, receiving) in tab_list {
+            for ny in (y as i64 - reach).max(0)..=(y as i64 + reach).min(height as i64 - 1) {
+                for nx in (x as i64 - reach).max(0)..=(x as i64 + reach).min(width as i64 - 1) {
+                    let index = layers.index(nx as u32, ny as u32);
+                    if owners[index] == Some(receiving) {
+                        owners[index] = None;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut tiles = Vec::new();
+    for (row, &(y0, y1)) in rows.iter().enumerate() {
+        for (column, &(x0, x1)) in columns.iter().enumerate() {
+            let owner = Some((column as u32, row as u32));
+            let right = if column + 1 < columns.len() { (x1 + depth).min(width) } else { x1 };
+            let bottom = if row + 1 < rows.len() { (y1 + depth).min(height) } else { y1 };
+            let mut tile_layers = LayerMap::new(right - x0, bottom - y0, 0);
+            for y in y0..bottom {
+                for x in x0..right {
+                    let (tx, ty) = (x - x0, y - y0);
+                    tile_layers.set(tx, ty, layers.layer(x, y));
+                    tile_layers.set_masked(tx, ty, owners[layers.index(x, y)] != owner || layers.is_masked(x, y));
+                }
+            }
+            if tile_layers.masked().iter().all(|&masked| masked) {
+                continue;
+            }
+            tiles.push(Tile { column: column as u32, row: row as u32, origin: (x0, y0), layers: tile_layers, pixel_mm });
+        }
+    }
+    Ok(tiles)
+}
+
+/// Split `size` cells into the fewest bands of at most `max` cells, as even as possible, as (start, end)
+fn bands(size: u32, max: u32) -> Vec<(u32, u32)> {
+    let count = size.div_ceil(max).max(1);
+    (0..count).map(|band| (band * size / count, (band + 1) * size / count)).collect()
+}
+
+/// Tabs along an edge of `length` cells as (center, half width of the end), one per `TAB_SPACING` mm.
+/// Each keeps `depth` cells away from the ends of the edge, so tabs of crossing edges never meet.
+fn tabs(length: u32, depth: u32, pixel_mm: f32) -> Vec<(u32, u32)> {
+    let half_width = depth;
+    let usable = length.saturating_sub(2 * depth);
+    if usable < 2 * half_width + 1 {
+        return Vec::new();
+    }
+    let count = ((length as f32 * pixel_mm / TAB_SPACING).round() as u32).clamp(1, usable / (2 * half_width + 1));
+    (0..count)
+        .map(|tab| (depth + usable * (2 * tab + 1) / (2 * count), half_width))
+        .collect()
+}
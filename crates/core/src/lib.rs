@@ -1,4 +1,6 @@
 pub mod config;
+pub mod export;
 pub mod utils;
 pub mod mesh;
-pub mod grayscale;
\ No newline at end of file
+pub mod grayscale;
+pub mod recipe;
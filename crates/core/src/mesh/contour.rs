@@ -0,0 +1,388 @@
+use threemf::{model::{Triangle, Vertex}, Mesh};
+
+use crate::mesh::{earcut::earcut, heightfield::ColumnField};
+
+/// Outlines move at most this fraction of a cell when simplified. Marching squares keeps separate outlines
+/// at least 1/√2 cells apart, so two outlines moving towards each other still cannot cross.
+const MAX_TOLERANCE_CELLS: f64 = 0.3;
+
+/// Outer ring counter-clockwise and holes clockwise, in cell units
+#[derive(Debug, Clone, Default)]
+pub(super) struct Polygon {
+    pub(super) outer: Vec<[f64; 2]>,
+    pub(super) holes: Vec<Vec<[f64; 2]>>,
+}
+
+/// Mesh one field as smooth extruded outlines instead of pixel boxes. Slabs between consecutive z levels
+/// with the same footprint form a band, the outlines of every band are traced with marching squares,
+/// simplified by up to `tolerance_mm` and extruded into closed prisms. Diagonal staircases become
+/// straight edges, so curves and mask silhouettes lose their pixel steps.
+pub fn mesh_column_field_contours(field: &ColumnField, tolerance_mm: f64) -> Mesh {
+    let (width, height) = (field.width() as usize, field.height() as usize);
+    let (cell_width, cell_height) = field.cell_size();
+    let tolerance = (tolerance_mm / cell_width.min(cell_height)).clamp(0.0, MAX_TOLERANCE_CELLS);
+    let mut mesh = Mesh::new();
+
+    for (bottom, top, mask) in bands(field) {
+        for polygon in trace_polygons(width, height, &mask, tolerance) {
+            extrude(&mut mesh, &polygon, (cell_width, cell_height), bottom, top);
+        }
+    }
+
+    mesh
+}
+
+/// Footprint of the field between consecutive z levels, slabs with the same footprint are merged
+/// into one band. Returns (bottom, top, row-major mask) from the bottom up.
+pub(super) fn bands(field: &ColumnField) -> Vec<(f64, f64, Vec<bool>)> {
+    let (width, height) = (field.width() as usize, field.height() as usize);
+    let levels = field.z_levels();
+    let slabs = levels.len().saturating_sub(1);
+
+    let mut masks = vec![vec![false; width * height]; slabs];
+    for y in 0..height {
+        for x in 0..width {
+            for span in field.spans(x as u32, y as u32) {
+                for mask in &mut masks[span.bottom as usize..span.top as usize] {
+                    mask[y * width + x] = true;
+                }
+            }
+        }
+    }
+
+    let mut bands = Vec::new();
+    let mut slab = 0;
+    while slab < slabs {
+        let mut end = slab + 1;
+        while end < slabs && masks[end] == masks[slab] {
+            end += 1;
+        }
+        bands.push((levels[slab], levels[end], std::mem::take(&mut masks[slab])));
+        slab = end;
+    }
+
+    bands
+}
+
+/// Outlines of the set cells as polygons with holes. Samples are the cell centers, so outlines run
+/// through the middle of cell edges and cut 45° across corners. Cells touching only diagonally
+/// belong to separate polygons, like the 4-connected features of the printability rules.
+fn trace_polygons(width: usize, height: usize, mask: &[bool], tolerance: f64) -> Vec<Polygon> {
+    let (w, h) = (width as i64, height as i64);
+    let solid = |x: i64, y: i64| x >= 0 && y >= 0 && x < w && y < h && mask[(y * w + x) as usize];
+    // Outline points lie between horizontally neighboring cells (x, y)-(x + 1, y) for x in -1..w,
+    // then between vertically neighboring cells (x, y)-(x, y + 1) for y in -1..h
+    let horizontal_points = (h * (w + 1)) as usize;
+    let horizontal = |x: i64, y: i64| (y * (w + 1) + x + 1) as usize;
+    let vertical = |x: i64, y: i64| horizontal_points + ((y + 1) * w + x) as usize;
+    let point_count = horizontal_points + ((h + 1) * w) as usize;
+
+    // Outline direction keeps the solid on the left, so outer rings are counter-clockwise and holes clockwise
+    let mut next = vec![usize::MAX; point_count];
+    for y in -1..h {
+        for x in -1..w {
+            // Corners counter-clockwise, edge k runs from corner k to corner k + 1
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+            let inside = corners.map(|(cx, cy)| solid(cx, cy));
+            if inside.iter().all(|&solid| solid == inside[0]) {
+                continue;
+            }
+            let edge_point = |k: usize| match k {
+                0 => horizontal(x, y),
+                1 => vertical(x + 1, y),
+                2 => horizontal(x, y + 1),
+                _ => vertical(x, y),
+            };
+
+            for k in 0..4 {
+                if inside[k] && !inside[(k + 1) % 4] {
+                    // The outline goes from edge k to the nearest edge before it that enters the solid,
+                    // in a saddle this cuts around each solid corner separately
+                    let mut j = (k + 3) % 4;
+                    while inside[j] || !inside[(j + 1) % 4] {
+                        j = (j + 3) % 4;
+                    }
+                    next[edge_point(k)] = edge_point(j);
+                }
+            }
+        }
+    }
+
+    let position = |point: usize| -> [f64; 2] {
+        if point < horizontal_points {
+            let (y, x) = (point as i64 / (w + 1), point as i64 % (w + 1) - 1);
+            [x as f64 + 1.0, y as f64 + 0.5]
+        } else {
+            let point = (point - horizontal_points) as i64;
+            let (y, x) = (point / w - 1, point % w);
+            [x as f64 + 0.5, y as f64 + 1.0]
+        }
+    };
+    // The cell on the solid side of an outline point
+    let solid_cell = |point: usize| -> (i64, i64) {
+        let [px, py] = position(point);
+        let candidates = if point < horizontal_points {
+            [(px as i64 - 1, py as i64), (px as i64, py as i64)]
+        } else {
+            [(px as i64, py as i64 - 1), (px as i64, py as i64)]
+        };
+        if solid(candidates[0].0, candidates[0].1) { candidates[0] } else { candidates[1] }
+    };
+
+    let components = label_components(width, height, mask);
+    let mut polygons: Vec<Polygon> = Vec::new();
+    let mut polygon_of_component = vec![usize::MAX; components.1];
+    let mut holes: Vec<(usize, Vec<[f64; 2]>)> = Vec::new();
+
+    let mut visited = vec![false; point_count];
+    for start in 0..point_count {
+        if next[start] == usize::MAX || visited[start] {
+            continue;
+        }
+
+        let mut ring = Vec::new();
+        let mut point = start;
+        while !visited[point] {
+            visited[point] = true;
+            ring.push(position(point));
+            point = next[point];
+        }
+
+        let (cx, cy) = solid_cell(start);
+        let component = components.0[(cy * w + cx) as usize];
+        let is_outer = ring_area(&ring) > 0.0;
+        let ring = simplify_ring(&remove_collinear(&ring), tolerance);
+
+        if is_outer {
+            polygon_of_component[component] = polygons.len();
+            polygons.push(Polygon { outer: ring, holes: Vec::new() });
+        } else {
+            holes.push((component, ring));
+        }
+    }
+
+    for (component, ring) in holes {
+        polygons[polygon_of_component[component]].holes.push(ring);
+    }
+
+    polygons
+}
+
+/// 4-connected components of the set cells, as (label per cell, number of components)
+pub(super) fn label_components(width: usize, height: usize, mask: &[bool]) -> (Vec<usize>, usize) {
+    let mut labels = vec![usize::MAX; mask.len()];
+    let mut count = 0;
+    let mut stack = Vec::new();
+
+    for start in 0..mask.len() {
+        if !mask[start] || labels[start] != usize::MAX {
+            continue;
+        }
+
+        labels[start] = count;
+        stack.push(start);
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            let neighbors = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width),
+                (y + 1 < height).then(|| index + width),
+            ];
+            for neighbor in neighbors.into_iter().flatten() {
+                if mask[neighbor] && labels[neighbor] == usize::MAX {
+                    labels[neighbor] = count;
+                    stack.push(neighbor);
+                }
+            }
+        }
+        count += 1;
+    }
+
+    (labels, count)
+}
+
+/// Twice the signed area, positive for counter-clockwise rings
+pub(super) fn ring_area(ring: &[[f64; 2]]) -> f64 {
+    (0..ring.len())
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            a[0] * b[1] - b[0] * a[1]
+        })
+        .sum()
+}
+
+pub(super) fn cross(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+/// Drop points in the middle of straight runs, outline points are on a half cell grid so the test is exact
+pub(super) fn remove_collinear(ring: &[[f64; 2]]) -> Vec<[f64; 2]> {
+    let n = ring.len();
+    (0..n)
+        .filter(|&i| cross(ring[(i + n - 1) % n], ring[i], ring[(i + 1) % n]) != 0.0)
+        .map(|i| ring[i])
+        .collect()
+}
+
+/// Douglas-Peucker on a closed ring, split at the point farthest from the first one.
+/// Rings that would degenerate or flip keep all their points.
+fn simplify_ring(ring: &[[f64; 2]], tolerance: f64) -> Vec<[f64; 2]> {
+    let n = ring.len();
+    if tolerance <= 0.0 || n <= 4 {
+        return ring.to_vec();
+    }
+
+    let distance2 = |a: [f64; 2], b: [f64; 2]| (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2);
+    let far = (1..n)
+        .max_by(|&a, &b| distance2(ring[0], ring[a]).total_cmp(&distance2(ring[0], ring[b])))
+        .unwrap_or(1);
+
+    let mut keep = vec![false; n];
+    keep[0] = true;
+    keep[far] = true;
+    let mut stack = vec![(0, far), (far, n)];
+    while let Some((start, end)) = stack.pop() {
+        let (a, b) = (ring[start], ring[end % n]);
+        let length = distance2(a, b).sqrt();
+        let mut farthest = (0.0, start);
+        for (i, &point) in ring.iter().enumerate().take(end).skip(start + 1) {
+            let distance = if length > 0.0 {
+                cross(a, b, point).abs() / length
+            } else {
+                distance2(a, point).sqrt()
+            };
+            if distance > farthest.0 {
+                farthest = (distance, i);
+            }
+        }
+        if farthest.0 > tolerance {
+            keep[farthest.1] = true;
+            stack.push((start, farthest.1));
+            stack.push((farthest.1, end));
+        }
+    }
+
+    let simplified: Vec<[f64; 2]> = ring.iter()
+        .zip(&keep)
+        .filter(|(_, &keep)| keep)
+        .map(|(&point, _)| point)
+        .collect();
+    if simplified.len() < 3 || ring_area(&simplified).signum() != ring_area(ring).signum() {
+        ring.to_vec()
+    } else {
+        simplified
+    }
+}
+
+/// Closed prism of the polygon between two heights, caps share their vertices with the walls
+fn extrude(mesh: &mut Mesh, polygon: &Polygon, cell_size: (f64, f64), bottom: f64, top: f64) {
+    let (points, next, caps) = triangulate(polygon);
+    let walls: Vec<(usize, usize)> = next.iter().copied().enumerate().collect();
+    push_prism(mesh, &points, &walls, &caps, cell_size, bottom, top);
+}
+
+/// Cap triangles of the polygon, counter-clockwise. Returns the points of all rings (and any added
+/// to split triangles), the successor of every ring point along its ring, and the triangles.
+pub(super) fn triangulate(polygon: &Polygon) -> (Vec<[f64; 2]>, Vec<usize>, Vec<[usize; 3]>) {
+    let mut points: Vec<[f64; 2]> = polygon.outer.clone();
+    let mut ring_starts = vec![0];
+    for hole in &polygon.holes {
+        ring_starts.push(points.len());
+        points.extend_from_slice(hole);
+    }
+    ring_starts.push(points.len());
+
+    // Successor of every point along its ring
+    let mut next = vec![0; points.len()];
+    for ring in ring_starts.windows(2) {
+        let (start, end) = (ring[0], ring[1]);
+        for (offset, successor) in next[start..end].iter_mut().enumerate() {
+            *successor = start + (offset + 1) % (end - start);
+        }
+    }
+
+    let mut caps = Vec::new();
+    for [a, b, c] in earcut(&points, &ring_starts[1..ring_starts.len() - 1]) {
+        if a == b || b == c || a == c {
+            continue;
+        }
+        let triangle = if cross(points[a], points[b], points[c]) < 0.0 { [a, c, b] } else { [a, b, c] };
+        split_skipped_points(triangle, &mut points, |points, from, to| skipped_ring_points(points, &next, from, to), &mut caps);
+    }
+
+    (points, next, caps)
+}
+
+/// Bottom and top cap plus a wall along every directed ring edge
+pub(super) fn push_prism(mesh: &mut Mesh, points: &[[f64; 2]], walls: &[(usize, usize)], caps: &[[usize; 3]], cell_size: (f64, f64), bottom: f64, top: f64) {
+    let base = mesh.vertices.vertex.len();
+    let count = points.len();
+    for z in [bottom, top] {
+        for point in points {
+            mesh.vertices.vertex.push(Vertex { x: point[0] * cell_size.0, y: point[1] * cell_size.1, z });
+        }
+    }
+
+    let triangles = &mut mesh.triangles.triangle;
+    for &[a, b, c] in caps {
+        triangles.push(Triangle { v1: base + a, v2: base + c, v3: base + b });
+        triangles.push(Triangle { v1: base + count + a, v2: base + count + b, v3: base + count + c });
+    }
+    // Walls face to the right of the ring direction, which is away from the solid
+    for &(p, q) in walls {
+        let (pb, qb, pt, qt) = (base + p, base + q, base + count + p, base + count + q);
+        triangles.push(Triangle { v1: pb, v2: qb, v3: qt });
+        triangles.push(Triangle { v1: pb, v2: qt, v3: pt });
+    }
+}
+
+/// Points earcut dropped between `from` and `to`, when the edge runs along a ring. Boundary edges
+/// of a counter-clockwise cap triangle run along the ring direction.
+fn skipped_ring_points(points: &[[f64; 2]], next: &[usize], from: usize, to: usize) -> Vec<usize> {
+    let mut between = Vec::new();
+    let mut point = next[from];
+    while point != to && between.len() < next.len() && cross(points[from], points[point], points[to]) == 0.0 {
+        between.push(point);
+        point = next[point];
+    }
+    if point == to { between } else { Vec::new() }
+}
+
+/// Earcut drops collinear points it comes across, the walls still use them. Split cap triangles
+/// whose edges skip such points so caps and walls meet in the same edges.
+/// `skipped(points, from, to)` lists the points strictly inside an edge, in order from `from`.
+pub(super) fn split_skipped_points<F>(triangle: [usize; 3], points: &mut Vec<[f64; 2]>, skipped: F, caps: &mut Vec<[usize; 3]>)
+where
+    F: Fn(&[[f64; 2]], usize, usize) -> Vec<usize>,
+{
+    let edges: Vec<Vec<usize>> = (0..3).map(|k| skipped(points, triangle[k], triangle[(k + 1) % 3])).collect();
+    let split: Vec<usize> = (0..3).filter(|&k| !edges[k].is_empty()).collect();
+    match split.as_slice() {
+        [] => caps.push(triangle),
+        [k] => {
+            // Fan from the corner opposite the split edge
+            let apex = triangle[(k + 2) % 3];
+            let mut chain = vec![triangle[*k]];
+            chain.extend(&edges[*k]);
+            chain.push(triangle[(k + 1) % 3]);
+            for pair in chain.windows(2) {
+                caps.push([apex, pair[0], pair[1]]);
+            }
+        }
+        _ => {
+            // Fan from a new point in the middle
+            let center = points.len();
+            let [a, b, c] = triangle.map(|i| points[i]);
+            points.push([(a[0] + b[0] + c[0]) / 3.0, (a[1] + b[1] + c[1]) / 3.0]);
+            let mut outline = Vec::new();
+            for k in 0..3 {
+                outline.push(triangle[k]);
+                outline.extend(&edges[k]);
+            }
+            for i in 0..outline.len() {
+                caps.push([center, outline[i], outline[(i + 1) % outline.len()]]);
+            }
+        }
+    }
+}
@@ -0,0 +1,635 @@
+// Ported from mapbox/earcut (https://github.com/mapbox/earcut), under its license:
+//
+// ISC License
+//
+// Copyright (c) 2016, Mapbox
+//
+// Permission to use, copy, modify, and/or distribute this software for any purpose
+// with or without fee is hereby granted, provided that the above copyright notice
+// and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND ISC DISCLAIMS ALL WARRANTIES WITH REGARD TO
+// THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS.
+// IN NO EVENT SHALL ISC BE LIABLE FOR ANY SPECIAL, DIRECT, INDIRECT, OR
+// CONSEQUENTIAL DAMAGES OR ANY DAMAGES WHATSOEVER RESULTING FROM LOSS OF USE, DATA
+// OR PROFITS, WHETHER IN AN ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION,
+// ARISING OUT OF OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+
+/// Triangulate a polygon with holes by ear clipping, a port of mapbox/earcut.
+/// `points` holds the outer ring followed by the holes, `hole_starts` the index of the first point of every hole.
+/// Rings may wind either way. Returns triangles as point indices, no points are added.
+pub(crate) fn earcut(points: &[[f64; 2]], hole_starts: &[usize]) -> Vec<[usize; 3]> {
+    let mut earcut = Earcut { nodes: Vec::with_capacity(points.len() * 3 / 2), triangles: Vec::new() };
+    let outer_end = hole_starts.first().copied().unwrap_or(points.len());
+    let Some(mut outer) = earcut.linked_list(points, 0, outer_end, true) else {
+        return Vec::new();
+    };
+    if earcut.next(outer) == earcut.prev(outer) {
+        return Vec::new();
+    }
+
+    if !hole_starts.is_empty() {
+        outer = earcut.eliminate_holes(points, hole_starts, outer);
+    }
+
+    // Larger shapes look up points through a z-order curve instead of walking the whole ring
+    let mut hash = None;
+    if points.len() > 80 {
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for point in &points[..outer_end] {
+            min_x = min_x.min(point[0]);
+            min_y = min_y.min(point[1]);
+            max_x = max_x.max(point[0]);
+            max_y = max_y.max(point[1]);
+        }
+        let size = (max_x - min_x).max(max_y - min_y);
+        if size > 0.0 {
+            hash = Some(ZHash { min_x, min_y, inv_size: 32767.0 / size });
+        }
+    }
+
+    earcut.earcut_linked(Some(outer), hash, 0);
+    earcut.triangles
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    /// Index of the point
+    i: usize,
+    x: f64,
+    y: f64,
+    prev: usize,
+    next: usize,
+    z: u32,
+    prev_z: Option<usize>,
+    next_z: Option<usize>,
+    steiner: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ZHash {
+    min_x: f64,
+    min_y: f64,
+    inv_size: f64,
+}
+
+impl ZHash {
+    /// Interleave the scaled coordinates into a z-order (Morton) code
+    fn z_order(&self, x: f64, y: f64) -> u32 {
+        let spread = |value: f64| {
+            let mut v = value as u32;
+            v = (v | (v << 8)) & 0x00FF00FF;
+            v = (v | (v << 4)) & 0x0F0F0F0F;
+            v = (v | (v << 2)) & 0x33333333;
+            (v | (v << 1)) & 0x55555555
+        };
+        spread((x - self.min_x) * self.inv_size) | (spread((y - self.min_y) * self.inv_size) << 1)
+    }
+}
+
+struct Earcut {
+    nodes: Vec<Node>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl Earcut {
+    fn next(&self, node: usize) -> usize {
+        self.nodes[node].next
+    }
+
+    fn prev(&self, node: usize) -> usize {
+        self.nodes[node].prev
+    }
+
+    fn xy(&self, node: usize) -> (f64, f64) {
+        (self.nodes[node].x, self.nodes[node].y)
+    }
+
+    /// Circular doubly linked list of a ring in the requested winding
+    fn linked_list(&mut self, points: &[[f64; 2]], start: usize, end: usize, clockwise: bool) -> Option<usize> {
+        let mut last = None;
+        if clockwise == (signed_area(&points[start..end]) > 0.0) {
+            for (offset, &point) in points[start..end].iter().enumerate() {
+                last = Some(self.insert_node(start + offset, point, last));
+            }
+        } else {
+            for (offset, &point) in points[start..end].iter().enumerate().rev() {
+                last = Some(self.insert_node(start + offset, point, last));
+            }
+        }
+
+        if let Some(node) = last {
+            if self.equals(node, self.next(node)) {
+                let next = self.next(node);
+                self.remove_node(node);
+                last = Some(next);
+            }
+        }
+        last
+    }
+
+    /// Drop duplicate and collinear points
+    fn filter_points(&mut self, start: usize, end: Option<usize>) -> usize {
+        let mut end = end.unwrap_or(start);
+        let mut p = start;
+        loop {
+            let mut again = false;
+            if !self.nodes[p].steiner && (self.equals(p, self.next(p)) || self.area(self.prev(p), p, self.next(p)) == 0.0) {
+                self.remove_node(p);
+                p = self.prev(p);
+                end = p;
+                if p == self.next(p) {
+                    break;
+                }
+                again = true;
+            } else {
+                p = self.next(p);
+            }
+            if !again && p == end {
+                break;
+            }
+        }
+        end
+    }
+
+    fn earcut_linked(&mut self, ear: Option<usize>, hash: Option<ZHash>, pass: u8) {
+        let Some(mut ear) = ear else {
+            return;
+        };
+        if pass == 0 {
+            if let Some(hash) = hash {
+                self.index_curve(ear, hash);
+            }
+        }
+
+        let mut stop = ear;
+        while self.prev(ear) != self.next(ear) {
+            let prev = self.prev(ear);
+            let next = self.next(ear);
+
+            let is_ear = match hash {
+                Some(hash) => self.is_ear_hashed(ear, hash),
+                None => self.is_ear(ear),
+            };
+            if is_ear {
+                self.triangles.push([self.nodes[prev].i, self.nodes[ear].i, self.nodes[next].i]);
+                self.remove_node(ear);
+                ear = self.next(next);
+                stop = ear;
+                continue;
+            }
+
+            ear = next;
+            if ear == stop {
+                // No ears left, try to recover before giving up
+                match pass {
+                    0 => {
+                        let filtered = self.filter_points(ear, None);
+                        self.earcut_linked(Some(filtered), hash, 1);
+                    }
+                    1 => {
+                        let filtered = self.filter_points(ear, None);
+                        let cured = self.cure_local_intersections(filtered);
+                        self.earcut_linked(Some(cured), hash, 2);
+                    }
+                    _ => self.split_earcut(ear, hash),
+                }
+                break;
+            }
+        }
+    }
+
+    fn is_ear(&self, ear: usize) -> bool {
+        let (a, b, c) = (self.prev(ear), ear, self.next(ear));
+        if self.area(a, b, c) >= 0.0 {
+            return false;
+        }
+
+        let (bounds_min, bounds_max) = self.triangle_bounds(a, b, c);
+        let mut p = self.next(c);
+        while p != a {
+            if self.blocks_ear(p, a, b, c, bounds_min, bounds_max) {
+                return false;
+            }
+            p = self.next(p);
+        }
+        true
+    }
+
+    fn is_ear_hashed(&self, ear: usize, hash: ZHash) -> bool {
+        let (a, b, c) = (self.prev(ear), ear, self.next(ear));
+        if self.area(a, b, c) >= 0.0 {
+            return false;
+        }
+
+        let (bounds_min, bounds_max) = self.triangle_bounds(a, b, c);
+        let min_z = hash.z_order(bounds_min.0, bounds_min.1);
+        let max_z = hash.z_order(bounds_max.0, bounds_max.1);
+
+        // Look in both directions along the z-order curve
+        let mut p = self.nodes[ear].prev_z;
+        let mut n = self.nodes[ear].next_z;
+        while let (Some(pi), Some(ni)) = (p, n) {
+            if self.nodes[pi].z < min_z || self.nodes[ni].z > max_z {
+                break;
+            }
+            if pi != a && pi != c && self.blocks_ear(pi, a, b, c, bounds_min, bounds_max) {
+                return false;
+            }
+            p = self.nodes[pi].prev_z;
+            if ni != a && ni != c && self.blocks_ear(ni, a, b, c, bounds_min, bounds_max) {
+                return false;
+            }
+            n = self.nodes[ni].next_z;
+        }
+        while let Some(pi) = p {
+            if self.nodes[pi].z < min_z {
+                break;
+            }
+            if pi != a && pi != c && self.blocks_ear(pi, a, b, c, bounds_min, bounds_max) {
+                return false;
+            }
+            p = self.nodes[pi].prev_z;
+        }
+        while let Some(ni) = n {
+            if self.nodes[ni].z > max_z {
+                break;
+            }
+            if ni != a && ni != c && self.blocks_ear(ni, a, b, c, bounds_min, bounds_max) {
+                return false;
+            }
+            n = self.nodes[ni].next_z;
+        }
+        true
+    }
+
+    fn triangle_bounds(&self, a: usize, b: usize, c: usize) -> ((f64, f64), (f64, f64)) {
+        let ((ax, ay), (bx, by), (cx, cy)) = (self.xy(a), self.xy(b), self.xy(c));
+        ((ax.min(bx).min(cx), ay.min(by).min(cy)), (ax.max(bx).max(cx), ay.max(by).max(cy)))
+    }
+
+    /// Whether a reflex point lies inside the candidate ear
+    fn blocks_ear(&self, p: usize, a: usize, b: usize, c: usize, bounds_min: (f64, f64), bounds_max: (f64, f64)) -> bool {
+        let (px, py) = self.xy(p);
+        px >= bounds_min.0 && px <= bounds_max.0 && py >= bounds_min.1 && py <= bounds_max.1
+            && point_in_triangle(self.xy(a), self.xy(b), self.xy(c), (px, py))
+            && self.area(self.prev(p), p, self.next(p)) >= 0.0
+    }
+
+    /// Clip the small self-intersections filtering can leave behind
+    fn cure_local_intersections(&mut self, start: usize) -> usize {
+        let mut start = start;
+        let mut p = start;
+        loop {
+            let a = self.prev(p);
+            let b = self.next(self.next(p));
+            if !self.equals(a, b)
+                && self.intersects(a, p, self.next(p), b)
+                && self.locally_inside(a, b)
+                && self.locally_inside(b, a)
+            {
+                self.triangles.push([self.nodes[a].i, self.nodes[p].i, self.nodes[b].i]);
+                let next = self.next(p);
+                self.remove_node(p);
+                self.remove_node(next);
+                p = b;
+                start = b;
+            }
+            p = self.next(p);
+            if p == start {
+                break;
+            }
+        }
+        self.filter_points(p, None)
+    }
+
+    /// Split the ring along a valid diagonal and triangulate both halves
+    fn split_earcut(&mut self, start: usize, hash: Option<ZHash>) {
+        let mut a = start;
+        loop {
+            let mut b = self.next(self.next(a));
+            while b != self.prev(a) {
+                if self.nodes[a].i != self.nodes[b].i && self.is_valid_diagonal(a, b) {
+                    let c = self.split_polygon(a, b);
+                    let a = self.filter_points(a, Some(self.next(a)));
+                    let c = self.filter_points(c, Some(self.next(c)));
+                    self.earcut_linked(Some(a), hash, 0);
+                    self.earcut_linked(Some(c), hash, 0);
+                    return;
+                }
+                b = self.next(b);
+            }
+            a = self.next(a);
+            if a == start {
+                break;
+            }
+        }
+    }
+
+    /// Connect every hole to the outer ring with a bridge, holes are handled from left to right
+    fn eliminate_holes(&mut self, points: &[[f64; 2]], hole_starts: &[usize], mut outer: usize) -> usize {
+        let mut queue = Vec::with_capacity(hole_starts.len());
+        for (index, &start) in hole_starts.iter().enumerate() {
+            let end = hole_starts.get(index + 1).copied().unwrap_or(points.len());
+            if let Some(list) = self.linked_list(points, start, end, false) {
+                if list == self.next(list) {
+                    self.nodes[list].steiner = true;
+                }
+                queue.push(self.leftmost(list));
+            }
+        }
+        queue.sort_by(|&a, &b| self.nodes[a].x.total_cmp(&self.nodes[b].x));
+
+        for hole in queue {
+            outer = self.eliminate_hole(hole, outer);
+        }
+        outer
+    }
+
+    fn eliminate_hole(&mut self, hole: usize, outer: usize) -> usize {
+        let Some(bridge) = self.find_hole_bridge(hole, outer) else {
+            return outer;
+        };
+        let bridge_reverse = self.split_polygon(bridge, hole);
+        self.filter_points(bridge_reverse, Some(self.next(bridge_reverse)));
+        self.filter_points(bridge, Some(self.next(bridge)))
+    }
+
+    /// Point of the outer ring the hole can be connected to without crossing any edge
+    fn find_hole_bridge(&self, hole: usize, outer: usize) -> Option<usize> {
+        let (hx, hy) = self.xy(hole);
+        let mut qx = f64::NEG_INFINITY;
+        let mut m = None;
+
+        // Nearest edge to the left of the hole point, on a ray going left
+        let mut p = outer;
+        loop {
+            let (px, py) = self.xy(p);
+            let (nx, ny) = self.xy(self.next(p));
+            if hy <= py && hy >= ny && ny != py {
+                let x = px + (hy - py) * (nx - px) / (ny - py);
+                if x <= hx && x > qx {
+                    qx = x;
+                    m = Some(if px < nx { p } else { self.next(p) });
+                    if x == hx {
+                        return m;
+                    }
+                }
+            }
+            p = self.next(p);
+            if p == outer {
+                break;
+            }
+        }
+
+        let mut m = m?;
+        // Points inside the triangle of the hole point, the intersection and the edge end could block the bridge,
+        // take the one with the smallest angle to the ray
+        let stop = m;
+        let (mx, my) = self.xy(m);
+        let mut tan_min = f64::INFINITY;
+        p = m;
+        loop {
+            let (px, py) = self.xy(p);
+            let triangle = if hy < my {
+                ((hx, hy), (mx, my), (qx, hy))
+            } else {
+                ((qx, hy), (mx, my), (hx, hy))
+            };
+            if hx >= px && px >= mx && hx != px && point_in_triangle(triangle.0, triangle.1, triangle.2, (px, py)) {
+                let tan = (hy - py).abs() / (hx - px);
+                let m_x = self.nodes[m].x;
+                if self.locally_inside(p, hole)
+                    && (tan < tan_min || (tan == tan_min && (px > m_x || (px == m_x && self.sector_contains_sector(m, p)))))
+                {
+                    m = p;
+                    tan_min = tan;
+                }
+            }
+            p = self.next(p);
+            if p == stop {
+                break;
+            }
+        }
+        Some(m)
+    }
+
+    fn sector_contains_sector(&self, m: usize, p: usize) -> bool {
+        self.area(self.prev(m), m, self.prev(p)) < 0.0 && self.area(self.next(p), m, self.next(m)) < 0.0
+    }
+
+    fn index_curve(&mut self, start: usize, hash: ZHash) {
+        let mut ring = Vec::new();
+        let mut p = start;
+        loop {
+            if self.nodes[p].z == 0 {
+                self.nodes[p].z = hash.z_order(self.nodes[p].x, self.nodes[p].y);
+            }
+            ring.push(p);
+            p = self.next(p);
+            if p == start {
+                break;
+            }
+        }
+
+        ring.sort_by_key(|&node| self.nodes[node].z);
+        for (index, &node) in ring.iter().enumerate() {
+            self.nodes[node].prev_z = index.checked_sub(1).map(|previous| ring[previous]);
+            self.nodes[node].next_z = ring.get(index + 1).copied();
+        }
+    }
+
+    fn leftmost(&self, start: usize) -> usize {
+        let mut p = start;
+        let mut leftmost = start;
+        loop {
+            let (px, py) = self.xy(p);
+            let (lx, ly) = self.xy(leftmost);
+            if px < lx || (px == lx && py < ly) {
+                leftmost = p;
+            }
+            p = self.next(p);
+            if p == start {
+                break;
+            }
+        }
+        leftmost
+    }
+
+    fn is_valid_diagonal(&self, a: usize, b: usize) -> bool {
+        let (na, nb) = (&self.nodes[a], &self.nodes[b]);
+        self.nodes[na.next].i != nb.i
+            && self.nodes[na.prev].i != nb.i
+            && !self.intersects_polygon(a, b)
+            && ((self.locally_inside(a, b)
+                && self.locally_inside(b, a)
+                && self.middle_inside(a, b)
+                && (self.area(na.prev, a, nb.prev) != 0.0 || self.area(a, nb.prev, b) != 0.0))
+                || (self.equals(a, b) && self.area(na.prev, a, na.next) > 0.0 && self.area(nb.prev, b, nb.next) > 0.0))
+    }
+
+    /// Twice the signed area of a triangle, negative when it turns the way the outer ring winds
+    fn area(&self, p: usize, q: usize, r: usize) -> f64 {
+        let ((px, py), (qx, qy), (rx, ry)) = (self.xy(p), self.xy(q), self.xy(r));
+        (qy - py) * (rx - qx) - (qx - px) * (ry - qy)
+    }
+
+    fn equals(&self, a: usize, b: usize) -> bool {
+        self.xy(a) == self.xy(b)
+    }
+
+    fn intersects(&self, p1: usize, q1: usize, p2: usize, q2: usize) -> bool {
+        let o1 = sign(self.area(p1, q1, p2));
+        let o2 = sign(self.area(p1, q1, q2));
+        let o3 = sign(self.area(p2, q2, p1));
+        let o4 = sign(self.area(p2, q2, q1));
+
+        (o1 != o2 && o3 != o4)
+            || (o1 == 0 && self.on_segment(p1, p2, q1))
+            || (o2 == 0 && self.on_segment(p1, q2, q1))
+            || (o3 == 0 && self.on_segment(p2, p1, q2))
+            || (o4 == 0 && self.on_segment(p2, q1, q2))
+    }
+
+    /// Whether `q` lies on segment `pr`, given the three are collinear
+    fn on_segment(&self, p: usize, q: usize, r: usize) -> bool {
+        let ((px, py), (qx, qy), (rx, ry)) = (self.xy(p), self.xy(q), self.xy(r));
+        qx <= px.max(rx) && qx >= px.min(rx) && qy <= py.max(ry) && qy >= py.min(ry)
+    }
+
+    fn intersects_polygon(&self, a: usize, b: usize) -> bool {
+        let (ai, bi) = (self.nodes[a].i, self.nodes[b].i);
+        let mut p = a;
+        loop {
+            let next = self.next(p);
+            let (pi, ni) = (self.nodes[p].i, self.nodes[next].i);
+            if pi != ai && ni != ai && pi != bi && ni != bi && self.intersects(p, next, a, b) {
+                return true;
+            }
+            p = next;
+            if p == a {
+                break;
+            }
+        }
+        false
+    }
+
+    fn locally_inside(&self, a: usize, b: usize) -> bool {
+        let (prev, next) = (self.prev(a), self.next(a));
+        if self.area(prev, a, next) < 0.0 {
+            self.area(a, b, next) >= 0.0 && self.area(a, prev, b) >= 0.0
+        } else {
+            self.area(a, b, prev) < 0.0 || self.area(a, next, b) < 0.0
+        }
+    }
+
+    /// Whether the middle of the diagonal is inside the polygon
+    fn middle_inside(&self, a: usize, b: usize) -> bool {
+        let ((ax, ay), (bx, by)) = (self.xy(a), self.xy(b));
+        let (mx, my) = ((ax + bx) / 2.0, (ay + by) / 2.0);
+        let mut inside = false;
+        let mut p = a;
+        loop {
+            let (px, py) = self.xy(p);
+            let (nx, ny) = self.xy(self.next(p));
+            if (py > my) != (ny > my) && ny != py && mx < (nx - px) * (my - py) / (ny - py) + px {
+                inside = !inside;
+            }
+            p = self.next(p);
+            if p == a {
+                break;
+            }
+        }
+        inside
+    }
+
+    /// Link `a` and `b` with a diagonal, splitting the ring in two. Returns the copy of `b` in the second ring.
+    fn split_polygon(&mut self, a: usize, b: usize) -> usize {
+        let a2 = self.new_node(self.nodes[a].i, self.xy(a));
+        let b2 = self.new_node(self.nodes[b].i, self.xy(b));
+        let an = self.next(a);
+        let bp = self.prev(b);
+
+        self.nodes[a].next = b;
+        self.nodes[b].prev = a;
+
+        self.nodes[a2].next = an;
+        self.nodes[an].prev = a2;
+
+        self.nodes[b2].next = a2;
+        self.nodes[a2].prev = b2;
+
+        self.nodes[bp].next = b2;
+        self.nodes[b2].prev = bp;
+
+        b2
+    }
+
+    fn new_node(&mut self, i: usize, (x, y): (f64, f64)) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            i,
+            x,
+            y,
+            prev: index,
+            next: index,
+            z: 0,
+            prev_z: None,
+            next_z: None,
+            steiner: false,
+        });
+        index
+    }
+
+    fn insert_node(&mut self, i: usize, point: [f64; 2], last: Option<usize>) -> usize {
+        let node = self.new_node(i, (point[0], point[1]));
+        if let Some(last) = last {
+            let next = self.next(last);
+            self.nodes[node].next = next;
+            self.nodes[node].prev = last;
+            self.nodes[next].prev = node;
+            self.nodes[last].next = node;
+        }
+        node
+    }
+
+    fn remove_node(&mut self, p: usize) {
+        let Node { prev, next, prev_z, next_z, .. } = self.nodes[p];
+        self.nodes[next].prev = prev;
+        self.nodes[prev].next = next;
+        if let Some(prev_z) = prev_z {
+            self.nodes[prev_z].next_z = next_z;
+        }
+        if let Some(next_z) = next_z {
+            self.nodes[next_z].prev_z = prev_z;
+        }
+    }
+}
+
+/// Twice the signed area of a ring, in the winding convention of `Earcut::area`
+fn signed_area(points: &[[f64; 2]]) -> f64 {
+    let mut sum = 0.0;
+    let mut j = points.len().wrapping_sub(1);
+    for (i, point) in points.iter().enumerate() {
+        let previous = points[j];
+        sum += (previous[0] - point[0]) * (point[1] + previous[1]);
+        j = i;
+    }
+    sum
+}
+
+fn point_in_triangle(a: (f64, f64), b: (f64, f64), c: (f64, f64), p: (f64, f64)) -> bool {
+    (c.0 - p.0) * (a.1 - p.1) >= (a.0 - p.0) * (c.1 - p.1)
+        && (a.0 - p.0) * (b.1 - p.1) >= (b.0 - p.0) * (a.1 - p.1)
+        && (b.0 - p.0) * (c.1 - p.1) >= (c.0 - p.0) * (b.1 - p.1)
+}
+
+fn sign(value: f64) -> i8 {
+    if value > 0.0 {
+        1
+    } else if value < 0.0 {
+        -1
+    } else {
+        0
+    }
+}
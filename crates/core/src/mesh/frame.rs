@@ -0,0 +1,189 @@
+use std::{f32::consts::FRAC_PI_2, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use threemf::{model::{Triangle, Vertex}, Mesh};
+
+use crate::utils::MaterialMesh;
+
+/// Segments of a rounded edge, enough for a few mm of radius
+const ARC_SEGMENTS: usize = 8;
+
+/// Cross-section of the frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameProfile {
+    /// Square bar
+    #[default]
+    Flat,
+    /// Inner top edge chamfered at 45°, the frame slopes down towards the image
+    Bevel,
+    /// Both top edges rounded, a half round once the frame is twice as high as wide
+    Rounded,
+}
+
+impl fmt::Display for FrameProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameProfile::Flat => write!(f, "flat"),
+            FrameProfile::Bevel => write!(f, "bevel"),
+            FrameProfile::Rounded => write!(f, "rounded"),
+        }
+    }
+}
+
+impl FromStr for FrameProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "flat" => Ok(FrameProfile::Flat),
+            "bevel" => Ok(FrameProfile::Bevel),
+            "rounded" | "round" => Ok(FrameProfile::Rounded),
+            _ => Err(format!("Unknown frame profile '{}', expected flat, bevel or rounded", s)),
+        }
+    }
+}
+
+/// Filament of the frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameMaterial {
+    #[default]
+    Black,
+    White,
+}
+
+impl FrameMaterial {
+    /// Object and material name in the exported files
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameMaterial::Black => "frame_black",
+            FrameMaterial::White => "frame_white",
+        }
+    }
+
+    /// Color the slicer shows for the frame
+    pub fn display_color(&self) -> &'static str {
+        match self {
+            FrameMaterial::Black => "#000000",
+            FrameMaterial::White => "#FFFFFF",
+        }
+    }
+}
+
+impl fmt::Display for FrameMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameMaterial::Black => write!(f, "black"),
+            FrameMaterial::White => write!(f, "white"),
+        }
+    }
+}
+
+impl FromStr for FrameMaterial {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "black" => Ok(FrameMaterial::Black),
+            "white" => Ok(FrameMaterial::White),
+            _ => Err(format!("Unknown frame material '{}', expected black or white", s)),
+        }
+    }
+}
+
+/// Frame around the image, printed as its own part
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub width_mm: f32,
+    /// Height above the build plate, `None` is level with the highest point of the image
+    #[serde(default)]
+    pub height_mm: Option<f32>,
+    #[serde(default)]
+    pub profile: FrameProfile,
+    #[serde(default)]
+    pub material: FrameMaterial,
+    /// Bare base between the image and the frame
+    #[serde(default)]
+    pub margin_mm: f32,
+}
+
+impl Frame {
+    /// How far the frame reaches out from the image on every side
+    pub fn extent(&self) -> f32 {
+        self.margin_mm + self.width_mm
+    }
+
+    /// Wrap the frame geometry for export
+    pub fn material_mesh(&self, mesh: Mesh) -> MaterialMesh {
+        MaterialMesh {
+            name: self.material.name().to_string(),
+            display_color: self.material.display_color().to_string(),
+            mesh,
+        }
+    }
+
+    /// Cross-section as (distance out from the inner edge, height), counter-clockwise
+    fn cross_section(&self, height: f32) -> Vec<(f32, f32)> {
+        let width = self.width_mm;
+        let mut points = vec![(0.0, 0.0), (width, 0.0)];
+        match self.profile {
+            FrameProfile::Flat => points.extend([(width, height), (0.0, height)]),
+            FrameProfile::Bevel => {
+                let bevel = width.min(height) / 2.0;
+                points.extend([(width, height), (bevel, height), (0.0, height - bevel)]);
+            }
+            FrameProfile::Rounded => {
+                let radius = (width / 2.0).min(height);
+                for (center, from) in [(width - radius, 0.0), (radius, FRAC_PI_2)] {
+                    for step in 0..=ARC_SEGMENTS {
+                        let angle = from + FRAC_PI_2 * step as f32 / ARC_SEGMENTS as f32;
+                        points.push((center + radius * angle.cos(), height - radius + radius * angle.sin()));
+                    }
+                }
+            }
+        }
+
+        // Corners collapse where the radius or bevel takes up the whole side
+        points.dedup_by(|a, b| (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6);
+        if points.len() > 1 && points.first().zip(points.last()).is_some_and(|(a, b)| (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6) {
+            points.pop();
+        }
+        points
+    }
+}
+
+/// Frame around the rectangle `inner` as (min x, min y, max x, max y), `margin_mm` away from it.
+/// `height` is the frame height when `Frame::height_mm` is not set.
+pub fn generate_frame(inner: [f32; 4], frame: &Frame, height: f32) -> Mesh {
+    let height = frame.height_mm.unwrap_or(height);
+    let [x0, y0, x1, y1] = inner;
+    let margin = frame.margin_mm;
+    let mut mesh = Mesh::new();
+    sweep_ring(&mut mesh, [x0 - margin, y0 - margin, x1 + margin, y1 + margin], &frame.cross_section(height));
+    mesh
+}
+
+/// Closed ring around the rectangle `inner`, with the counter-clockwise cross-section (distance out, height)
+/// swept along its sides and mitered in the corners. Every vertex is shared, so the ring is a single manifold shell.
+pub fn sweep_ring(mesh: &mut Mesh, inner: [f32; 4], cross_section: &[(f32, f32)]) {
+    let [x0, y0, x1, y1] = inner;
+    let base = mesh.vertices.vertex.len();
+    for &(out, z) in cross_section {
+        for (x, y) in [(x0 - out, y0 - out), (x1 + out, y0 - out), (x1 + out, y1 + out), (x0 - out, y1 + out)] {
+            mesh.vertices.vertex.push(Vertex { x: x as f64, y: y as f64, z: z as f64 });
+        }
+    }
+
+    let count = cross_section.len();
+    for k in 0..count {
+        let next = (k + 1) % count;
+        for corner in 0..4 {
+            let following = (corner + 1) % 4;
+            let (a, b) = (base + 4 * k + corner, base + 4 * k + following);
+            let (c, d) = (base + 4 * next + following, base + 4 * next + corner);
+            mesh.triangles.triangle.push(Triangle { v1: a, v2: b, v3: c });
+            mesh.triangles.triangle.push(Triangle { v1: a, v2: c, v3: d });
+        }
+    }
+}
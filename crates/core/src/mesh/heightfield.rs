@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, HashMap};
+
+use threemf::{model::{Triangle, Vertex}, Mesh};
+
+use crate::mesh::rectangles::decompose_cells;
+
+/// Heights are snapped to this many steps per mm before they are compared
+const Z_RESOLUTION: f64 = 10_000.0;
+
+/// Solid part of one column, `bottom` and `top` index `ColumnField::z_levels`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+    pub bottom: u32,
+    pub top: u32,
+}
+
+/// One material as a grid of columns, every cell holds zero or more disjoint vertical spans.
+/// This is the input of the heightfield mesher, heights are shared through a table of z levels
+/// so equal heights are equal exactly and not just within float precision.
+#[derive(Debug, Clone)]
+pub struct ColumnField {
+    width: u32,
+    height: u32,
+    cell_size: (f64, f64),
+    z_levels: Vec<f64>,
+    /// Spans of cell `i` are `spans[offsets[i]..offsets[i + 1]]`, sorted bottom to top
+    offsets: Vec<u32>,
+    spans: Vec<Span>,
+}
+
+impl ColumnField {
+    /// Build the field from the (bottom, top) heights in mm of every cell.
+    /// Overlapping or touching spans of a cell are merged and empty ones dropped.
+    pub fn from_fn<F, I>(width: u32, height: u32, cell_size: (f32, f32), mut column: F) -> Self
+    where
+        F: FnMut(u32, u32) -> I,
+        I: IntoIterator<Item = (f32, f32)>,
+    {
+        let mut offsets = Vec::with_capacity((width * height) as usize + 1);
+        let mut raw: Vec<(i64, i64)> = Vec::new();
+        offsets.push(0);
+
+        for y in 0..height {
+            for x in 0..width {
+                let start = raw.len();
+                for (bottom, top) in column(x, y) {
+                    let (bottom, top) = (quantize(bottom), quantize(top));
+                    if top > bottom {
+                        raw.push((bottom, top));
+                    }
+                }
+
+                raw[start..].sort_unstable();
+                let mut end = start;
+                for i in start..raw.len() {
+                    if end > start && raw[i].0 <= raw[end - 1].1 {
+                        raw[end - 1].1 = raw[end - 1].1.max(raw[i].1);
+                    } else {
+                        raw[end] = raw[i];
+                        end += 1;
+                    }
+                }
+                raw.truncate(end);
+                offsets.push(raw.len() as u32);
+            }
+        }
+
+        let mut keys: Vec<i64> = raw.iter().flat_map(|&(bottom, top)| [bottom, top]).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        let level = |key: i64| keys.binary_search(&key).unwrap_or_default() as u32;
+        let spans = raw.iter()
+            .map(|&(bottom, top)| Span { bottom: level(bottom), top: level(top) })
+            .collect();
+
+        Self {
+            width,
+            height,
+            cell_size: (cell_size.0 as f64, cell_size.1 as f64),
+            z_levels: keys.iter().map(|&key| key as f64 / Z_RESOLUTION).collect(),
+            offsets,
+            spans,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn cell_size(&self) -> (f64, f64) {
+        self.cell_size
+    }
+
+    /// Distinct heights in mm, ascending
+    pub fn z_levels(&self) -> &[f64] {
+        &self.z_levels
+    }
+
+    pub fn spans(&self, x: u32, y: u32) -> &[Span] {
+        let index = (y * self.width + x) as usize;
+        &self.spans[self.offsets[index] as usize..self.offsets[index + 1] as usize]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Spans of a cell, cells outside the grid are empty
+    fn spans_at(&self, x: i64, y: i64) -> &[Span] {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            &[]
+        } else {
+            self.spans(x as u32, y as u32)
+        }
+    }
+
+    /// Whether the slab between `level` and `level + 1` of the cell is solid
+    fn is_solid(&self, x: i64, y: i64, level: i64) -> bool {
+        level >= 0 && self.spans_at(x, y).iter()
+            .any(|span| (span.bottom as i64) <= level && level < span.top as i64)
+    }
+}
+
+fn quantize(z: f32) -> i64 {
+    (z as f64 * Z_RESOLUTION).round() as i64
+}
+
+/// Axis a face is perpendicular to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// Axis-aligned rectangle on the surface of a field. In-plane coordinates (a, b) are cyclic,
+/// (y, z) for X faces, (z, x) for Y faces and (x, y) for Z faces, so a × b points along the axis.
+/// Grid coordinates are used for x and y, z level indices for z.
+#[derive(Debug, Clone, Copy)]
+struct Face {
+    axis: Axis,
+    /// Whether the outward normal points along the positive axis
+    positive: bool,
+    /// Grid line or z level the face lies on
+    position: u32,
+    min: (u32, u32),
+    max: (u32, u32),
+}
+
+impl Face {
+    /// Grid point and z level of an in-plane point
+    fn point(&self, a: u32, b: u32) -> (u32, u32, u32) {
+        match self.axis {
+            Axis::X => (self.position, a, b),
+            Axis::Y => (b, self.position, a),
+            Axis::Z => (a, b, self.position),
+        }
+    }
+
+    /// Corners counter-clockwise around the outward normal
+    fn corners(&self) -> [(u32, u32, u32); 4] {
+        let corners = [
+            self.point(self.min.0, self.min.1),
+            self.point(self.max.0, self.min.1),
+            self.point(self.max.0, self.max.1),
+            self.point(self.min.0, self.max.1),
+        ];
+        if self.positive {
+            corners
+        } else {
+            [corners[0], corners[3], corners[2], corners[1]]
+        }
+    }
+
+    /// Solid octant the face touches at one of its boundary points, as (cell x, cell y, slab)
+    fn inner_octant(&self, (x, y, level): (u32, u32, u32)) -> (i64, i64, i64) {
+        let clamp = |value: u32, min: u32, max: u32| value.clamp(min, max - 1) as i64;
+        // Slab above the point at the bottom of a span, below it anywhere else
+        let slab = |bottom: u32| if level == bottom { level as i64 } else { level as i64 - 1 };
+        let solid_side = |position: u32| if self.positive { position as i64 - 1 } else { position as i64 };
+
+        match self.axis {
+            Axis::X => (solid_side(self.position), clamp(y, self.min.0, self.max.0), slab(self.min.1)),
+            Axis::Y => (clamp(x, self.min.1, self.max.1), solid_side(self.position), slab(self.min.0)),
+            Axis::Z => (
+                clamp(x, self.min.0, self.max.0),
+                clamp(y, self.min.1, self.max.1),
+                if self.positive { level as i64 - 1 } else { level as i64 },
+            ),
+        }
+    }
+}
+
+/// Mesh one field into a single closed surface. Only faces between solid and empty space are emitted,
+/// coplanar faces are merged into rectangles, vertices are welded and every triangle winds outwards.
+/// Solids that touch only along an edge or in a corner get separate vertices there, so every edge
+/// belongs to exactly two triangles and the result is manifold without any repair.
+pub fn mesh_column_field(field: &ColumnField) -> Mesh {
+    let mut faces = horizontal_faces(field);
+    faces.extend(wall_faces(field, Axis::X));
+    faces.extend(wall_faces(field, Axis::Y));
+
+    let corners = CornerIndex::new(field, &faces);
+    let mut builder = MeshBuilder {
+        field,
+        vertices: HashMap::new(),
+        mesh: Mesh::new(),
+    };
+    for face in &faces {
+        builder.add_face(face, &corners);
+    }
+
+    builder.mesh
+}
+
+/// Tops and bottoms of all spans, merged per z level
+fn horizontal_faces(field: &ColumnField) -> Vec<Face> {
+    let levels = field.z_levels.len();
+    let mut tops: Vec<Vec<(u32, u32)>> = vec![Vec::new(); levels];
+    let mut bottoms: Vec<Vec<(u32, u32)>> = vec![Vec::new(); levels];
+
+    for y in 0..field.height {
+        for x in 0..field.width {
+            for span in field.spans(x, y) {
+                tops[span.top as usize].push((x, y));
+                bottoms[span.bottom as usize].push((x, y));
+            }
+        }
+    }
+
+    let mut faces = Vec::new();
+    for level in 0..levels {
+        for (cells, positive) in [(&bottoms[level], false), (&tops[level], true)] {
+            for rect in decompose_cells(cells) {
+                faces.push(Face {
+                    axis: Axis::Z,
+                    positive,
+                    position: level as u32,
+                    min: (rect.x, rect.y),
+                    max: (rect.x + rect.width, rect.y + rect.height),
+                });
+            }
+        }
+    }
+
+    faces
+}
+
+/// Vertical faces on the grid lines perpendicular to `axis`, merged along the line
+fn wall_faces(field: &ColumnField, axis: Axis) -> Vec<Face> {
+    let (lines, length) = match axis {
+        Axis::X => (field.width, field.height),
+        _ => (field.height, field.width),
+    };
+    let mut faces = Vec::new();
+
+    for line in 0..=lines {
+        // (faces positive side, bottom, top) -> first cell along the line
+        let mut open: BTreeMap<(bool, u32, u32), u32> = BTreeMap::new();
+
+        for along in 0..=length {
+            let mut current = Vec::new();
+            if along < length {
+                let (before, after) = match axis {
+                    Axis::X => (field.spans_at(line as i64 - 1, along as i64), field.spans_at(line as i64, along as i64)),
+                    _ => (field.spans_at(along as i64, line as i64 - 1), field.spans_at(along as i64, line as i64)),
+                };
+                current.extend(subtract_spans(before, after).into_iter().map(|span| (true, span.bottom, span.top)));
+                current.extend(subtract_spans(after, before).into_iter().map(|span| (false, span.bottom, span.top)));
+            }
+
+            let closed: Vec<(bool, u32, u32)> = open.keys()
+                .filter(|key| !current.contains(key))
+                .copied()
+                .collect();
+            for key in closed {
+                let start = open.remove(&key).unwrap_or_default();
+                let (positive, bottom, top) = key;
+                faces.push(match axis {
+                    Axis::X => Face { axis, positive, position: line, min: (start, bottom), max: (along, top) },
+                    _ => Face { axis, positive, position: line, min: (bottom, start), max: (top, along) },
+                });
+            }
+            for key in current {
+                open.entry(key).or_insert(along);
+            }
+        }
+    }
+
+    faces
+}
+
+/// Parts of the spans in `a` not covered by `b`, both sorted and disjoint
+fn subtract_spans(a: &[Span], b: &[Span]) -> Vec<Span> {
+    let mut result = Vec::new();
+    for span in a {
+        let mut bottom = span.bottom;
+        for cover in b {
+            if cover.top <= bottom || cover.bottom >= span.top {
+                continue;
+            }
+            if cover.bottom > bottom {
+                result.push(Span { bottom, top: cover.bottom });
+            }
+            bottom = bottom.max(cover.top);
+        }
+        if bottom < span.top {
+            result.push(Span { bottom, top: span.top });
+        }
+    }
+    result
+}
+
+/// Z levels of all face corners per grid point. Every face includes the corners lying on its
+/// boundary, so neighboring faces split their shared edges the same way and no T-junctions remain.
+struct CornerIndex {
+    row: u32,
+    offsets: Vec<u32>,
+    levels: Vec<u32>,
+}
+
+impl CornerIndex {
+    fn new(field: &ColumnField, faces: &[Face]) -> Self {
+        let row = field.width + 1;
+        let mut corners: Vec<(u32, u32)> = faces.iter()
+            .flat_map(|face| face.corners())
+            .map(|(x, y, level)| (y * row + x, level))
+            .collect();
+        corners.sort_unstable();
+        corners.dedup();
+
+        let points = (row * (field.height + 1)) as usize;
+        let mut offsets = vec![0u32; points + 1];
+        for &(point, _) in &corners {
+            offsets[point as usize + 1] += 1;
+        }
+        for i in 0..points {
+            offsets[i + 1] += offsets[i];
+        }
+
+        Self {
+            row,
+            offsets,
+            levels: corners.into_iter().map(|(_, level)| level).collect(),
+        }
+    }
+
+    fn levels_at(&self, x: u32, y: u32) -> &[u32] {
+        let point = (y * self.row + x) as usize;
+        &self.levels[self.offsets[point] as usize..self.offsets[point + 1] as usize]
+    }
+
+    /// Points strictly between two corners of a face edge, in order from `from` to `to`
+    fn between(&self, from: (u32, u32, u32), to: (u32, u32, u32)) -> Vec<(u32, u32, u32)> {
+        let mut points = Vec::new();
+        if from.0 != to.0 || from.1 != to.1 {
+            // Along the grid at a fixed z level
+            let steps = from.0.abs_diff(to.0) + from.1.abs_diff(to.1);
+            for step in 1..steps {
+                let x = step_towards(from.0, to.0, step);
+                let y = step_towards(from.1, to.1, step);
+                if self.levels_at(x, y).binary_search(&from.2).is_ok() {
+                    points.push((x, y, from.2));
+                }
+            }
+        } else {
+            let levels = self.levels_at(from.0, from.1);
+            let (low, high) = (from.2.min(to.2), from.2.max(to.2));
+            let start = levels.partition_point(|&level| level <= low);
+            let end = levels.partition_point(|&level| level < high);
+            points.extend(levels[start..end].iter().map(|&level| (from.0, from.1, level)));
+            if from.2 > to.2 {
+                points.reverse();
+            }
+        }
+        points
+    }
+}
+
+fn step_towards(from: u32, to: u32, step: u32) -> u32 {
+    if to >= from { from + step.min(to - from) } else { from - step.min(from - to) }
+}
+
+struct MeshBuilder<'a> {
+    field: &'a ColumnField,
+    /// (grid x, grid y, z level, solid component) -> vertex index
+    vertices: HashMap<(u32, u32, u32, u8), usize>,
+    mesh: Mesh,
+}
+
+impl MeshBuilder<'_> {
+    fn add_face(&mut self, face: &Face, corners: &CornerIndex) {
+        let face_corners = face.corners();
+        let mut boundary = Vec::with_capacity(4);
+        for (i, &corner) in face_corners.iter().enumerate() {
+            let next = face_corners[(i + 1) % 4];
+            boundary.push(self.vertex(face, corner));
+            for point in corners.between(corner, next) {
+                boundary.push(self.vertex(face, point));
+            }
+        }
+
+        let triangles = &mut self.mesh.triangles.triangle;
+        if boundary.len() == 4 {
+            triangles.push(Triangle { v1: boundary[0], v2: boundary[1], v3: boundary[2] });
+            triangles.push(Triangle { v1: boundary[0], v2: boundary[2], v3: boundary[3] });
+        } else {
+            // Extra points on the edges, fan from the center so no triangle degenerates
+            let center = self.position_of(face_corners[0], face_corners[2]);
+            let center_index = self.mesh.vertices.vertex.len();
+            self.mesh.vertices.vertex.push(center);
+            let triangles = &mut self.mesh.triangles.triangle;
+            for i in 0..boundary.len() {
+                triangles.push(Triangle { v1: center_index, v2: boundary[i], v3: boundary[(i + 1) % boundary.len()] });
+            }
+        }
+    }
+
+    fn vertex(&mut self, face: &Face, point: (u32, u32, u32)) -> usize {
+        let component = self.solid_component(point, face.inner_octant(point));
+        let key = (point.0, point.1, point.2, component);
+        if let Some(&index) = self.vertices.get(&key) {
+            return index;
+        }
+
+        let index = self.mesh.vertices.vertex.len();
+        let vertex = self.position_of(point, point);
+        self.mesh.vertices.vertex.push(vertex);
+        self.vertices.insert(key, index);
+        index
+    }
+
+    /// Midpoint of two grid points in mm
+    fn position_of(&self, a: (u32, u32, u32), b: (u32, u32, u32)) -> Vertex {
+        let (cell_width, cell_height) = self.field.cell_size;
+        let z = |level: u32| self.field.z_levels[level as usize];
+        Vertex {
+            x: (a.0 + b.0) as f64 * cell_width / 2.0,
+            y: (a.1 + b.1) as f64 * cell_height / 2.0,
+            z: (z(a.2) + z(b.2)) / 2.0,
+        }
+    }
+
+    /// Group of face connected solid octants around the point that contains the given octant,
+    /// identified by its lowest octant index
+    fn solid_component(&self, (x, y, level): (u32, u32, u32), (cell_x, cell_y, slab): (i64, i64, i64)) -> u8 {
+        let octant = |index: usize| (
+            x as i64 - 1 + (index & 1) as i64,
+            y as i64 - 1 + ((index >> 1) & 1) as i64,
+            level as i64 - 1 + ((index >> 2) & 1) as i64,
+        );
+        let solid: Vec<bool> = (0..8)
+            .map(|index| {
+                let (ox, oy, oz) = octant(index);
+                self.field.is_solid(ox, oy, oz)
+            })
+            .collect();
+
+        let mut parents: [usize; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+        fn find(parents: &mut [usize; 8], mut index: usize) -> usize {
+            while parents[index] != index {
+                index = parents[index];
+            }
+            index
+        }
+        for index in 0..8 {
+            // Neighbors differ in exactly one of the x, y or z bits
+            for bit in [1, 2, 4] {
+                let neighbor = index ^ bit;
+                if neighbor > index && solid[index] && solid[neighbor] {
+                    let (a, b) = (find(&mut parents, index), find(&mut parents, neighbor));
+                    parents[a.max(b)] = a.min(b);
+                }
+            }
+        }
+
+        let own = (0..8)
+            .find(|&index| octant(index) == (cell_x, cell_y, slab))
+            .unwrap_or_default();
+        find(&mut parents, own) as u8
+    }
+}
@@ -1,4 +1,16 @@
-use threemf::{model::{Triangle, Triangles, Vertex, Vertices}, Mesh};
+use threemf::model::{Triangle, Triangles, Vertex, Vertices};
+
+pub mod contour;
+mod earcut;
+pub mod frame;
+pub mod heightfield;
+pub mod mount;
+pub mod packing;
+pub mod placement;
+pub mod rectangles;
+pub mod terrace;
+pub mod text;
+pub mod validate;
 
 #[derive(Debug, Clone)]
 pub struct Rectangle {
@@ -47,7 +59,8 @@ fn add_quad_triangles(triangles: &mut Triangles, base: usize, v0: usize, v1: usi
     triangles.triangle.push(Triangle { v1: base + v0, v2: base + v2, v3: base + v3 });
 }
 
-pub fn calculate_normal(v0: &Vertex, v1: &Vertex, v2: &Vertex) -> Vertex {
+/// Unit normal of the triangle, `None` when it has no area. Such triangles are reported by `validate::validate_mesh`.
+pub fn calculate_normal(v0: &Vertex, v1: &Vertex, v2: &Vertex) -> Option<Vertex> {
     let edge1 = Vertex { x: v1.x - v0.x, y: v1.y - v0.y, z: v1.z - v0.z };
     let edge2 = Vertex { x: v2.x - v0.x, y: v2.y - v0.y, z: v2.z - v0.z };
     
@@ -58,39 +71,9 @@ pub fn calculate_normal(v0: &Vertex, v1: &Vertex, v2: &Vertex) -> Vertex {
     };
     
     let length = (normal.x * normal.x + normal.y * normal.y + normal.z * normal.z).sqrt();
-    if length > 0.0 {
-        Vertex { x: normal.x / length, y: normal.y / length, z: normal.z / length }
-    } else {
-        Vertex { x: 0.0, y: 1.0, z: 0.0 }
-    }
+    (length > 0.0).then(|| Vertex { x: normal.x / length, y: normal.y / length, z: normal.z / length })
 }
 
-// pub fn export_to_stl(mesh: &Mesh, filename: &str) -> std::io::Result<()> {
-//     use std::fs::File;
-//     use std::io::Write;
-    
-//     let mut file = File::create(filename)?;
-//     writeln!(file, "solid object")?;
-    
-//     for triangle in &mesh.triangles.triangle {
-//         let v0 = &mesh.vertices.vertex[triangle.v1];
-//         let v1 = &mesh.vertices.vertex[triangle.v2];
-//         let v2 = &mesh.vertices.vertex[triangle.v3];
-//         let normal = calculate_normal(v0, v1, v2);
-        
-//         writeln!(file, "  facet normal {} {} {}", normal.x, normal.y, normal.z)?;
-//         writeln!(file, "    outer loop")?;
-//         writeln!(file, "      vertex {} {} {}", v0.x, v0.y, v0.z)?;
-//         writeln!(file, "      vertex {} {} {}", v1.x, v1.y, v1.z)?;
-//         writeln!(file, "      vertex {} {} {}", v2.x, v2.y, v2.z)?;
-//         writeln!(file, "    endloop")?;
-//         writeln!(file, "  endfacet")?;
-//     }
-    
-//     writeln!(file, "endsolid object")?;
-//     Ok(())
-// }
-
 pub fn calculate_xy_bounds(vertices: &Vertices) -> (f32, f32, f32, f32) {
     if vertices.vertex.is_empty() {
         return (0.0, 0.0, 0.0, 0.0);
@@ -114,12 +97,3 @@ pub fn calculate_xy_bounds(vertices: &Vertices) -> (f32, f32, f32, f32) {
     
     (min_x, max_x, min_y, max_y)
 }
-
-pub fn add_build_plate_padding(mesh: &mut Mesh, margin: f32) {
-        generate_box(
-            &mut mesh.vertices,
-            &mut mesh.triangles,
-            -margin, -margin, 0.0,
-            1.0, 1.0, 0.2,
-        );
-}
\ No newline at end of file
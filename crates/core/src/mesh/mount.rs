@@ -0,0 +1,204 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use threemf::Mesh;
+
+use crate::{
+    mesh::contour::{push_prism, triangulate, Polygon},
+    utils::MaterialMesh,
+};
+
+/// Thickness of the kickstand plate, it is printed lying flat
+const KICKSTAND_THICKNESS: f32 = 3.0;
+/// Width of the tab that plugs the kickstand into its slot
+const KICKSTAND_TAB: f32 = 8.0;
+/// Solid kickstand above the tab, and the width of its top end
+const KICKSTAND_TOP: f32 = 5.0;
+/// Play around the tab so it slides into the slot
+const KICKSTAND_CLEARANCE: f32 = 0.2;
+/// Gap between the print and the kickstand on the build plate
+const KICKSTAND_GAP: f32 = 5.0;
+
+/// How the print is hung or stood up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MountKind {
+    /// Round hole through the print, to hang it from a nail
+    #[default]
+    HangingHole,
+    /// Screw head hole with a narrower slot towards the top, the print slides down onto the screw
+    Keyhole,
+    /// Slot near the bottom and a separate stand that plugs into it
+    Kickstand,
+}
+
+impl MountKind {
+    /// Hole or screw head diameter, 0 makes the kickstand half as high as the print
+    pub fn default_size(&self) -> f32 {
+        match self {
+            MountKind::HangingHole => 5.0,
+            MountKind::Keyhole => 8.0,
+            MountKind::Kickstand => 0.0,
+        }
+    }
+
+    /// Distance of the hole center from the top edge
+    pub fn default_offset(&self) -> f32 {
+        match self {
+            MountKind::HangingHole => 8.0,
+            MountKind::Keyhole => 16.0,
+            MountKind::Kickstand => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for MountKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MountKind::HangingHole => write!(f, "hanging_hole"),
+            MountKind::Keyhole => write!(f, "keyhole"),
+            MountKind::Kickstand => write!(f, "kickstand"),
+        }
+    }
+}
+
+impl FromStr for MountKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "hanging_hole" | "hole" => Ok(MountKind::HangingHole),
+            "keyhole" => Ok(MountKind::Keyhole),
+            "kickstand" | "stand" => Ok(MountKind::Kickstand),
+            _ => Err(format!("Unknown mount '{}', expected hanging_hole, keyhole or kickstand", s)),
+        }
+    }
+}
+
+/// Hole, keyhole or kickstand, centered horizontally on the image. The top edge of the image
+/// is its lowest y, the print stands on the edge with the highest y.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Mount {
+    pub kind: MountKind,
+    /// Hole or screw head diameter, or the height of the kickstand with 0 for half the print
+    pub size_mm: f32,
+    /// Distance of the hole center from the top edge, not used by the kickstand
+    #[serde(default)]
+    pub offset_mm: f32,
+}
+
+impl Mount {
+    pub fn new(kind: MountKind) -> Self {
+        Self { kind, size_mm: kind.default_size(), offset_mm: kind.default_offset() }
+    }
+
+    /// Shape cut through the print. `bounds` are the image bounds as `calculate_xy_bounds` gives them,
+    /// `extent` is how far the print reaches out past them on every side, e.g. for a frame.
+    pub fn cut(&self, bounds: (f32, f32, f32, f32), extent: f32) -> MountCut {
+        let (min_x, max_x, min_y, max_y) = bounds;
+        let center_x = (min_x + max_x) / 2.0;
+        let shapes = match self.kind {
+            MountKind::HangingHole => vec![CutShape::Circle { center: [center_x, min_y + self.offset_mm], radius: self.size_mm / 2.0 }],
+            MountKind::Keyhole => {
+                let (head, center_y) = (self.size_mm / 2.0, min_y + self.offset_mm);
+                let (shank, end_y) = (head / 2.0, center_y - self.size_mm);
+                vec![
+                    CutShape::Circle { center: [center_x, center_y], radius: head },
+                    CutShape::Rect { min: [center_x - shank, end_y], max: [center_x + shank, center_y] },
+                    CutShape::Circle { center: [center_x, end_y], radius: shank },
+                ]
+            }
+            MountKind::Kickstand => {
+                // The tab sits at the top of the stand, measured from the edge the print stands on
+                let bottom = max_y + extent;
+                let (tab_low, tab_high) = self.kickstand_tab(bounds, extent);
+                let (half_width, play) = (KICKSTAND_THICKNESS / 2.0 + KICKSTAND_CLEARANCE, KICKSTAND_CLEARANCE);
+                vec![CutShape::Rect {
+                    min: [center_x - half_width, bottom - tab_high - play],
+                    max: [center_x + half_width, bottom - tab_low + play],
+                }]
+            }
+        };
+        MountCut { shapes }
+    }
+
+    /// Stand that plugs into the kickstand slot, printed lying flat next to the print.
+    /// `tab_length` is how deep the tab reaches into the slot. `None` for holes.
+    pub fn generate_kickstand(&self, bounds: (f32, f32, f32, f32), extent: f32, tab_length: f32) -> Option<Mesh> {
+        if self.kind != MountKind::Kickstand {
+            return None;
+        }
+
+        let (_, max_x, min_y, _) = bounds;
+        let height = self.kickstand_height(bounds, extent);
+        let depth = 0.6 * height;
+        let (tab_low, tab_high) = self.kickstand_tab(bounds, extent);
+        let top = KICKSTAND_TOP.min(depth);
+
+        // Across the plate the distance out from the back of the print, along it the height above the table
+        let outline = [
+            (0.0, 0.0), (depth, 0.0), (top, height), (0.0, height),
+            (0.0, tab_high), (-tab_length, tab_high), (-tab_length, tab_low), (0.0, tab_low),
+        ];
+        let (origin_x, origin_y) = (max_x + extent + KICKSTAND_GAP + tab_length, min_y - extent);
+        let polygon = Polygon {
+            outer: outline.iter().map(|&(out, up)| [(origin_x + out) as f64, (origin_y + up) as f64]).collect(),
+            holes: Vec::new(),
+        };
+
+        let mut mesh = Mesh::new();
+        let (points, next, caps) = triangulate(&polygon);
+        let walls: Vec<(usize, usize)> = next.iter().copied().enumerate().collect();
+        push_prism(&mut mesh, &points, &walls, &caps, (1.0, 1.0), 0.0, KICKSTAND_THICKNESS as f64);
+        Some(mesh)
+    }
+
+    /// Wrap the kickstand geometry for export
+    pub fn material_mesh(&self, mesh: Mesh) -> MaterialMesh {
+        MaterialMesh {
+            name: "kickstand".to_string(),
+            display_color: "#000000".to_string(),
+            mesh,
+        }
+    }
+
+    fn kickstand_height(&self, bounds: (f32, f32, f32, f32), extent: f32) -> f32 {
+        let (_, _, min_y, max_y) = bounds;
+        let print_height = max_y - min_y + 2.0 * extent;
+        let height = if self.size_mm > 0.0 { self.size_mm } else { print_height / 2.0 };
+        height.clamp(KICKSTAND_TAB + 2.0 * KICKSTAND_TOP, print_height.max(KICKSTAND_TAB + 2.0 * KICKSTAND_TOP))
+    }
+
+    /// Tab as (lowest, highest) height above the table
+    fn kickstand_tab(&self, bounds: (f32, f32, f32, f32), extent: f32) -> (f32, f32) {
+        let high = self.kickstand_height(bounds, extent) - KICKSTAND_TOP;
+        (high - KICKSTAND_TAB, high)
+    }
+}
+
+/// Area a mount cuts out of the print
+#[derive(Debug, Clone, PartialEq)]
+pub struct MountCut {
+    shapes: Vec<CutShape>,
+}
+
+impl MountCut {
+    /// Whether any part of the rectangle from `min` to `max` lies in the cut. Cells on the edge go too,
+    /// so the hole is never narrower than asked for.
+    pub fn overlaps(&self, min: [f32; 2], max: [f32; 2]) -> bool {
+        self.shapes.iter().any(|shape| match *shape {
+            CutShape::Circle { center, radius } => {
+                let dx = center[0].clamp(min[0], max[0]) - center[0];
+                let dy = center[1].clamp(min[1], max[1]) - center[1];
+                dx * dx + dy * dy < radius * radius
+            }
+            CutShape::Rect { min: low, max: high } => min[0] < high[0] && max[0] > low[0] && min[1] < high[1] && max[1] > low[1],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CutShape {
+    Circle { center: [f32; 2], radius: f32 },
+    Rect { min: [f32; 2], max: [f32; 2] },
+}
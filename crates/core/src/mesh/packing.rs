@@ -0,0 +1,95 @@
+use crate::{mesh::calculate_xy_bounds, utils::PrintObjects};
+
+/// Where `pack_objects` put a print, as (min x, min y, max x, max y)
+pub type PackedBounds = [f32; 4];
+
+/// Arrange rectangles of the given (width, depth) in rows, tallest first, keeping `spacing` between them.
+/// Rows are as wide as the bed, or about as wide as the plate gets deep without one.
+/// Returns the min corner of each rectangle in the order of `sizes`, the arrangement starts at the origin.
+pub fn pack_rectangles(sizes: &[[f32; 2]], bed: Option<[f32; 2]>, spacing: f32) -> Result<Vec<[f32; 2]>, String> {
+    let spacing = spacing.max(0.0);
+    let widest = sizes.iter().map(|[width, _]| *width).fold(0.0, f32::max);
+    let row_width = match bed {
+        Some([width, _]) => {
+            if widest > width {
+                return Err(format!("A {:.1} mm wide print does not fit a {:.1} mm wide bed", widest, width));
+            }
+            width
+        }
+        None => {
+            let area: f32 = sizes.iter().map(|[width, depth]| (width + spacing) * (depth + spacing)).sum();
+            area.sqrt().max(widest)
+        }
+    };
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| sizes[b][1].total_cmp(&sizes[a][1]).then(a.cmp(&b)));
+    let mut positions = vec![[0.0, 0.0]; sizes.len()];
+    let (mut x, mut y, mut row_depth) = (0.0f32, 0.0f32, 0.0f32);
+    for index in order {
+        let [width, depth] = sizes[index];
+        if x > 0.0 && x + width > row_width {
+            y += row_depth + spacing;
+            x = 0.0;
+            row_depth = 0.0;
+        }
+        positions[index] = [x, y];
+        x += width + spacing;
+        row_depth = row_depth.max(depth);
+    }
+
+    if let Some([width, bed_depth]) = bed {
+        let depth = y + row_depth;
+        if depth > bed_depth {
+            return Err(format!(
+                "The prints need {:.1} mm of a {:.1}x{:.1} mm bed, leave some out or make them smaller",
+                depth, width, bed_depth
+            ));
+        }
+    }
+    Ok(positions)
+}
+
+/// Move the prints next to each other with `pack_rectangles` and center them on the bed. Call it after
+/// `place_objects` without a bed, so the pads are packed along with each print.
+pub fn pack_objects(groups: &mut [PrintObjects], bed: Option<[f32; 2]>, spacing: f32) -> Result<Vec<PackedBounds>, String> {
+    let bounds: Vec<[f32; 4]> = groups.iter().map(group_bounds).collect();
+    let sizes: Vec<[f32; 2]> = bounds.iter().map(|[x0, y0, x1, y1]| [x1 - x0, y1 - y0]).collect();
+    let positions = pack_rectangles(&sizes, bed, spacing)?;
+
+    let extent = positions.iter().zip(&sizes).fold([0.0f32, 0.0f32], |extent, ([x, y], [width, depth])| {
+        [extent[0].max(x + width), extent[1].max(y + depth)]
+    });
+    let center = match bed {
+        Some([width, depth]) => [(width - extent[0]) / 2.0, (depth - extent[1]) / 2.0],
+        None => [0.0, 0.0],
+    };
+
+    let mut packed = Vec::with_capacity(groups.len());
+    for ((objects, [x0, y0, x1, y1]), [x, y]) in groups.iter_mut().zip(&bounds).zip(&positions) {
+        let offset = [x + center[0] - x0, y + center[1] - y0];
+        let meshes = [&mut objects.black_mesh, &mut objects.white_mesh].into_iter()
+            .chain(objects.extras.iter_mut().map(|extra| &mut extra.mesh));
+        for mesh in meshes {
+            for vertex in &mut mesh.vertices.vertex {
+                vertex.x += offset[0] as f64;
+                vertex.y += offset[1] as f64;
+            }
+        }
+        packed.push([x0 + offset[0], y0 + offset[1], x1 + offset[0], y1 + offset[1]]);
+    }
+    Ok(packed)
+}
+
+/// Footprint of all parts of a print together, empty prints take no room
+fn group_bounds(objects: &PrintObjects) -> [f32; 4] {
+    objects.parts().into_iter()
+        .map(|(_, mesh)| mesh)
+        .filter(|mesh| !mesh.vertices.vertex.is_empty())
+        .map(|mesh| {
+            let (x0, x1, y0, y1) = calculate_xy_bounds(&mesh.vertices);
+            [x0, y0, x1, y1]
+        })
+        .reduce(|a, b| [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])])
+        .unwrap_or_default()
+}
@@ -0,0 +1,217 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{mesh::{calculate_xy_bounds, generate_box}, utils::PrintObjects};
+
+/// Pads are one thick first layer, they only have to stick to the plate
+const PAD_HEIGHT: f32 = 0.2;
+
+/// Pads printed on the build plate next to the model
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PadStyle {
+    #[default]
+    None,
+    /// Small pads at the edges of a shared footprint, so every part has the same bounds. Parts that float above
+    /// the plate keep their height, and slicers that center the parts one by one keep them aligned.
+    Anchors,
+    /// Anchors with a larger pad per material in front of the model, to prime the nozzle on after a filament change
+    Wipe,
+}
+
+impl PadStyle {
+    /// Edge length of the pads
+    pub fn default_size(&self) -> f32 {
+        match self {
+            PadStyle::None | PadStyle::Anchors => 1.0,
+            PadStyle::Wipe => 5.0,
+        }
+    }
+}
+
+impl fmt::Display for PadStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PadStyle::None => write!(f, "none"),
+            PadStyle::Anchors => write!(f, "anchors"),
+            PadStyle::Wipe => write!(f, "wipe"),
+        }
+    }
+}
+
+impl FromStr for PadStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(PadStyle::None),
+            "anchors" | "anchor" => Ok(PadStyle::Anchors),
+            "wipe" => Ok(PadStyle::Wipe),
+            _ => Err(format!("Unknown pad style '{}', expected none, anchors or wipe", s)),
+        }
+    }
+}
+
+/// Pads of every part
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PadSettings {
+    #[serde(default)]
+    pub style: PadStyle,
+    /// Edge length of the wipe pads, or of the anchors without them. `None` for the default of the style.
+    #[serde(default)]
+    pub size_mm: Option<f32>,
+    /// Space between the model and the pads, and between the pads
+    #[serde(default = "default_pad_gap")]
+    pub gap_mm: f32,
+}
+
+fn default_pad_gap() -> f32 {
+    3.0
+}
+
+impl Default for PadSettings {
+    fn default() -> Self {
+        Self { style: PadStyle::default(), size_mm: None, gap_mm: default_pad_gap() }
+    }
+}
+
+/// Where the model goes on the build plate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Placement {
+    /// Bed width and depth in mm, the model is centered on it. `None` leaves the model where it was generated.
+    #[serde(default)]
+    pub bed: Option<[f32; 2]>,
+    #[serde(default)]
+    pub pads: PadSettings,
+}
+
+/// Final position of a part as (min x, min y, max x, max y), without its pads
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartPosition {
+    pub name: String,
+    pub bounds: [f32; 4],
+    /// Centers of the pads of the part
+    pub pads: Vec<[f32; 2]>,
+}
+
+/// What `place_objects` did
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlacementReport {
+    /// Shift of the model on the plate
+    pub offset: [f32; 2],
+    /// Footprint of the model with its pads as (min x, min y, max x, max y)
+    pub bounds: [f32; 4],
+    pub parts: Vec<PartPosition>,
+    /// Whether the footprint fits the bed, `None` without a bed
+    pub fits_bed: Option<bool>,
+}
+
+impl PlacementReport {
+    /// One line per part, then the footprint
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.parts.iter()
+            .map(|part| {
+                let [x0, y0, x1, y1] = part.bounds;
+                let mut line = format!("{}: x {:.1} to {:.1} mm, y {:.1} to {:.1} mm", part.name, x0, x1, y0, y1);
+                if !part.pads.is_empty() {
+                    let pads: Vec<String> = part.pads.iter().map(|[x, y]| format!("({:.1}, {:.1})", x, y)).collect();
+                    line.push_str(&format!(", pads at {}", pads.join(" ")));
+                }
+                line
+            })
+            .collect();
+        let [x0, y0, x1, y1] = self.bounds;
+        let mut footprint = format!("Footprint {:.1} x {:.1} mm, moved by ({:.1}, {:.1}) mm", x1 - x0, y1 - y0, self.offset[0], self.offset[1]);
+        match self.fits_bed {
+            Some(true) => footprint.push_str(", fits the bed"),
+            Some(false) => footprint.push_str(", does NOT fit the bed"),
+            None => {}
+        }
+        lines.push(footprint);
+        lines
+    }
+}
+
+/// Add the pads to every part and center the whole model on the bed. Call it after validation,
+/// the pads lie outside of the image.
+pub fn place_objects(objects: &mut PrintObjects, placement: &Placement) -> PlacementReport {
+    let pads = &placement.pads;
+    let part_bounds: Vec<Option<[f32; 4]>> = parts_mut(objects).into_iter()
+        .map(|(_, mesh)| {
+            (!mesh.vertices.vertex.is_empty()).then(|| {
+                let (x0, x1, y0, y1) = calculate_xy_bounds(&mesh.vertices);
+                [x0, y0, x1, y1]
+            })
+        })
+        .collect();
+    let Some(model) = part_bounds.iter().flatten().copied().reduce(|a, b| [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])]) else {
+        return PlacementReport::default();
+    };
+
+    // Every part gets a pad on each edge of the shared footprint, the pads of the parts side by side along the edge
+    let size = pads.size_mm.filter(|&size| size > 0.0).unwrap_or(pads.style.default_size());
+    let anchor = if pads.style == PadStyle::Wipe { PadStyle::Anchors.default_size() } else { size };
+    let front = if pads.style == PadStyle::Wipe { size } else { anchor };
+    let gap = pads.gap_mm.max(0.0);
+    let step = size + gap;
+    let ring = if pads.style == PadStyle::None { 0.0 } else { step };
+    let mut footprint = [model[0] - ring, model[1] - ring, model[2] + ring, model[3] + ring];
+    let mut pad_boxes: Vec<Vec<[f32; 4]>> = vec![Vec::new(); part_bounds.len()];
+    if pads.style != PadStyle::None {
+        // The pads start a step from each corner and end a step before the next one, where the pads of the
+        // other edge are. Edges too short for the pads of every part grow on both sides.
+        let count = part_bounds.iter().flatten().count() as f32;
+        let (needed_x, needed_y) = ((count + 1.0) * step + front, (count + 1.0) * step + anchor);
+        let grow_x = (needed_x - (footprint[2] - footprint[0])).max(0.0) / 2.0;
+        let grow_y = (needed_y - (footprint[3] - footprint[1])).max(0.0) / 2.0;
+        footprint = [footprint[0] - grow_x, footprint[1] - grow_y, footprint[2] + grow_x, footprint[3] + grow_y];
+
+        let slots = pad_boxes.iter_mut().enumerate().filter(|(index, _)| part_bounds[*index].is_some());
+        for (slot, (_, boxes)) in slots.enumerate() {
+            let along = (slot as f32 + 1.0) * step;
+            let [x0, y0, x1, y1] = footprint;
+            // Front edge first, the wipe pad is the one there
+            boxes.push([x0 + along, y0, x0 + along + front, y0 + front]);
+            boxes.push([x0, y0 + along, x0 + anchor, y0 + along + anchor]);
+            boxes.push([x1 - along - anchor, y1 - anchor, x1 - along, y1]);
+            boxes.push([x1 - anchor, y1 - along - anchor, x1, y1 - along]);
+        }
+    }
+
+    let offset = match placement.bed {
+        Some([width, depth]) => [width / 2.0 - (footprint[0] + footprint[2]) / 2.0, depth / 2.0 - (footprint[1] + footprint[3]) / 2.0],
+        None => [0.0, 0.0],
+    };
+
+    let mut report = PlacementReport {
+        offset,
+        bounds: [footprint[0] + offset[0], footprint[1] + offset[1], footprint[2] + offset[0], footprint[3] + offset[1]],
+        parts: Vec::new(),
+        fits_bed: placement.bed.map(|[width, depth]| footprint[2] - footprint[0] <= width && footprint[3] - footprint[1] <= depth),
+    };
+    for ((name, mesh), (bounds, boxes)) in parts_mut(objects).into_iter().zip(part_bounds.iter().zip(&pad_boxes)) {
+        let Some([x0, y0, x1, y1]) = bounds else {
+            continue;
+        };
+        for [bx0, by0, bx1, by1] in boxes {
+            generate_box(&mut mesh.vertices, &mut mesh.triangles, *bx0, *by0, 0.0, bx1 - bx0, by1 - by0, PAD_HEIGHT);
+        }
+        for vertex in &mut mesh.vertices.vertex {
+            vertex.x += offset[0] as f64;
+            vertex.y += offset[1] as f64;
+        }
+        report.parts.push(PartPosition {
+            name,
+            bounds: [x0 + offset[0], y0 + offset[1], x1 + offset[0], y1 + offset[1]],
+            pads: boxes.iter().map(|[bx0, by0, bx1, by1]| [(bx0 + bx1) / 2.0 + offset[0], (by0 + by1) / 2.0 + offset[1]]).collect(),
+        });
+    }
+    report
+}
+
+fn parts_mut(objects: &mut PrintObjects) -> Vec<(String, &mut threemf::Mesh)> {
+    let mut parts = vec![("black".to_string(), &mut objects.black_mesh), ("white".to_string(), &mut objects.white_mesh)];
+    parts.extend(objects.extras.iter_mut().map(|extra| (extra.name.clone(), &mut extra.mesh)));
+    parts
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+
+use threemf::Mesh;
+
+use crate::mesh::{generate_box, heightfield::{ColumnField, Span}, Rectangle};
+
+/// Cover the set cells of a dense row-major mask with disjoint rectangles.
+/// Cells are visited in raster order, so the same mask always gives the same rectangles in the same order.
+/// At every uncovered cell the larger of two candidates is taken: widest run extended down,
+/// or tallest column extended right. This stays close to the minimum number of rectangles
+/// for the blobby regions dithering produces, at the cost of a single pass over the mask.
+pub fn decompose_mask(width: u32, height: u32, mask: &[bool]) -> Vec<Rectangle> {
+    let mut remaining = mask.to_vec();
+    let mut rectangles = Vec::new();
+    let w = width as usize;
+    let is_free = |remaining: &[bool], x: u32, y: u32| remaining[y as usize * w + x as usize];
+
+    for y in 0..height {
+        for x in 0..width {
+            if !is_free(&remaining, x, y) {
+                continue;
+            }
+
+            let row_first = {
+                let mut run = 1;
+                while x + run < width && is_free(&remaining, x + run, y) {
+                    run += 1;
+                }
+                let mut rows = 1;
+                while y + rows < height && (x..x + run).all(|cx| is_free(&remaining, cx, y + rows)) {
+                    rows += 1;
+                }
+                Rectangle { x, y, width: run, height: rows }
+            };
+            let column_first = {
+                let mut rows = 1;
+                while y + rows < height && is_free(&remaining, x, y + rows) {
+                    rows += 1;
+                }
+                let mut run = 1;
+                while x + run < width && (y..y + rows).all(|cy| is_free(&remaining, x + run, cy)) {
+                    run += 1;
+                }
+                Rectangle { x, y, width: run, height: rows }
+            };
+
+            let rect = if column_first.width * column_first.height > row_first.width * row_first.height {
+                column_first
+            } else {
+                row_first
+            };
+            for cy in rect.y..rect.y + rect.height {
+                let row = cy as usize * w;
+                remaining[row + rect.x as usize..row + (rect.x + rect.width) as usize].fill(false);
+            }
+            rectangles.push(rect);
+        }
+    }
+
+    rectangles
+}
+
+/// Same as `decompose_mask` for a sparse list of cells, in any order and without duplicates
+pub fn decompose_cells(cells: &[(u32, u32)]) -> Vec<Rectangle> {
+    let Some(&(first_x, first_y)) = cells.first() else {
+        return Vec::new();
+    };
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (first_x, first_y, first_x, first_y);
+    for &(x, y) in cells {
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+
+    let (width, height) = (max_x - min_x + 1, max_y - min_y + 1);
+    let mut mask = vec![false; (width * height) as usize];
+    for &(x, y) in cells {
+        mask[((y - min_y) * width + (x - min_x)) as usize] = true;
+    }
+
+    decompose_mask(width, height, &mask)
+        .into_iter()
+        .map(|rect| Rectangle { x: rect.x + min_x, y: rect.y + min_y, ..rect })
+        .collect()
+}
+
+/// Mesh one field as boxes, one per span over every rectangle of cells with the same spans.
+/// Unlike the heightfield mesher the boxes only touch their neighbors, as the box meshers do.
+pub fn mesh_column_field_boxes(field: &ColumnField) -> Mesh {
+    let mut columns: BTreeMap<&[Span], Vec<(u32, u32)>> = BTreeMap::new();
+    for y in 0..field.height() {
+        for x in 0..field.width() {
+            let spans = field.spans(x, y);
+            if !spans.is_empty() {
+                columns.entry(spans).or_default().push((x, y));
+            }
+        }
+    }
+
+    let (cell_width, cell_height) = field.cell_size();
+    let levels = field.z_levels();
+    let mut mesh = Mesh::new();
+    for (spans, cells) in columns {
+        for rect in decompose_cells(&cells) {
+            for span in spans {
+                let (bottom, top) = (levels[span.bottom as usize], levels[span.top as usize]);
+                generate_box(
+                    &mut mesh.vertices,
+                    &mut mesh.triangles,
+                    (rect.x as f64 * cell_width) as f32, (rect.y as f64 * cell_height) as f32, bottom as f32,
+                    (rect.width as f64 * cell_width) as f32, (rect.height as f64 * cell_height) as f32, (top - bottom) as f32,
+                );
+            }
+        }
+    }
+    mesh
+}
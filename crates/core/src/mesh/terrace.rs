@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use threemf::Mesh;
+
+use crate::mesh::{
+    contour::{bands, cross, label_components, push_prism, remove_collinear, ring_area, split_skipped_points, triangulate, Polygon},
+    generate_box,
+    heightfield::ColumnField,
+    rectangles::decompose_cells,
+};
+
+/// Mesh one field as nested terraces: every band of slabs with the same footprint becomes one prism
+/// per 4-connected region, with holes, following the cell edges exactly. For a reflective image slab k
+/// covers every cell with at least k layers, so a region costs triangles for its outline only and
+/// not for every pixel inside it. The geometry is the same as one box per cell.
+pub fn mesh_column_field_terraces(field: &ColumnField) -> Mesh {
+    let (width, height) = (field.width() as usize, field.height() as usize);
+    let cell_size = field.cell_size();
+    let mut mesh = Mesh::new();
+
+    for (bottom, top, mask) in bands(field) {
+        for (polygon, cells) in trace_cell_outlines(width, height, &mask) {
+            let (mut points, next, caps) = triangulate(&polygon);
+            let (walls, caps) = weld_corners(&mut points, &next, caps);
+            let expected = ring_area(&polygon.outer) + polygon.holes.iter().map(|hole| ring_area(hole)).sum::<f64>();
+            let covered: f64 = caps.iter().map(|&[a, b, c]| cross(points[a], points[b], points[c])).sum();
+
+            if (covered - expected).abs() <= 1e-6 * expected.abs().max(1.0) {
+                push_prism(&mut mesh, &points, &walls, &caps, cell_size, bottom, top);
+            } else {
+                // Earcut can give up on outlines that touch themselves in many corners,
+                // boxes are not as clean but keep the geometry exact
+                for rect in decompose_cells(&cells) {
+                    generate_box(
+                        &mut mesh.vertices,
+                        &mut mesh.triangles,
+                        (rect.x as f64 * cell_size.0) as f32, (rect.y as f64 * cell_size.1) as f32, bottom as f32,
+                        (rect.width as f64 * cell_size.0) as f32, (rect.height as f64 * cell_size.1) as f32, (top - bottom) as f32,
+                    );
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+/// Outlines along the cell edges of every 4-connected region of set cells, with the cells of the region.
+/// Where two regions touch in a corner the outline turns left, around the cell it came along,
+/// so regions that only touch diagonally get separate outlines.
+fn trace_cell_outlines(width: usize, height: usize, mask: &[bool]) -> Vec<(Polygon, Vec<(u32, u32)>)> {
+    let (w, h) = (width as i64, height as i64);
+    let solid = |x: i64, y: i64| x >= 0 && y >= 0 && x < w && y < h && mask[(y * w + x) as usize];
+    let corner = |x: i64, y: i64| (y * (w + 1) + x) as usize;
+
+    // Directed boundary edges with the solid on the left, so outer rings are counter-clockwise and holes clockwise.
+    // A corner has at most two outgoing edges, in a corner where regions touch diagonally.
+    let mut edges: Vec<(i64, i64, i64, i64)> = Vec::new();
+    let mut outgoing = vec![[usize::MAX; 2]; ((w + 1) * (h + 1)) as usize];
+    for y in 0..h {
+        for x in 0..w {
+            if !solid(x, y) {
+                continue;
+            }
+            let sides = [
+                (!solid(x, y - 1), (x, y, 1, 0)),
+                (!solid(x + 1, y), (x + 1, y, 0, 1)),
+                (!solid(x, y + 1), (x + 1, y + 1, -1, 0)),
+                (!solid(x - 1, y), (x, y + 1, 0, -1)),
+            ];
+            for (open, edge) in sides {
+                if open {
+                    let slots = &mut outgoing[corner(edge.0, edge.1)];
+                    let slot = if slots[0] == usize::MAX { 0 } else { 1 };
+                    slots[slot] = edges.len();
+                    edges.push(edge);
+                }
+            }
+        }
+    }
+
+    let (labels, count) = label_components(width, height, mask);
+    let mut cells = vec![Vec::new(); count];
+    for (index, &label) in labels.iter().enumerate() {
+        if label != usize::MAX {
+            cells[label].push(((index % width) as u32, (index / width) as u32));
+        }
+    }
+
+    let mut polygons: Vec<Polygon> = vec![Polygon::default(); count];
+    let mut visited = vec![false; edges.len()];
+    for start in 0..edges.len() {
+        if visited[start] {
+            continue;
+        }
+
+        let mut ring = Vec::new();
+        let mut edge = start;
+        while !visited[edge] {
+            visited[edge] = true;
+            let (x, y, dx, dy) = edges[edge];
+            ring.push([x as f64, y as f64]);
+            let slots = outgoing[corner(x + dx, y + dy)];
+            edge = if slots[1] == usize::MAX {
+                slots[0]
+            } else {
+                // Two ways on, take the left turn
+                let left = (-dy, dx);
+                if (edges[slots[0]].2, edges[slots[0]].3) == left { slots[0] } else { slots[1] }
+            };
+        }
+
+        // The cell on the left of the first edge belongs to the region the ring bounds
+        let (x, y, dx, dy) = edges[start];
+        let (cx, cy) = (x + (dx - dy - 1) / 2, y + (dx + dy - 1) / 2);
+        let component = labels[(cy * w + cx) as usize];
+        let ring = remove_collinear(&ring);
+        if ring_area(&ring) > 0.0 {
+            polygons[component].outer = ring;
+        } else {
+            polygons[component].holes.push(ring);
+        }
+    }
+
+    polygons.into_iter().zip(cells).collect()
+}
+
+/// Match caps and walls up where earcut leaves them apart. Earcut runs edges straight through corners
+/// of other rings, e.g. between two holes in a line, so every cap edge is split at the corners on it.
+/// Rings also pass the same corner twice where a region touches itself or a hole diagonally, and earcut
+/// may use either copy. Every copy keeps its own walls, a cap triangle takes the copy whose corner it fills.
+/// Returns the wall edges and caps.
+fn weld_corners(points: &mut Vec<[f64; 2]>, next: &[usize], caps: Vec<[usize; 3]>) -> (Vec<(usize, usize)>, Vec<[usize; 3]>) {
+    let ring_points = next.len();
+    let mut prev = vec![0; ring_points];
+    for (p, &q) in next.iter().enumerate() {
+        prev[q] = p;
+    }
+    let mut copies: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, point) in points[..ring_points].iter().enumerate() {
+        copies.entry((point[0] as i64, point[1] as i64)).or_default().push(i);
+    }
+    let first_copy = |point: [f64; 2]| copies.get(&(point[0] as i64, point[1] as i64)).map(|copies| copies[0]);
+
+    // Corners are on the cell grid, so the ones strictly inside an edge are at its lattice points
+    let corners_on = |points: &[[f64; 2]], from: usize, to: usize| -> Vec<usize> {
+        let (a, b) = (points[from], points[to]);
+        if [a[0], a[1], b[0], b[1]].iter().any(|c| c.fract() != 0.0) {
+            return Vec::new();
+        }
+        let (dx, dy) = ((b[0] - a[0]) as i64, (b[1] - a[1]) as i64);
+        let steps = gcd(dx.abs(), dy.abs());
+        (1..steps)
+            .filter_map(|k| first_copy([a[0] + (k * dx / steps) as f64, a[1] + (k * dy / steps) as f64]))
+            .collect()
+    };
+
+    let mut walls = Vec::with_capacity(ring_points);
+    for (p, &q) in next.iter().enumerate() {
+        let mut from = p;
+        for corner in corners_on(points, p, q).into_iter().chain([q]) {
+            walls.push((from, corner));
+            from = corner;
+        }
+    }
+
+    let mut split = Vec::with_capacity(caps.len());
+    for triangle in caps {
+        let triangle = triangle.map(|i| if i < ring_points { first_copy(points[i]).unwrap_or(i) } else { i });
+        if triangle[0] != triangle[1] && triangle[1] != triangle[2] && triangle[0] != triangle[2] {
+            split_skipped_points(triangle, points, corners_on, &mut split);
+        }
+    }
+
+    // Every pass through a corner turns left, the solid is the quadrant between its two ring edges
+    let caps = split.into_iter()
+        .map(|triangle| {
+            let [a, b, c] = triangle.map(|i| points[i]);
+            let center = [(a[0] + b[0] + c[0]) / 3.0, (a[1] + b[1] + c[1]) / 3.0];
+            triangle.map(|i| {
+                let point = points[i];
+                let Some(candidates) = copies.get(&(point[0] as i64, point[1] as i64)).filter(|copies| copies.len() > 1 && i < ring_points) else {
+                    return i;
+                };
+                let fill = |copy: usize| {
+                    let (before, after) = (points[prev[copy]], points[next[copy]]);
+                    let quadrant = [before[0] + after[0] - 2.0 * point[0], before[1] + after[1] - 2.0 * point[1]];
+                    quadrant[0] * (center[0] - point[0]) + quadrant[1] * (center[1] - point[1])
+                };
+                candidates.iter().copied().max_by(|&x, &y| fill(x).total_cmp(&fill(y))).unwrap_or(i)
+            })
+        })
+        .collect();
+
+    (walls, caps)
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
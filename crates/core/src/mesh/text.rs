@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use threemf::Mesh;
+
+use crate::mesh::{generate_box, rectangles::decompose_mask};
+
+/// Rows of a glyph, the bottom one holds the descenders
+pub const GLYPH_ROWS: u32 = 8;
+/// Columns of a glyph without the space between letters
+const GLYPH_COLUMNS: u32 = 5;
+/// Empty rows between lines
+const LINE_GAP: u32 = 2;
+
+/// 5x8 bitmap font for printable ASCII from space to tilde, one byte per column with the top row in the lowest bit
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7F, 0x14, 0x7F, 0x14],
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], [0x36, 0x49, 0x56, 0x20, 0x50], [0x00, 0x08, 0x07, 0x03, 0x00],
+    [0x00, 0x1C, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1C, 0x00], [0x2A, 0x1C, 0x7F, 0x1C, 0x2A], [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x80, 0x70, 0x30, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x00, 0x60, 0x60, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00], [0x72, 0x49, 0x49, 0x49, 0x46], [0x21, 0x41, 0x49, 0x4D, 0x33],
+    [0x18, 0x14, 0x12, 0x7F, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], [0x3C, 0x4A, 0x49, 0x49, 0x31], [0x41, 0x21, 0x11, 0x09, 0x07],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x46, 0x49, 0x49, 0x29, 0x1E], [0x00, 0x00, 0x14, 0x00, 0x00], [0x00, 0x40, 0x34, 0x00, 0x00],
+    [0x00, 0x08, 0x14, 0x22, 0x41], [0x14, 0x14, 0x14, 0x14, 0x14], [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x59, 0x09, 0x06],
+    [0x3E, 0x41, 0x5D, 0x59, 0x4E], [0x7C, 0x12, 0x11, 0x12, 0x7C], [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x41, 0x3E], [0x7F, 0x49, 0x49, 0x49, 0x41], [0x7F, 0x09, 0x09, 0x09, 0x01], [0x3E, 0x41, 0x41, 0x51, 0x73],
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00], [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41],
+    [0x7F, 0x40, 0x40, 0x40, 0x40], [0x7F, 0x02, 0x1C, 0x02, 0x7F], [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], [0x7F, 0x09, 0x19, 0x29, 0x46], [0x26, 0x49, 0x49, 0x49, 0x32],
+    [0x03, 0x01, 0x7F, 0x01, 0x03], [0x3F, 0x40, 0x40, 0x40, 0x3F], [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F],
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x03, 0x04, 0x78, 0x04, 0x03], [0x61, 0x59, 0x49, 0x4D, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x41],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x41, 0x7F], [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x03, 0x07, 0x08, 0x00], [0x20, 0x54, 0x54, 0x78, 0x40], [0x7F, 0x28, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x28],
+    [0x38, 0x44, 0x44, 0x28, 0x7F], [0x38, 0x54, 0x54, 0x54, 0x18], [0x00, 0x08, 0x7E, 0x09, 0x02], [0x18, 0xA4, 0xA4, 0x9C, 0x78],
+    [0x7F, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7D, 0x40, 0x00], [0x20, 0x40, 0x40, 0x3D, 0x00], [0x7F, 0x10, 0x28, 0x44, 0x00],
+    [0x00, 0x41, 0x7F, 0x40, 0x00], [0x7C, 0x04, 0x78, 0x04, 0x78], [0x7C, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0xFC, 0x18, 0x24, 0x24, 0x18], [0x18, 0x24, 0x24, 0x18, 0xFC], [0x7C, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x24],
+    [0x04, 0x04, 0x3F, 0x44, 0x24], [0x3C, 0x40, 0x40, 0x20, 0x7C], [0x1C, 0x20, 0x40, 0x20, 0x1C], [0x3C, 0x40, 0x30, 0x40, 0x3C],
+    [0x44, 0x28, 0x10, 0x28, 0x44], [0x4C, 0x90, 0x90, 0x90, 0x7C], [0x44, 0x64, 0x54, 0x4C, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x77, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00], [0x02, 0x01, 0x02, 0x04, 0x02],
+];
+
+/// Text printed on the print, `height_mm` is the height of a line including descenders
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Label {
+    pub text: String,
+    #[serde(default = "default_label_height")]
+    pub height_mm: f32,
+}
+
+fn default_label_height() -> f32 {
+    4.0
+}
+
+impl Label {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self { text: text.into(), height_mm: default_label_height() }
+    }
+}
+
+/// Text rasterized with the built-in font, one cell per font pixel in row-major order.
+/// Lines are centered on each other, characters outside printable ASCII print as '?'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextBitmap {
+    pub width: u32,
+    pub height: u32,
+    pub cells: Vec<bool>,
+}
+
+impl TextBitmap {
+    pub fn new(lines: &[&str]) -> Self {
+        let line_width = |line: &str| (line.chars().count() as u32 * (GLYPH_COLUMNS + 1)).saturating_sub(1);
+        let width = lines.iter().map(|line| line_width(line)).max().unwrap_or(0);
+        let height = (lines.len() as u32 * (GLYPH_ROWS + LINE_GAP)).saturating_sub(LINE_GAP);
+        let mut cells = vec![false; (width * height) as usize];
+
+        for (row, line) in lines.iter().enumerate() {
+            let top = row as u32 * (GLYPH_ROWS + LINE_GAP);
+            let left = (width - line_width(line)) / 2;
+            for (index, character) in line.chars().enumerate() {
+                let glyph = FONT[glyph_index(character)];
+                for (column, bits) in glyph.iter().enumerate() {
+                    let x = left + index as u32 * (GLYPH_COLUMNS + 1) + column as u32;
+                    for y in (0..GLYPH_ROWS).filter(|&y| bits & (1 << y) != 0) {
+                        cells[((top + y) * width + x) as usize] = true;
+                    }
+                }
+            }
+        }
+
+        Self { width, height, cells }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.cells.iter().any(|&cell| cell)
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height && self.cells[(y * self.width + x) as usize]
+    }
+
+    /// Flipped left to right, to read from the back of the print
+    pub fn mirrored(&self) -> Self {
+        let cells = (0..self.height)
+            .flat_map(|y| (0..self.width).rev().map(move |x| (x, y)))
+            .map(|(x, y)| self.get(x, y))
+            .collect();
+        Self { width: self.width, height: self.height, cells }
+    }
+
+    /// Boxes over the set cells, with the top left corner at `origin` and `pixel` mm per font pixel
+    pub fn extrude(&self, mesh: &mut Mesh, origin: (f32, f32), pixel: f32, bottom: f32, top: f32) {
+        for rect in decompose_mask(self.width, self.height, &self.cells) {
+            generate_box(
+                &mut mesh.vertices,
+                &mut mesh.triangles,
+                origin.0 + rect.x as f32 * pixel, origin.1 + rect.y as f32 * pixel, bottom,
+                rect.width as f32 * pixel, rect.height as f32 * pixel, top - bottom,
+            );
+        }
+    }
+}
+
+fn glyph_index(character: char) -> usize {
+    match character {
+        ' '..='~' => character as usize - ' ' as usize,
+        _ => '?' as usize - ' ' as usize,
+    }
+}
@@ -0,0 +1,300 @@
+use std::{collections::{BTreeMap, HashMap}, fmt};
+
+use serde::{Deserialize, Serialize};
+use threemf::Mesh;
+
+/// Vertices closer than this are the same point, slicers weld them too
+const WELD_TOLERANCE: f64 = 1e-4;
+/// Overlaps and bounds are only problems when they exceed this, in mm
+const TOLERANCE: f64 = 1e-5;
+/// Examples kept per kind of problem
+const MAX_EXAMPLES: usize = 8;
+
+/// Kind of problem `validate_mesh` looks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeshIssue {
+    /// Triangle refers to a vertex that does not exist
+    IndexOutOfRange,
+    /// Triangle with a repeated vertex or without area
+    DegenerateTriangle,
+    /// Edge shared by more than two triangles
+    NonManifoldEdge,
+    /// Edge shared by two triangles that run it in the same direction, one of them faces inwards
+    InconsistentWinding,
+    /// Edge of a single triangle, the surface is open there
+    OpenBoundary,
+    /// Coplanar triangles covering the same area, e.g. where two boxes touch
+    OverlappingFaces,
+    /// Geometry outside of the expected bounds
+    OutOfBounds,
+}
+
+impl fmt::Display for MeshIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MeshIssue::IndexOutOfRange => "indices out of range",
+            MeshIssue::DegenerateTriangle => "degenerate triangles",
+            MeshIssue::NonManifoldEdge => "non-manifold edges",
+            MeshIssue::InconsistentWinding => "inconsistently wound edges",
+            MeshIssue::OpenBoundary => "open boundary edges",
+            MeshIssue::OverlappingFaces => "overlapping coplanar faces",
+            MeshIssue::OutOfBounds => "triangles out of bounds",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Axis aligned box in mm
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl Bounds {
+    pub fn size(&self) -> [f64; 3] {
+        [0, 1, 2].map(|axis| self.max[axis] - self.min[axis])
+    }
+
+    fn contains(&self, point: [f64; 3]) -> bool {
+        (0..3).all(|axis| point[axis] >= self.min[axis] - WELD_TOLERANCE && point[axis] <= self.max[axis] + WELD_TOLERANCE)
+    }
+}
+
+/// One problem, located by the triangles or vertex involved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IssueExample {
+    pub issue: MeshIssue,
+    pub triangles: Vec<usize>,
+    /// Point of the problem in mm, a vertex of the edge or triangle
+    pub position: [f64; 3],
+}
+
+/// Triangle that passed the index and area checks, with the welded ids of its corners
+struct Face {
+    index: usize,
+    points: [[f64; 3]; 3],
+    ids: [usize; 3],
+}
+
+/// Triangles running an undirected edge from the lower to the higher welded id and back, with one end of it
+type EdgeUses = (Vec<usize>, Vec<usize>, [f64; 3]);
+
+/// Triangle projected into its plane
+type Triangle2 = [[f64; 2]; 3];
+
+/// What `validate_mesh` found
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub vertices: usize,
+    pub triangles: usize,
+    /// Bounds of the vertices used by triangles, `None` for an empty mesh
+    pub bounds: Option<Bounds>,
+    /// Number of problems of every kind, kinds without problems are left out
+    pub counts: BTreeMap<MeshIssue, usize>,
+    /// The first few problems of every kind
+    pub examples: Vec<IssueExample>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn count(&self, issue: MeshIssue) -> usize {
+        self.counts.get(&issue).copied().unwrap_or(0)
+    }
+
+    /// Closed and manifold with consistent winding, slicers take it without repair
+    pub fn is_watertight(&self) -> bool {
+        [
+            MeshIssue::IndexOutOfRange,
+            MeshIssue::DegenerateTriangle,
+            MeshIssue::NonManifoldEdge,
+            MeshIssue::InconsistentWinding,
+            MeshIssue::OpenBoundary,
+        ]
+        .iter()
+        .all(|&issue| self.count(issue) == 0)
+    }
+
+    /// Short human readable summary, one line per kind of problem
+    pub fn summary(&self) -> Vec<String> {
+        self.counts.iter()
+            .map(|(issue, count)| format!("{}: {}", issue, count))
+            .collect()
+    }
+
+    fn record(&mut self, issue: MeshIssue, triangles: Vec<usize>, position: [f64; 3]) {
+        let count = self.counts.entry(issue).or_default();
+        *count += 1;
+        if *count <= MAX_EXAMPLES {
+            self.examples.push(IssueExample { issue, triangles, position });
+        }
+    }
+}
+
+/// Check a mesh the way a slicer would see it: vertices at the same position are welded, then every
+/// edge has to be shared by exactly two triangles running it in opposite directions. Boxes that touch
+/// are reported as overlapping faces and non-manifold edges, which is what slicers repair.
+/// With `expected` every vertex has to be inside those bounds.
+pub fn validate_mesh(mesh: &Mesh, expected: Option<&Bounds>) -> ValidationReport {
+    let vertices = &mesh.vertices.vertex;
+    let mut report = ValidationReport {
+        vertices: vertices.len(),
+        triangles: mesh.triangles.triangle.len(),
+        ..ValidationReport::default()
+    };
+    let position = |index: usize| [vertices[index].x, vertices[index].y, vertices[index].z];
+
+    // Triangles that can be checked any further, with welded corners
+    let mut welded_ids: HashMap<[i64; 3], usize> = HashMap::new();
+    let mut weld = |point: [f64; 3]| {
+        let key = point.map(|c| (c / WELD_TOLERANCE).round() as i64);
+        let next = welded_ids.len();
+        *welded_ids.entry(key).or_insert(next)
+    };
+    let mut valid = Vec::with_capacity(report.triangles);
+    for (index, triangle) in mesh.triangles.triangle.iter().enumerate() {
+        let corners = [triangle.v1, triangle.v2, triangle.v3];
+        if corners.iter().any(|&corner| corner >= vertices.len()) {
+            let inside = corners.iter().find(|&&corner| corner < vertices.len()).map_or([0.0; 3], |&corner| position(corner));
+            report.record(MeshIssue::IndexOutOfRange, vec![index], inside);
+            continue;
+        }
+
+        let points = corners.map(position);
+        let ids = points.map(&mut weld);
+        if ids[0] == ids[1] || ids[1] == ids[2] || ids[0] == ids[2] || area(points) <= TOLERANCE * TOLERANCE {
+            report.record(MeshIssue::DegenerateTriangle, vec![index], points[0]);
+            continue;
+        }
+        valid.push(Face { index, points, ids });
+    }
+
+    let used: Vec<[f64; 3]> = valid.iter().flat_map(|face| face.points).collect();
+    report.bounds = used.first().map(|&first| {
+        used.iter().fold(Bounds { min: first, max: first }, |bounds, point| Bounds {
+            min: [0, 1, 2].map(|axis| bounds.min[axis].min(point[axis])),
+            max: [0, 1, 2].map(|axis| bounds.max[axis].max(point[axis])),
+        })
+    });
+    if let Some(expected) = expected {
+        for face in &valid {
+            if let Some(&outside) = face.points.iter().find(|&&point| !expected.contains(point)) {
+                report.record(MeshIssue::OutOfBounds, vec![face.index], outside);
+            }
+        }
+    }
+
+    check_edges(&valid, &mut report);
+    check_overlaps(&valid, &mut report);
+
+    report
+}
+
+/// Count the triangles on every welded edge by direction
+fn check_edges(faces: &[Face], report: &mut ValidationReport) {
+    let mut edges: BTreeMap<(usize, usize), EdgeUses> = BTreeMap::new();
+    for Face { index, points, ids } in faces {
+        for (from, to) in [(0, 1), (1, 2), (2, 0)] {
+            let (a, b) = (ids[from], ids[to]);
+            let entry = edges.entry((a.min(b), a.max(b))).or_insert_with(|| (Vec::new(), Vec::new(), points[from]));
+            if a < b { entry.0.push(*index) } else { entry.1.push(*index) }
+        }
+    }
+
+    for (forward, backward, position) in edges.into_values() {
+        let issue = match (forward.len(), backward.len()) {
+            (1, 1) => continue,
+            (1, 0) | (0, 1) => MeshIssue::OpenBoundary,
+            (2, 0) | (0, 2) => MeshIssue::InconsistentWinding,
+            _ => MeshIssue::NonManifoldEdge,
+        };
+        report.record(issue, forward.into_iter().chain(backward).collect(), position);
+    }
+}
+
+/// Find pairs of triangles in the same plane whose interiors overlap
+fn check_overlaps(triangles: &[Face], report: &mut ValidationReport) {
+    // Triangles by plane, the normal is flipped to a canonical direction so back to back faces meet too
+    let mut planes: BTreeMap<[i64; 4], Vec<(usize, Triangle2)>> = BTreeMap::new();
+    for (slot, Face { points, .. }) in triangles.iter().enumerate() {
+        let mut normal = normal(*points);
+        if normal.iter().find(|c| c.abs() > 1e-9).is_some_and(|&c| c < 0.0) {
+            normal = normal.map(|c| -c);
+        }
+        let offset = normal[0] * points[0][0] + normal[1] * points[0][1] + normal[2] * points[0][2];
+        let key = [
+            (normal[0] * 1e6).round() as i64,
+            (normal[1] * 1e6).round() as i64,
+            (normal[2] * 1e6).round() as i64,
+            (offset / WELD_TOLERANCE).round() as i64,
+        ];
+
+        // Drop the axis the plane faces most, the other two keep the area
+        let dropped = (0..3).max_by(|&a, &b| normal[a].abs().total_cmp(&normal[b].abs())).unwrap_or(2);
+        let (u, v) = ((dropped + 1) % 3, (dropped + 2) % 3);
+        planes.entry(key).or_default().push((slot, points.map(|point| [point[u], point[v]])));
+    }
+
+    for mut faces in planes.into_values() {
+        // Sweep along u, only triangles whose u ranges overlap can overlap
+        let range = |triangle: &Triangle2, axis: usize| {
+            let values = triangle.map(|point| point[axis]);
+            (values.iter().copied().fold(f64::MAX, f64::min), values.iter().copied().fold(f64::MIN, f64::max))
+        };
+        faces.sort_by(|a, b| range(&a.1, 0).0.total_cmp(&range(&b.1, 0).0).then(a.0.cmp(&b.0)));
+        let mut active: Vec<usize> = Vec::new();
+        for current in 0..faces.len() {
+            let (start, _) = range(&faces[current].1, 0);
+            active.retain(|&other| range(&faces[other].1, 0).1 > start + TOLERANCE);
+            for &other in &active {
+                let ((v_min, v_max), (w_min, w_max)) = (range(&faces[current].1, 1), range(&faces[other].1, 1));
+                if v_min < w_max - TOLERANCE && w_min < v_max - TOLERANCE && overlap(&faces[current].1, &faces[other].1) {
+                    let (first, second) = (faces[other].0.min(faces[current].0), faces[other].0.max(faces[current].0));
+                    report.record(MeshIssue::OverlappingFaces, vec![triangles[first].index, triangles[second].index], triangles[first].points[0]);
+                }
+            }
+            active.push(current);
+        }
+    }
+}
+
+/// Whether two triangles in a plane share more than an edge or a corner, separating axis test
+fn overlap(a: &Triangle2, b: &Triangle2) -> bool {
+    for triangle in [a, b] {
+        for edge in 0..3 {
+            let (p, q) = (triangle[edge], triangle[(edge + 1) % 3]);
+            let axis = [q[1] - p[1], p[0] - q[0]];
+            let length = (axis[0] * axis[0] + axis[1] * axis[1]).sqrt();
+            let project = |t: &Triangle2| {
+                let values = t.map(|point| (point[0] * axis[0] + point[1] * axis[1]) / length);
+                (values.iter().copied().fold(f64::MAX, f64::min), values.iter().copied().fold(f64::MIN, f64::max))
+            };
+            let ((a_min, a_max), (b_min, b_max)) = (project(a), project(b));
+            if a_max <= b_min + TOLERANCE || b_max <= a_min + TOLERANCE {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn cross(points: [[f64; 3]; 3]) -> [f64; 3] {
+    let [a, b, c] = points;
+    let (u, v) = ([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]]);
+    [u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2], u[0] * v[1] - u[1] * v[0]]
+}
+
+fn area(points: [[f64; 3]; 3]) -> f64 {
+    let n = cross(points);
+    (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt() / 2.0
+}
+
+fn normal(points: [[f64; 3]; 3]) -> [f64; 3] {
+    let n = cross(points);
+    let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    n.map(|c| c / length)
+}
@@ -0,0 +1,61 @@
+use image::{GrayImage, RgbImage};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// SHA-256 over every input that determines the generated model.
+/// Identical inputs give the same hash on every machine, so it can be used as a cache key
+/// and stored in the exported files to tell whether a model has to be regenerated.
+pub struct RecipeHasher {
+    hasher: Sha256,
+}
+
+impl RecipeHasher {
+    /// Start a recipe, the crate version is part of it because meshing changes between releases
+    pub fn new() -> Self {
+        let mut recipe = Self { hasher: Sha256::new() };
+        recipe.add_bytes("version", env!("CARGO_PKG_VERSION").as_bytes());
+        recipe
+    }
+
+    pub fn add_image(&mut self, name: &str, image: &RgbImage) -> &mut Self {
+        self.add_bytes(&format!("{}:{}x{}", name, image.width(), image.height()), image.as_raw())
+    }
+
+    /// Add the alpha mask, no mask and a fully opaque mask are different recipes
+    pub fn add_mask(&mut self, name: &str, mask: Option<&GrayImage>) -> &mut Self {
+        match mask {
+            Some(mask) => self.add_bytes(&format!("{}:{}x{}", name, mask.width(), mask.height()), mask.as_raw()),
+            None => self.add_bytes(name, &[]),
+        }
+    }
+
+    /// Add settings by their JSON form, field order is fixed by the struct so the bytes are stable
+    pub fn add_settings<T: Serialize>(&mut self, name: &str, settings: &T) -> &mut Self {
+        let json = serde_json::to_vec(settings).unwrap_or_default();
+        self.add_bytes(name, &json)
+    }
+
+    /// Add a named blob, names and lengths are hashed too so adjacent values cannot run into each other
+    pub fn add_bytes(&mut self, name: &str, bytes: &[u8]) -> &mut Self {
+        self.hasher.update((name.len() as u64).to_le_bytes());
+        self.hasher.update(name.as_bytes());
+        self.hasher.update((bytes.len() as u64).to_le_bytes());
+        self.hasher.update(bytes);
+        self
+    }
+
+    /// Lowercase hex digest
+    pub fn finish(&self) -> String {
+        self.hasher.clone()
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+impl Default for RecipeHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,6 +1,46 @@
+use image::GrayImage;
 use threemf::model::{Vertices, Triangles, Mesh};
 
+/// Mask values below this are transparent, such pixels get no geometry
+pub const MASK_THRESHOLD: u8 = 128;
+
 pub struct PrintObjects {
     pub black_mesh: Mesh,
     pub white_mesh: Mesh,
-}
\ No newline at end of file
+    /// Parts in other materials, like a cap over a flat top
+    pub extras: Vec<MaterialMesh>,
+}
+
+impl PrintObjects {
+    /// Triangles of all parts together
+    pub fn triangle_count(&self) -> usize {
+        self.black_mesh.triangles.triangle.len()
+            + self.white_mesh.triangles.triangle.len()
+            + self.extras.iter().map(|extra| extra.mesh.triangles.triangle.len()).sum::<usize>()
+    }
+
+    /// Vertices of all parts together
+    pub fn vertex_count(&self) -> usize {
+        self.parts().iter().map(|(_, mesh)| mesh.vertices.vertex.len()).sum()
+    }
+
+    /// Every part with its name, black and white first
+    pub fn parts(&self) -> Vec<(&str, &Mesh)> {
+        let mut parts = vec![("black", &self.black_mesh), ("white", &self.white_mesh)];
+        parts.extend(self.extras.iter().map(|extra| (extra.name.as_str(), &extra.mesh)));
+        parts
+    }
+}
+
+/// Mesh printed in its own material
+pub struct MaterialMesh {
+    pub name: String,
+    /// sRGB color shown in slicers, as #RRGGBB or #RRGGBBAA
+    pub display_color: String,
+    pub mesh: Mesh,
+}
+
+/// Whether the pixel is cut away by the (optional) alpha mask
+pub fn is_masked(mask: Option<&GrayImage>, x: u32, y: u32) -> bool {
+    mask.is_some_and(|mask_img| mask_img.get_pixel(x, y)[0] < MASK_THRESHOLD)
+}
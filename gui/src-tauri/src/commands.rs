@@ -3,13 +3,15 @@ use std::fs;
 use std::process::Command;
 use serde::{Deserialize, Serialize};
 use printspots_core::{
-    config::{PrintConfig,PrintingConstraints},
-    grayscale::{calibration::generate_calibration_objects, export_to_3mf, generate::generate_image},
+    config::{load_config_overrides, BacklitBase, EffectiveSettings, Lighting, PrintConfig, PrinterProfile, SettingsOverrides, SettingsSource},
+    grayscale::{calibration::{generate_backlit_calibration_objects, generate_calibration_objects}, export_groups_to_3mf, export_to_3mf, generate::{expected_bounds, generate_image_within_budget, ignored_options, validate_objects, BudgetTarget, GenerateOptions, MeshBackend, MeshBudget, TopCap}, PRINT_OBJECT_NAME},
+    recipe::RecipeHasher,
 };
-use printspots_core::mesh::add_build_plate_padding;
+use printspots_core::export::{gltf::export_to_glb, slicer::SlicerSettings};
+use printspots_core::mesh::{frame::Frame, mount::Mount, placement::{place_objects, PadSettings, PadStyle, Placement}, text::Label};
 use image::Rgb;
-use printspots_core::grayscale::{ColorPalette, image_processing::dither_to_palette, enforce_min_feature_size};
-use image::{DynamicImage, ImageReader, Luma, GrayImage};
+use printspots_core::grayscale::{ColorPalette, image_processing::dither_to_layer_map, shape::{apply_shape, BaseShape}, tiling::{split_into_tiles, Tile, TileJoint, Tiling}, enforce_min_feature_size_with_report, height::enforce_height_constraints, layer_map::LayerMap, report::{ConstraintRule, PrintabilityReport}};
+use image::{DynamicImage, ImageFormat, ImageReader, Luma, GrayImage};
 use std::io::Cursor;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +29,15 @@ pub struct CalibrationSettings {
     pub layer_thickness: f32,
     pub square_size: f32,
     pub max_layers: f32,
+    #[serde(default)]
+    pub lighting: Lighting,
+    #[serde(default)]
+    pub backlit_base: BacklitBase,
+    /// Print the squares the way flat top images are printed
+    #[serde(default)]
+    pub flat_top: bool,
+    #[serde(default)]
+    pub cap: Option<TopCap>,
 }
 
 #[tauri::command]
@@ -63,9 +74,14 @@ pub fn generate_calibration(profile: PrinterProfile, settings: CalibrationSettin
         layer_thickness: settings.layer_thickness,
         image_size_mm: 100.0, // Not used for calibration box generation directly but good to have
         max_layers: settings.max_layers, 
+        backlit_base: settings.backlit_base,
+        ..PrintConfig::default()
     };
 
-    let objects = generate_calibration_objects(&config, settings.square_size, false);
+    let objects = match settings.lighting {
+        Lighting::Reflective => generate_calibration_objects(&config, settings.square_size, settings.flat_top, settings.cap),
+        Lighting::Backlit => generate_backlit_calibration_objects(&config, settings.square_size),
+    };
 
     export_to_3mf(&objects, &output_path_str)
         .map_err(|e| e.to_string())?;
@@ -74,19 +90,6 @@ pub fn generate_calibration(profile: PrinterProfile, settings: CalibrationSettin
 }
 
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PrinterProfile {
-    pub id: String, // uuid
-    pub name: String,
-    pub description: String,
-    pub bed_width: f32,
-    pub bed_depth: f32,
-    pub nozzle_diameter: f32,
-    pub min_layer_height: f32,
-    #[serde(default)]
-    pub has_automatic_filament_change: bool,
-}
-
 #[tauri::command]
 pub fn save_printer_profile(profile: PrinterProfile) -> Result<(), String> {
     let config_dir = dirs::config_dir()
@@ -110,6 +113,8 @@ pub struct PaletteData {
     pub id: Option<String>,
     pub colors: Vec<[u8; 3]>,
     pub layer_counts: Vec<u32>,
+    #[serde(default)]
+    pub lighting: Lighting,
 }
 
 #[tauri::command]
@@ -121,6 +126,7 @@ pub fn save_palette(data: PaletteData, name: String) -> Result<String, String> {
     let palette = ColorPalette {
         colors,
         layer_counts: data.layer_counts,
+        lighting: data.lighting,
     };
     
     let config_dir = dirs::config_dir()
@@ -197,6 +203,7 @@ pub fn get_palettes() -> Result<Vec<PaletteData>, String> {
                      let palette = PaletteData {
                          colors,
                          layer_counts: core_palette.layer_counts,
+                         lighting: core_palette.lighting,
                          id: Some(path.file_stem().unwrap().to_string_lossy().to_string())
                      };
                      palettes.push(palette);
@@ -382,10 +389,138 @@ pub struct ProjectConfig {
     pub base_thickness: f32,
     pub layer_thickness: f32,
     pub image_size_mm: f32,
+    /// Anchor pads of projects saved before `pads`
     #[serde(default)]
     pub add_pads: bool,
+    /// Pads on the build plate, the model is always centered on the bed of the printer
+    #[serde(default)]
+    pub pads: PadSettings,
     #[serde(default)]
     pub flat_top: bool,
+    /// Cap over the flat top, ignored without it
+    #[serde(default)]
+    pub cap: Option<TopCap>,
+    /// Outline of the base, the image is cut or padded to it
+    #[serde(default)]
+    pub shape: Option<BaseShape>,
+    #[serde(default)]
+    pub frame: Option<Frame>,
+    #[serde(default)]
+    pub mount: Option<Mount>,
+    /// Text on a strip below the image
+    #[serde(default)]
+    pub label: Option<Label>,
+    /// Text debossed into the back
+    #[serde(default)]
+    pub back_label: Option<Label>,
+    /// Palette and config identifiers on the back
+    #[serde(default)]
+    pub back_ids: bool,
+    #[serde(default)]
+    pub backlit_base: BacklitBase,
+    #[serde(default)]
+    pub mesher: MeshBackend,
+    /// Largest model to export, small features are merged until it fits
+    #[serde(default)]
+    pub budget: MeshBudget,
+    /// Check the meshes after generating them
+    #[serde(default)]
+    pub validate: bool,
+    /// Split the image into tiles as large as the bed of the printer
+    #[serde(default)]
+    pub tiling: Option<ProjectTiling>,
+    /// Advanced settings, anything set here wins over the config file and the printer profile
+    #[serde(default)]
+    pub overrides: SettingsOverrides,
+}
+
+/// Joints between the tiles of a project, the size of the tiles comes from the printer profile
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ProjectTiling {
+    #[serde(default)]
+    pub joint: TileJoint,
+    #[serde(default = "default_joint_size")]
+    pub joint_size_mm: f32,
+    #[serde(default = "default_joint_clearance")]
+    pub joint_clearance_mm: f32,
+    /// Name of every tile on its back
+    #[serde(default)]
+    pub name_tiles: bool,
+}
+
+fn default_joint_size() -> f32 {
+    5.0
+}
+
+fn default_joint_clearance() -> f32 {
+    0.2
+}
+
+impl ProjectTiling {
+    fn for_bed(&self, printer_profile: &PrinterProfile) -> Tiling {
+        Tiling {
+            joint: self.joint,
+            joint_size_mm: self.joint_size_mm,
+            joint_clearance_mm: self.joint_clearance_mm,
+            name_tiles: self.name_tiles,
+            ..Tiling::from_profile(printer_profile)
+        }
+    }
+}
+
+impl ProjectConfig {
+    fn placement(&self, printer_profile: &PrinterProfile) -> Placement {
+        let mut pads = self.pads;
+        if self.add_pads && pads.style == PadStyle::None {
+            pads.style = PadStyle::Anchors;
+        }
+        Placement { bed: Some([printer_profile.bed_width, printer_profile.bed_depth]), pads }
+    }
+
+    /// Project level settings, `max_layers` comes from the palette unless overridden
+    fn settings_overrides(&self, max_layers: f32) -> SettingsOverrides {
+        let advanced = &self.overrides;
+        SettingsOverrides {
+            base_thickness: advanced.base_thickness.or(Some(self.base_thickness)),
+            layer_thickness: advanced.layer_thickness.or(Some(self.layer_thickness)),
+            image_size_mm: advanced.image_size_mm.or(Some(self.image_size_mm)),
+            max_layers: advanced.max_layers.or(Some(max_layers)),
+            backlit_base: advanced.backlit_base.or(Some(self.backlit_base)),
+            ..advanced.clone()
+        }
+    }
+}
+
+/// defaults < config file < printer profile < project
+fn resolve_settings(project_config: &ProjectConfig, printer_profile: &PrinterProfile, max_layers: f32) -> EffectiveSettings {
+    EffectiveSettings::resolve(&[
+        (SettingsSource::ConfigFile, &load_config_overrides()),
+        (SettingsSource::PrinterProfile, &printer_profile.settings_overrides()),
+        (SettingsSource::Project, &project_config.settings_overrides(max_layers)),
+    ])
+}
+
+fn load_printer_profile(printer_profile_id: &str) -> Result<PrinterProfile, String> {
+    let profile_path = dirs::config_dir()
+        .ok_or("Could not determine config directory")?
+        .join("printspots")
+        .join("profiles")
+        .join(format!("{}.json", printer_profile_id));
+
+    PrinterProfile::load_from_file(&profile_path)
+        .map_err(|e| format!("Failed to load printer profile: {}", e))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PredictionResult {
+    /// Prediction as a PNG data URL
+    pub prediction: String,
+    /// Prediction with the pixels changed for printability highlighted, as a PNG data URL
+    pub overlay: String,
+    pub summary: Vec<String>,
+    pub report: PrintabilityReport,
+    /// Settings the prediction was made with
+    pub settings: EffectiveSettings,
 }
 
 #[tauri::command]
@@ -395,7 +530,7 @@ pub async fn generate_prediction(
     project_config: ProjectConfig,
     printer_profile_id: String,
     palette_id: String,
-) -> Result<String, String> {
+) -> Result<PredictionResult, String> {
     
 
     // Decode base64 image data
@@ -436,41 +571,34 @@ pub async fn generate_prediction(
     let palette = ColorPalette::load_from_file(&palette_path)
         .map_err(|e| format!("Failed to load palette: {}", e))?;
 
+    // Cut the image to the base shape, padding shows the bare base
+    let (rgb_img, mask) = match &project_config.shape {
+        Some(shape) => {
+            let (rgb_img, mask) = apply_shape(&rgb_img, mask.as_ref(), shape, palette.base_color())?;
+            (rgb_img, Some(mask))
+        }
+        None => (rgb_img, mask),
+    };
+
     // Derive max_layers from palette
-    let max_layers = palette.layer_counts.len() as f32;
+    let max_layers = palette.max_layer_count() as f32;
 
     // Load printer profile
-    let profile_path = dirs::config_dir()
-        .ok_or("Could not determine config directory")?
-        .join("printspots")
-        .join("profiles")
-        .join(format!("{}.json", printer_profile_id));
-    
-    let profile_content = fs::read_to_string(&profile_path)
-        .map_err(|e| format!("Failed to read printer profile: {}", e))?;
-    let printer_profile: PrinterProfile = serde_json::from_str(&profile_content)
-        .map_err(|e| format!("Failed to parse printer profile: {}", e))?;
-
-    // Build PrintConfig
-    let config = PrintConfig {
-        base_thickness: project_config.base_thickness,
-        layer_thickness: project_config.layer_thickness,
-        image_size_mm: project_config.image_size_mm,
-        max_layers,
-    };
+    let printer_profile = load_printer_profile(&printer_profile_id)?;
 
-    // Build PrintingConstraints
-    let constraints = PrintingConstraints {
-        min_feature_size_mm: 2.0 * printer_profile.nozzle_diameter,
-        merge_small_features: true, // TODO: expose in advanced section
-        erosion_dilation_passes: 1, // TODO: expose in advanced section
-    };
+    let settings = resolve_settings(&project_config, &printer_profile, max_layers);
+    let config = settings.config.clone();
+    let constraints = settings.constraints.clone();
 
     // Apply dithering
-    let dithered = dither_to_palette(&rgb_img, &palette);
+    let dithered = dither_to_layer_map(&rgb_img, &palette, mask.as_ref())
+        .map_err(|e| format!("Failed to dither image: {}", e))?;
 
     // Apply feature size enforcement
-    let printable = enforce_min_feature_size(&dithered, &palette, &constraints, &config);
+    let (printable, mut report) = enforce_min_feature_size_with_report(&dithered, &constraints, &config);
+    let (constrained, _) = enforce_height_constraints(&printable, &palette, &constraints, &config);
+    report.record_diff(ConstraintRule::Height, &printable, &constrained);
+    let printable = constrained;
 
     // Save to project directory
     let project_dir = dirs::config_dir()
@@ -484,21 +612,38 @@ pub async fn generate_prediction(
     }
 
     let prediction_path = project_dir.join("prediction.png");
-    printable.save(&prediction_path)
+    let prediction = printable.to_image(&palette)
+        .map_err(|e| format!("Failed to render prediction: {}", e))?;
+    prediction.save(&prediction_path)
         .map_err(|e| format!("Failed to save prediction: {}", e))?;
+
+    let overlay_path = project_dir.join("prediction_overlay.png");
+    report.render_overlay(&prediction)
+        .save(&overlay_path)
+        .map_err(|e| format!("Failed to save overlay: {}", e))?;
     
-    // Save mask if present
+    // Save mask if present, a mask left from an earlier shape would cut the new prediction
+    let mask_path = project_dir.join("mask.png");
     if let Some(mask_img) = mask {
-        let mask_path = project_dir.join("mask.png");
         mask_img.save(&mask_path)
             .map_err(|e| format!("Failed to save mask: {}", e))?;
-    };
+    } else if mask_path.exists() {
+        fs::remove_file(&mask_path).map_err(|e| format!("Failed to remove old mask: {}", e))?;
+    }
 
     // Encode as base64 and return
     let prediction_bytes = fs::read(&prediction_path).map_err(|e| e.to_string())?;
     let base64_prediction = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &prediction_bytes);
-    
-    Ok(format!("data:image/png;base64,{}", base64_prediction))
+    let overlay_bytes = fs::read(&overlay_path).map_err(|e| e.to_string())?;
+    let base64_overlay = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &overlay_bytes);
+    
+    Ok(PredictionResult {
+        prediction: format!("data:image/png;base64,{}", base64_prediction),
+        overlay: format!("data:image/png;base64,{}", base64_overlay),
+        summary: report.summary(),
+        report,
+        settings,
+    })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -508,13 +653,32 @@ pub struct MeshStats {
     pub black_triangles: usize,
     pub white_vertices: usize,
     pub white_triangles: usize,
+    pub settings: EffectiveSettings,
+    /// Hash of all inputs, also stored in the 3MF metadata
+    pub recipe: String,
+    /// What was traded to fit the mesh budget, empty without a budget
+    pub budget: Vec<String>,
+    /// New prediction as a PNG data URL when the budget coarsened the image
+    pub prediction: Option<String>,
+    /// Problems found per part, empty when validation is off
+    pub validation: Vec<String>,
+    /// Where every part ended up on the bed
+    pub placement: Vec<String>,
+    /// 3MF file of every tile, empty when the image fits the bed in one piece
+    pub tiles: Vec<String>,
+    /// GLB of the model (of the first tile when tiled) with the part colors, for the 3D preview
+    pub preview_glb: Option<String>,
+    /// Slicer settings embedded in the 3MF, `None` for bare geometry
+    pub slicer: Option<SlicerSettings>,
+    /// Project options the palette does not allow, left off the model
+    pub warnings: Vec<String>,
 }
 
 #[tauri::command]
 pub async fn generate_3mf(
     project_id: String,
     project_config: ProjectConfig,
-    _printer_profile_id: String,
+    printer_profile_id: String,
     palette_id: String,
 ) -> Result<MeshStats, String> {
     
@@ -562,38 +726,142 @@ pub async fn generate_3mf(
         .map_err(|e| format!("Failed to load palette: {}", e))?;
 
     // Derive max_layers from palette
-    let max_layers = palette.layer_counts.len() as f32;
+    let max_layers = palette.max_layer_count() as f32;
 
-    // Build PrintConfig
-    let config = PrintConfig {
-        base_thickness: project_config.base_thickness,
-        layer_thickness: project_config.layer_thickness,
-        image_size_mm: project_config.image_size_mm,
-        max_layers,
-    };
+    let printer_profile = load_printer_profile(&printer_profile_id)?;
+    let settings = resolve_settings(&project_config, &printer_profile, max_layers);
+    let config = settings.config.clone();
+
+    // Convert the prediction back to layer counts, it must only contain palette colors
+    let layers = LayerMap::from_image(&prediction, &palette, mask.as_ref())
+        .map_err(|e| format!("Prediction does not match the palette: {}", e))?;
 
     // Generate 3D meshes
-    let mut image_objects = generate_image(&prediction, &palette, &config, project_config.flat_top, mask.as_ref());
+    let options = GenerateOptions {
+        flat_top: project_config.flat_top,
+        backend: project_config.mesher,
+        nozzle_diameter: printer_profile.nozzle_diameter,
+        cap: project_config.cap,
+        frame: project_config.frame,
+        mount: project_config.mount,
+        label: project_config.label.clone(),
+        back_label: project_config.back_label.clone(),
+        back_ids: project_config.back_ids,
+    };
+    let placement = project_config.placement(&printer_profile);
+    // Layer height, infill and extruders for the slicer of the printer, if it has one
+    let slicer = SlicerSettings::new(printer_profile.slicer, &config);
+    let recipe = RecipeHasher::new()
+        .add_image("image", &prediction)
+        .add_mask("mask", mask.as_ref())
+        .add_settings("palette", &palette)
+        .add_settings("config", &settings.config)
+        .add_settings("constraints", &settings.constraints)
+        .add_settings("options", &options)
+        .add_settings("budget", &project_config.budget)
+        .add_settings("tiling", &project_config.tiling)
+        .add_settings("placement", &placement)
+        .add_settings("slicer", &slicer)
+        .finish();
+
+    // Tiles are cut from the whole prediction, so they continue seamlessly into each other
+    let tiling = project_config.tiling.map(|tiling| tiling.for_bed(&printer_profile));
+    let tiles = match &tiling {
+        Some(tiling) => split_into_tiles(&layers, &config, tiling)?,
+        None => vec![Tile::whole(&layers, &config)],
+    };
+    let tiled = tiles.len() > 1;
+
+    let mut printed = layers.clone();
+    let mut stats = MeshStats {
+        output_path: String::new(),
+        black_vertices: 0,
+        black_triangles: 0,
+        white_vertices: 0,
+        white_triangles: 0,
+        settings,
+        recipe,
+        budget: Vec::new(),
+        prediction: None,
+        validation: Vec::new(),
+        placement: Vec::new(),
+        tiles: Vec::new(),
+        preview_glb: None,
+        slicer,
+        warnings: ignored_options(&palette, &options),
+    };
+    // The stats change tile by tile, the budget counts the metadata of every 3MF
+    let (recipe, slicer) = (stats.recipe.clone(), stats.slicer);
+    let budget_target = BudgetTarget { limits: project_config.budget, recipe: Some(&recipe), slicer: slicer.as_ref() };
+    for tile in &tiles {
+        let (tile_config, tile_options) = match tiling.filter(|_| tiled) {
+            Some(tiling) => (tile.config(&config), tile.options(&options, &tiling)),
+            None => (config.clone(), options.clone()),
+        };
+        // Names the lines of the stats after the tile they are about
+        let prefix = if tiled { format!("{}: ", tile.name()) } else { String::new() };
+
+        let (width, height) = tile.layers.dimensions();
+        let mut budget_changes = PrintabilityReport::new(width, height, &tile_config);
+        let (tile_printed, mut image_objects, budget_report) = generate_image_within_budget(
+            &tile.layers, &palette, &tile_config, &stats.settings.constraints, &tile_options, &budget_target, &mut budget_changes,
+        );
+        tile.write_back(&tile_printed, &mut printed);
+
+        // Validate before the pads, they lie outside of the image
+        if project_config.validate {
+            let bounds = expected_bounds(&tile_printed, &palette, &tile_config, &tile_options);
+            stats.validation.extend(validate_objects(&image_objects, &bounds)
+                .into_iter()
+                .map(|(name, report)| match report.is_valid() {
+                    true => format!("{}{} mesh: no problems found", prefix, name),
+                    false => format!("{}{} mesh: {}", prefix, name, report.summary().join(", ")),
+                }));
+        }
+
+        // Pads and the position on the bed
+        let placed = place_objects(&mut image_objects, &placement);
+        stats.placement.extend(placed.summary().into_iter().map(|line| format!("{}{}", prefix, line)));
 
-    // Add pads if requested
-    if project_config.add_pads {
-        add_build_plate_padding(&mut image_objects.black_mesh, 10.0);
-        add_build_plate_padding(&mut image_objects.white_mesh, 10.0);
+        // Export to 3MF
+        let output_path = match tiled {
+            true => project_dir.join(format!("output_{}.3mf", tile.name())),
+            false => project_dir.join("output.3mf"),
+        };
+        export_groups_to_3mf(&[(PRINT_OBJECT_NAME, &image_objects)], output_path.to_str().unwrap(), Some(&stats.recipe), stats.slicer.as_ref())
+            .map_err(|e| format!("Failed to export 3MF: {}", e))?;
+        let glb_path = output_path.with_extension("glb");
+        export_to_glb(&image_objects, &glb_path)
+            .map_err(|e| format!("Failed to export GLB preview: {}", e))?;
+        stats.preview_glb.get_or_insert_with(|| glb_path.to_string_lossy().to_string());
+
+        // Collect stats
+        stats.black_vertices += image_objects.black_mesh.vertices.vertex.len();
+        stats.black_triangles += image_objects.black_mesh.triangles.triangle.len();
+        stats.white_vertices += image_objects.white_mesh.vertices.vertex.len();
+        stats.white_triangles += image_objects.white_mesh.triangles.triangle.len();
+        if !project_config.budget.is_unlimited() {
+            stats.budget.extend(budget_report.summary().into_iter().map(|line| format!("{}{}", prefix, line)));
+        }
+        if stats.output_path.is_empty() {
+            stats.output_path = output_path.to_string_lossy().to_string();
+        }
+        if tiled {
+            stats.tiles.push(output_path.to_string_lossy().to_string());
+        }
     }
 
-    // Export to 3MF
-    let output_path = project_dir.join("output.3mf");
-    export_to_3mf(&image_objects, output_path.to_str().unwrap())
-        .map_err(|e| format!("Failed to export 3MF: {}", e))?;
-
-    // Collect stats
-    let stats = MeshStats {
-        output_path: output_path.to_string_lossy().to_string(),
-        black_vertices: image_objects.black_mesh.vertices.vertex.len(),
-        black_triangles: image_objects.black_mesh.triangles.triangle.len(),
-        white_vertices: image_objects.white_mesh.vertices.vertex.len(),
-        white_triangles: image_objects.white_mesh.triangles.triangle.len(),
-    };
+    // The budget coarsened the image, show what actually gets printed. prediction.png stays the input
+    // of this command, overwriting it would coarsen it again on every run.
+    if printed != layers {
+        let mut prediction_bytes = Vec::new();
+        printed.to_image(&palette)
+            .map_err(|e| format!("Failed to render prediction: {}", e))?
+            .write_to(&mut Cursor::new(&mut prediction_bytes), ImageFormat::Png)
+            .map_err(|e| format!("Failed to encode prediction: {}", e))?;
+        let base64_prediction = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &prediction_bytes);
+        stats.prediction = Some(format!("data:image/png;base64,{}", base64_prediction));
+    }
 
     Ok(stats)
 }
@@ -608,7 +876,35 @@ pub struct ProjectData {
     #[serde(default)]
     pub add_pads: bool,
     #[serde(default)]
+    pub pads: PadSettings,
+    #[serde(default)]
     pub flat_top: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cap: Option<TopCap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shape: Option<BaseShape>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame: Option<Frame>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount: Option<Mount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<Label>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub back_label: Option<Label>,
+    #[serde(default)]
+    pub back_ids: bool,
+    #[serde(default)]
+    pub backlit_base: BacklitBase,
+    #[serde(default)]
+    pub mesher: MeshBackend,
+    #[serde(default)]
+    pub budget: MeshBudget,
+    #[serde(default)]
+    pub validate: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tiling: Option<ProjectTiling>,
+    #[serde(default, skip_serializing_if = "SettingsOverrides::is_empty")]
+    pub overrides: SettingsOverrides,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub source_image: Option<String>,
     #[serde(default)]
@@ -29,7 +29,21 @@ export const useProjectStore = defineStore('project', () => {
                 base_thickness: config.base_thickness,
                 layer_thickness: config.layer_thickness,
                 add_pads: config.add_pads,
+                pads: config.pads || {},
                 flat_top: config.flat_top,
+                cap: config.cap || null,
+                shape: config.shape || null,
+                frame: config.frame || null,
+                mount: config.mount || null,
+                label: config.label || null,
+                back_label: config.back_label || null,
+                back_ids: config.back_ids || false,
+                backlit_base: config.backlit_base,
+                mesher: config.mesher,
+                budget: config.budget || {},
+                tiling: config.tiling || null,
+                validate: config.validate || false,
+                overrides: config.overrides || {},
                 source_image: null,
                 settings: {},
                 last_modified: now
@@ -46,6 +60,8 @@ export const useProjectStore = defineStore('project', () => {
                 sourceImage: null,
                 sourceImageUrl: null,
                 predictionImageUrl: null,
+                predictionOverlayUrl: null,
+                printabilitySummary: [],
                 meshStats: null,
                 lastModified: now
             };
@@ -133,19 +149,36 @@ export const useProjectStore = defineStore('project', () => {
             };
 
             // Call backend
-            const predictionDataUrl = await invoke('generate_prediction', {
+            const prediction = await invoke('generate_prediction', {
                 projectId: currentProject.value.id,
                 imageData,
                 projectConfig: {
                     ...projectConfig,
                     add_pads: currentProject.value.add_pads,
-                    flat_top: currentProject.value.flat_top
+                    pads: currentProject.value.pads || {},
+                    flat_top: currentProject.value.flat_top,
+                    cap: currentProject.value.cap || null,
+                    shape: currentProject.value.shape || null,
+                    frame: currentProject.value.frame || null,
+                    mount: currentProject.value.mount || null,
+                    label: currentProject.value.label || null,
+                    back_label: currentProject.value.back_label || null,
+                    back_ids: currentProject.value.back_ids || false,
+                    backlit_base: currentProject.value.backlit_base,
+                    mesher: currentProject.value.mesher,
+                    budget: currentProject.value.budget || {},
+                    tiling: currentProject.value.tiling || null,
+                    validate: currentProject.value.validate || false,
+                    overrides: currentProject.value.overrides || {}
                 },
                 printerProfileId: profileStore.activeProfileId,
                 paletteId: paletteStore.activePaletteId,
             });
 
-            currentProject.value.predictionImageUrl = predictionDataUrl;
+            currentProject.value.predictionImageUrl = prediction.prediction;
+            currentProject.value.predictionOverlayUrl = prediction.overlay;
+            currentProject.value.printabilitySummary = prediction.summary;
+            currentProject.value.effectiveSettings = prediction.settings;
             isDirty.value = true;
         } catch (err) {
             console.error('Failed to generate prediction:', err);
@@ -186,7 +219,21 @@ export const useProjectStore = defineStore('project', () => {
                 base_thickness: currentProject.value.base_thickness,
                 layer_thickness: currentProject.value.layer_thickness,
                 add_pads: currentProject.value.add_pads,
-                flat_top: currentProject.value.flat_top
+                pads: currentProject.value.pads || {},
+                flat_top: currentProject.value.flat_top,
+                cap: currentProject.value.cap || null,
+                shape: currentProject.value.shape || null,
+                frame: currentProject.value.frame || null,
+                mount: currentProject.value.mount || null,
+                label: currentProject.value.label || null,
+                back_label: currentProject.value.back_label || null,
+                back_ids: currentProject.value.back_ids || false,
+                backlit_base: currentProject.value.backlit_base,
+                mesher: currentProject.value.mesher,
+                budget: currentProject.value.budget || {},
+                tiling: currentProject.value.tiling || null,
+                validate: currentProject.value.validate || false,
+                overrides: currentProject.value.overrides || {}
             };
 
             // Call backend
@@ -198,6 +245,11 @@ export const useProjectStore = defineStore('project', () => {
             });
 
             currentProject.value.meshStats = meshStats;
+            if (meshStats.prediction) {
+                // The mesh budget merged small features, the prediction shows the coarsened image
+                currentProject.value.predictionImageUrl = meshStats.prediction;
+                currentProject.value.predictionOverlayUrl = null;
+            }
             isDirty.value = true;
             return meshStats;
         } catch (err) {
@@ -265,12 +317,28 @@ export const useProjectStore = defineStore('project', () => {
                 sourceImage: projectData.source_image,
                 sourceImageUrl,
                 predictionImageUrl,
+                predictionOverlayUrl: null, // Only available right after generating a prediction
+                printabilitySummary: [],
                 meshStats: null, // Will be loaded if 3MF exists
                 image_size_mm: projectData.image_size_mm,
                 base_thickness: projectData.base_thickness,
                 layer_thickness: projectData.layer_thickness,
                 add_pads: projectData.add_pads,
+                pads: projectData.pads || {},
                 flat_top: projectData.flat_top,
+                cap: projectData.cap || null,
+                shape: projectData.shape || null,
+                frame: projectData.frame || null,
+                mount: projectData.mount || null,
+                label: projectData.label || null,
+                back_label: projectData.back_label || null,
+                back_ids: projectData.back_ids || false,
+                backlit_base: projectData.backlit_base,
+                mesher: projectData.mesher,
+                budget: projectData.budget || {},
+                tiling: projectData.tiling || null,
+                validate: projectData.validate || false,
+                overrides: projectData.overrides || {},
                 settings: projectData.settings || {},
                 lastModified: projectData.last_modified
             };